@@ -0,0 +1,188 @@
+//! 全文検索の語の切り出し (アナライザー)。
+//!
+//! `[search] analyzers` (`SEARCH_ANALYZERS`) で言語ごとの処理を選ぶ。何も選ばなければ、
+//! Tantivy は空白と記号で区切った語、走査は部分文字列の一致で検索する。
+//!
+//! - `english`: 英単語を小文字にして語幹にする (Snowball の English)。`books` でも `book` でも見つかる。
+//! - `japanese`: 漢字・ひらがな・カタカナの並びを 2 文字ずつ重ねて切る (bigram)。選ばなければ
+//!   並び全体を 1 語として扱うので、文の途中の語では見つからない。1 文字だけの語は、
+//!   1 文字だけの並び (`本` だけで区切られているもの) にしか一致しない。
+//!
+//! 両方を選べば、英語と日本語の混ざった文にもそれぞれの処理をかける。
+//! どの場合も、切り出す前に `crate::normalize` で全角・半角や大文字小文字をそろえる。
+
+use std::str::FromStr;
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::Deserialize;
+
+use crate::normalize::Folded;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    English,
+    Japanese,
+}
+
+impl FromStr for Language {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "english" => Ok(Language::English),
+            "japanese" => Ok(Language::Japanese),
+            _ => Err(()),
+        }
+    }
+}
+
+/// 切り出した語。`start` / `end` は元の文字列のバイト位置。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+    pub position: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Analyzer {
+    english: bool,
+    japanese: bool,
+}
+
+/// 漢字・ひらがな・カタカナ (半角カタカナを含む)。
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' | '\u{31f0}'..='\u{31ff}' | '\u{3400}'..='\u{4dbf}' |
+        '\u{4e00}'..='\u{9fff}' | '\u{f900}'..='\u{faff}' | '\u{ff66}'..='\u{ff9f}' | '々')
+}
+
+impl Analyzer {
+    pub fn new(languages: &[Language]) -> Self {
+        Analyzer {
+            english: languages.contains(&Language::English),
+            japanese: languages.contains(&Language::Japanese),
+        }
+    }
+
+    /// 言語を 1 つでも選んだか。
+    pub fn is_enabled(&self) -> bool {
+        self.english || self.japanese
+    }
+
+    /// 語を切り出す。語は `crate::normalize` で正規化したもので、`start` / `end` は元の文字列を指す。
+    pub fn tokens(&self, text: &str) -> Vec<Token> {
+        let stemmer = self.english.then(|| Stemmer::create(Algorithm::English));
+        let folded = Folded::new(text);
+        let chars: Vec<char> = folded.text.chars().collect();
+
+        let mut tokens = Vec::new();
+        let mut push = |from: usize, to: usize, stem: bool| {
+            let word: String = chars[from..to].iter().collect();
+            let text = match &stemmer {
+                Some(stemmer) if stem => stemmer.stem(&word).into_owned(),
+                _ => word,
+            };
+            let position = tokens.len();
+            tokens.push(Token { text, start: folded.origin(from).0, end: folded.origin(to - 1).1, position });
+        };
+
+        let mut i = 0;
+        while i < chars.len() {
+            let start = i;
+            if is_cjk(chars[i]) {
+                while i < chars.len() && is_cjk(chars[i]) {
+                    i += 1;
+                }
+                if !self.japanese || i - start == 1 {
+                    push(start, i, false);
+                } else {
+                    for k in start..i - 1 {
+                        push(k, k + 2, false);
+                    }
+                }
+            } else if chars[i].is_alphanumeric() {
+                while i < chars.len() && chars[i].is_alphanumeric() && !is_cjk(chars[i]) {
+                    i += 1;
+                }
+                push(start, i, true);
+            } else {
+                i += 1;
+            }
+        }
+
+        tokens
+    }
+}
+
+#[cfg(feature = "fulltext")]
+pub(crate) mod tantivy_tokenizer {
+    use tantivy::tokenizer::{Token, TokenStream, Tokenizer};
+
+    use super::Analyzer;
+
+    /// Tantivy の索引と検索で同じ切り出しを使うためのトークナイザー。
+    #[derive(Clone)]
+    pub struct AnalyzerTokenizer(pub Analyzer);
+
+    pub struct AnalyzerTokenStream {
+        tokens: Vec<Token>,
+        next: usize,
+    }
+
+    impl Tokenizer for AnalyzerTokenizer {
+        type TokenStream<'a> = AnalyzerTokenStream;
+
+        fn token_stream<'a>(&'a mut self, text: &'a str) -> AnalyzerTokenStream {
+            let tokens = self.0.tokens(text)
+                .into_iter()
+                .map(|t| Token { offset_from: t.start, offset_to: t.end, position: t.position, text: t.text, position_length: 1 })
+                .collect();
+            AnalyzerTokenStream { tokens, next: 0 }
+        }
+    }
+
+    impl TokenStream for AnalyzerTokenStream {
+        fn advance(&mut self) -> bool {
+            self.next += 1;
+            self.next <= self.tokens.len()
+        }
+
+        fn token(&self) -> &Token {
+            &self.tokens[self.next - 1]
+        }
+
+        fn token_mut(&mut self) -> &mut Token {
+            &mut self.tokens[self.next - 1]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(analyzer: Analyzer, text: &str) -> Vec<String> {
+        analyzer.tokens(text).into_iter().map(|t| t.text).collect()
+    }
+
+    #[test]
+    fn test_tokens() {
+        let english = Analyzer::new(&[Language::English]);
+        assert_eq!(texts(english, "Running Rust books"), vec!["run", "rust", "book"]);
+
+        let japanese = Analyzer::new(&[Language::Japanese]);
+        assert_eq!(texts(japanese, "東京都のRust本"), vec!["東京", "京都", "都の", "rust", "本"]);
+
+        let both = Analyzer::new(&[Language::English, Language::Japanese]);
+        let tokens = both.tokens("非同期 programming");
+        assert_eq!(tokens.iter().map(|t| t.text.as_str()).collect::<Vec<_>>(), vec!["非同", "同期", "program"]);
+        assert_eq!((tokens[1].start, tokens[1].end, tokens[1].position), (3, 9, 1));
+
+        assert_eq!(texts(Analyzer::default(), "東京都 Books"), vec!["東京都", "books"]);
+        // 全角英数字や半角カナも正規化してから切り出す
+        assert_eq!(texts(both, "ＲＵＳＴ ﾌﾟﾛｸﾞﾗﾐﾝｸﾞ"), texts(both, "rust プログラミング"));
+        assert!(!Analyzer::default().is_enabled());
+    }
+}
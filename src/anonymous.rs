@@ -0,0 +1,247 @@
+//! 認証なしのアクセス。
+//!
+//! 書庫を公開したい人向けに、読み取りは認証なしで、書き込みには認証を求める設定にできる。
+//! 認証なしのリクエストには、本ごとの見せ方 (`visibility`) に従って見せる本を絞る。
+//! `public_books` では、`public` を指定した本しか見せない。
+
+use std::str::FromStr;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, HttpRequest, ResponseError};
+use serde::Deserialize;
+
+use crate::events::{BookEvent, BookEventKind};
+use crate::ratelimit::API_KEY_HEADER;
+use crate::{Book, BookError, Visibility};
+
+/// 認証なしのリクエストに許すこと。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnonymousAccess {
+    /// これまでどおり。管理画面以外は認証なしで読み書きできる。
+    #[default]
+    Unrestricted,
+    /// 読み取りだけ認証なしでできる
+    ReadOnly,
+    /// 読み取りだけ認証なしででき、`visibility` が `public` の本だけを見せる
+    PublicBooks,
+}
+
+impl FromStr for AnonymousAccess {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unrestricted" => Ok(AnonymousAccess::Unrestricted),
+            "read_only" => Ok(AnonymousAccess::ReadOnly),
+            "public_books" => Ok(AnonymousAccess::PublicBooks),
+            _ => Err(()),
+        }
+    }
+}
+
+/// 認証なしで書き込めるパス。登録とトークンの発行はそれぞれのハンドラーが確かめる。
+const OPEN_WRITE_PATHS: [&str; 2] = ["/register", "/auth/token"];
+
+/// `public_books` で認証なしに読めるパス。本を返すものは見せ方で絞る。
+const PUBLIC_PATHS: [&str; 7] = ["/", "/healthz", "/readyz", "/version", "/metrics", "/books", "/books/search"];
+
+/// 認証されていないリクエストに付ける。本の見せ方はこれで決まる。
+#[derive(Clone, Copy, Debug)]
+pub struct Anonymous {
+    access: AnonymousAccess,
+}
+
+impl Anonymous {
+    pub fn new(access: AnonymousAccess) -> Self {
+        Anonymous { access }
+    }
+
+    pub fn access(&self) -> AnonymousAccess {
+        self.access
+    }
+
+    /// 見せ方の指定がない本は、`public_books` なら非公開、それ以外なら公開として扱う。
+    pub fn visibility(&self, book: &Book) -> Visibility {
+        self.resolve(book.visibility)
+    }
+
+    fn resolve(&self, visibility: Option<Visibility>) -> Visibility {
+        visibility.unwrap_or(match self.access {
+            AnonymousAccess::PublicBooks => Visibility::Private,
+            _ => Visibility::Public,
+        })
+    }
+
+    /// 一覧・検索・エクスポートに出すか。
+    pub fn lists(&self, book: &Book) -> bool {
+        self.visibility(book) == Visibility::Public
+    }
+
+    /// ID を指定されたときに見せるか。
+    pub fn shows(&self, book: &Book) -> bool {
+        self.visibility(book) != Visibility::Private
+    }
+
+    /// 認証なしに見せない項目を外す。書庫を公開しても、誰が何を借りているか (`loan`) は見せない。
+    pub fn redact(&self, book: &mut Book) {
+        if self.access != AnonymousAccess::Unrestricted {
+            book.loan = None;
+        }
+    }
+}
+
+fn has_credentials(req: &HttpRequest) -> bool {
+    req.headers().contains_key(header::AUTHORIZATION)
+        || req.headers()
+            .get(API_KEY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("bk_"))
+}
+
+fn is_public_path(path: &str) -> bool {
+    PUBLIC_PATHS.contains(&path)
+        || path.starts_with(crate::share::SHARED_PATH)
+        || path.strip_prefix("/books/id/").is_some_and(|id| id.parse::<u32>().is_ok())
+}
+
+/// `middleware::from_fn` に渡すミドルウェア。設定は `web::Data<AnonymousAccess>` から読む。
+///
+/// 認証情報の付いたリクエストはここで認証する。`unrestricted` 以外では認証できなければ 401 にし、
+/// `unrestricted` ではこれまでどおり認証なしとして通す。認証していないリクエストには `Anonymous` を付ける。
+pub async fn restrict(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let access = req.app_data::<web::Data<AnonymousAccess>>()
+        .map(|access| *access.get_ref())
+        .unwrap_or_default();
+
+    let result = match has_credentials(req.request()) {
+        true => Some(crate::auth::authenticate_async(req.request()).await),
+        false => None,
+    };
+    let authenticated = match result {
+        Some(Ok(_)) => true,
+        Some(Err(e)) if access != AnonymousAccess::Unrestricted => {
+            let resp = e.error_response();
+            return Ok(req.into_response(resp));
+        }
+        _ => false,
+    };
+
+    if !authenticated {
+        let read = matches!(*req.method(), Method::GET | Method::HEAD);
+
+        let allowed = match access {
+            AnonymousAccess::Unrestricted => true,
+            _ if !read => OPEN_WRITE_PATHS.contains(&req.path()),
+            AnonymousAccess::PublicBooks => is_public_path(req.path()),
+            AnonymousAccess::ReadOnly => true,
+        };
+        if !allowed {
+            let resp = BookError::Unauthorized.error_response();
+            return Ok(req.into_response(resp));
+        }
+
+        req.extensions_mut().insert(Anonymous { access });
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+/// 認証していないリクエストなら、その見せ方。認証済みなら `None` で、すべての本が見える。
+pub fn viewer(req: &HttpRequest) -> Option<Anonymous> {
+    req.extensions().get::<Anonymous>().copied()
+}
+
+/// 一覧に出す本だけに絞り、見せない項目を外す。
+pub fn listed(req: &HttpRequest, mut books: Vec<Book>) -> Vec<Book> {
+    if let Some(anonymous) = viewer(req) {
+        books.retain(|book| anonymous.lists(book));
+        books.iter_mut().for_each(|book| anonymous.redact(book));
+    }
+    books
+}
+
+/// 変更イベントを送るか。発行したときに一覧に出る本のものだけを送り、削除された本のイベントは送らない。
+///
+/// 購読者ごと・イベントごとにストレージを読まないよう、見せ方はイベントに載せたものを使う。
+pub fn sees_event(viewer: Option<Anonymous>, event: &BookEvent) -> bool {
+    viewer.is_none_or(|anonymous| {
+        event.kind != BookEventKind::Deleted && anonymous.resolve(event.visibility) == Visibility::Public
+    })
+}
+
+/// ID で指定された本を見せるか。見せないなら `None`。見せるなら見せない項目を外す。
+pub fn shown(req: &HttpRequest, book: Option<Book>) -> Option<Book> {
+    let viewer = viewer(req);
+    let mut book = book.filter(|book| viewer.is_none_or(|anonymous| anonymous.shows(book)))?;
+    if let Some(anonymous) = viewer {
+        anonymous.redact(&mut book);
+    }
+    Some(book)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visibility() {
+        let book = |visibility| Book { visibility, ..Default::default() };
+        let open = Anonymous { access: AnonymousAccess::ReadOnly };
+        let closed = Anonymous { access: AnonymousAccess::PublicBooks };
+
+        assert!(open.lists(&book(None)));
+        assert!(!closed.shows(&book(None)));
+        assert!(closed.lists(&book(Some(Visibility::Public))));
+
+        let unlisted = book(Some(Visibility::Unlisted));
+        assert!(!open.lists(&unlisted) && open.shows(&unlisted));
+        assert!(!open.shows(&book(Some(Visibility::Private))));
+    }
+
+    #[test]
+    fn test_sees_event() {
+        let event = |kind, visibility| BookEvent { seq: 1, kind, id: 1, title: String::new(), visibility };
+        let open = Anonymous { access: AnonymousAccess::ReadOnly };
+        let closed = Anonymous { access: AnonymousAccess::PublicBooks };
+
+        assert!(sees_event(None, &event(BookEventKind::Deleted, None)));
+        assert!(sees_event(Some(open), &event(BookEventKind::Updated, None)));
+        assert!(!sees_event(Some(open), &event(BookEventKind::Deleted, None)));
+        assert!(!sees_event(Some(closed), &event(BookEventKind::Created, None)));
+        assert!(sees_event(Some(closed), &event(BookEventKind::Created, Some(Visibility::Public))));
+        assert!(!sees_event(Some(open), &event(BookEventKind::Updated, Some(Visibility::Unlisted))));
+    }
+
+    #[test]
+    fn test_redact_hides_loan() {
+        let loaned = || Book {
+            loan: Some(crate::Loan { borrower: "erin".to_string(), due: time::macros::date!(2030 - 01 - 01) }),
+            ..Default::default()
+        };
+
+        let mut book = loaned();
+        Anonymous { access: AnonymousAccess::ReadOnly }.redact(&mut book);
+        assert!(book.loan.is_none());
+
+        let mut book = loaned();
+        Anonymous { access: AnonymousAccess::Unrestricted }.redact(&mut book);
+        assert!(book.loan.is_some());
+    }
+
+    #[test]
+    fn test_is_public_path() {
+        assert!(is_public_path("/books"));
+        assert!(is_public_path("/books/id/3"));
+        assert!(!is_public_path("/books/3/citation"));
+        assert!(!is_public_path("/books/stream"));
+        assert!(!is_public_path("/export/xlsx"));
+        assert!(is_public_path("/shared/0123456789abcdef"));
+        assert!(!is_public_path("/admin"));
+    }
+}
@@ -0,0 +1,336 @@
+//! スコープ付きの API キー。
+//!
+//! `POST /admin/api-keys` で発行したキーを `X-Api-Key` ヘッダーで送ると、そのキーのスコープの範囲で
+//! 認証される。静的サイトのビルドには `books:read` だけのキーを渡す、といった使い方をする。
+//! 発行したキーは `bk_` で始まる。それ以外の値の `X-Api-Key` は、これまでどおりレート制限にだけ使う。
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{delete, get, post, web, Error, HttpMessage, HttpRequest, HttpResponse, Responder, ResponseError};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::auth::AdminUser;
+use crate::invites::hash_token;
+use crate::ratelimit::API_KEY_HEADER;
+use crate::tenant::Tenant;
+use crate::{BookError, Role, User};
+
+pub const DEFAULT_API_KEYS_FILE: &str = "src/users/api_keys.json";
+
+/// 発行したキーの先頭に付ける。
+const PREFIX: &str = "bk_";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    /// 本の一覧・検索などの読み取り
+    #[serde(rename = "books:read")]
+    BooksRead,
+    /// 本の追加・更新・取り込み
+    #[serde(rename = "books:write")]
+    BooksWrite,
+    /// `/admin` 以下
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    /// ルートに要るスコープ。`/admin` 以下は `admin`、GET / HEAD は `books:read`、それ以外は `books:write`。
+    pub fn required_for(method: &Method, path: &str) -> Scope {
+        if path == "/admin" || path.starts_with("/admin/") {
+            Scope::Admin
+        } else if matches!(*method, Method::GET | Method::HEAD) {
+            Scope::BooksRead
+        } else {
+            Scope::BooksWrite
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub created_by: String,
+    pub created_at: i64,
+}
+
+impl ApiKey {
+    /// 認証したときのユーザー。`admin` スコープがあれば管理者として扱う。
+    fn user(&self) -> User {
+        User {
+            username: format!("key:{}", self.name),
+            password: String::new(),
+            role: if self.scopes.contains(&Scope::Admin) { Role::Admin } else { Role::User },
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredKey {
+    /// キーの SHA-256 (16 進)
+    key_hash: String,
+    #[serde(flatten)]
+    key: ApiKey,
+}
+
+/// 発行した API キーの一覧。
+pub struct ApiKeys {
+    file: PathBuf,
+    keys: RwLock<Vec<StoredKey>>,
+}
+
+fn write_keys(path: &Path, keys: &[StoredKey]) -> io::Result<()> {
+    crate::private_file::write_private(path, serde_json::to_string_pretty(keys)?.as_bytes())
+}
+
+impl ApiKeys {
+    /// キーのファイルを読む。なければ空で始める。
+    pub fn open(file: impl Into<PathBuf>) -> io::Result<Self> {
+        let file = file.into();
+
+        let keys = match fs::read_to_string(&file) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(ApiKeys { file, keys: RwLock::new(keys) })
+    }
+
+    /// キーを発行し、キーそのものと情報を返す。キーはここでしか分からない。
+    pub fn create(
+        &self,
+        name: &str,
+        scopes: Vec<Scope>,
+        tenant: Option<&str>,
+        created_by: &str,
+    ) -> Result<(String, ApiKey), BookError> {
+        let mut bytes = [0u8; 24];
+        OsRng.fill_bytes(&mut bytes);
+        let secret = format!("{}{}", PREFIX, URL_SAFE_NO_PAD.encode(bytes));
+
+        let key = ApiKey {
+            id: format!("{:016x}", OsRng.next_u64()),
+            name: name.to_string(),
+            scopes,
+            tenant: tenant.map(str::to_string),
+            created_by: created_by.to_string(),
+            created_at: OffsetDateTime::now_utc().unix_timestamp(),
+        };
+
+        let mut keys = self.keys.write().unwrap();
+        keys.push(StoredKey { key_hash: hash_token(&secret), key: key.clone() });
+        write_keys(&self.file, &keys)?;
+
+        Ok((secret, key))
+    }
+
+    pub fn list(&self, tenant: Option<&str>) -> Vec<ApiKey> {
+        self.keys.read().unwrap()
+            .iter()
+            .map(|k| &k.key)
+            .filter(|k| k.tenant.as_deref() == tenant)
+            .cloned()
+            .collect()
+    }
+
+    /// キーを取り消す。なければ false。
+    pub fn revoke(&self, id: &str, tenant: Option<&str>) -> Result<bool, BookError> {
+        let mut keys = self.keys.write().unwrap();
+        let before = keys.len();
+
+        keys.retain(|k| !(k.key.id == id && k.key.tenant.as_deref() == tenant));
+        if keys.len() == before {
+            return Ok(false);
+        }
+
+        write_keys(&self.file, &keys)?;
+        Ok(true)
+    }
+
+    /// `tenant` で `username` が発行したキーの発行者を `[erased]` に置き換え、件数を返す。
+    /// `write` が false なら数えるだけ。ユーザーの消去に使う。
+    pub fn erase_creator(&self, tenant: Option<&str>, username: &str, write: bool) -> Result<usize, BookError> {
+        let mut keys = self.keys.write().unwrap();
+        let mut count = 0;
+        for key in keys.iter_mut().map(|k| &mut k.key) {
+            if key.tenant.as_deref() == tenant && key.created_by == username {
+                if write {
+                    key.created_by = crate::audit::ERASED_ACTOR.to_string();
+                }
+                count += 1;
+            }
+        }
+
+        if write && count > 0 {
+            write_keys(&self.file, &keys)?;
+        }
+        Ok(count)
+    }
+
+    /// `tenant` で使えるキーを探す。
+    pub fn find(&self, secret: &str, tenant: Option<&str>) -> Option<ApiKey> {
+        let hash = hash_token(secret);
+
+        self.keys.read().unwrap()
+            .iter()
+            .find(|k| k.key_hash == hash && k.key.tenant.as_deref() == tenant)
+            .map(|k| k.key.clone())
+    }
+}
+
+/// `X-Api-Key` で送られた発行済みのキー。発行した形でなければ `Ok(None)`、見つからなければ 401。
+fn key_for(req: &HttpRequest) -> Result<Option<ApiKey>, BookError> {
+    let Some(secret) = req.headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| v.starts_with(PREFIX))
+    else {
+        return Ok(None);
+    };
+
+    let keys = req.app_data::<web::Data<ApiKeys>>().ok_or(BookError::Unauthorized)?;
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+
+    keys.find(secret.trim(), tenant.as_deref()).map(Some).ok_or(BookError::Unauthorized)
+}
+
+/// API キーで認証したユーザー。`authenticate` が `Authorization` ヘッダーのないときに使う。
+pub(crate) fn authenticate_key(req: &HttpRequest) -> Result<Option<User>, BookError> {
+    Ok(key_for(req)?.map(|key| key.user()))
+}
+
+/// `middleware::from_fn` に渡すミドルウェア。API キーで来たリクエストが、ルートに要るスコープを
+/// 持っているかを確かめる。キーのないリクエストはそのまま通す。
+pub async fn require_scope(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let checked = key_for(req.request()).and_then(|key| match key {
+        Some(key) if !key.scopes.contains(&Scope::required_for(req.method(), req.path())) => {
+            Err(BookError::Forbidden)
+        }
+        _ => Ok(()),
+    });
+
+    if let Err(e) = checked {
+        let resp = e.error_response();
+        return Ok(req.into_response(resp));
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+#[derive(Deserialize)]
+pub struct NewApiKey {
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    key: String,
+    #[serde(flatten)]
+    info: ApiKey,
+}
+
+/// API キーを発行する。キーはこのレスポンスでしか返さない。
+#[post("/admin/api-keys")]
+pub async fn create_api_key(
+    req: HttpRequest,
+    admin: AdminUser,
+    keys: Option<web::Data<ApiKeys>>,
+    body: web::Json<NewApiKey>,
+) -> Result<impl Responder, BookError> {
+    let keys = keys.ok_or(BookError::NotFound)?;
+    let NewApiKey { name, scopes } = body.into_inner();
+    if name.trim().is_empty() || scopes.is_empty() {
+        return Err(BookError::BadRequest("name and at least one scope are required".to_string()));
+    }
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+
+    let (key, info) = keys.create(&name, scopes, tenant.as_deref(), &admin.0.username)?;
+    log::info!("API key {} ({}) created by {}", info.id, info.name, admin.0.username);
+
+    Ok(HttpResponse::Created().json(CreatedApiKey { key, info }))
+}
+
+#[get("/admin/api-keys")]
+pub async fn list_api_keys(
+    req: HttpRequest,
+    _admin: AdminUser,
+    keys: Option<web::Data<ApiKeys>>,
+) -> Result<impl Responder, BookError> {
+    let keys = keys.ok_or(BookError::NotFound)?;
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+
+    Ok(HttpResponse::Ok().json(keys.list(tenant.as_deref())))
+}
+
+#[delete("/admin/api-keys/{id}")]
+pub async fn revoke_api_key(
+    req: HttpRequest,
+    admin: AdminUser,
+    keys: Option<web::Data<ApiKeys>>,
+    id: web::Path<String>,
+) -> Result<impl Responder, BookError> {
+    let keys = keys.ok_or(BookError::NotFound)?;
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+
+    if !keys.revoke(&id, tenant.as_deref())? {
+        return Err(BookError::NotFound);
+    }
+    log::info!("API key {} revoked by {}", id, admin.0.username);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(Scope::required_for(&Method::GET, "/books"), Scope::BooksRead);
+        assert_eq!(Scope::required_for(&Method::HEAD, "/books/id/1"), Scope::BooksRead);
+        assert_eq!(Scope::required_for(&Method::POST, "/books"), Scope::BooksWrite);
+        assert_eq!(Scope::required_for(&Method::GET, "/admin/check"), Scope::Admin);
+        assert_eq!(Scope::required_for(&Method::GET, "/administrators"), Scope::BooksRead);
+    }
+
+    #[test]
+    fn test_create_find_revoke() {
+        let path = std::env::temp_dir().join(format!("books_backend_api_keys_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let keys = ApiKeys::open(&path).unwrap();
+
+        let (secret, key) = keys.create("site build", vec![Scope::BooksRead], None, "admin").unwrap();
+        assert!(secret.starts_with(PREFIX));
+        assert!(!fs::read_to_string(&path).unwrap().contains(&secret));
+
+        let reopened = ApiKeys::open(&path).unwrap();
+        assert_eq!(reopened.find(&secret, None).unwrap().id, key.id);
+        assert!(reopened.find(&secret, Some("acme")).is_none());
+        assert_eq!(reopened.find(&secret, None).unwrap().user().role, Role::User);
+
+        assert!(reopened.revoke(&key.id, None).unwrap());
+        assert!(reopened.find(&secret, None).is_none());
+        assert!(!reopened.revoke(&key.id, None).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! オーディオブックの再生位置。
+//!
+//! 形式に `audiobook` を持つ書籍は、`audio` に全体の長さと聴いた長さ (分) を記録できる。
+//! 再生アプリは `PUT /books/{id}/progress` に秒単位の再生位置を送って同期する。
+//! レスポンスの `audio` には、聴いた割合 (`percent_complete`、小数第 1 位まで) を添える。
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{Book, BookError, Format};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Audio {
+    pub duration_minutes: u32,
+    #[serde(default)]
+    pub listened_minutes: f64,
+}
+
+impl Audio {
+    /// 聴いた割合 (%)。
+    pub fn percent_complete(&self) -> f64 {
+        if self.duration_minutes == 0 {
+            return 0.0;
+        }
+        let percent = (self.listened_minutes / f64::from(self.duration_minutes) * 100.0).min(100.0);
+        (percent * 10.0).round() / 10.0
+    }
+
+    /// 再生位置を秒で受け取る。全体より長ければ 400。
+    pub fn set_position(&mut self, seconds: u32) -> Result<(), BookError> {
+        if seconds > self.duration_minutes.saturating_mul(60) {
+            return Err(BookError::BadRequest(format!(
+                "position_seconds: {} is past the end ({} minutes)", seconds, self.duration_minutes
+            )));
+        }
+        self.listened_minutes = f64::from(seconds) / 60.0;
+        Ok(())
+    }
+}
+
+impl Serialize for Audio {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct WithPercent {
+            duration_minutes: u32,
+            listened_minutes: f64,
+            percent_complete: f64,
+        }
+
+        WithPercent {
+            duration_minutes: self.duration_minutes,
+            listened_minutes: self.listened_minutes,
+            percent_complete: self.percent_complete(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// 保存する書籍の `audio` を確かめる。オーディオブックでない、長さが 0、聴いた長さが範囲外なら 400。
+pub fn check(book: &Book) -> Result<(), BookError> {
+    let Some(audio) = &book.audio else {
+        return Ok(());
+    };
+
+    if !book.formats.contains(&Format::Audiobook) {
+        return Err(BookError::BadRequest("audio: only audiobooks can record listening progress".to_string()));
+    }
+    if audio.duration_minutes == 0 {
+        return Err(BookError::BadRequest("audio: duration_minutes must be positive".to_string()));
+    }
+    if !(0.0..=f64::from(audio.duration_minutes)).contains(&audio.listened_minutes) {
+        return Err(BookError::BadRequest("audio: listened_minutes must be between 0 and duration_minutes".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress() {
+        let mut audio = Audio { duration_minutes: 600, listened_minutes: 0.0 };
+        audio.set_position(4500).unwrap();
+        assert_eq!(audio.listened_minutes, 75.0);
+        assert_eq!(audio.percent_complete(), 12.5);
+        assert!(audio.set_position(600 * 60 + 1).is_err());
+
+        assert_eq!(
+            serde_json::to_value(&audio).unwrap(),
+            serde_json::json!({ "duration_minutes": 600, "listened_minutes": 75.0, "percent_complete": 12.5 })
+        );
+        let read: Audio = serde_json::from_str(r#"{ "duration_minutes": 600, "percent_complete": 50 }"#).unwrap();
+        assert_eq!(read.listened_minutes, 0.0);
+    }
+
+    #[test]
+    fn test_check() {
+        let audio = Some(Audio { duration_minutes: 60, listened_minutes: 30.0 });
+        let book = Book { formats: vec![Format::Audiobook], audio: audio.clone(), ..Default::default() };
+        assert!(check(&book).is_ok());
+        assert!(check(&Book { formats: vec![Format::Ebook], ..book.clone() }).is_err());
+        assert!(check(&Book { audio: Some(Audio { duration_minutes: 60, listened_minutes: 61.0 }), ..book }).is_err());
+    }
+}
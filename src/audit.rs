@@ -0,0 +1,470 @@
+//! 書き込み操作の監査ログ。
+//!
+//! POST / PUT / PATCH / DELETE のうち成功したものを、誰が・いつ・どのルートで行ったかと一緒に
+//! 1 行 1 件の JSON でファイルに追記する。本を変えたハンドラーは `note` で変更前後の要約を添える。
+//! 記録は `GET /admin/audit` で絞り込んで読める。追記するだけで、書き換えや削除はしない。
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{get, web, Error, HttpMessage, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::auth::{AdminUser, AuthenticatedUser, Impersonator};
+use crate::request_id::RequestId;
+use crate::tenant::Tenant;
+use crate::{Book, BookError, Visibility};
+
+pub const DEFAULT_AUDIT_FILE: &str = "src/data/audit.jsonl";
+
+/// 消去したユーザーの記録に、ユーザー名の代わりに入れる。
+pub const ERASED_ACTOR: &str = "[erased]";
+
+/// 書き込みでも記録しないパス。トークンの発行は何も変えない。
+const SKIPPED_PATHS: [&str; 1] = ["/auth/token"];
+
+/// `GET /admin/audit` で一度に返す件数の既定値と上限。
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// 変更前後の本の要約。本文は大きいので含めない。
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookSummary {
+    pub title: String,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+}
+
+impl From<&Book> for BookSummary {
+    fn from(book: &Book) -> Self {
+        BookSummary {
+            title: book.title.clone(),
+            authors: book.authors.clone(),
+            tags: book.tags.clone(),
+            visibility: book.visibility,
+        }
+    }
+}
+
+/// ハンドラーが記録に添える変更内容。
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub book_id: Option<u32>,
+    /// 変更前。新しく作ったときは `None`。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<BookSummary>,
+    /// 変更後。削除したときは `None`。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<BookSummary>,
+    /// まとめて変えたときの説明 (例: `imported 12 books`)。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// 操作の対象になったユーザー (例: なりすまされたユーザー)。消去のときに名前を消す。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+}
+
+impl Change {
+    /// 1 冊の本の変更。
+    pub fn book(id: u32, before: Option<&Book>, after: Option<&Book>) -> Self {
+        Change {
+            book_id: Some(id),
+            before: before.map(BookSummary::from),
+            after: after.map(BookSummary::from),
+            summary: None,
+            subject: None,
+        }
+    }
+
+    /// 複数の本をまとめて変えたときの説明。
+    pub fn summary(summary: impl Into<String>) -> Self {
+        Change { summary: Some(summary.into()), ..Default::default() }
+    }
+
+    /// ユーザーに対する操作の説明。
+    pub fn about(subject: &str, summary: impl Into<String>) -> Self {
+        Change { subject: Some(subject.to_string()), ..Change::summary(summary) }
+    }
+}
+
+/// `text` の中の `username` を `[erased]` に置き換える。ほかの語の一部になっているところは残す。
+fn scrub(text: &str, username: &str) -> Option<String> {
+    if username.is_empty() {
+        return None;
+    }
+    let part_of_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '-');
+
+    let mut scrubbed = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut found = false;
+    while let Some(index) = rest.find(username) {
+        let (before, after) = (&rest[..index], &rest[index + username.len()..]);
+        scrubbed.push_str(before);
+        if part_of_word(scrubbed.chars().next_back()) || part_of_word(after.chars().next()) {
+            scrubbed.push_str(username);
+        } else {
+            scrubbed.push_str(ERASED_ACTOR);
+            found = true;
+        }
+        rest = after;
+    }
+    scrubbed.push_str(rest);
+
+    found.then_some(scrubbed)
+}
+
+impl AuditEntry {
+    /// `username` の名前を消す。名前が出てこなければ false。
+    fn erase(&mut self, username: &str) -> bool {
+        let mut erased = false;
+        for name in [&mut self.actor, &mut self.impersonator, &mut self.change.subject] {
+            if name.as_deref() == Some(username) {
+                *name = Some(ERASED_ACTOR.to_string());
+                erased = true;
+            }
+        }
+        if let Some(summary) = self.change.summary.as_deref().and_then(|summary| scrub(summary, username)) {
+            self.change.summary = Some(summary);
+            erased = true;
+        }
+        erased
+    }
+}
+
+/// このリクエストの記録に変更内容を添える。
+pub fn note(req: &HttpRequest, change: Change) {
+    req.extensions_mut().insert(change);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// 記録した時刻 (UNIX 秒)
+    pub at: i64,
+    /// 認証したユーザー。認証なしなら `None`。
+    #[serde(default)]
+    pub actor: Option<String>,
+    /// なりすましのトークンで来たなら、発行した管理者。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonator: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub method: String,
+    /// ルートの定義 (例: `/books/{id}/share`)
+    pub route: String,
+    pub path: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+impl AuditEntry {
+    /// gRPC の書き込みの記録。gRPC には認証がないので `actor` は `None` (HTTP の認証なしと同じ)。
+    pub fn grpc(route: &str, change: Change) -> Self {
+        AuditEntry {
+            at: OffsetDateTime::now_utc().unix_timestamp(),
+            actor: None,
+            impersonator: None,
+            tenant: None,
+            // gRPC の呼び出しは HTTP/2 の POST
+            method: "POST".to_string(),
+            route: route.to_string(),
+            path: route.to_string(),
+            status: 200,
+            request_id: None,
+            change,
+        }
+    }
+
+    fn from_response(res: &ServiceResponse<impl MessageBody>) -> Self {
+        let req = res.request();
+        let extensions = req.extensions();
+
+        AuditEntry {
+            at: OffsetDateTime::now_utc().unix_timestamp(),
+            actor: extensions.get::<AuthenticatedUser>().map(|u| u.0.clone()),
+            impersonator: extensions.get::<Impersonator>().map(|i| i.0.clone()),
+            tenant: extensions.get::<Tenant>().map(|t| t.name.clone()),
+            method: req.method().to_string(),
+            route: req.match_pattern().unwrap_or_else(|| req.path().to_string()),
+            path: req.path().to_string(),
+            status: res.status().as_u16(),
+            request_id: extensions.get::<RequestId>().map(|id| id.0.clone()),
+            change: extensions.get::<Change>().cloned().unwrap_or_default(),
+        }
+    }
+}
+
+/// `GET /admin/audit` の絞り込み。
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    /// なりすましをした管理者
+    pub impersonator: Option<String>,
+    pub method: Option<String>,
+    /// このパスとその下の操作
+    pub path: Option<String>,
+    pub book_id: Option<u32>,
+    /// この時刻 (UNIX 秒) 以降
+    pub since: Option<i64>,
+    /// この時刻 (UNIX 秒) より前
+    pub until: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        let under = |prefix: &str| {
+            let prefix = prefix.trim_end_matches('/');
+            entry.path == prefix || entry.path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+        };
+
+        self.actor.as_ref().is_none_or(|actor| entry.actor.as_ref() == Some(actor))
+            && self.impersonator.as_ref().is_none_or(|admin| entry.impersonator.as_ref() == Some(admin))
+            && self.method.as_ref().is_none_or(|method| entry.method.eq_ignore_ascii_case(method))
+            && self.path.as_deref().is_none_or(under)
+            && self.book_id.is_none_or(|id| entry.change.book_id == Some(id))
+            && self.since.is_none_or(|since| entry.at >= since)
+            && self.until.is_none_or(|until| entry.at < until)
+    }
+}
+
+/// 追記専用の監査ログ。
+pub struct Audit {
+    file: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl Audit {
+    /// ファイルは最初の記録で作る。
+    pub fn new(file: impl Into<PathBuf>) -> Self {
+        Audit { file: file.into(), lock: Mutex::new(()) }
+    }
+
+    pub fn append(&self, entry: &AuditEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let _guard = self.lock.lock().unwrap();
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        options.open(&self.file)?.write_all(&line)
+    }
+
+    /// `tenant` の `username` の記録 (なりすましや、対象になった操作を含む) から名前を消し、書き換えた件数を返す。
+    /// 追記専用の例外で、ユーザーの消去にだけ使う。読めない行はそのまま残す。
+    pub fn anonymize(&self, tenant: Option<&str>, username: &str) -> io::Result<usize> {
+        self.rewrite_mentions(tenant, username, true)
+    }
+
+    /// `anonymize` で書き換える件数。何も変えない。
+    pub fn count_mentions(&self, tenant: Option<&str>, username: &str) -> io::Result<usize> {
+        self.rewrite_mentions(tenant, username, false)
+    }
+
+    fn rewrite_mentions(&self, tenant: Option<&str>, username: &str, write: bool) -> io::Result<usize> {
+        let _guard = self.lock.lock().unwrap();
+
+        let contents = match fs::read_to_string(&self.file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+
+        let mut count = 0;
+        let mut rewritten = String::with_capacity(contents.len());
+        for line in contents.lines() {
+            match serde_json::from_str::<AuditEntry>(line) {
+                Ok(mut entry) if entry.tenant.as_deref() == tenant => {
+                    if entry.erase(username) {
+                        rewritten.push_str(&serde_json::to_string(&entry)?);
+                        count += 1;
+                    } else {
+                        rewritten.push_str(line);
+                    }
+                }
+                _ => rewritten.push_str(line),
+            }
+            rewritten.push('\n');
+        }
+
+        if write && count > 0 {
+            crate::private_file::write_private(&self.file, rewritten.as_bytes())?;
+        }
+
+        Ok(count)
+    }
+
+    /// `tenant` の記録のうち `query` に合うものを、新しい順に返す。`limit` がなければすべて返す。
+    /// 読めない行は飛ばす。
+    pub fn query(&self, tenant: Option<&str>, query: &AuditQuery) -> io::Result<Vec<AuditEntry>> {
+        let limit = query.limit.unwrap_or(usize::MAX);
+
+        let file = match File::open(&self.file) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut entries = VecDeque::new();
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let entry: AuditEntry = match serde_json::from_str(&line?) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    log::warn!("Skipping line {} of {}: {}", n + 1, self.file.display(), e);
+                    continue;
+                }
+            };
+
+            if entry.tenant.as_deref() == tenant && query.matches(&entry) {
+                if entries.len() == limit {
+                    entries.pop_front();
+                }
+                if limit > 0 {
+                    entries.push_back(entry);
+                }
+            }
+        }
+
+        Ok(entries.into_iter().rev().collect())
+    }
+}
+
+/// `middleware::from_fn` に渡すミドルウェア。記録先は `web::Data<Audit>` から読み、なければ何もしない。
+///
+/// なりすましのトークンで来たリクエストは、読み取りも記録する。失敗したリクエストは記録しない。記録に失敗しても、操作は済んでいるのでレスポンスはそのまま返す。
+pub async fn record(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let write = matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE)
+        && !SKIPPED_PATHS.contains(&req.path());
+
+    let res = next.call(req).await?;
+
+    let impersonated = res.request().extensions().contains::<Impersonator>();
+    if let Some(audit) = res.request().app_data::<web::Data<Audit>>().filter(|_| write || impersonated) {
+        if res.status().is_success() || res.status().is_redirection() {
+            let entry = AuditEntry::from_response(&res);
+            let audit = audit.clone();
+
+            if let Err(e) = web::block(move || audit.append(&entry)).await? {
+                log::error!("Failed to write the audit log: {}", e);
+            }
+        }
+    }
+
+    Ok(res.map_into_boxed_body())
+}
+
+/// 監査ログを新しい順に返す。
+#[get("/admin/audit")]
+pub async fn list_audit(
+    req: HttpRequest,
+    _admin: AdminUser,
+    audit: Option<web::Data<Audit>>,
+    query: web::Query<AuditQuery>,
+) -> Result<impl Responder, BookError> {
+    let audit = audit.ok_or(BookError::NotFound)?;
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+    let mut query = query.into_inner();
+    query.limit = Some(query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT));
+
+    let entries = web::block(move || audit.query(tenant.as_deref(), &query)).await??;
+
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(at: i64, actor: &str, path: &str, book_id: Option<u32>) -> AuditEntry {
+        AuditEntry {
+            at,
+            actor: Some(actor.to_string()),
+            impersonator: None,
+            tenant: None,
+            method: "POST".to_string(),
+            route: path.to_string(),
+            path: path.to_string(),
+            status: 200,
+            request_id: None,
+            change: Change { book_id, ..Default::default() },
+        }
+    }
+
+    #[test]
+    fn test_append_and_query() {
+        let path = std::env::temp_dir().join(format!("books_backend_audit_{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let audit = Audit::new(&path);
+
+        audit.append(&entry(100, "alice", "/books", Some(1))).unwrap();
+        audit.append(&entry(200, "bob", "/admin/books", Some(2))).unwrap();
+        audit.append(&AuditEntry { tenant: Some("acme".to_string()), ..entry(300, "alice", "/books", Some(3)) }).unwrap();
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"not json\n").unwrap();
+        audit.append(&entry(400, "alice", "/admin/webhooks", None)).unwrap();
+
+        let at = |query: AuditQuery| -> Vec<i64> {
+            audit.query(None, &query).unwrap().iter().map(|e| e.at).collect()
+        };
+        assert_eq!(at(AuditQuery::default()), vec![400, 200, 100]);
+        assert_eq!(at(AuditQuery { actor: Some("alice".to_string()), ..Default::default() }), vec![400, 100]);
+        assert_eq!(at(AuditQuery { path: Some("/admin".to_string()), ..Default::default() }), vec![400, 200]);
+        assert_eq!(at(AuditQuery { book_id: Some(2), ..Default::default() }), vec![200]);
+        assert_eq!(at(AuditQuery { since: Some(200), until: Some(400), ..Default::default() }), vec![200]);
+        assert_eq!(at(AuditQuery { limit: Some(1), ..Default::default() }), vec![400]);
+        assert_eq!(audit.query(Some("acme"), &AuditQuery::default()).unwrap().len(), 1);
+
+        assert_eq!(audit.anonymize(None, "alice").unwrap(), 2);
+        assert_eq!(at(AuditQuery { actor: Some(ERASED_ACTOR.to_string()), ..Default::default() }), vec![400, 100]);
+        assert_eq!(audit.query(Some("acme"), &AuditQuery { actor: Some("alice".to_string()), ..Default::default() }).unwrap().len(), 1);
+        assert!(fs::read_to_string(&path).unwrap().contains("not json\n"));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_anonymize_subject_and_summary() {
+        let path = std::env::temp_dir().join(format!("books_backend_audit_erase_{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let audit = Audit::new(&path);
+
+        let change = Change::about("frank", "impersonating frank: ticket for frank-2");
+        audit.append(&AuditEntry { change, ..entry(100, "admin", "/admin/impersonate", None) }).unwrap();
+        audit.append(&entry(200, "frankie", "/books", Some(1))).unwrap();
+
+        assert_eq!(audit.count_mentions(None, "frank").unwrap(), 1);
+        assert_eq!(audit.anonymize(None, "frank").unwrap(), 1);
+        let entries = audit.query(None, &AuditQuery::default()).unwrap();
+        assert_eq!(entries[1].change.subject.as_deref(), Some(ERASED_ACTOR));
+        assert_eq!(entries[1].change.summary.as_deref(), Some("impersonating [erased]: ticket for frank-2"));
+        assert_eq!(entries[0].actor.as_deref(), Some("frankie"));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
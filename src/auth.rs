@@ -0,0 +1,377 @@
+use std::fs;
+use std::future::{ready, Ready};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::io;
+use std::sync::{Mutex, OnceLock};
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpMessage, HttpRequest};
+use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, SaltString};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::jwt::Keys;
+use crate::tenant::Tenant;
+use crate::{BookError, Role, User};
+
+pub const DEFAULT_USERS_FILE: &str = "src/users/users.json";
+
+static USERS_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// ユーザーのファイルの読み書きを 1 つずつにする。登録とパスワードの変更が同時に来ても更新を失わない。
+static USERS_LOCK: Mutex<()> = Mutex::new(());
+
+/// ユーザー情報の保存先を変える。起動時に 1 回だけ呼ぶ。2 回目以降は無視する。
+pub fn set_users_file(path: impl Into<PathBuf>) {
+    let _ = USERS_FILE.set(path.into());
+}
+
+pub fn users_file() -> &'static Path {
+    USERS_FILE.get().map(PathBuf::as_path).unwrap_or(Path::new(DEFAULT_USERS_FILE))
+}
+
+/// リクエストのテナントのユーザー情報のファイル。テナントがなければ `users_file()`。
+pub fn users_file_for(req: &HttpRequest) -> PathBuf {
+    req.extensions()
+        .get::<Tenant>()
+        .map(|tenant| tenant.users_file.clone())
+        .unwrap_or_else(|| users_file().to_path_buf())
+}
+
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    let argon2 = Argon2::default();
+
+    // 既定のパラメーターと生成したソルトでは失敗しない
+    argon2.hash_password(password.as_bytes(), &salt)
+        .expect("argon2 hashing with default parameters")
+        .to_string()
+}
+
+pub fn verify_password(stored_hash: &str, password: &str) -> bool {
+    let parsed_hash = match PasswordHash::new(stored_hash) {
+        Ok(hash) => hash,
+        Err(_) => return false,
+    };
+    let argon2 = Argon2::default();
+
+    argon2.verify_password(password.as_bytes(), &parsed_hash).is_ok()
+}
+
+pub fn load_users() -> Vec<User> {
+    load_users_from(users_file())
+}
+
+pub fn load_users_from(path: &Path) -> Vec<User> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut contents = String::new();
+    if file.read_to_string(&mut contents).is_err() {
+        return Vec::new();
+    }
+
+    serde_json::from_str(&contents).unwrap_or_else(|_| Vec::new())
+}
+
+/// `path` のユーザーを読んで `f` で書き換え、`f` が true を返したら書き戻す。
+/// 読めないファイルを空として上書きしないように、ファイルがないとき以外の読み込みの失敗はエラーにする。
+fn update_users(path: &Path, f: impl FnOnce(&mut Vec<User>) -> bool) -> io::Result<bool> {
+    let _guard = USERS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut users: Vec<User> = match fs::read(path) {
+        Ok(contents) => serde_json::from_slice(&contents)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    if !f(&mut users) {
+        return Ok(false);
+    }
+
+    crate::private_file::write_private(path, serde_json::to_string_pretty(&users)?.as_bytes())?;
+    Ok(true)
+}
+
+/// ユーザーを追加する。同名のユーザーが既にいれば何もせず false を返す。
+pub fn save_user(username: &str, password: &str, role: Role) -> io::Result<bool> {
+    save_user_to(users_file(), username, password, role)
+}
+
+/// `save_user` の保存先を指定する版。テナントごとのユーザーに使う。
+pub fn save_user_to(path: &Path, username: &str, password: &str, role: Role) -> io::Result<bool> {
+    // ロックの外でハッシュを計算する
+    let hashed_password = hash_password(password);
+
+    update_users(path, |users| {
+        // 起動のたびに同じユーザーが重複して追加されないようにする
+        if users.iter().any(|u| u.username == username) {
+            return false;
+        }
+
+        users.push(User {
+            username: username.to_string(),
+            password: hashed_password,
+            role,
+        });
+        true
+    })
+}
+
+/// パスワードを変える。ユーザーがいなければ false を返す。
+pub fn set_password_in(path: &Path, username: &str, password: &str) -> io::Result<bool> {
+    let hashed_password = hash_password(password);
+
+    update_users(path, |users| match users.iter_mut().find(|u| u.username == username) {
+        Some(user) => {
+            user.password = hashed_password;
+            true
+        }
+        None => false,
+    })
+}
+
+pub fn remove_user(username: &str) -> io::Result<bool> {
+    remove_user_from(users_file(), username)
+}
+
+/// `remove_user` の対象のファイルを指定する版。
+pub fn remove_user_from(path: &Path, username: &str) -> io::Result<bool> {
+    update_users(path, |users| {
+        let before = users.len();
+        users.retain(|u| u.username != username);
+        users.len() != before
+    })
+}
+
+/// 認証済みのユーザー名。アクセスログに使うのでリクエストの extensions に入れておく。
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser(pub String);
+
+/// なりすましのトークンで来たときの、発行した管理者の名前。監査ログに使う。
+#[derive(Clone, Debug)]
+pub struct Impersonator(pub String);
+
+/// `Authorization: Bearer` のトークンを検証し、その時点のユーザー情報を返す。
+/// 削除されたユーザーや、別のテナントで発行されたトークンは通さない。LDAP のユーザーはトークンの役割を使う。なりすましのトークンは、
+/// 発行した管理者がまだ管理者で、相手が管理者でないときだけ通す。
+fn authenticate_bearer(req: &HttpRequest, token: &str) -> Result<User, BookError> {
+    let keys = req.app_data::<web::Data<Keys>>().ok_or(BookError::Unauthorized)?;
+    let claims = keys.verify(token.trim())?;
+
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+    if claims.tenant != tenant {
+        return Err(BookError::Unauthorized);
+    }
+
+    let users = load_users_from(&users_file_for(req));
+    let user = users.iter()
+        .find(|u| u.username == claims.sub)
+        .cloned()
+        .or_else(|| claims.role.and_then(|role| crate::ldap::directory_user(req, &claims.sub, role)))
+        .ok_or(BookError::Unauthorized)?;
+
+    if let Some(actor) = claims.act {
+        let is_admin = |username: &str| users.iter().any(|u| u.username == username && u.role == Role::Admin);
+        if !is_admin(&actor.sub) || user.role == Role::Admin {
+            return Err(BookError::Unauthorized);
+        }
+        req.extensions_mut().insert(Impersonator(actor.sub));
+    }
+
+    Ok(user)
+}
+
+/// 認証済みのユーザー。同じリクエストで何度もパスワードを検証しないように覚えておく。
+#[derive(Clone)]
+struct Verified(User);
+
+/// `Authorization: Basic` ヘッダーか `Bearer` のトークンからユーザーを認証する。
+/// `Authorization` ヘッダーがなければ `X-Api-Key` の API キーで認証する。
+pub(crate) fn authenticate(req: &HttpRequest) -> Result<User, BookError> {
+    if let Some(Verified(user)) = req.extensions().get::<Verified>() {
+        return Ok(user.clone());
+    }
+
+    let user = verify(req)?;
+    req.extensions_mut().insert(AuthenticatedUser(user.username.clone()));
+    req.extensions_mut().insert(Verified(user.clone()));
+
+    Ok(user)
+}
+
+fn verify(req: &HttpRequest) -> Result<User, BookError> {
+    let Some(authorization) = req.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) else {
+        return crate::api_keys::authenticate_key(req)?.ok_or(BookError::Unauthorized);
+    };
+
+    if let Some(token) = authorization.strip_prefix("Bearer ") {
+        return authenticate_bearer(req, token);
+    }
+
+    let (username, password) = basic_credentials(authorization).ok_or(BookError::Unauthorized)?;
+
+    // ユーザーファイルにいないユーザーは `authenticate_async` で LDAP に問い合わせてある
+    load_users_from(&users_file_for(req))
+        .into_iter()
+        .find(|u| u.username == username)
+        .filter(|u| verify_password(&u.password, &password))
+        .ok_or(BookError::Unauthorized)
+}
+
+/// `Basic` のユーザー名とパスワード。
+fn basic_credentials(authorization: &str) -> Option<(String, String)> {
+    let credentials = authorization
+        .strip_prefix("Basic ")
+        .and_then(|v| STANDARD.decode(v.trim()).ok())
+        .and_then(|v| String::from_utf8(v).ok())?;
+
+    credentials.split_once(':').map(|(username, password)| (username.to_string(), password.to_string()))
+}
+
+/// `authenticate` と同じだが、ユーザーファイルにいない Basic 認証のユーザーは LDAP に問い合わせる。
+///
+/// LDAP の問い合わせはブロックするので、ワーカーを止めないようにミドルウェアからこちらを呼ぶ。
+/// 認証できたユーザーは覚えておくので、あとのエクストラクタは `authenticate` で同じユーザーを得られる。
+pub(crate) async fn authenticate_async(req: &HttpRequest) -> Result<User, BookError> {
+    if let Some(Verified(user)) = req.extensions().get::<Verified>() {
+        return Ok(user.clone());
+    }
+
+    let credentials = req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(basic_credentials);
+    let Some((username, password)) = credentials else {
+        return authenticate(req);
+    };
+    if load_users_from(&users_file_for(req)).iter().any(|u| u.username == username) {
+        return authenticate(req);
+    }
+
+    let user = crate::ldap::authenticate(req, &username, &password).await?;
+    req.extensions_mut().insert(AuthenticatedUser(user.username.clone()));
+    req.extensions_mut().insert(Verified(user.clone()));
+
+    Ok(user)
+}
+
+/// ほかのサイトから送られてきた書き込みか。
+///
+/// ブラウザーは覚えた Basic 認証の資格情報を自動で送り直すので、管理者が開いた別のサイトのフォームからでも
+/// 管理画面に POST できてしまう。Basic 認証の書き込みは、`Sec-Fetch-Site` か `Origin` で同じオリジンから
+/// 来たことを確かめる。どちらもないのはブラウザー以外のクライアントなので通す。
+fn cross_origin_write(req: &HttpRequest) -> bool {
+    if req.method().is_safe() {
+        return false;
+    }
+
+    let basic = req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("Basic "));
+    if !basic {
+        return false;
+    }
+
+    if let Some(site) = req.headers().get("Sec-Fetch-Site").and_then(|v| v.to_str().ok()) {
+        return !matches!(site, "same-origin" | "none");
+    }
+
+    req.headers().get(header::ORIGIN).is_some_and(|origin| {
+        let host = origin.to_str().ok().and_then(|origin| origin.split_once("://")).map(|(_, host)| host);
+        host.is_none_or(|host| !host.eq_ignore_ascii_case(req.connection_info().host()))
+    })
+}
+
+/// admin ロールを持つユーザーでなければ拒否するエクストラクタ。
+/// ほかのサイトからの Basic 認証の書き込みは 403。
+pub struct AdminUser(pub User);
+
+impl FromRequest for AdminUser {
+    type Error = BookError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        if cross_origin_write(req) {
+            return ready(Err(BookError::Forbidden));
+        }
+        ready(authenticate(req).and_then(|user| match user.role {
+            Role::Admin => Ok(AdminUser(user)),
+            Role::User => Err(BookError::Forbidden),
+        }))
+    }
+}
+
+/// 既定の書庫の管理者でなければ拒否するエクストラクタ。メンテナンスモードや機能フラグのように
+/// すべてのテナントで共有する状態を変えるのに使う。テナントの管理者は 403。
+pub struct OperatorUser(pub User);
+
+impl FromRequest for OperatorUser {
+    type Error = BookError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        if cross_origin_write(req) {
+            return ready(Err(BookError::Forbidden));
+        }
+        ready(authenticate(req).and_then(|user| match user.role {
+            Role::Admin if !req.extensions().contains::<Tenant>() => Ok(OperatorUser(user)),
+            _ => Err(BookError::Forbidden),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_cross_origin_write() {
+        let basic = ("Authorization", "Basic YWRtaW46YWRtaW4=");
+        let post = || TestRequest::post().uri("/admin/books").insert_header((header::HOST, "books.example.com")).insert_header(basic);
+
+        assert!(!cross_origin_write(&post().to_http_request()));
+        assert!(!cross_origin_write(&post().insert_header(("Sec-Fetch-Site", "same-origin")).to_http_request()));
+        assert!(cross_origin_write(&post().insert_header(("Sec-Fetch-Site", "cross-site")).to_http_request()));
+        assert!(!cross_origin_write(&post().insert_header((header::ORIGIN, "https://books.example.com")).to_http_request()));
+        assert!(cross_origin_write(&post().insert_header((header::ORIGIN, "https://evil.example")).to_http_request()));
+        assert!(cross_origin_write(&post().insert_header((header::ORIGIN, "null")).to_http_request()));
+
+        // 読み取りと、ブラウザーが自動で付けないトークンでの書き込みは確かめない
+        let req = TestRequest::get().insert_header(basic).insert_header(("Sec-Fetch-Site", "cross-site")).to_http_request();
+        assert!(!cross_origin_write(&req));
+        let req = TestRequest::post()
+            .insert_header((header::AUTHORIZATION, "Bearer token"))
+            .insert_header(("Sec-Fetch-Site", "cross-site"))
+            .to_http_request();
+        assert!(!cross_origin_write(&req));
+    }
+
+    #[test]
+    fn test_concurrent_updates() {
+        let path = std::env::temp_dir().join(format!("books_backend_users_concurrent_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                let path = &path;
+                scope.spawn(move || save_user_to(path, &format!("user{}", i), "correct horse", Role::User).unwrap());
+            }
+        });
+        assert_eq!(load_users_from(&path).len(), 4);
+        assert!(!save_user_to(&path, "user0", "another", Role::User).unwrap());
+        assert!(set_password_in(&path, "user3", "battery staple").unwrap());
+        assert!(remove_user_from(&path, "user2").unwrap());
+        assert_eq!(load_users_from(&path).len(), 3);
+
+        // 読めないファイルを空として上書きしない
+        fs::write(&path, "not json").unwrap();
+        assert!(save_user_to(&path, "user9", "correct horse", Role::User).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not json");
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+//! 著者の記録 (`/authors`)。
+//!
+//! 書籍の `authors` はこれまでどおり名前の文字列で持ち、著者の記録とは名前か別名で結び付ける
+//! (`crate::registry`)。書籍を保存するときに別名で書かれた著者は記録の名前にそろえ、記録の名前を
+//! 変えたり別の記録をまとめたり (merge) すると、書籍の著者名も書き換える。
+//! "S. Klabnik" の記録を "Steve Klabnik" にまとめると、"S. Klabnik" は "Steve Klabnik" の別名になる。
+//! 記録はデータファイルの隣 (`book.json` なら `book.authors.json`) に置く。
+//! `/authors/import` で、書籍にあって記録のない名前をまとめて記録にできる。
+
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::normalize::fold;
+use crate::registry::{self, Named, Registry};
+use crate::{Book, BookError};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Author {
+    pub id: u32,
+    pub name: String,
+    /// 並べ替えに使う名前。指定がなければ `Klabnik, Steve` のように姓を先にする
+    pub sort_name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub bio: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<String>,
+    /// 同じ著者の別の表記
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+}
+
+impl Named for Author {
+    const KIND: &'static str = "author";
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn aliases(&self) -> &[String] {
+        &self.aliases
+    }
+
+    fn names_in(book: &Book) -> impl Iterator<Item = &str> {
+        book.authors.iter().map(String::as_str)
+    }
+
+    /// 名前を書き換え、同じ著者が重なったら 1 つにする。
+    fn rename_in(book: &mut Book, renames: &[(String, String)]) -> bool {
+        if renames.is_empty() {
+            return false;
+        }
+
+        let mut renamed: Vec<String> = Vec::with_capacity(book.authors.len());
+        for name in &book.authors {
+            let name = registry::renamed(name, renames).unwrap_or(name);
+            if !renamed.iter().any(|n| fold(n) == fold(name)) {
+                renamed.push(name.to_string());
+            }
+        }
+
+        if renamed == book.authors {
+            return false;
+        }
+        book.authors = renamed;
+        true
+    }
+}
+
+/// 著者を作る・更新するときの内容。
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct AuthorInput {
+    pub name: String,
+    #[serde(default)]
+    pub sort_name: Option<String>,
+    #[serde(default)]
+    pub bio: String,
+    #[serde(default)]
+    pub links: Vec<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+impl AuthorInput {
+    fn into_author(self, id: u32) -> Result<Author, BookError> {
+        let (name, aliases) = registry::clean_names(&self.name, &self.aliases)?;
+        let sort_name = self.sort_name
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| sort_name(&name));
+
+        Ok(Author { id, name, sort_name, bio: self.bio.trim().to_string(), links: self.links, aliases })
+    }
+}
+
+/// `Steve Klabnik` → `Klabnik, Steve`。空白のない名前やすでにカンマを含む名前はそのまま。
+pub fn sort_name(name: &str) -> String {
+    match name.rsplit_once(' ') {
+        Some((given, family)) if !name.contains(',') => format!("{}, {}", family, given.trim()),
+        _ => name.to_string(),
+    }
+}
+
+/// 著者の記録の一覧。
+pub type Authors = Registry<Author>;
+
+/// 記録への変更。
+#[derive(Debug)]
+pub enum Edit {
+    Create(AuthorInput),
+    Update(u32, AuthorInput),
+    Delete(u32),
+    /// `from` の記録を `into` にまとめる
+    Merge { into: u32, from: Vec<u32> },
+    /// 書籍にあって記録のない名前を記録にする
+    Import,
+}
+
+/// 作成・更新・まとめた先の記録。削除なら削除した記録。取り込みなら作った記録。
+pub type Plan = registry::Plan<Author, Vec<Author>>;
+
+impl Registry<Author> {
+    /// 書籍の著者名を記録の名前にそろえ、重なりを除く。記録のない名前はそのまま。
+    pub fn canonical(&self, names: &[String]) -> Vec<String> {
+        let mut canonical: Vec<String> = Vec::with_capacity(names.len());
+        for name in names {
+            let name = self.find(name).map_or(name.as_str(), |a| a.name.as_str());
+            if !canonical.iter().any(|n| fold(n) == fold(name)) {
+                canonical.push(name.to_string());
+            }
+        }
+        canonical
+    }
+
+    /// `edit` を適用した結果を求める。記録も書籍もまだ変えない。
+    pub fn plan(&self, edit: Edit, books: &[Book]) -> Result<Plan, BookError> {
+        let mut authors = self.clone();
+        let mut renames = Vec::new();
+
+        let result = match edit {
+            Edit::Create(input) => {
+                let author = input.into_author(self.next_id())?;
+                authors.push(author.clone());
+                vec![author]
+            }
+            Edit::Update(id, input) => {
+                let author = input.into_author(id)?;
+                authors.update(author.clone(), &mut renames)?;
+                vec![author]
+            }
+            Edit::Delete(id) => vec![authors.remove(id)?],
+            Edit::Merge { into, from } => {
+                let mut target = self.get(into).cloned().ok_or(BookError::NotFound)?;
+                for id in from.into_iter().filter(|&id| id != into) {
+                    let merged = authors.remove(id)?;
+                    for name in std::iter::once(merged.name.clone()).chain(merged.aliases.iter().cloned()) {
+                        renames.push((fold(&name), target.name.clone()));
+                        if !target.is_named(&name) {
+                            target.aliases.push(name);
+                        }
+                    }
+                    if target.bio.is_empty() {
+                        target.bio = merged.bio;
+                    }
+                    for link in merged.links {
+                        if !target.links.contains(&link) {
+                            target.links.push(link);
+                        }
+                    }
+                }
+                authors.update(target.clone(), &mut renames)?;
+                vec![target]
+            }
+            Edit::Import => {
+                let mut created = Vec::new();
+                for name in books.iter().flat_map(|b| &b.authors) {
+                    if name.trim().is_empty() || authors.find(name).is_some() {
+                        continue;
+                    }
+                    let author = AuthorInput { name: name.clone(), ..Default::default() }.into_author(authors.next_id())?;
+                    authors.push(author.clone());
+                    created.push(author);
+                }
+                created
+            }
+        };
+
+        authors.check()?;
+        Ok(Plan { records: authors, renames, result })
+    }
+}
+
+/// データファイルに対応する記録のファイル。
+pub fn path_for(data_file: &Path) -> PathBuf {
+    data_file.with_extension("authors.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(name: &str) -> AuthorInput {
+        AuthorInput { name: name.to_string(), ..Default::default() }
+    }
+
+    fn book(id: u32, authors: &[&str]) -> Book {
+        Book { id, authors: authors.iter().map(|a| a.to_string()).collect(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_sort_name() {
+        assert_eq!(sort_name("Steve Klabnik"), "Klabnik, Steve");
+        assert_eq!(sort_name("Klabnik, Steve"), "Klabnik, Steve");
+        assert_eq!(sort_name("村上春樹"), "村上春樹");
+    }
+
+    #[test]
+    fn test_merge() {
+        let books = [book(1, &["Steve Klabnik"]), book(2, &["S. Klabnik", "Carol Nichols"])];
+        let authors = Authors::default().plan(Edit::Import, &books).unwrap().records;
+        assert_eq!(authors.list().iter().map(|a| a.name.as_str()).collect::<Vec<_>>(), vec!["Steve Klabnik", "S. Klabnik", "Carol Nichols"]);
+        assert_eq!(authors.get(1).unwrap().sort_name, "Klabnik, Steve");
+
+        let plan = authors.plan(Edit::Merge { into: 1, from: vec![2] }, &books).unwrap();
+        assert_eq!(plan.records.list().len(), 2);
+        assert_eq!(plan.result[0].aliases, vec!["S. Klabnik"]);
+        let [mut first, mut second] = books.clone();
+        assert!(Author::rename_in(&mut second, &plan.renames));
+        assert_eq!(second.authors, vec!["Steve Klabnik", "Carol Nichols"]);
+        assert!(!Author::rename_in(&mut first, &plan.renames));
+
+        // 別名で書いた著者は記録の名前にそろえる
+        let merged = plan.records;
+        assert_eq!(merged.canonical(&["s. klabnik".to_string(), "Steve Klabnik".to_string()]), vec!["Steve Klabnik"]);
+        assert!(matches!(merged.plan(Edit::Merge { into: 1, from: vec![9] }, &books), Err(BookError::NotFound)));
+    }
+
+    #[test]
+    fn test_update_and_conflicts() {
+        let authors = Authors::default().plan(Edit::Create(input("Steve Klabnik")), &[]).unwrap().records;
+        let authors = authors.plan(Edit::Create(input("Carol Nichols")), &[]).unwrap().records;
+
+        let plan = authors.plan(Edit::Update(1, input("Steve K. Klabnik")), &[]).unwrap();
+        assert_eq!(plan.renames, vec![("steve klabnik".to_string(), "Steve K. Klabnik".to_string())]);
+
+        let taken = AuthorInput { aliases: vec!["carol nichols".to_string()], ..input("Steve Klabnik") };
+        assert!(matches!(authors.plan(Edit::Update(1, taken), &[]), Err(BookError::Conflict(_))));
+        assert!(matches!(authors.plan(Edit::Create(input(" ")), &[]), Err(BookError::BadRequest(_))));
+        assert!(matches!(authors.plan(Edit::Delete(5), &[]), Err(BookError::NotFound)));
+    }
+}
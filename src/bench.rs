@@ -0,0 +1,194 @@
+//! `books bench`: アプリをプロセス内で起動し、GET / POST を混ぜた負荷をかけてレイテンシを測る。
+//!
+//! ネットワークを介さず `actix_web::test` のサービスを直接呼ぶので、ストレージや
+//! キャッシュの変更前後を同じ条件で比べられる。POST で書き換えるのはデータファイルの一時コピー。
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use actix_web::{test, web};
+use futures_util::StreamExt;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::cli::CliError;
+use crate::storage::{BookRepository, StorageOptions};
+use crate::webhooks::Webhooks;
+use crate::{app, AppState, Book};
+
+#[derive(Clone, Debug, clap::Args)]
+pub struct BenchOptions {
+    /// Total number of requests to send
+    #[arg(long, default_value_t = 1000)]
+    pub requests: usize,
+    /// Number of requests in flight at once
+    #[arg(long, default_value_t = 8)]
+    pub concurrency: usize,
+    /// Fraction of requests that are POST /books (0.0 - 1.0)
+    #[arg(long, default_value_t = 0.1)]
+    pub write_ratio: f64,
+    /// Seed for the request mix, so runs can be compared
+    #[arg(long, default_value_t = 42)]
+    pub seed: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    List,
+    Get,
+    Search,
+    Upsert,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::List => "GET /books",
+            Kind::Get => "GET /books/id/{id}",
+            Kind::Search => "GET /books/search",
+            Kind::Upsert => "POST /books",
+        }
+    }
+}
+
+/// 昇順に並んだ値から p (0.0 - 1.0) パーセンタイルを取る。
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+
+    let rank = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1]
+}
+
+fn temp_copy(data_file: &Path) -> Result<PathBuf, CliError> {
+    let path = std::env::temp_dir().join(format!("books_bench_{}.json", std::process::id()));
+    fs::copy(data_file, &path)?;
+    Ok(path)
+}
+
+fn plan(options: &BenchOptions, ids: &[u32], tags: &[String]) -> Vec<(Kind, test::TestRequest)> {
+    let mut rng = StdRng::seed_from_u64(options.seed);
+
+    (0..options.requests)
+        .map(|i| {
+            if rng.gen_bool(options.write_ratio.clamp(0.0, 1.0)) {
+                let book = Book {
+                    id: 1_000_000 + i as u32,
+                    title: format!("Bench {}", i),
+                    content: "x".repeat(256),
+                    tags: vec!["bench".to_string()],
+                    ..Default::default()
+                };
+                return (Kind::Upsert, test::TestRequest::post().uri("/books").set_json(book));
+            }
+
+            match rng.gen_range(0..3) {
+                0 => (Kind::List, test::TestRequest::get().uri("/books")),
+                1 if !ids.is_empty() => {
+                    let id = ids[rng.gen_range(0..ids.len())];
+                    (Kind::Get, test::TestRequest::get().uri(&format!("/books/id/{}", id)))
+                }
+                _ if !tags.is_empty() => {
+                    let tag = &tags[rng.gen_range(0..tags.len())];
+                    let query = serde_urlencoded::to_string([("tag", tag)]).unwrap_or_default();
+                    (Kind::Search, test::TestRequest::get().uri(&format!("/books/search?{}", query)))
+                }
+                _ => (Kind::List, test::TestRequest::get().uri("/books")),
+            }
+        })
+        .collect()
+}
+
+/// ベンチマークを実行し、種類ごとのレイテンシを標準出力に表として書く。
+pub async fn run(data_file: &Path, storage: StorageOptions, options: BenchOptions) -> Result<(), CliError> {
+    let copy = temp_copy(data_file)?;
+    let result = run_on(&copy, storage, &options).await;
+    let _ = fs::remove_file(&copy);
+
+    let (elapsed, samples, failures) = result?;
+
+    println!(
+        "{} requests in {:.2?} ({:.0} req/s, concurrency {}, {} failed)",
+        options.requests,
+        elapsed,
+        options.requests as f64 / elapsed.as_secs_f64(),
+        options.concurrency,
+        failures,
+    );
+    println!("{:<20} {:>7} {:>10} {:>10} {:>10} {:>10}", "endpoint", "count", "p50", "p90", "p99", "max");
+
+    for (kind, mut latencies) in samples {
+        latencies.sort();
+        println!(
+            "{:<20} {:>7} {:>10.2?} {:>10.2?} {:>10.2?} {:>10.2?}",
+            kind.as_str(),
+            latencies.len(),
+            percentile(&latencies, 0.50),
+            percentile(&latencies, 0.90),
+            percentile(&latencies, 0.99),
+            latencies.last().copied().unwrap_or_default(),
+        );
+    }
+
+    Ok(())
+}
+
+type Samples = BTreeMap<Kind, Vec<Duration>>;
+
+async fn run_on(data_file: &Path, storage: StorageOptions, options: &BenchOptions) -> Result<(Duration, Samples, usize), CliError> {
+    let repository = BookRepository::with_options(data_file, storage);
+    let books = repository.list()?;
+    let ids: Vec<u32> = books.iter().map(|b| b.id).collect();
+    let tags: Vec<String> = repository.tags()?.into_iter().map(|(tag, _)| tag).collect();
+
+    let webhooks = Webhooks::new(std::env::temp_dir().join(format!("books_bench_webhooks_{}.json", std::process::id())));
+    let state = web::Data::new(AppState::new(repository, webhooks));
+    let service = test::init_service(app(state)).await;
+
+    let requests = plan(options, &ids, &tags);
+    let started = Instant::now();
+
+    let results: Vec<(Kind, Duration, bool)> = futures_util::stream::iter(requests)
+        .map(|(kind, req)| {
+            let service = &service;
+            async move {
+                let start = Instant::now();
+                let resp = test::call_service(service, req.to_request()).await;
+                let ok = resp.status().is_success();
+                // ボディを読み切るまでを 1 リクエストとして測る
+                test::read_body(resp).await;
+                (kind, start.elapsed(), ok)
+            }
+        })
+        .buffer_unordered(options.concurrency.max(1))
+        .collect()
+        .await;
+
+    let elapsed = started.elapsed();
+    let failures = results.iter().filter(|(_, _, ok)| !ok).count();
+
+    let mut samples = Samples::new();
+    for (kind, latency, _) in results {
+        samples.entry(kind).or_default().push(latency);
+    }
+
+    Ok((elapsed, samples, failures))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::percentile;
+    use std::time::Duration;
+
+    #[test]
+    fn test_percentile() {
+        let latencies: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&latencies, 0.50), Duration::from_millis(50));
+        assert_eq!(percentile(&latencies, 0.99), Duration::from_millis(99));
+        assert_eq!(percentile(&latencies, 1.0), Duration::from_millis(100));
+        assert_eq!(percentile(&[], 0.5), Duration::ZERO);
+    }
+}
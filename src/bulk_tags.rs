@@ -0,0 +1,132 @@
+//! 複数の書籍のタグをまとめて付け外しする (`POST /books/tags/bulk`)。
+//!
+//! 取り込んだ書籍のタグの整理のため。対象は id の一覧か `q=` と同じ検索式で選び、
+//! 変更は 1 回の書き込みで反映する。タグは `crate::normalize` で正規化して比べるので、
+//! `Rust` を外すと `rust` も外れ、すでに付いているタグを重ねて付けることはない。
+
+use serde::{Deserialize, Serialize};
+
+use crate::normalize::fold;
+use crate::BookError;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct BulkTagRequest {
+    /// 対象の書籍の id。`q` とどちらか一方を指定する
+    #[serde(default)]
+    pub ids: Option<Vec<u32>>,
+    /// 対象を選ぶ検索式 (`crate::query`)
+    #[serde(default)]
+    pub q: Option<String>,
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+/// 対象の選び方。
+pub enum Target {
+    Ids(Vec<u32>),
+    Query(String),
+}
+
+/// 付け外しするタグ。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TagChange {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+impl BulkTagRequest {
+    /// 対象と変更を取り出す。対象の指定がないか両方あるとき、タグが空のとき、何も変えないときは 400。
+    pub fn validate(self) -> Result<(Target, TagChange), BookError> {
+        let target = match (self.ids, self.q.filter(|q| !q.trim().is_empty())) {
+            (Some(ids), None) => Target::Ids(ids),
+            (None, Some(q)) => Target::Query(q),
+            _ => return Err(BookError::BadRequest("specify exactly one of ids or q".to_string())),
+        };
+
+        let trimmed = |tags: Vec<String>| -> Result<Vec<String>, BookError> {
+            tags.into_iter()
+                .map(|tag| {
+                    let tag = tag.trim().to_string();
+                    if tag.is_empty() {
+                        Err(BookError::BadRequest("tags must not be empty".to_string()))
+                    } else {
+                        Ok(tag)
+                    }
+                })
+                .collect()
+        };
+        let (add, remove) = (trimmed(self.add)?, trimmed(self.remove)?);
+        if add.is_empty() && remove.is_empty() {
+            return Err(BookError::BadRequest("nothing to add or remove".to_string()));
+        }
+        if let Some(tag) = add.iter().find(|a| remove.iter().any(|r| fold(r) == fold(a))) {
+            return Err(BookError::BadRequest(format!("{:?} is both added and removed", tag)));
+        }
+
+        Ok((target, TagChange { add, remove }))
+    }
+}
+
+impl TagChange {
+    /// `tags` に適用する。変わったら新しいタグを返す。
+    pub fn apply(&self, tags: &[String]) -> Option<Vec<String>> {
+        let mut changed: Vec<String> = tags.iter()
+            .filter(|t| !self.remove.iter().any(|r| fold(r) == fold(t)))
+            .cloned()
+            .collect();
+        for tag in &self.add {
+            if !changed.iter().any(|t| fold(t) == fold(tag)) {
+                changed.push(tag.clone());
+            }
+        }
+
+        (changed != tags).then_some(changed)
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct BulkTagReport {
+    /// 対象になった書籍の数
+    pub matched: usize,
+    /// タグが実際に変わった書籍の数
+    pub updated: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn test_apply() {
+        let change = TagChange { add: tags(&["Rust", "imported"]), remove: tags(&["TODO"]) };
+        assert_eq!(change.apply(&tags(&["rust", "todo", "async"])), Some(tags(&["rust", "async", "imported"])));
+        assert_eq!(change.apply(&tags(&["rust", "imported"])), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let request = |ids: Option<Vec<u32>>, q: Option<&str>, add: &[&str], remove: &[&str]| BulkTagRequest {
+            ids,
+            q: q.map(str::to_string),
+            add: tags(add),
+            remove: tags(remove),
+        };
+
+        let (target, change) = request(Some(vec![1, 2]), None, &[" rust "], &[]).validate().unwrap();
+        assert!(matches!(target, Target::Ids(ids) if ids == vec![1, 2]));
+        assert_eq!(change.add, vec!["rust"]);
+        assert!(matches!(request(None, Some("tag:todo"), &[], &["todo"]).validate().unwrap().0, Target::Query(_)));
+
+        assert!(request(None, None, &["rust"], &[]).validate().is_err());
+        assert!(request(Some(vec![1]), Some("tag:todo"), &["rust"], &[]).validate().is_err());
+        assert!(request(Some(vec![1]), None, &[], &[]).validate().is_err());
+        assert!(request(Some(vec![1]), None, &[" "], &[]).validate().is_err());
+        assert!(request(Some(vec![1]), None, &["Rust"], &["rust"]).validate().is_err());
+    }
+}
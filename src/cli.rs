@@ -0,0 +1,240 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+
+use crate::bench::BenchOptions;
+use crate::seed::SeedOptions;
+use crate::storage::BookRepository;
+use crate::auth::{remove_user, save_user};
+use crate::password::PasswordPolicy;
+use crate::{Book, BookError, Role};
+
+#[derive(Parser)]
+#[command(name = "books", version, about = "Books backend server and admin tools")]
+pub struct Cli {
+    /// Path to the TOML config file [default: books.toml, if present]
+    #[arg(long, global = true, env = "BOOKS_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Path to the books JSON data file, overriding the config file [default: src/data/book.json]
+    #[arg(long, global = true, env = "BOOKS_DATA_FILE")]
+    pub data_file: Option<PathBuf>,
+
+    /// Run the command against a tenant from the config file instead of the default library
+    #[arg(long, global = true, env = "BOOKS_TENANT")]
+    pub tenant: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP and gRPC servers (default when no command is given)
+    Serve,
+    /// Import books from a JSON file, replacing books with the same id
+    Import { file: PathBuf },
+    /// Export all books to a JSON file (`-` for stdout)
+    Export { file: PathBuf },
+    /// Manage users
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Validate the data file
+    Check,
+    /// Salvage readable books from a corrupt data file, moving the rest to a quarantine file
+    Repair {
+        /// Only report what would be recovered, without touching any file
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Generate fake books (and optionally users) for development and load testing
+    Seed(SeedOptions),
+    /// Run an in-process load test against a temporary copy of the data file
+    Bench(BenchOptions),
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Add a user, reading the password from stdin when --password is omitted
+    Add {
+        username: String,
+        #[arg(long)]
+        password: Option<String>,
+        #[arg(long)]
+        admin: bool,
+    },
+    /// Remove a user
+    Remove { username: String },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CliError {
+    #[error(transparent)]
+    Book(#[from] BookError),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("{0}")]
+    Failed(String),
+}
+
+/// `policy` は `user add` のパスワードを確かめるのに使う。
+pub fn run(command: Command, repository: &BookRepository, policy: &PasswordPolicy) -> Result<(), CliError> {
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::Bench(_) => unreachable!("bench is handled by main"),
+        Command::Import { file } => import(repository, &file),
+        Command::Export { file } => export(repository, &file),
+        Command::User { command } => user(command, policy),
+        Command::Check => check(repository),
+        Command::Repair { dry_run } => repair(repository, dry_run),
+        Command::Seed(options) => seed(repository, &options),
+    }
+}
+
+fn import(repository: &BookRepository, file: &PathBuf) -> Result<(), CliError> {
+    let contents = fs::read_to_string(file)?;
+    let books: Vec<Book> = serde_json::from_str(&contents).map_err(BookError::from)?;
+
+    let count = books.len();
+    let created = repository.upsert_many(books)?;
+
+    println!("Imported {} books ({} new, {} updated)", count, created, count - created);
+    Ok(())
+}
+
+fn export(repository: &BookRepository, file: &PathBuf) -> Result<(), CliError> {
+    let books = repository.list()?;
+    let contents = serde_json::to_string_pretty(&books).map_err(BookError::from)?;
+
+    if file.as_os_str() == "-" {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(contents.as_bytes())?;
+        stdout.write_all(b"\n")?;
+    } else {
+        fs::write(file, contents)?;
+        println!("Exported {} books to {}", books.len(), file.display());
+    }
+
+    Ok(())
+}
+
+fn user(command: UserCommand, policy: &PasswordPolicy) -> Result<(), CliError> {
+    match command {
+        UserCommand::Add { username, password, admin } => {
+            let password = match password {
+                Some(password) => password,
+                None => {
+                    let mut line = String::new();
+                    io::stdin().lock().read_line(&mut line)?;
+                    line.trim_end_matches(['\r', '\n']).to_string()
+                }
+            };
+
+            if password.is_empty() {
+                return Err(CliError::Failed("password must not be empty".to_string()));
+            }
+
+            let violations = policy.check(&username, &password);
+            if !violations.is_empty() {
+                let messages: Vec<String> = violations.into_iter().map(|v| format!("password {}", v.message)).collect();
+                return Err(CliError::Failed(messages.join(", ")));
+            }
+
+            let role = if admin { Role::Admin } else { Role::User };
+            if !save_user(&username, &password, role)? {
+                return Err(CliError::Failed(format!("user {} already exists", username)));
+            }
+
+            println!("Added {} user {}", role.as_str(), username);
+        }
+        UserCommand::Remove { username } => {
+            if !remove_user(&username)? {
+                return Err(CliError::Failed(format!("user {} not found", username)));
+            }
+
+            println!("Removed user {}", username);
+        }
+    }
+
+    Ok(())
+}
+
+fn check(repository: &BookRepository) -> Result<(), CliError> {
+    let report = crate::integrity::check(repository)?;
+
+    for issue in &report.issues {
+        let id = issue.id.map(|id| id.to_string()).unwrap_or_else(|| "-".to_string());
+        println!("#{} (id {}): {}", issue.index, id, issue.detail);
+    }
+
+    if !report.ok {
+        return Err(CliError::Failed(format!("{} problems found in {} books", report.issues.len(), report.books)));
+    }
+
+    println!("OK: {} books", report.books);
+    Ok(())
+}
+
+fn repair(repository: &BookRepository, dry_run: bool) -> Result<(), CliError> {
+    let report = crate::repair::repair(repository, dry_run)?;
+
+    if report.quarantined == 0 {
+        println!("OK: {} books, nothing to repair", report.recovered);
+        return Ok(());
+    }
+
+    if dry_run {
+        println!("Would recover {} books and quarantine {} fragments", report.recovered, report.quarantined);
+        return Ok(());
+    }
+
+    println!("Recovered {} books and quarantined {} fragments", report.recovered, report.quarantined);
+    if let (Some(backup), Some(quarantine_file)) = (report.backup, report.quarantine_file) {
+        println!("Original file: {}", backup.display());
+        println!("Quarantined fragments: {}", quarantine_file.display());
+    }
+    Ok(())
+}
+
+fn seed(repository: &BookRepository, options: &SeedOptions) -> Result<(), CliError> {
+    let report = crate::seed::run(repository, options, crate::auth::users_file())?;
+
+    println!(
+        "Generated {} books (ids {}..={}) and {} users",
+        report.books,
+        report.first_id,
+        report.first_id as usize + report.books.saturating_sub(1),
+        report.users,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_subcommands() {
+        let cli = Cli::parse_from(["books"]);
+        assert!(cli.command.is_none());
+
+        let cli = Cli::parse_from(["books", "--data-file", "other.json", "user", "add", "alice", "--admin"]);
+        assert_eq!(cli.data_file, Some(PathBuf::from("other.json")));
+        assert!(matches!(
+            cli.command,
+            Some(Command::User { command: UserCommand::Add { admin: true, .. } })
+        ));
+
+        let cli = Cli::parse_from(["books", "seed", "--count", "500", "--seed", "1", "--tenant", "acme"]);
+        assert_eq!(cli.tenant.as_deref(), Some("acme"));
+        assert!(matches!(
+            cli.command,
+            Some(Command::Seed(SeedOptions { count: 500, seed: Some(1), replace: false, .. }))
+        ));
+    }
+}
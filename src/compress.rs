@@ -0,0 +1,136 @@
+//! gzip / brotli によるレスポンス圧縮。
+//!
+//! actix の `Compress` は圧縮レベルを変えられないので、サイズの分かっている
+//! レスポンスだけをまとめて圧縮する小さなミドルウェアを使う。SSE や WebSocket
+//! のようなストリームはそのまま流す。
+
+use std::io::Write;
+use actix_web::body::{self, BodySize, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+
+/// これより小さいレスポンスは圧縮しても得にならない。
+const MIN_SIZE: u64 = 1024;
+
+/// 圧縮レベル。0 で無効、1 (速い) 〜 9 (よく縮む)。brotli は 11 まで使える。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compression(pub u32);
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression(6)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+/// `Accept-Encoding` から使う符号化を選ぶ。q 値が同じなら brotli を優先する。
+fn choose_encoding(accept: &str) -> Option<Encoding> {
+    accept.split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let name = parts.next()?.trim();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let encoding = match name {
+                "br" => Encoding::Brotli,
+                "gzip" | "*" => Encoding::Gzip,
+                _ => return None,
+            };
+
+            (q > 0.0).then_some((encoding, q))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1).then_with(|| (a.0 == Encoding::Brotli).cmp(&(b.0 == Encoding::Brotli))))
+        .map(|(encoding, _)| encoding)
+}
+
+fn encode(encoding: Encoding, level: u32, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level.min(9)));
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Encoding::Brotli => {
+            let mut out = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, level.min(11), 22);
+                encoder.write_all(data)?;
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// `middleware::from_fn` に渡す圧縮ミドルウェア。レベルは `web::Data<Compression>` から読む。
+pub async fn compress(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let level = req.app_data::<web::Data<Compression>>()
+        .map(|c| c.0)
+        .unwrap_or(Compression::default().0);
+
+    let encoding = req.headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .and_then(choose_encoding);
+
+    let res = next.call(req).await?;
+
+    let compressible = matches!(res.response().body().size(), BodySize::Sized(n) if n >= MIN_SIZE)
+        && !res.headers().contains_key(header::CONTENT_ENCODING);
+
+    let Some(encoding) = encoding.filter(|_| level > 0 && compressible) else {
+        return Ok(res.map_into_boxed_body());
+    };
+
+    let (req, res) = res.into_parts();
+    let (mut res, body) = res.into_parts();
+
+    let bytes = body::to_bytes(body)
+        .await
+        .map_err(|e| {
+            let e: Box<dyn std::error::Error> = e.into();
+            actix_web::error::ErrorInternalServerError(e.to_string())
+        })?;
+    let compressed = encode(encoding, level, &bytes)?;
+
+    let headers = res.headers_mut();
+    headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+    headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    headers.remove(header::CONTENT_LENGTH);
+
+    Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(compressed))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_encoding() {
+        assert_eq!(choose_encoding("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(choose_encoding("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(choose_encoding("br;q=0, gzip"), Some(Encoding::Gzip));
+        assert_eq!(choose_encoding("identity"), None);
+    }
+}
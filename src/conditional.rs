@@ -0,0 +1,96 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use actix_web::http::header::{self, Header, HeaderValue, HttpDate, IfModifiedSince, LastModified, TryIntoHeaderValue};
+use actix_web::{HttpRequest, HttpResponse};
+
+use crate::anonymous::{self, AnonymousAccess};
+
+/// 読み取りの応答を変えるリクエストヘッダー。
+const VARY: &str = "Authorization, X-Api-Key, Accept, Accept-Encoding";
+
+/// HTTP の日付は秒単位なので、比較する前に端数を切り捨てる。
+fn truncate(time: SystemTime) -> SystemTime {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// `If-Modified-Since` 以降に更新されていなければ 304 を返す。
+pub fn not_modified(req: &HttpRequest, modified: Option<SystemTime>) -> Option<HttpResponse> {
+    let modified = truncate(modified?);
+    let IfModifiedSince(since) = IfModifiedSince::parse(req).ok()?;
+
+    (modified <= SystemTime::from(since)).then(|| {
+        let mut resp = HttpResponse::NotModified().finish();
+        insert_headers(req, &mut resp, Some(modified));
+        resp
+    })
+}
+
+/// 誰が見ても同じ内容になるか。認証すれば見える本が増え、`unrestricted` 以外では見せ方で絞るので、共有キャッシュに置けない。
+fn shared(req: &HttpRequest) -> bool {
+    anonymous::viewer(req).is_some_and(|anonymous| anonymous.access() == AnonymousAccess::Unrestricted)
+}
+
+/// `Last-Modified` と、毎回再検証させる `Cache-Control` を付与する。
+///
+/// 内容は認証情報と `Accept` / `Accept-Encoding` で変わるので、`Vary` も付ける。
+pub fn insert_headers(req: &HttpRequest, resp: &mut HttpResponse, modified: Option<SystemTime>) {
+    let headers = resp.headers_mut();
+
+    let cache_control = if shared(req) { "public, no-cache" } else { "private, no-cache" };
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    headers.insert(header::VARY, HeaderValue::from_static(VARY));
+
+    if let Some(modified) = modified {
+        let value = LastModified(HttpDate::from(truncate(modified)));
+        if let Ok(value) = value.try_into_value() {
+            headers.insert(header::LAST_MODIFIED, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use actix_web::HttpMessage;
+    use crate::anonymous::Anonymous;
+
+    #[test]
+    fn test_not_modified() {
+        let modified = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        let since = HttpDate::from(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+
+        let req = TestRequest::default()
+            .insert_header((header::IF_MODIFIED_SINCE, since))
+            .to_http_request();
+        assert!(not_modified(&req, Some(modified)).is_some());
+
+        let later = modified + Duration::from_secs(1);
+        assert!(not_modified(&req, Some(later)).is_none());
+
+        let req = TestRequest::default().to_http_request();
+        assert!(not_modified(&req, Some(modified)).is_none());
+    }
+
+    #[test]
+    fn test_cache_control() {
+        let cache_control = |req: &HttpRequest| {
+            let mut resp = HttpResponse::Ok().finish();
+            insert_headers(req, &mut resp, None);
+            assert_eq!(resp.headers().get(header::VARY).unwrap(), VARY);
+            resp.headers().get(header::CACHE_CONTROL).unwrap().to_str().unwrap().to_string()
+        };
+
+        // 認証済み
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(cache_control(&req), "private, no-cache");
+
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut().insert(Anonymous::new(AnonymousAccess::PublicBooks));
+        assert_eq!(cache_control(&req), "private, no-cache");
+
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut().insert(Anonymous::new(AnonymousAccess::Unrestricted));
+        assert_eq!(cache_control(&req), "public, no-cache");
+    }
+}
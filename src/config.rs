@@ -0,0 +1,1248 @@
+//! サーバーの設定。
+//!
+//! 既定値 → `books.toml` → 環境変数 → コマンドラインの順に上書きし、起動時に検証する。
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::analysis::{Analyzer, Language};
+use crate::flags::Flag;
+use crate::anonymous::AnonymousAccess;
+use crate::ipfilter::{IpFilter, IpRule};
+use crate::ldap::LdapConfig;
+use crate::logging::{BodyLogging, LogFormat, Rotation, RotationPolicy};
+use crate::password::PasswordPolicy;
+use crate::pwned::PwnedMode;
+use crate::storage::{BookRepository, EncryptionKey, InitialData, StorageOptions};
+use crate::tenant::TenantConfig;
+
+/// `--config` を省略したときに読む設定ファイル。なければ既定値のまま起動する。
+pub const DEFAULT_CONFIG_FILE: &str = "books.toml";
+
+/// `--data-file` も設定ファイルの指定もないときのデータファイル。
+pub const DEFAULT_DATA_FILE: &str = "src/data/book.json";
+
+/// ログファイルをローテーションする既定のサイズ (10 MiB)。
+const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// 残しておくローテーション済みログファイルの既定の数。
+const DEFAULT_LOG_MAX_FILES: usize = 7;
+
+/// 開発用フロントエンドのオリジン。
+pub const DEFAULT_CORS_ORIGINS: [&str; 2] = ["http://localhost:3000", "http://localhost:5173"];
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("{name} must be a socket address, got {value:?}")]
+    InvalidAddress { name: &'static str, value: String },
+    #[error("{name} must be a non-negative integer, got {value:?}")]
+    InvalidNumber { name: &'static str, value: String },
+    #[error("{name} must be an octal file mode such as 660, got {value:?}")]
+    InvalidMode { name: &'static str, value: String },
+    #[error("{name} must be a number between {min} and {max}, got {value:?}")]
+    OutOfRange { name: &'static str, value: String, min: u32, max: u32 },
+    #[error("{name} must be one of {expected}, got {value:?}")]
+    Unsupported { name: &'static str, value: String, expected: &'static str },
+    #[error("{name} must be an origin such as https://books.example.com or https://*.example.com, got {value:?}")]
+    InvalidOrigin { name: &'static str, value: String },
+    #[error("{name} must be a log filter such as info or info,books_backend=debug, got {value:?}")]
+    InvalidLogLevel { name: &'static str, value: String },
+    #[error("{name} must be a 32-byte key encoded in base64 (e.g. `openssl rand -base64 32`): {reason}")]
+    InvalidKey { name: &'static str, reason: String },
+    #[error("tenant name {0:?} must be 1-64 characters of A-Z, a-z, 0-9, '-' and '_'")]
+    InvalidTenant(String),
+    #[error("tenant {0:?} is not configured")]
+    UnknownTenant(String),
+    #[error("invalid [ldap] settings: {0}")]
+    InvalidLdap(String),
+    #[error("{name} is set but {requires} is not")]
+    Incomplete { name: &'static str, requires: &'static str },
+    #[error("Failed to read {}: {source}", path.display())]
+    Read { path: PathBuf, source: std::io::Error },
+    #[error("Invalid config file {}: {source}", path.display())]
+    Parse { path: PathBuf, source: Box<toml::de::Error> },
+}
+
+/// サーバー起動に必要な設定。
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// 読み込んだ設定ファイル。再読み込みで同じファイルを読む。
+    pub config_file: Option<PathBuf>,
+    /// ストレージの種類。今のところ `json-file` だけ。
+    pub storage_backend: String,
+    pub data_file: PathBuf,
+    pub webhooks_file: PathBuf,
+    /// 書き込み操作を追記する監査ログ。
+    pub audit_file: PathBuf,
+    pub grpc_addr: SocketAddr,
+    /// HTTP サーバーが待ち受けるアドレス。複数指定できる。
+    pub bind: Vec<(String, u16)>,
+    /// TCP に加えて待ち受ける Unix ドメインソケットのパス。
+    pub unix_socket: Option<PathBuf>,
+    /// ソケットファイルのパーミッション (例: 0o660)。
+    pub unix_socket_mode: Option<u32>,
+    /// ソケットファイルの所有ユーザー (uid)。
+    pub unix_socket_uid: Option<u32>,
+    /// ソケットファイルの所有グループ (gid)。
+    pub unix_socket_gid: Option<u32>,
+    /// HTTPS で待ち受けるときの証明書 (PEM)。鍵と両方指定したときだけ有効。
+    pub tls_cert: Option<PathBuf>,
+    /// HTTPS で待ち受けるときの秘密鍵 (PEM)。
+    pub tls_key: Option<PathBuf>,
+    /// レスポンス圧縮のレベル。0 で圧縮しない。
+    pub compression_level: u32,
+    /// 接続元 IP ごとの 1 分あたりのリクエスト上限。0 で無制限。
+    pub rate_limit_per_ip: u32,
+    /// API キーごとの 1 分あたりのリクエスト上限。0 で無制限。
+    pub rate_limit_per_api_key: u32,
+    /// 1 リクエストあたりの処理時間の上限。0 秒で無効。
+    pub request_timeout: Duration,
+    /// `Idempotency-Key` 付きの POST のレスポンスを覚えておく時間。0 秒で無効。
+    pub idempotency_window: Duration,
+    /// HTTP ワーカー数。`None` なら actix の既定値 (物理 CPU 数)。
+    pub workers: Option<usize>,
+    /// Keep-Alive の保持時間。`None` なら actix の既定値 (5 秒)。0 秒で無効。
+    pub keep_alive: Option<Duration>,
+    /// リクエストヘッダーを受け取り終えるまでの待ち時間。`None` なら actix の既定値 (5 秒)。
+    pub client_timeout: Option<Duration>,
+    /// ワーカーあたりの同時接続数の上限。`None` なら actix の既定値 (25,000)。
+    pub max_connections: Option<usize>,
+    /// データファイルをメモリマップして読むか。
+    pub mmap: bool,
+    /// 保存する前に本の `content` の HTML を無害化するか。
+    pub sanitize_content: bool,
+    /// `POST /admin/seed` でダミーデータを生成できるか。開発・負荷試験の環境だけで有効にする。
+    pub seed_endpoint: bool,
+    /// 全文検索で語を切り出すときに使う言語ごとの処理。空なら従来どおり。
+    pub search_analyzers: Vec<Language>,
+    /// データファイルがないときに用意する内容。
+    pub initial_data: InitialData,
+    /// データファイルとバックアップを暗号化する鍵。
+    pub encryption_key: Option<EncryptionKey>,
+    /// JSON / フォームのボディの上限 (バイト)。
+    pub json_limit: usize,
+    /// 取り込み用の生ボディの上限 (バイト)。
+    pub upload_limit: usize,
+    /// 1 ページあたりの件数の上限。
+    pub max_per_page: usize,
+    /// ページを指定しない一覧・検索で返す件数の上限。
+    pub max_results: usize,
+    /// エクスポートで書き出す件数の上限。
+    pub max_export: usize,
+    /// ログの出力形式。
+    pub log_format: LogFormat,
+    /// ログのフィルター (`info` や `info,books_backend=debug`)。`None` なら `RUST_LOG`、なければ `debug`。
+    pub log_level: Option<String>,
+    /// ログを書くディレクトリ。`None` なら標準エラー (JSON は標準出力) に出す。
+    pub log_dir: Option<PathBuf>,
+    /// ログファイルのローテーション。
+    pub log_rotation: RotationPolicy,
+    /// 調査用に、リクエストとレスポンスのボディをログに書くか。
+    pub body_logging: BodyLogging,
+    /// 転送ヘッダー (`Forwarded` / `X-Forwarded-*`) を信頼するプロキシ。IP か `10.0.0.0/8` のような範囲。
+    pub trusted_proxies: Vec<String>,
+    /// データの書き出しの `notify_url` に使ってよいホスト名。空なら `notify_url` を受け付けない。
+    pub export_notify_hosts: Vec<String>,
+    /// 接続元 IP による許可・拒否のルール。
+    pub ip_rules: Vec<IpRule>,
+    /// ユーザーファイルにいないユーザーを認証する LDAP サーバー。
+    pub ldap: Option<LdapConfig>,
+    /// CORS で許可するオリジン。完全一致か、`https://*.example.com` のようなサブドメインのワイルドカード。
+    pub cors_origins: Vec<String>,
+    /// 機能フラグの初期値。書かなかったフラグは有効。
+    pub flags: BTreeMap<Flag, bool>,
+    /// ユーザー情報を保存するファイル。
+    pub users_file: PathBuf,
+    /// トークンの署名鍵を保存するファイル。
+    pub keys_file: PathBuf,
+    /// 発行したトークンの有効期間。鍵を切り替えた後、古い鍵もこの間は検証に使う。
+    pub token_ttl: Duration,
+    /// 認証なしのリクエストに許すこと。
+    pub anonymous_access: AnonymousAccess,
+    /// API キーの保存先。
+    pub api_keys_file: PathBuf,
+    /// 共有リンクの保存先。
+    pub shares_file: PathBuf,
+    /// 誰でも `POST /register` で登録できるか。閉じていても招待トークンがあれば登録できる。
+    pub open_signup: bool,
+    /// 招待の保存先。
+    pub invites_file: PathBuf,
+    /// 招待トークンの有効期間。
+    pub invite_ttl: Duration,
+    /// パスワードの最短の文字数。
+    pub password_min_length: usize,
+    /// パスワードの最長の文字数。
+    pub password_max_length: usize,
+    /// 埋め込みの一覧に加えて拒否するパスワードのファイル (1 行に 1 つ)。
+    pub password_deny_list: Option<PathBuf>,
+    /// 新しいパスワードを Have I Been Pwned で確かめるか。
+    pub pwned_check: PwnedMode,
+    pub pwned_api_url: String,
+    /// この時間内に答えがなければ確かめずに通す。
+    pub pwned_timeout: Duration,
+    /// 既定の書庫とは別に開くテナント。
+    pub tenants: BTreeMap<String, TenantConfig>,
+    /// 起動時に作成する管理者のユーザー名とパスワード。
+    pub admin: Option<(String, String)>,
+}
+
+impl Config {
+    pub fn new(data_file: impl Into<PathBuf>) -> Self {
+        Config {
+            config_file: None,
+            storage_backend: BookRepository::BACKEND.to_string(),
+            data_file: data_file.into(),
+            webhooks_file: PathBuf::from("src/data/webhooks.json"),
+            audit_file: PathBuf::from(crate::audit::DEFAULT_AUDIT_FILE),
+            grpc_addr: SocketAddr::from(([127, 0, 0, 1], 50051)),
+            bind: vec![("127.0.0.1".to_string(), 8080)],
+            unix_socket: None,
+            unix_socket_mode: None,
+            unix_socket_uid: None,
+            unix_socket_gid: None,
+            tls_cert: None,
+            tls_key: None,
+            compression_level: 6,
+            rate_limit_per_ip: 600,
+            rate_limit_per_api_key: 6000,
+            request_timeout: Duration::from_secs(30),
+            idempotency_window: crate::idempotency::DEFAULT_WINDOW,
+            workers: None,
+            keep_alive: None,
+            client_timeout: None,
+            max_connections: None,
+            mmap: false,
+            sanitize_content: false,
+            seed_endpoint: false,
+            search_analyzers: Vec::new(),
+            initial_data: InitialData::default(),
+            encryption_key: None,
+            json_limit: crate::limits::DEFAULT_JSON_LIMIT,
+            upload_limit: crate::limits::DEFAULT_UPLOAD_LIMIT,
+            max_per_page: crate::limits::DEFAULT_MAX_PER_PAGE,
+            max_results: crate::limits::DEFAULT_MAX_RESULTS,
+            max_export: crate::limits::DEFAULT_MAX_EXPORT,
+            log_format: LogFormat::Text,
+            log_level: None,
+            log_dir: None,
+            log_rotation: RotationPolicy {
+                max_size: DEFAULT_LOG_MAX_SIZE,
+                rotation: Rotation::Daily,
+                max_files: DEFAULT_LOG_MAX_FILES,
+            },
+            body_logging: BodyLogging::default(),
+            trusted_proxies: Vec::new(),
+            export_notify_hosts: Vec::new(),
+            ip_rules: Vec::new(),
+            ldap: None,
+            cors_origins: DEFAULT_CORS_ORIGINS.iter().map(|o| o.to_string()).collect(),
+            flags: BTreeMap::new(),
+            users_file: PathBuf::from(crate::auth::DEFAULT_USERS_FILE),
+            keys_file: PathBuf::from(crate::jwt::DEFAULT_KEYS_FILE),
+            token_ttl: crate::jwt::DEFAULT_TOKEN_TTL,
+            anonymous_access: AnonymousAccess::default(),
+            api_keys_file: PathBuf::from(crate::api_keys::DEFAULT_API_KEYS_FILE),
+            shares_file: PathBuf::from(crate::share::DEFAULT_SHARES_FILE),
+            open_signup: false,
+            invites_file: PathBuf::from(crate::invites::DEFAULT_INVITES_FILE),
+            invite_ttl: crate::invites::DEFAULT_INVITE_TTL,
+            password_min_length: crate::password::DEFAULT_MIN_LENGTH,
+            password_max_length: crate::password::DEFAULT_MAX_LENGTH,
+            password_deny_list: None,
+            pwned_check: PwnedMode::default(),
+            pwned_api_url: crate::pwned::DEFAULT_API_URL.to_string(),
+            pwned_timeout: crate::pwned::DEFAULT_TIMEOUT,
+            tenants: BTreeMap::new(),
+            admin: None,
+        }
+    }
+
+    /// 設定ファイルと環境変数から設定を組み立てる。
+    ///
+    /// `path` が `None` なら `books.toml` があるときだけ読む。`data_file` はコマンドラインの
+    /// `--data-file` (`BOOKS_DATA_FILE`) で、指定されていれば設定ファイルより優先する。
+    pub fn load(path: Option<&Path>, data_file: Option<PathBuf>) -> Result<Self, ConfigError> {
+        let mut config = Config::new(DEFAULT_DATA_FILE);
+
+        let file = match path {
+            Some(path) => Some(path),
+            None => Some(Path::new(DEFAULT_CONFIG_FILE)).filter(|p| p.exists()),
+        };
+        if let Some(path) = file {
+            let contents = fs::read_to_string(path)
+                .map_err(|source| ConfigError::Read { path: path.to_path_buf(), source })?;
+            let file: FileConfig = toml::from_str(&contents)
+                .map_err(|source| ConfigError::Parse { path: path.to_path_buf(), source: Box::new(source) })?;
+
+            config.apply_file(file)?;
+            config.config_file = Some(path.to_path_buf());
+        }
+
+        config.apply_env()?;
+
+        if let Some(data_file) = data_file {
+            config.data_file = data_file;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// パスワードの規則。拒否リストのファイルが読めなければエラーにする。
+    pub fn password_policy(&self) -> Result<PasswordPolicy, ConfigError> {
+        let policy = PasswordPolicy::new(self.password_min_length, self.password_max_length);
+
+        match &self.password_deny_list {
+            Some(path) => policy.with_deny_list_file(path)
+                .map_err(|source| ConfigError::Read { path: path.clone(), source }),
+            None => Ok(policy),
+        }
+    }
+
+    pub fn storage_options(&self) -> StorageOptions {
+        StorageOptions {
+            mmap: self.mmap,
+            encryption_key: self.encryption_key.clone(),
+            sanitize_content: self.sanitize_content,
+            analyzer: Analyzer::new(&self.search_analyzers),
+        }
+    }
+
+    /// 既定の書庫の代わりにテナント `name` のデータファイルとユーザーを使う。
+    /// コマンドラインの `--tenant` で、管理コマンドをテナントに対して実行するのに使う。
+    pub fn select_tenant(&mut self, name: &str) -> Result<(), ConfigError> {
+        let tenant = self.tenants.get(name).ok_or_else(|| ConfigError::UnknownTenant(name.to_string()))?;
+
+        self.data_file = tenant.data_file.clone();
+        self.users_file = tenant.users_file.clone();
+        self.webhooks_file = tenant.webhooks_file.clone();
+
+        Ok(())
+    }
+
+    fn apply_file(&mut self, file: FileConfig) -> Result<(), ConfigError> {
+        let FileConfig { server, tls, storage, search, cors, auth, limits, logging, flags, tenants, ip_rules, ldap } = file;
+
+        self.flags.extend(flags);
+        self.ip_rules.extend(ip_rules);
+        if let Some(ldap) = ldap {
+            self.ldap = Some(ldap);
+        }
+
+        for (name, tenant) in tenants {
+            // Webhook の保存先を省略したら、データファイルの隣に置く
+            let webhooks_file = tenant.webhooks_file
+                .unwrap_or_else(|| tenant.data_file.with_extension("webhooks.json"));
+
+            self.tenants.insert(name, TenantConfig {
+                data_file: tenant.data_file,
+                users_file: tenant.users_file,
+                webhooks_file,
+            });
+        }
+
+        if let Some(bind) = server.bind {
+            let addrs = match bind {
+                Bind::One(addr) => vec![addr],
+                Bind::Many(addrs) => addrs,
+            };
+            self.bind = addrs.iter()
+                .map(|addr| parse_bind("server.bind", addr))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(path) = server.unix_socket {
+            self.unix_socket = Some(path);
+        }
+        if let Some(mode) = server.unix_socket_mode {
+            self.unix_socket_mode = Some(mode);
+        }
+        if let Some(uid) = server.unix_socket_uid {
+            self.unix_socket_uid = Some(uid);
+        }
+        if let Some(gid) = server.unix_socket_gid {
+            self.unix_socket_gid = Some(gid);
+        }
+        if let Some(addr) = server.grpc_addr {
+            self.grpc_addr = addr;
+        }
+        if let Some(level) = server.compression_level {
+            self.compression_level = level;
+        }
+        if let Some(secs) = server.request_timeout_secs {
+            self.request_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = server.idempotency_window_secs {
+            self.idempotency_window = Duration::from_secs(secs);
+        }
+        if let Some(workers) = server.workers {
+            self.workers = Some(workers);
+        }
+        if let Some(secs) = server.keep_alive_secs {
+            self.keep_alive = Some(Duration::from_secs(secs));
+        }
+        if let Some(secs) = server.client_timeout_secs {
+            self.client_timeout = Some(Duration::from_secs(secs));
+        }
+        if let Some(max) = server.max_connections {
+            self.max_connections = Some(max);
+        }
+        if let Some(proxies) = server.trusted_proxies {
+            self.trusted_proxies = proxies;
+        }
+        if let Some(hosts) = server.export_notify_hosts {
+            self.export_notify_hosts = hosts;
+        }
+
+        if let Some(cert) = tls.cert {
+            self.tls_cert = Some(cert);
+        }
+        if let Some(key) = tls.key {
+            self.tls_key = Some(key);
+        }
+
+        if let Some(backend) = storage.backend {
+            self.storage_backend = backend;
+        }
+        if let Some(data_file) = storage.data_file {
+            self.data_file = data_file;
+        }
+        if let Some(webhooks_file) = storage.webhooks_file {
+            self.webhooks_file = webhooks_file;
+        }
+        if let Some(audit_file) = storage.audit_file {
+            self.audit_file = audit_file;
+        }
+        if let Some(mmap) = storage.mmap {
+            self.mmap = mmap;
+        }
+        if let Some(sanitize) = storage.sanitize_content {
+            self.sanitize_content = sanitize;
+        }
+        if let Some(enabled) = storage.seed_endpoint {
+            self.seed_endpoint = enabled;
+        }
+        if let Some(initial_data) = storage.initial_data {
+            self.initial_data = initial_data;
+        }
+        if let Some(path) = storage.key_file {
+            self.encryption_key = Some(read_key_file("storage.key_file", &path)?);
+        }
+
+        if let Some(analyzers) = search.analyzers {
+            self.search_analyzers = analyzers;
+        }
+
+        if let Some(format) = logging.format {
+            self.log_format = format;
+        }
+        if let Some(level) = logging.level {
+            self.log_level = Some(level);
+        }
+        if let Some(dir) = logging.dir {
+            self.log_dir = Some(dir);
+        }
+        if let Some(max_size) = logging.max_size_bytes {
+            self.log_rotation.max_size = max_size;
+        }
+        if let Some(rotation) = logging.rotation {
+            self.log_rotation.rotation = rotation;
+        }
+        if let Some(max_files) = logging.max_files {
+            self.log_rotation.max_files = max_files;
+        }
+        if let Some(enabled) = logging.log_bodies {
+            self.body_logging.enabled = enabled;
+        }
+        if let Some(paths) = logging.log_bodies_skip_paths {
+            self.body_logging.skip_paths = paths;
+        }
+
+        if let Some(origins) = cors.allowed_origins {
+            self.cors_origins = origins;
+        }
+
+        if let Some(users_file) = auth.users_file {
+            self.users_file = users_file;
+        }
+        if let Some(keys_file) = auth.keys_file {
+            self.keys_file = keys_file;
+        }
+        if let Some(secs) = auth.token_ttl_secs {
+            self.token_ttl = Duration::from_secs(secs);
+        }
+        if let Some(access) = auth.anonymous_access {
+            self.anonymous_access = access;
+        }
+        if let Some(api_keys_file) = auth.api_keys_file {
+            self.api_keys_file = api_keys_file;
+        }
+        if let Some(shares_file) = auth.shares_file {
+            self.shares_file = shares_file;
+        }
+        if let Some(open) = auth.open_signup {
+            self.open_signup = open;
+        }
+        if let Some(invites_file) = auth.invites_file {
+            self.invites_file = invites_file;
+        }
+        if let Some(secs) = auth.invite_ttl_secs {
+            self.invite_ttl = Duration::from_secs(secs);
+        }
+        if let Some(min) = auth.password_min_length {
+            self.password_min_length = min;
+        }
+        if let Some(max) = auth.password_max_length {
+            self.password_max_length = max;
+        }
+        if let Some(path) = auth.password_deny_list_file {
+            self.password_deny_list = Some(path);
+        }
+        if let Some(mode) = auth.pwned_check {
+            self.pwned_check = mode;
+        }
+        if let Some(url) = auth.pwned_api_url {
+            self.pwned_api_url = url;
+        }
+        if let Some(secs) = auth.pwned_timeout_secs {
+            self.pwned_timeout = Duration::from_secs(secs);
+        }
+        if let (Some(username), Some(password)) = (auth.admin_username, auth.admin_password) {
+            self.admin = Some((username, password));
+        }
+
+        if let Some(limit) = limits.rate_limit_per_ip {
+            self.rate_limit_per_ip = limit;
+        }
+        if let Some(limit) = limits.rate_limit_per_api_key {
+            self.rate_limit_per_api_key = limit;
+        }
+        if let Some(limit) = limits.json_bytes {
+            self.json_limit = limit;
+        }
+        if let Some(limit) = limits.upload_bytes {
+            self.upload_limit = limit;
+        }
+        if let Some(max) = limits.max_per_page {
+            self.max_per_page = max;
+        }
+        if let Some(max) = limits.max_results {
+            self.max_results = max;
+        }
+        if let Some(max) = limits.max_export {
+            self.max_export = max;
+        }
+
+        Ok(())
+    }
+
+    /// 起動前に、組み合わせや範囲の誤りを見つける。
+    fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(name) = self.tenants.keys().find(|name| !crate::tenant::is_valid_name(name)) {
+            return Err(ConfigError::InvalidTenant(name.clone()));
+        }
+
+        if self.bind.is_empty() && self.unix_socket.is_none() {
+            return Err(ConfigError::InvalidAddress { name: "server.bind", value: String::new() });
+        }
+
+        if self.storage_backend != BookRepository::BACKEND {
+            return Err(ConfigError::Unsupported {
+                name: "storage.backend",
+                value: self.storage_backend.clone(),
+                expected: BookRepository::BACKEND,
+            });
+        }
+
+        if let Some(mode) = self.unix_socket_mode.filter(|mode| *mode > 0o777) {
+            return Err(ConfigError::InvalidMode { name: "server.unix_socket_mode", value: format!("{:o}", mode) });
+        }
+
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(_), None) => return Err(ConfigError::Incomplete { name: "tls.cert", requires: "tls.key" }),
+            (None, Some(_)) => return Err(ConfigError::Incomplete { name: "tls.key", requires: "tls.cert" }),
+            _ => {}
+        }
+
+        if self.password_min_length > self.password_max_length {
+            return Err(ConfigError::OutOfRange {
+                name: "auth.password_min_length",
+                value: self.password_min_length.to_string(),
+                min: 0,
+                max: self.password_max_length as u32,
+            });
+        }
+        self.password_policy()?;
+
+        if let Err(proxy) = crate::proxy::TrustedProxies::parse(&self.trusted_proxies) {
+            return Err(ConfigError::InvalidAddress { name: "server.trusted_proxies", value: proxy });
+        }
+        if let Err(range) = IpFilter::parse(&self.ip_rules) {
+            return Err(ConfigError::InvalidAddress { name: "ip_rules", value: range });
+        }
+        if let Some(ldap) = &self.ldap {
+            ldap.check().map_err(ConfigError::InvalidLdap)?;
+        }
+
+        if let Some(level) = self.log_level.as_ref().filter(|level| !crate::telemetry::is_valid_filter(level)) {
+            return Err(ConfigError::InvalidLogLevel { name: "logging.level", value: level.clone() });
+        }
+
+        if let Some(origin) = self.cors_origins.iter().find(|o| crate::cors::OriginPattern::parse(o).is_none()) {
+            return Err(ConfigError::InvalidOrigin { name: "cors.allowed_origins", value: origin.clone() });
+        }
+
+        if self.compression_level > 11 {
+            return Err(ConfigError::OutOfRange {
+                name: "compression_level",
+                value: self.compression_level.to_string(),
+                min: 0,
+                max: 11,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// `BIND_ADDR` / `UNIX_SOCKET` / `UNIX_SOCKET_MODE` / `UNIX_SOCKET_UID` / `UNIX_SOCKET_GID` /
+    /// `TLS_CERT_FILE` / `TLS_KEY_FILE` / `WEBHOOKS_FILE` / `AUDIT_FILE` / `GRPC_ADDR` / `COMPRESSION_LEVEL` /
+    /// `RATE_LIMIT_PER_IP` / `RATE_LIMIT_PER_API_KEY` / `REQUEST_TIMEOUT_SECS` / `IDEMPOTENCY_WINDOW_SECS` /
+    /// `WORKERS` / `KEEP_ALIVE_SECS` / `CLIENT_TIMEOUT_SECS` / `MAX_CONNECTIONS` / `MMAP_DATA_FILE` / `SANITIZE_CONTENT` / `SEED_ENDPOINT` /
+    /// `SEARCH_ANALYZERS` /
+    /// `JSON_LIMIT_BYTES` / `UPLOAD_LIMIT_BYTES` / `MAX_PER_PAGE` / `MAX_RESULTS` / `MAX_EXPORT` /
+    /// `STORAGE_BACKEND` / `DATA_KEY` / `DATA_KEY_FILE` / `INITIAL_DATA` / `LOG_FORMAT` / `LOG_LEVEL` / `LOG_DIR` / `LOG_MAX_SIZE_BYTES` / `LOG_ROTATION` / `LOG_MAX_FILES` / `LOG_BODIES` /
+    /// `TRUSTED_PROXIES` / `EXPORT_NOTIFY_HOSTS` / `IP_ALLOW` / `IP_DENY` / `LDAP_URL` / `LDAP_BASE_DN` / `LDAP_BIND_DN` / `LDAP_BIND_PASSWORD` /
+    /// `CORS_ORIGINS` / `FEATURE_FLAGS` / `USERS_FILE` / `KEYS_FILE` / `TOKEN_TTL_SECS` /
+    /// `ANONYMOUS_ACCESS` / `API_KEYS_FILE` / `SHARES_FILE` / `OPEN_SIGNUP` / `INVITES_FILE` / `INVITE_TTL_SECS` / `PASSWORD_MIN_LENGTH` / `PASSWORD_MAX_LENGTH` / `PASSWORD_DENY_LIST_FILE` /
+    /// `PWNED_CHECK` / `PWNED_API_URL` / `PWNED_TIMEOUT_SECS` / `ADMIN_USERNAME` / `ADMIN_PASSWORD`
+    /// の環境変数で上書きする。
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        let config = self;
+
+        if let Ok(bind) = env::var("BIND_ADDR") {
+            // カンマ区切りで複数のアドレスを指定できる
+            config.bind = bind.split(',')
+                .map(str::trim)
+                .filter(|addr| !addr.is_empty())
+                .map(|addr| parse_bind("BIND_ADDR", addr))
+                .collect::<Result<_, _>>()?;
+        }
+
+        if let Ok(path) = env::var("UNIX_SOCKET") {
+            config.unix_socket = Some(PathBuf::from(path));
+        }
+
+        if let Ok(mode) = env::var("UNIX_SOCKET_MODE") {
+            let octal = mode.trim_start_matches("0o");
+            config.unix_socket_mode = Some(u32::from_str_radix(octal, 8)
+                .map_err(|_| ConfigError::InvalidMode { name: "UNIX_SOCKET_MODE", value: mode.clone() })?);
+        }
+
+        if let Some(uid) = number_var("UNIX_SOCKET_UID")? {
+            config.unix_socket_uid = Some(uid);
+        }
+
+        if let Some(gid) = number_var("UNIX_SOCKET_GID")? {
+            config.unix_socket_gid = Some(gid);
+        }
+
+        if let Ok(file) = env::var("TLS_CERT_FILE") {
+            config.tls_cert = Some(PathBuf::from(file));
+        }
+
+        if let Ok(file) = env::var("TLS_KEY_FILE") {
+            config.tls_key = Some(PathBuf::from(file));
+        }
+
+        if let Ok(file) = env::var("WEBHOOKS_FILE") {
+            config.webhooks_file = PathBuf::from(file);
+        }
+
+        if let Ok(file) = env::var("AUDIT_FILE") {
+            config.audit_file = PathBuf::from(file);
+        }
+
+        if let Ok(addr) = env::var("GRPC_ADDR") {
+            config.grpc_addr = addr.parse().map_err(|_| ConfigError::InvalidAddress {
+                name: "GRPC_ADDR",
+                value: addr,
+            })?;
+        }
+
+        if let Ok(level) = env::var("COMPRESSION_LEVEL") {
+            config.compression_level = level.parse()
+                .ok()
+                .filter(|l| *l <= 11)
+                .ok_or(ConfigError::OutOfRange { name: "COMPRESSION_LEVEL", value: level, min: 0, max: 11 })?;
+        }
+
+        if let Some(limit) = number_var("RATE_LIMIT_PER_IP")? {
+            config.rate_limit_per_ip = limit;
+        }
+
+        if let Some(limit) = number_var("RATE_LIMIT_PER_API_KEY")? {
+            config.rate_limit_per_api_key = limit;
+        }
+
+        if let Some(secs) = number_var("REQUEST_TIMEOUT_SECS")? {
+            config.request_timeout = Duration::from_secs(secs.into());
+        }
+
+        if let Some(secs) = number_var("IDEMPOTENCY_WINDOW_SECS")? {
+            config.idempotency_window = Duration::from_secs(secs.into());
+        }
+
+        if let Some(workers) = number_var("WORKERS")? {
+            config.workers = Some(workers as usize);
+        }
+
+        if let Some(secs) = number_var("KEEP_ALIVE_SECS")? {
+            config.keep_alive = Some(Duration::from_secs(secs.into()));
+        }
+
+        if let Some(secs) = number_var("CLIENT_TIMEOUT_SECS")? {
+            config.client_timeout = Some(Duration::from_secs(secs.into()));
+        }
+
+        if let Some(max) = number_var("MAX_CONNECTIONS")? {
+            config.max_connections = Some(max as usize);
+        }
+
+        if let Ok(value) = env::var("MMAP_DATA_FILE") {
+            config.mmap = matches!(value.as_str(), "1" | "true" | "yes");
+        }
+
+        if let Ok(value) = env::var("SANITIZE_CONTENT") {
+            config.sanitize_content = matches!(value.as_str(), "1" | "true" | "yes");
+        }
+
+        if let Ok(value) = env::var("SEED_ENDPOINT") {
+            config.seed_endpoint = matches!(value.as_str(), "1" | "true" | "yes");
+        }
+
+        if let Ok(value) = env::var("SEARCH_ANALYZERS") {
+            // カンマ区切りで複数の言語を指定できる
+            config.search_analyzers = value.split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| name.parse().map_err(|_| ConfigError::Unsupported {
+                    name: "SEARCH_ANALYZERS",
+                    value: name.to_string(),
+                    expected: "english, japanese",
+                }))
+                .collect::<Result<_, _>>()?;
+        }
+
+        if let Some(limit) = number_var("JSON_LIMIT_BYTES")? {
+            config.json_limit = limit as usize;
+        }
+
+        if let Some(limit) = number_var("UPLOAD_LIMIT_BYTES")? {
+            config.upload_limit = limit as usize;
+        }
+
+        if let Some(max) = number_var("MAX_PER_PAGE")? {
+            config.max_per_page = max as usize;
+        }
+
+        if let Some(max) = number_var("MAX_RESULTS")? {
+            config.max_results = max as usize;
+        }
+
+        if let Some(max) = number_var("MAX_EXPORT")? {
+            config.max_export = max as usize;
+        }
+
+        if let Ok(backend) = env::var("STORAGE_BACKEND") {
+            config.storage_backend = backend;
+        }
+
+        if let Ok(path) = env::var("DATA_KEY_FILE") {
+            config.encryption_key = Some(read_key_file("DATA_KEY_FILE", Path::new(&path))?);
+        }
+
+        if let Ok(key) = env::var("DATA_KEY") {
+            config.encryption_key = Some(EncryptionKey::parse(&key)
+                .map_err(|reason| ConfigError::InvalidKey { name: "DATA_KEY", reason })?);
+        }
+
+        if let Ok(value) = env::var("INITIAL_DATA") {
+            config.initial_data = value.parse()
+                .map_err(|_| ConfigError::Unsupported { name: "INITIAL_DATA", value, expected: "demo, empty, none" })?;
+        }
+
+        if let Ok(format) = env::var("LOG_FORMAT") {
+            config.log_format = format.parse()
+                .map_err(|_| ConfigError::Unsupported { name: "LOG_FORMAT", value: format, expected: "text, json" })?;
+        }
+
+        if let Ok(dir) = env::var("LOG_DIR") {
+            config.log_dir = Some(PathBuf::from(dir));
+        }
+
+        if let Some(max_size) = number_var("LOG_MAX_SIZE_BYTES")? {
+            config.log_rotation.max_size = max_size.into();
+        }
+
+        if let Ok(level) = env::var("LOG_LEVEL") {
+            config.log_level = Some(level);
+        }
+
+        if let Ok(rotation) = env::var("LOG_ROTATION") {
+            config.log_rotation.rotation = rotation.parse()
+                .map_err(|_| ConfigError::Unsupported { name: "LOG_ROTATION", value: rotation, expected: "never, hourly, daily" })?;
+        }
+
+        if let Some(max_files) = number_var("LOG_MAX_FILES")? {
+            config.log_rotation.max_files = max_files as usize;
+        }
+
+        if let Ok(value) = env::var("LOG_BODIES") {
+            config.body_logging.enabled = matches!(value.as_str(), "1" | "true" | "yes");
+        }
+
+        if let Ok(proxies) = env::var("TRUSTED_PROXIES") {
+            config.trusted_proxies = proxies.split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        if let Ok(hosts) = env::var("EXPORT_NOTIFY_HOSTS") {
+            config.export_notify_hosts = hosts.split(',')
+                .map(str::trim)
+                .filter(|h| !h.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        // 環境変数のルールはすべてのパスに当てる
+        let list = |name: &str| -> Vec<String> {
+            env::var(name).unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        let (allow, deny) = (list("IP_ALLOW"), list("IP_DENY"));
+        if !allow.is_empty() || !deny.is_empty() {
+            config.ip_rules.push(IpRule { path: "/".to_string(), allow, deny });
+        }
+
+        // URL と base DN がそろえば、設定ファイルに [ldap] がなくても使う
+        let (url, base_dn) = (env::var("LDAP_URL").ok(), env::var("LDAP_BASE_DN").ok());
+        if config.ldap.is_none() {
+            match (&url, &base_dn) {
+                (Some(url), Some(base_dn)) => config.ldap = Some(LdapConfig::new(url, base_dn)),
+                (Some(_), None) => return Err(ConfigError::Incomplete { name: "LDAP_URL", requires: "LDAP_BASE_DN" }),
+                (None, Some(_)) => return Err(ConfigError::Incomplete { name: "LDAP_BASE_DN", requires: "LDAP_URL" }),
+                (None, None) => {}
+            }
+        }
+        if let Some(ldap) = &mut config.ldap {
+            if let Some(url) = url {
+                ldap.url = url;
+            }
+            if let Some(base_dn) = base_dn {
+                ldap.base_dn = base_dn;
+            }
+            if let Ok(dn) = env::var("LDAP_BIND_DN") {
+                ldap.bind_dn = Some(dn);
+            }
+            if let Ok(password) = env::var("LDAP_BIND_PASSWORD") {
+                ldap.bind_password = Some(password);
+            }
+        }
+
+        if let Ok(origins) = env::var("CORS_ORIGINS") {
+            config.cors_origins = origins.split(',')
+                .map(str::trim)
+                .filter(|o| !o.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        if let Ok(flags) = env::var("FEATURE_FLAGS") {
+            // `webhooks=false,export=true` のようにカンマ区切りで並べる
+            for entry in flags.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let unsupported = || ConfigError::Unsupported {
+                    name: "FEATURE_FLAGS",
+                    value: entry.to_string(),
+                    expected: "webhooks, public_read, export, import set to true or false",
+                };
+
+                let (name, value) = entry.split_once('=').ok_or_else(unsupported)?;
+                let flag = name.trim().parse().map_err(|_| unsupported())?;
+                let enabled = value.trim().parse().map_err(|_| unsupported())?;
+                config.flags.insert(flag, enabled);
+            }
+        }
+
+        if let Ok(file) = env::var("USERS_FILE") {
+            config.users_file = PathBuf::from(file);
+        }
+
+        if let Ok(file) = env::var("KEYS_FILE") {
+            config.keys_file = PathBuf::from(file);
+        }
+
+        if let Some(secs) = number_var("TOKEN_TTL_SECS")? {
+            config.token_ttl = Duration::from_secs(secs.into());
+        }
+
+        if let Ok(value) = env::var("ANONYMOUS_ACCESS") {
+            config.anonymous_access = value.parse().map_err(|_| ConfigError::Unsupported {
+                name: "ANONYMOUS_ACCESS",
+                value,
+                expected: "unrestricted, read_only, public_books",
+            })?;
+        }
+
+        if let Ok(file) = env::var("API_KEYS_FILE") {
+            config.api_keys_file = PathBuf::from(file);
+        }
+
+        if let Ok(file) = env::var("SHARES_FILE") {
+            config.shares_file = PathBuf::from(file);
+        }
+
+        if let Ok(value) = env::var("OPEN_SIGNUP") {
+            config.open_signup = matches!(value.as_str(), "1" | "true" | "yes");
+        }
+
+        if let Ok(file) = env::var("INVITES_FILE") {
+            config.invites_file = PathBuf::from(file);
+        }
+
+        if let Some(secs) = number_var("INVITE_TTL_SECS")? {
+            config.invite_ttl = Duration::from_secs(secs.into());
+        }
+
+        if let Some(min) = number_var("PASSWORD_MIN_LENGTH")? {
+            config.password_min_length = min as usize;
+        }
+
+        if let Some(max) = number_var("PASSWORD_MAX_LENGTH")? {
+            config.password_max_length = max as usize;
+        }
+
+        if let Ok(file) = env::var("PASSWORD_DENY_LIST_FILE") {
+            config.password_deny_list = Some(PathBuf::from(file));
+        }
+
+        if let Ok(value) = env::var("PWNED_CHECK") {
+            config.pwned_check = value.parse()
+                .map_err(|_| ConfigError::Unsupported { name: "PWNED_CHECK", value, expected: "off, warn, reject" })?;
+        }
+
+        if let Ok(url) = env::var("PWNED_API_URL") {
+            config.pwned_api_url = url;
+        }
+
+        if let Some(secs) = number_var("PWNED_TIMEOUT_SECS")? {
+            config.pwned_timeout = Duration::from_secs(secs.into());
+        }
+
+        if let (Ok(username), Ok(password)) = (env::var("ADMIN_USERNAME"), env::var("ADMIN_PASSWORD")) {
+            config.admin = Some((username, password));
+        }
+
+        Ok(())
+    }
+}
+
+/// `host:port` を分ける。IPv6 は `[::1]:8080` のように括弧で囲む。
+fn parse_bind(name: &'static str, value: &str) -> Result<(String, u16), ConfigError> {
+    let invalid = || ConfigError::InvalidAddress { name, value: value.to_string() };
+
+    let (host, port) = value.rsplit_once(':').ok_or_else(invalid)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = port.parse().map_err(|_| invalid())?;
+
+    if host.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok((host.to_string(), port))
+}
+
+/// base64 で 32 バイトの鍵を書いたファイルを読む。
+fn read_key_file(name: &'static str, path: &Path) -> Result<EncryptionKey, ConfigError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|source| ConfigError::Read { path: path.to_path_buf(), source })?;
+
+    EncryptionKey::parse(&contents).map_err(|reason| ConfigError::InvalidKey { name, reason })
+}
+
+fn number_var(name: &'static str) -> Result<Option<u32>, ConfigError> {
+    match env::var(name) {
+        Ok(value) => value.parse()
+            .map(Some)
+            .map_err(|_| ConfigError::InvalidNumber { name, value }),
+        Err(_) => Ok(None),
+    }
+}
+
+/// `books.toml` の内容。省略した項目は既定値 (または環境変数) のまま。
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    server: ServerSection,
+    tls: TlsSection,
+    storage: StorageSection,
+    search: SearchSection,
+    cors: CorsSection,
+    auth: AuthSection,
+    limits: LimitsSection,
+    logging: LoggingSection,
+    /// `webhooks = false` のようにフラグ名と値を並べる。
+    flags: BTreeMap<Flag, bool>,
+    /// `[tenants.<name>]` ごとにデータファイルとユーザーを分ける。
+    tenants: BTreeMap<String, TenantSection>,
+    /// `[[ip_rules]]` を並べる。
+    ip_rules: Vec<IpRule>,
+    ldap: Option<LdapConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TenantSection {
+    data_file: PathBuf,
+    users_file: PathBuf,
+    webhooks_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSection {
+    bind: Option<Bind>,
+    unix_socket: Option<PathBuf>,
+    unix_socket_mode: Option<u32>,
+    unix_socket_uid: Option<u32>,
+    unix_socket_gid: Option<u32>,
+    grpc_addr: Option<SocketAddr>,
+    compression_level: Option<u32>,
+    request_timeout_secs: Option<u64>,
+    idempotency_window_secs: Option<u64>,
+    workers: Option<usize>,
+    keep_alive_secs: Option<u64>,
+    client_timeout_secs: Option<u64>,
+    max_connections: Option<usize>,
+    trusted_proxies: Option<Vec<String>>,
+    export_notify_hosts: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TlsSection {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+}
+
+/// `bind = "host:port"` と `bind = ["host:port", ...]` のどちらでも書ける。
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Bind {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StorageSection {
+    backend: Option<String>,
+    data_file: Option<PathBuf>,
+    webhooks_file: Option<PathBuf>,
+    audit_file: Option<PathBuf>,
+    mmap: Option<bool>,
+    sanitize_content: Option<bool>,
+    seed_endpoint: Option<bool>,
+    initial_data: Option<InitialData>,
+    key_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SearchSection {
+    analyzers: Option<Vec<Language>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingSection {
+    format: Option<LogFormat>,
+    level: Option<String>,
+    dir: Option<PathBuf>,
+    max_size_bytes: Option<u64>,
+    rotation: Option<Rotation>,
+    max_files: Option<usize>,
+    log_bodies: Option<bool>,
+    log_bodies_skip_paths: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CorsSection {
+    allowed_origins: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuthSection {
+    users_file: Option<PathBuf>,
+    admin_username: Option<String>,
+    admin_password: Option<String>,
+    keys_file: Option<PathBuf>,
+    token_ttl_secs: Option<u64>,
+    anonymous_access: Option<AnonymousAccess>,
+    api_keys_file: Option<PathBuf>,
+    shares_file: Option<PathBuf>,
+    open_signup: Option<bool>,
+    invites_file: Option<PathBuf>,
+    invite_ttl_secs: Option<u64>,
+    password_min_length: Option<usize>,
+    password_max_length: Option<usize>,
+    password_deny_list_file: Option<PathBuf>,
+    pwned_check: Option<PwnedMode>,
+    pwned_api_url: Option<String>,
+    pwned_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LimitsSection {
+    rate_limit_per_ip: Option<u32>,
+    rate_limit_per_api_key: Option<u32>,
+    json_bytes: Option<usize>,
+    upload_bytes: Option<usize>,
+    max_per_page: Option<usize>,
+    max_results: Option<usize>,
+    max_export: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_file() {
+        let file: FileConfig = toml::from_str(r#"
+            [server]
+            bind = "0.0.0.0:9000"
+
+            [storage]
+            data_file = "/var/lib/books/books.json"
+
+            [search]
+            analyzers = ["english", "japanese"]
+
+            [cors]
+            allowed_origins = ["https://books.example.com"]
+
+            [flags]
+            webhooks = false
+        "#).unwrap();
+
+        let mut config = Config::new(DEFAULT_DATA_FILE);
+        config.apply_file(file).unwrap();
+
+        assert_eq!(config.bind, vec![("0.0.0.0".to_string(), 9000)]);
+        assert_eq!(config.data_file, PathBuf::from("/var/lib/books/books.json"));
+        assert_eq!(config.search_analyzers, vec![Language::English, Language::Japanese]);
+        assert_eq!(config.cors_origins, vec!["https://books.example.com"]);
+        assert_eq!(config.rate_limit_per_ip, 600);
+        assert_eq!(config.flags.get(&Flag::Webhooks), Some(&false));
+        assert!(config.validate().is_ok());
+
+        config.tls_cert = Some(PathBuf::from("cert.pem"));
+        assert!(matches!(config.validate(), Err(ConfigError::Incomplete { .. })));
+        config.tls_cert = None;
+
+        config.storage_backend = "postgres".to_string();
+        assert!(matches!(config.validate(), Err(ConfigError::Unsupported { .. })));
+
+        assert!(toml::from_str::<FileConfig>("[server]\nport = 80").is_err());
+        assert!(toml::from_str::<FileConfig>("[flags]\nenrichment = true").is_err());
+        assert_eq!(parse_bind("bind", "[::1]:8080").unwrap(), ("::1".to_string(), 8080));
+        assert!(parse_bind("bind", "localhost").is_err());
+    }
+
+    #[test]
+    fn test_bind_multiple_addresses() {
+        let file: FileConfig = toml::from_str(r#"
+            [server]
+            bind = ["127.0.0.1:8080", "[::1]:8080"]
+        "#).unwrap();
+
+        let mut config = Config::new(DEFAULT_DATA_FILE);
+        config.apply_file(file).unwrap();
+
+        assert_eq!(config.bind, vec![("127.0.0.1".to_string(), 8080), ("::1".to_string(), 8080)]);
+
+        let file: FileConfig = toml::from_str("[server]\nbind = [\"127.0.0.1\"]").unwrap();
+        assert!(matches!(config.apply_file(file), Err(ConfigError::InvalidAddress { .. })));
+
+        // Unix ソケットだけで待ち受けることもできる
+        let file: FileConfig = toml::from_str("[server]\nbind = []\nunix_socket = \"/run/books.sock\"\nunix_socket_mode = 0o660").unwrap();
+        config.apply_file(file).unwrap();
+        assert!(config.bind.is_empty());
+        assert_eq!(config.unix_socket_mode, Some(0o660));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_ip_rules() {
+        let file: FileConfig = toml::from_str(r#"
+            [[ip_rules]]
+            path = "/admin"
+            allow = ["10.8.0.0/16"]
+
+            [[ip_rules]]
+            deny = ["203.0.113.0/24"]
+        "#).unwrap();
+
+        let mut config = Config::new(DEFAULT_DATA_FILE);
+        config.apply_file(file).unwrap();
+
+        assert_eq!(config.ip_rules.len(), 2);
+        assert_eq!(config.ip_rules[1].path, "/");
+        assert!(config.validate().is_ok());
+
+        config.ip_rules[0].allow.push("10.8.0.0/40".to_string());
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidAddress { name: "ip_rules", .. })));
+    }
+
+    #[test]
+    fn test_ldap() {
+        let file: FileConfig = toml::from_str(r#"
+            [ldap]
+            url = "ldaps://dc.example.com"
+            base_dn = "dc=example,dc=com"
+            user_filter = "(&(objectClass=user)(sAMAccountName={username}))"
+            admin_groups = ["cn=Books Admins,ou=Groups,dc=example,dc=com"]
+        "#).unwrap();
+
+        let mut config = Config::new(DEFAULT_DATA_FILE);
+        config.apply_file(file).unwrap();
+
+        let ldap = config.ldap.as_mut().unwrap();
+        assert_eq!(ldap.group_attribute, "memberOf");
+        // ldaps:// には CA が要る
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidLdap(_))));
+
+        let ldap = config.ldap.as_mut().unwrap();
+        ldap.ca_file = Some(PathBuf::from("/etc/ssl/certs/ad-ca.pem"));
+        assert!(config.validate().is_ok());
+
+        let ldap = config.ldap.as_mut().unwrap();
+        ldap.user_filter = "(uid=alice)".to_string();
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidLdap(_))));
+    }
+}
@@ -0,0 +1,118 @@
+//! CORS で許可するオリジン。
+//!
+//! 完全一致 (`https://books.example.com`) と、サブドメインのワイルドカード
+//! (`https://*.example.com`) を書ける。ワイルドカードは `example.com` 自体には一致しない。
+
+use std::sync::{Arc, RwLock};
+use actix_cors::Cors;
+use log::error;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OriginPattern {
+    Exact(String),
+    /// `suffix` は `.example.com` のようにドットから始まる (ポートがあれば含む)。
+    Subdomain { scheme: String, suffix: String },
+}
+
+impl OriginPattern {
+    /// `scheme://host[:port]` か `scheme://*.host[:port]` を読む。それ以外は `None`。
+    pub fn parse(pattern: &str) -> Option<Self> {
+        let pattern = pattern.trim().trim_end_matches('/').to_ascii_lowercase();
+        let (scheme, rest) = pattern.split_once("://")?;
+
+        if scheme.is_empty() || rest.is_empty() {
+            return None;
+        }
+
+        match rest.strip_prefix("*.") {
+            Some(domain) if !domain.is_empty() && !domain.contains(['*', '/']) => Some(OriginPattern::Subdomain {
+                scheme: scheme.to_string(),
+                suffix: format!(".{}", domain),
+            }),
+            Some(_) => None,
+            None if !rest.contains(['*', '/']) => Some(OriginPattern::Exact(pattern)),
+            None => None,
+        }
+    }
+
+    pub fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginPattern::Exact(allowed) => allowed.eq_ignore_ascii_case(origin),
+            OriginPattern::Subdomain { scheme, suffix } => {
+                let origin = origin.to_ascii_lowercase();
+
+                origin.strip_prefix(scheme.as_str())
+                    .and_then(|rest| rest.strip_prefix("://"))
+                    .and_then(|host| host.strip_suffix(suffix.as_str()))
+                    .is_some_and(|sub| {
+                        !sub.is_empty() && sub.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                    })
+            }
+        }
+    }
+}
+
+/// 許可するオリジンの一覧。複製しても同じ一覧を指すので、設定の再読み込みで差し替えられる。
+#[derive(Clone, Debug, Default)]
+pub struct AllowedOrigins(Arc<RwLock<Vec<OriginPattern>>>);
+
+impl AllowedOrigins {
+    /// 読めないパターンは無視する。
+    pub fn new(origins: &[String]) -> Self {
+        let allowed = AllowedOrigins::default();
+        allowed.set(origins);
+        allowed
+    }
+
+    pub fn set(&self, origins: &[String]) {
+        *self.0.write().unwrap() = origins.iter().filter_map(|o| OriginPattern::parse(o)).collect();
+    }
+
+    pub fn matches(&self, origin: &str) -> bool {
+        self.0.read().unwrap().iter().any(|pattern| pattern.matches(origin))
+    }
+}
+
+pub fn cors(allowed_origins: AllowedOrigins) -> Cors {
+    Cors::default()
+        .allowed_origin_fn(move |origin, _req_head| {
+            // UTF-8 でないオリジンはどのパターンにも一致しないものとして扱う
+            let allowed = origin.to_str().is_ok_and(|origin| allowed_origins.matches(origin));
+
+            if !allowed {
+                error!("CORS violation: Origin {:?} is not allowed", origin);
+            }
+
+            allowed
+        })
+        .allow_any_method()
+        .allow_any_header()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origin_patterns() {
+        let exact = OriginPattern::parse("http://localhost:3000").unwrap();
+        assert!(exact.matches("http://localhost:3000"));
+        assert!(!exact.matches("http://localhost:5173"));
+
+        let wildcard = OriginPattern::parse("https://*.example.com").unwrap();
+        assert!(wildcard.matches("https://app.example.com"));
+        assert!(wildcard.matches("https://a.b.example.com"));
+        assert!(!wildcard.matches("https://example.com"));
+        assert!(!wildcard.matches("http://app.example.com"));
+        assert!(!wildcard.matches("https://app.example.com.evil.test"));
+        assert!(!wildcard.matches("https://evilexample.com"));
+
+        let with_port = OriginPattern::parse("https://*.example.com:8443").unwrap();
+        assert!(with_port.matches("https://app.example.com:8443"));
+        assert!(!with_port.matches("https://app.example.com"));
+
+        assert!(OriginPattern::parse("example.com").is_none());
+        assert!(OriginPattern::parse("https://app.*.com").is_none());
+        assert!(OriginPattern::parse("https://*.").is_none());
+    }
+}
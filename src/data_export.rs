@@ -0,0 +1,440 @@
+//! 利用者本人のデータの書き出し (GDPR のデータポータビリティ)。
+//!
+//! `POST /users/me/export` で書き出しを始め、裏で作り終えたら `GET /users/me/export` で
+//! JSON のファイルとしてダウンロードできる。`notify_url` を渡すと、できあがったときにその URL へ
+//! 知らせる。書き出したデータはメモリにだけ置き、`EXPORT_TTL` を過ぎたら捨てる。
+//!
+//! 誰でも登録できる構成でサーバーから内部のネットワークへ送らせないように、`notify_url` のホストは
+//! `export_notify_hosts` にあるものに限る。送る直前に名前を引き、プライベートやリンクローカルなどの
+//! アドレスになれば送らない。引いたアドレスに固定して送り、リダイレクトはたどらない。
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use actix_web::http::header;
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::audit::{Audit, AuditEntry, AuditQuery};
+use crate::auth::{authenticate, load_users_from, users_file_for};
+use crate::share::{Share, Shares};
+use crate::storage::BookRepository;
+use crate::tenant::Tenant;
+use crate::{AppState, Book, BookError, Role};
+
+/// できあがった書き出しを残しておく時間。
+pub const EXPORT_TTL: Duration = Duration::from_secs(3600);
+
+/// 作っている間、`GET` に返す `Retry-After` (秒)。
+const RETRY_AFTER: u64 = 5;
+
+/// 知らせの送信を待つ時間。
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const EXPORT_PATH: &str = "/users/me/export";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+/// 書き出しの状態。`GET /users/me/export` で作っている途中のときと、知らせの本文に使う。
+#[derive(Clone, Debug, Serialize)]
+pub struct ExportJob {
+    pub status: ExportStatus,
+    pub requested_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    archive: Option<web::Bytes>,
+}
+
+#[derive(Serialize)]
+struct Account {
+    username: String,
+    role: Role,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+}
+
+/// 書き出すデータ。
+#[derive(Serialize)]
+struct PersonalData {
+    generated_at: i64,
+    account: Account,
+    /// 借りている本
+    loans: Vec<Book>,
+    /// 発行した共有リンク
+    shares: Vec<Share>,
+    /// 監査ログに残っている本人の操作
+    activity: Vec<AuditEntry>,
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// 書き出しの受付簿。テナントとユーザーごとに 1 件だけ持つ。
+#[derive(Default)]
+pub struct DataExports {
+    jobs: Mutex<HashMap<(Option<String>, String), ExportJob>>,
+    /// `notify_url` に使ってよいホスト名
+    notify_hosts: Vec<String>,
+}
+
+impl DataExports {
+    pub fn new(notify_hosts: Vec<String>) -> Self {
+        DataExports { notify_hosts, ..Default::default() }
+    }
+
+    /// `notify_url` を確かめる。http(s) でないか、ホストが許可されていなければ 400。
+    fn check_notify_url(&self, url: &str) -> Result<reqwest::Url, BookError> {
+        let url = reqwest::Url::parse(url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or_else(|| BookError::BadRequest("notify_url must be an http(s) URL".to_string()))?;
+
+        let allowed = url.host_str()
+            .is_some_and(|host| self.notify_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host)));
+        if !allowed {
+            return Err(BookError::BadRequest("notify_url: host is not allowed".to_string()));
+        }
+
+        Ok(url)
+    }
+
+    /// 期限内の書き出し。
+    fn get(&self, key: &(Option<String>, String)) -> Option<ExportJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let now = now();
+        jobs.retain(|_, job| job.expires_at.is_none_or(|expires_at| expires_at > now));
+
+        jobs.get(key).cloned()
+    }
+
+    /// 作っている途中でなければ受け付ける。受け付けたら true。
+    fn start(&self, key: (Option<String>, String)) -> (bool, ExportJob) {
+        let mut jobs = self.jobs.lock().unwrap();
+
+        if let Some(job) = jobs.get(&key).filter(|job| job.status == ExportStatus::Pending) {
+            return (false, job.clone());
+        }
+
+        let job = ExportJob {
+            status: ExportStatus::Pending,
+            requested_at: now(),
+            finished_at: None,
+            expires_at: None,
+            error: None,
+            archive: None,
+        };
+        jobs.insert(key, job.clone());
+        (true, job)
+    }
+
+    /// 書き出しを捨てる。ユーザーを消去したときに使う。
+    pub(crate) fn discard(&self, tenant: Option<&str>, username: &str) {
+        self.jobs.lock().unwrap().remove(&(tenant.map(str::to_string), username.to_string()));
+    }
+
+    fn finish(&self, key: &(Option<String>, String), result: Result<web::Bytes, BookError>) -> Option<ExportJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(key)?;
+
+        let now = now();
+        job.finished_at = Some(now);
+        job.expires_at = Some(now + EXPORT_TTL.as_secs() as i64);
+        match result {
+            Ok(archive) => {
+                job.status = ExportStatus::Ready;
+                job.archive = Some(archive);
+            }
+            Err(e) => {
+                job.status = ExportStatus::Failed;
+                job.error = Some(e.to_string());
+            }
+        }
+
+        Some(job.clone())
+    }
+}
+
+struct Sources {
+    repository: BookRepository,
+    users_file: PathBuf,
+    shares: Option<web::Data<Shares>>,
+    audit: Option<web::Data<Audit>>,
+}
+
+fn collect(sources: &Sources, tenant: Option<&str>, username: &str) -> Result<web::Bytes, BookError> {
+    let user = load_users_from(&sources.users_file)
+        .into_iter()
+        .find(|u| u.username == username)
+        .ok_or(BookError::NotFound)?;
+
+    let loans = sources.repository.list()?
+        .into_iter()
+        .filter(|book| book.loan.as_ref().is_some_and(|loan| loan.borrower == username))
+        .collect();
+
+    let shares = sources.shares.as_ref()
+        .map(|shares| shares.created_by(username, tenant))
+        .unwrap_or_default();
+
+    let activity = match &sources.audit {
+        Some(audit) => audit.query(tenant, &AuditQuery { actor: Some(username.to_string()), ..Default::default() })?,
+        None => Vec::new(),
+    };
+
+    let data = PersonalData {
+        generated_at: now(),
+        account: Account { username: user.username, role: user.role, tenant: tenant.map(str::to_string) },
+        loans,
+        shares,
+        activity,
+    };
+
+    Ok(web::Bytes::from(serde_json::to_vec_pretty(&data)?))
+}
+
+#[derive(Serialize)]
+struct Notification<'a> {
+    event: &'static str,
+    username: &'a str,
+    download: &'a str,
+    #[serde(flatten)]
+    job: &'a ExportJob,
+}
+
+/// 外へ送ってよいアドレスか。ループバック、プライベート、リンクローカル (クラウドのメタデータを含む) などは送らない。
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // 100.64.0.0/10 (CGNAT)
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public(IpAddr::V4(v4)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // fc00::/7 (ユニークローカル) と fe80::/10 (リンクローカル)
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// `url` のホストを引き、外へ送ってよいアドレスだけなら最初のものを返す。
+async fn resolve_public(url: &reqwest::Url) -> Result<SocketAddr, String> {
+    let host = url.host_str().ok_or("no host")?;
+    let port = url.port_or_known_default().ok_or("no port")?;
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.trim_matches(['[', ']']), port))
+        .await
+        .map_err(|e| e.to_string())?
+        .collect();
+
+    match addrs.iter().find(|addr| !is_public(addr.ip())) {
+        Some(addr) => Err(format!("{} resolves to a non-public address {}", host, addr.ip())),
+        None => addrs.first().copied().ok_or_else(|| format!("{} has no addresses", host)),
+    }
+}
+
+async fn notify(url: &reqwest::Url, username: &str, download: &str, job: &ExportJob) {
+    let event = match job.status {
+        ExportStatus::Ready => "export.ready",
+        _ => "export.failed",
+    };
+    let addr = match resolve_public(url).await {
+        Ok(addr) => addr,
+        Err(e) => {
+            log::warn!("Did not notify {} that the export for {} is done: {}", url, username, e);
+            return;
+        }
+    };
+
+    // 名前を引き直して別のアドレスへ送らないように、確かめたアドレスに固定する
+    let client = reqwest::Client::builder()
+        .resolve(url.host_str().unwrap_or_default(), addr)
+        .redirect(reqwest::redirect::Policy::none())
+        .build();
+    let result = match client {
+        Ok(client) => client
+            .post(url.clone())
+            .timeout(NOTIFY_TIMEOUT)
+            .json(&Notification { event, username, download, job })
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status()),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        log::warn!("Failed to notify {} that the export for {} is done: {}", url, username, e);
+    }
+}
+
+#[derive(Default, Deserialize)]
+pub struct ExportRequest {
+    /// できあがったら JSON を POST する http(s) の URL。ホストは `export_notify_hosts` にあるものだけ
+    notify_url: Option<String>,
+}
+
+/// 自分のデータの書き出しを始める。作っている途中なら、その状態を返す。
+#[post("/users/me/export")]
+pub async fn start_export(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    exports: Option<web::Data<DataExports>>,
+    body: Option<web::Json<ExportRequest>>,
+) -> Result<impl Responder, BookError> {
+    let exports = exports.ok_or(BookError::NotFound)?;
+    let user = authenticate(&req)?;
+    let notify_url = body.map(|body| body.into_inner()).unwrap_or_default().notify_url
+        .map(|url| exports.check_notify_url(&url))
+        .transpose()?;
+
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+    let key = (tenant, user.username.clone());
+    let location = format!("{}{}", crate::tenant::path_prefix(&req), EXPORT_PATH);
+    let download = crate::proxy::absolute_url(&req, &location);
+
+    let (started, job) = exports.start(key.clone());
+    if started {
+        let sources = Sources {
+            repository: data.repository.clone(),
+            users_file: users_file_for(&req),
+            shares: req.app_data::<web::Data<Shares>>().cloned(),
+            audit: req.app_data::<web::Data<Audit>>().cloned(),
+        };
+        let exports = exports.clone();
+        log::info!("Data export requested by {}", user.username);
+
+        actix_web::rt::spawn(async move {
+            let (tenant, username) = key.clone();
+            let result = web::block(move || collect(&sources, tenant.as_deref(), &username))
+                .await
+                .map_err(BookError::from)
+                .and_then(|result| result);
+            if let Err(e) = &result {
+                log::error!("Data export for {} failed: {}", key.1, e);
+            }
+
+            let finished = exports.finish(&key, result);
+            if let (Some(url), Some(job)) = (notify_url, finished) {
+                notify(&url, &key.1, &download, &job).await;
+            }
+        });
+    }
+
+    Ok(HttpResponse::Accepted()
+        .insert_header((header::LOCATION, location))
+        .json(job))
+}
+
+/// できあがった書き出しをダウンロードする。作っている途中なら 202 と状態を返す。
+#[get("/users/me/export")]
+pub async fn download_export(
+    req: HttpRequest,
+    exports: Option<web::Data<DataExports>>,
+) -> Result<impl Responder, BookError> {
+    let exports = exports.ok_or(BookError::NotFound)?;
+    let user = authenticate(&req)?;
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+
+    let job = exports.get(&(tenant, user.username.clone())).ok_or(BookError::NotFound)?;
+
+    Ok(match (job.status, &job.archive) {
+        (ExportStatus::Ready, Some(archive)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"books-export-{}.json\"", sanitize(&user.username)),
+            ))
+            .body(archive.clone()),
+        (ExportStatus::Pending, _) => HttpResponse::Accepted()
+            .insert_header((header::RETRY_AFTER, RETRY_AFTER.to_string()))
+            .json(job),
+        _ => HttpResponse::InternalServerError().json(job),
+    })
+}
+
+/// ファイル名に使えない文字を `_` にする。
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_and_finish() {
+        let exports = DataExports::default();
+        let key = (None, "alice".to_string());
+
+        assert!(exports.start(key.clone()).0);
+        assert!(!exports.start(key.clone()).0);
+        assert_eq!(exports.get(&key).unwrap().status, ExportStatus::Pending);
+
+        let job = exports.finish(&key, Ok(web::Bytes::from_static(b"{}"))).unwrap();
+        assert_eq!(job.status, ExportStatus::Ready);
+        assert!(job.expires_at.is_some());
+        assert!(exports.get(&(Some("acme".to_string()), "alice".to_string())).is_none());
+
+        // できあがった後はもう一度始められる
+        assert!(exports.start(key).0);
+    }
+
+    #[actix_rt::test]
+    async fn test_notify_url() {
+        let exports = DataExports::new(vec!["hooks.example.com".to_string(), "127.0.0.1".to_string()]);
+        assert!(exports.check_notify_url("https://HOOKS.example.com/export").is_ok());
+        assert!(exports.check_notify_url("ftp://hooks.example.com/").is_err());
+        assert!(exports.check_notify_url("http://169.254.169.254/latest/meta-data").is_err());
+        assert!(exports.check_notify_url("http://hooks.example.com.evil.test/").is_err());
+        assert!(DataExports::default().check_notify_url("https://hooks.example.com/").is_err());
+
+        // 許可したホストでも、内部のアドレスへは送らない
+        let url = exports.check_notify_url("http://127.0.0.1:8080/").unwrap();
+        assert!(resolve_public(&url).await.is_err());
+
+        let ip = |value: &str| value.parse::<IpAddr>().unwrap();
+        assert!(is_public(ip("93.184.216.34")));
+        assert!(is_public(ip("2606:4700::1111")));
+        for private in ["10.1.2.3", "192.168.0.1", "172.16.5.5", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public(ip(private)), "{}", private);
+        }
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("alice"), "alice");
+        assert_eq!(sanitize("key:site build"), "key_site_build");
+        assert_eq!(sanitize("a\"/b"), "a__b");
+    }
+}
@@ -0,0 +1,138 @@
+//! ユーザーの消去 (忘れられる権利)。
+//!
+//! `POST /admin/erasure` でユーザーのアカウントを消し、そのユーザーに結び付いたデータを消すか
+//! 匿名にする。貸出は返却扱いにし、発行した共有リンクは取り消し、監査ログ・招待・API キーに残る
+//! 名前は `[erased]` に置き換える。`dry_run` を付けると何も変えずに、影響する範囲だけを返す。
+//!
+//! 消去したことは、ランダムな ID を添えた監査ログの記録 (墓標) として残す。ユーザー名のハッシュは
+//! 辞書で簡単に戻せるので、名前から作った値は残さない。
+
+use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use rand::rngs::OsRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::api_keys::ApiKeys;
+use crate::audit::{self, Audit};
+use crate::auth::{load_users_from, remove_user_from, users_file_for, AdminUser};
+use crate::data_export::DataExports;
+use crate::handlers::block;
+use crate::invites::Invites;
+use crate::share::Shares;
+use crate::tenant::Tenant;
+use crate::{AppState, BookError};
+
+#[derive(Deserialize)]
+pub struct ErasureRequest {
+    username: String,
+    /// true なら何も変えずに影響の範囲を返す
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// 消去したもの (`dry_run` では消去するもの)。
+#[derive(Debug, Default, Serialize)]
+pub struct ErasureReport {
+    pub dry_run: bool,
+    /// 墓標に残すランダムな ID (16 進)。ユーザー名とは結び付かない。
+    pub tombstone: String,
+    /// 返却扱いにする貸出の本の ID
+    pub loans: Vec<u32>,
+    pub shares: usize,
+    pub audit_entries: usize,
+    /// 発行者の名前を消す招待
+    pub invites: usize,
+    /// 発行者の名前を消す API キー
+    pub api_keys: usize,
+}
+
+/// ユーザーを消去する。自分自身は消せない。
+#[post("/admin/erasure")]
+pub async fn erase_user(
+    req: HttpRequest,
+    admin: AdminUser,
+    data: web::Data<AppState>,
+    body: web::Json<ErasureRequest>,
+) -> Result<impl Responder, BookError> {
+    let ErasureRequest { username, dry_run } = body.into_inner();
+    if username == admin.0.username {
+        return Err(BookError::BadRequest("cannot erase the signed-in user".to_string()));
+    }
+
+    let users_file = users_file_for(&req);
+    let exists = {
+        let users_file = users_file.clone();
+        let username = username.clone();
+        web::block(move || load_users_from(&users_file).iter().any(|u| u.username == username)).await?
+    };
+    if !exists {
+        return Err(BookError::NotFound);
+    }
+
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+    let shares = req.app_data::<web::Data<Shares>>().cloned();
+    let audit = req.app_data::<web::Data<Audit>>().cloned();
+    let invites = req.app_data::<web::Data<Invites>>();
+    let api_keys = req.app_data::<web::Data<ApiKeys>>();
+
+    let mut report = ErasureReport {
+        dry_run,
+        tombstone: format!("{:032x}", OsRng.gen::<u128>()),
+        ..Default::default()
+    };
+
+    if dry_run {
+        let name = username.clone();
+        let books = block(&data.repository, |r| r.list()).await?;
+        report.loans = books.iter()
+            .filter(|book| book.loan.as_ref().is_some_and(|loan| loan.borrower == name))
+            .map(|book| book.id)
+            .collect();
+        if let Some(invites) = invites {
+            report.invites = invites.erase_creator(tenant.as_deref(), &username, false)?;
+        }
+        if let Some(api_keys) = api_keys {
+            report.api_keys = api_keys.erase_creator(tenant.as_deref(), &username, false)?;
+        }
+        report.shares = shares.map(|s| s.created_by(&username, tenant.as_deref()).len()).unwrap_or_default();
+        if let Some(audit) = audit {
+            report.audit_entries = web::block(move || audit.count_mentions(tenant.as_deref(), &username)).await??;
+        }
+
+        return Ok(HttpResponse::Ok().json(report));
+    }
+
+    // アカウントを先に消し、以後そのユーザーとして認証できないようにする。
+    // 消せなければほかのデータには触れずにエラーを返す
+    {
+        let username = username.clone();
+        web::block(move || remove_user_from(&users_file, &username)).await??;
+    }
+
+    let name = username.clone();
+    report.loans = block(&data.repository, move |r| r.return_loans(&name)).await?;
+    if let Some(invites) = invites {
+        report.invites = invites.erase_creator(tenant.as_deref(), &username, true)?;
+    }
+    if let Some(api_keys) = api_keys {
+        report.api_keys = api_keys.erase_creator(tenant.as_deref(), &username, true)?;
+    }
+    if let Some(shares) = shares {
+        report.shares = shares.revoke_created_by(&username, tenant.as_deref())?;
+    }
+    if let Some(audit) = audit {
+        let (tenant, username) = (tenant.clone(), username.clone());
+        report.audit_entries = web::block(move || audit.anonymize(tenant.as_deref(), &username)).await??;
+    }
+    if let Some(exports) = req.app_data::<web::Data<DataExports>>() {
+        exports.discard(tenant.as_deref(), &username);
+    }
+
+    audit::note(&req, audit::Change::summary(format!(
+        "erased user tombstone:{} ({} loans, {} shares, {} audit entries, {} invites, {} API keys)",
+        report.tombstone, report.loans.len(), report.shares, report.audit_entries, report.invites, report.api_keys,
+    )));
+    log::warn!("User tombstone:{} erased by {}", report.tombstone, admin.0.username);
+
+    Ok(HttpResponse::Ok().json(report))
+}
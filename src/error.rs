@@ -0,0 +1,103 @@
+use actix_web::HttpResponse;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BookError {
+    #[error("Failed to read JSON file")]
+    FileReadError(#[from] std::io::Error),
+
+    #[error("Failed to parse JSON")]
+    JsonParseError(#[from] serde_json::Error),
+
+    #[error("Authentication required")]
+    Unauthorized,
+
+    #[error("Permission denied")]
+    Forbidden,
+
+    #[error("Not found")]
+    NotFound,
+
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
+    #[error("Not acceptable")]
+    NotAcceptable,
+
+    #[error("Failed to serialize response: {0}")]
+    Serialize(String),
+
+    #[error("Payload too large (limit {0} bytes)")]
+    PayloadTooLarge(usize),
+
+    #[error("Too many requests, retry after {0}s")]
+    RateLimited(u64),
+
+    #[error("Service is in maintenance mode, retry after {0}s")]
+    Maintenance(u64),
+
+    #[error("Feature is disabled: {0}")]
+    FeatureDisabled(crate::flags::Flag),
+
+    #[error("Request timed out")]
+    Timeout,
+
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Gone: {0}")]
+    Gone(String),
+
+    #[error("Invalid regular expression: {0}")]
+    InvalidPattern(String),
+
+    #[error("Password does not meet the policy")]
+    WeakPassword(Vec<crate::password::Violation>),
+
+    #[error("Blocking task was cancelled")]
+    Blocking(#[from] actix_web::error::BlockingError),
+}
+
+impl actix_web::ResponseError for BookError {
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        match self {
+            BookError::FileReadError(_) => HttpResponse::InternalServerError().body("Failed to read JSON"),
+            BookError::JsonParseError(_) => HttpResponse::InternalServerError().body("Failed to parse JSON"),
+            BookError::Unauthorized => HttpResponse::Unauthorized()
+                .insert_header(("WWW-Authenticate", "Basic realm=\"books\""))
+                .body("Authentication required"),
+            BookError::Forbidden => HttpResponse::Forbidden().body("Permission denied"),
+            BookError::NotFound => HttpResponse::NotFound().body("Not found"),
+            BookError::BadRequest(message) => HttpResponse::BadRequest().body(message.clone()),
+            BookError::NotAcceptable => HttpResponse::NotAcceptable()
+                .body("Supported formats: application/json, application/vnd.api+json, text/csv, application/xml, application/msgpack"),
+            BookError::Serialize(_) => HttpResponse::InternalServerError().body("Failed to serialize response"),
+            BookError::PayloadTooLarge(limit) => HttpResponse::PayloadTooLarge()
+                .body(format!("Payload too large: the limit is {} bytes", limit)),
+            BookError::RateLimited(retry_after) => HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .body("Too many requests"),
+            BookError::Maintenance(retry_after) => HttpResponse::ServiceUnavailable()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .body("Service is in maintenance mode, only reads are available"),
+            BookError::FeatureDisabled(flag) => HttpResponse::NotFound()
+                .body(format!("This feature is disabled: {}", flag)),
+            BookError::Timeout => HttpResponse::GatewayTimeout().body("Request timed out"),
+            BookError::Encryption(_) => HttpResponse::InternalServerError().body("Failed to read the data file"),
+            BookError::Conflict(message) => HttpResponse::Conflict().body(message.clone()),
+            BookError::Gone(message) => HttpResponse::Gone().body(message.clone()),
+            BookError::WeakPassword(violations) => HttpResponse::UnprocessableEntity().json(serde_json::json!({
+                "error": "Password does not meet the policy",
+                "violations": violations,
+            })),
+            BookError::InvalidPattern(message) => HttpResponse::UnprocessableEntity().json(serde_json::json!({
+                "error": "Invalid regular expression",
+                "message": message,
+            })),
+            BookError::Blocking(_) => HttpResponse::InternalServerError().body("Blocking task was cancelled"),
+        }
+    }
+}
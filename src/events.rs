@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::Visibility;
+
+const EVENT_CAPACITY: usize = 256;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BookEventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl BookEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BookEventKind::Created => "created",
+            BookEventKind::Updated => "updated",
+            BookEventKind::Deleted => "deleted",
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BookEvent {
+    pub seq: u64,
+    pub kind: BookEventKind,
+    pub id: u32,
+    pub title: String,
+    /// 発行したときの本の見せ方。認証なしの購読者に送るかをストレージを読まずに決める
+    #[serde(skip)]
+    pub visibility: Option<Visibility>,
+}
+
+struct EventHistory {
+    next_seq: u64,
+    recent: VecDeque<BookEvent>,
+}
+
+/// 変更イベントの配信口。再接続したクライアント向けに直近のイベントも保持する。
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<BookEvent>,
+    history: Arc<Mutex<EventHistory>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CAPACITY);
+
+        EventBus {
+            sender,
+            history: Arc::new(Mutex::new(EventHistory {
+                next_seq: 1,
+                recent: VecDeque::with_capacity(EVENT_CAPACITY),
+            })),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BookEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish(&self, kind: BookEventKind, id: u32, title: &str, visibility: Option<Visibility>) {
+        // 採番・履歴への追加・送信を同じロック内で行い、順序を保証する
+        let mut history = self.history.lock().unwrap();
+
+        let event = BookEvent {
+            seq: history.next_seq,
+            kind,
+            id,
+            title: title.to_string(),
+            visibility,
+        };
+        history.next_seq += 1;
+
+        if history.recent.len() == EVENT_CAPACITY {
+            history.recent.pop_front();
+        }
+        history.recent.push_back(event.clone());
+
+        // 購読者がいない場合の送信エラーは無視してよい
+        let _ = self.sender.send(event);
+    }
+
+    /// `seq` より後に発行されたイベントを返す。
+    ///
+    /// `seq` がまだ発行していない番号 (再起動で番号が戻った) のときや、その後のイベントが履歴から
+    /// 押し出されているときは取りこぼしなく返せないので、`Replay::Reset` にする。
+    pub fn since(&self, seq: u64) -> Replay {
+        let history = self.history.lock().unwrap();
+        let last_seq = history.next_seq - 1;
+        let oldest = history.recent.front().map_or(history.next_seq, |e| e.seq);
+
+        if seq > last_seq || seq + 1 < oldest {
+            return Replay::Reset { last_seq };
+        }
+
+        Replay::Events(history.recent.iter().filter(|e| e.seq > seq).cloned().collect())
+    }
+}
+
+/// 再接続したクライアントに送り直すもの。
+#[derive(Debug)]
+pub enum Replay {
+    Events(Vec<BookEvent>),
+    /// 送り直せない。クライアントには取り直してもらい、`last_seq` より後から送る。
+    Reset { last_seq: u64 },
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_since_replays_newer_events() {
+        let bus = EventBus::new();
+
+        bus.publish(BookEventKind::Created, 1, "Rust Basics", None);
+        bus.publish(BookEventKind::Updated, 1, "Rust Basics", None);
+        bus.publish(BookEventKind::Deleted, 1, "Rust Basics", None);
+
+        let Replay::Events(replayed) = bus.since(1) else { panic!("expected events") };
+        assert_eq!(replayed.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![2, 3]);
+
+        assert!(matches!(bus.since(3), Replay::Events(events) if events.is_empty()));
+    }
+
+    #[test]
+    fn test_since_resets_unknown_or_dropped_seq() {
+        let bus = EventBus::new();
+        assert!(matches!(bus.since(0), Replay::Events(events) if events.is_empty()));
+        // 再起動前の番号
+        assert!(matches!(bus.since(500), Replay::Reset { last_seq: 0 }));
+
+        for id in 0..EVENT_CAPACITY as u32 + 10 {
+            bus.publish(BookEventKind::Created, id, "Rust Basics", None);
+        }
+        let last_seq = EVENT_CAPACITY as u64 + 10;
+        assert!(matches!(bus.since(5), Replay::Reset { last_seq: seq } if seq == last_seq));
+        assert!(matches!(bus.since(10), Replay::Events(events) if events.len() == EVENT_CAPACITY));
+    }
+}
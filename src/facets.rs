@@ -0,0 +1,136 @@
+//! 検索結果の絞り込み用の集計 (ファセット)。
+//!
+//! `/books/search?facets=true` で、ページに切り出す前の一致した書籍全体について、タグ・貸出状況・
+//! 著者・出版年・形式ごとの件数を結果と一緒に返す。フロントエンドが絞り込みのサイドバーを出すのに、
+//! 値ごとに検索し直さなくて済むようにするため。
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use serde::{Deserialize, Serialize};
+use time::Date;
+
+use crate::Book;
+
+/// 1 つのファセットに返す値の数の上限。件数の多い順に残す。
+const MAX_VALUES: usize = 50;
+
+#[derive(Deserialize, Default)]
+pub struct FacetOptions {
+    /// 検索結果と一緒にファセットを返すか
+    #[serde(default)]
+    pub facets: bool,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FacetCount<T> {
+    pub value: T,
+    pub count: usize,
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Facets {
+    pub tags: Vec<FacetCount<String>>,
+    /// `available` / `on_loan` / `overdue`
+    pub status: Vec<FacetCount<&'static str>>,
+    pub authors: Vec<FacetCount<String>>,
+    /// 出版年の新しい順。出版年のない書籍は数えない。
+    pub years: Vec<FacetCount<i32>>,
+    /// 持っている形式。複数の形式を持つ書籍はそれぞれで数える。
+    pub formats: Vec<FacetCount<&'static str>>,
+}
+
+/// 貸出状況。`today` を過ぎても返されていなければ延滞。
+pub fn status(book: &Book, today: Date) -> &'static str {
+    match &book.loan {
+        None => "available",
+        Some(loan) if loan.due < today => "overdue",
+        Some(_) => "on_loan",
+    }
+}
+
+/// 件数の多い順 (同数なら値の順) に並べる。
+fn ranked<T: Ord + Hash>(counts: HashMap<T, usize>) -> Vec<FacetCount<T>> {
+    let mut counts: Vec<FacetCount<T>> = counts.into_iter()
+        .map(|(value, count)| FacetCount { value, count })
+        .collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    counts.truncate(MAX_VALUES);
+    counts
+}
+
+impl Facets {
+    pub fn count(books: &[Book], today: Date) -> Self {
+        let mut tags = HashMap::new();
+        let mut status_counts = HashMap::new();
+        let mut authors = HashMap::new();
+        let mut years = HashMap::new();
+        let mut formats = HashMap::new();
+
+        for book in books {
+            for tag in &book.tags {
+                *tags.entry(tag.clone()).or_insert(0) += 1;
+            }
+            *status_counts.entry(status(book, today)).or_insert(0) += 1;
+            for author in &book.authors {
+                *authors.entry(author.clone()).or_insert(0) += 1;
+            }
+            if let Some(year) = book.published_year {
+                *years.entry(year).or_insert(0) += 1;
+            }
+            for format in &book.formats {
+                *formats.entry(format.as_str()).or_insert(0) += 1;
+            }
+        }
+
+        let mut years = ranked(years);
+        years.sort_by_key(|c| std::cmp::Reverse(c.value));
+
+        Facets {
+            tags: ranked(tags),
+            status: ranked(status_counts),
+            authors: ranked(authors),
+            years,
+            formats: ranked(formats),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    use crate::{Format, Loan};
+
+    #[test]
+    fn test_count() {
+        let book = |id, tags: &[&str], year, due: Option<Date>| Book {
+            id,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            authors: vec!["Ann".to_string()],
+            published_year: year,
+            loan: due.map(|due| Loan { borrower: "bob".to_string(), due }),
+            ..Default::default()
+        };
+        let books = [
+            book(1, &["rust", "async"], Some(2020), None),
+            book(2, &["rust"], Some(2021), Some(date!(2024 - 01 - 01))),
+            Book { formats: vec![Format::Paperback, Format::Ebook], ..book(3, &["go"], None, Some(date!(2024 - 03 - 01))) },
+        ];
+
+        let facets = Facets::count(&books, date!(2024 - 02 - 01));
+        assert_eq!(facets.tags, vec![
+            FacetCount { value: "rust".to_string(), count: 2 },
+            FacetCount { value: "async".to_string(), count: 1 },
+            FacetCount { value: "go".to_string(), count: 1 },
+        ]);
+        assert_eq!(facets.status, vec![
+            FacetCount { value: "available", count: 1 },
+            FacetCount { value: "on_loan", count: 1 },
+            FacetCount { value: "overdue", count: 1 },
+        ]);
+        assert_eq!(facets.authors, vec![FacetCount { value: "Ann".to_string(), count: 3 }]);
+        assert_eq!(facets.years, vec![FacetCount { value: 2021, count: 1 }, FacetCount { value: 2020, count: 1 }]);
+        assert_eq!(facets.formats, vec![FacetCount { value: "ebook", count: 1 }, FacetCount { value: "paperback", count: 1 }]);
+    }
+}
@@ -0,0 +1,148 @@
+//! 実行中に切り替えられる機能フラグ。
+//!
+//! 初期値は設定ファイルの `[flags]` と `FEATURE_FLAGS` から読み、`/admin/flags` で確認・変更できる。
+//! 変更は再起動するか設定を再読み込みすると、設定の値に戻る。
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, ResponseError};
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, BookError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Flag {
+    /// 書籍の変更を登録済みの URL に配信する。
+    Webhooks,
+    /// 認証なしで書籍を読める。無効にすると読み取りにもログインが要る。
+    PublicRead,
+    /// `/export/*` と `/calendar.ics`。
+    Export,
+    /// `/import/*`。
+    Import,
+}
+
+impl Flag {
+    pub const ALL: [Flag; 4] = [Flag::Webhooks, Flag::PublicRead, Flag::Export, Flag::Import];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Flag::Webhooks => "webhooks",
+            Flag::PublicRead => "public_read",
+            Flag::Export => "export",
+            Flag::Import => "import",
+        }
+    }
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Flag {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Flag::ALL.into_iter().find(|flag| flag.as_str() == s).ok_or(())
+    }
+}
+
+/// 各フラグの現在の値。複製しても同じ状態を指す。既定ではすべて有効。
+#[derive(Clone, Debug)]
+pub struct Flags(Arc<[AtomicBool; Flag::ALL.len()]>);
+
+impl Default for Flags {
+    fn default() -> Self {
+        Flags(Arc::new(Flag::ALL.map(|_| AtomicBool::new(true))))
+    }
+}
+
+impl Flags {
+    /// 既定値に `overrides` を重ねる。
+    pub fn new(overrides: impl IntoIterator<Item = (Flag, bool)>) -> Self {
+        let flags = Flags::default();
+        flags.reset(overrides);
+        flags
+    }
+
+    /// すべて既定値に戻してから `overrides` を重ねる。設定の再読み込みで使う。
+    pub fn reset(&self, overrides: impl IntoIterator<Item = (Flag, bool)>) {
+        for flag in Flag::ALL {
+            self.set(flag, true);
+        }
+        for (flag, enabled) in overrides {
+            self.set(flag, enabled);
+        }
+    }
+
+    pub fn is_enabled(&self, flag: Flag) -> bool {
+        self.0[flag as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, flag: Flag, enabled: bool) {
+        self.0[flag as usize].store(enabled, Ordering::Relaxed);
+    }
+
+    /// 無効なら 404 で断るエラーを返す。
+    pub fn check(&self, flag: Flag) -> Result<(), BookError> {
+        if self.is_enabled(flag) { Ok(()) } else { Err(BookError::FeatureDisabled(flag)) }
+    }
+
+    /// すべてのフラグと値。
+    pub fn snapshot(&self) -> Vec<(Flag, bool)> {
+        Flag::ALL.into_iter().map(|flag| (flag, self.is_enabled(flag))).collect()
+    }
+}
+
+/// `public_read` を無効にしても認証なしで使えるパス。共有リンク (`/shared/...`) もこれに加わる。
+const PUBLIC_PATHS: [&str; 5] = ["/", "/healthz", "/readyz", "/version", "/metrics"];
+
+/// `middleware::from_fn` に渡すミドルウェア。`public_read` が無効なら読み取りにも認証を求める。
+/// 書き込みや管理画面はそれぞれのハンドラーが認証する。
+pub async fn require_read_auth(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let public = req.app_data::<web::Data<AppState>>()
+        .is_none_or(|state| state.flags().is_enabled(Flag::PublicRead));
+
+    let open_path = PUBLIC_PATHS.contains(&req.path()) || req.path().starts_with(crate::share::SHARED_PATH);
+
+    if !public && matches!(*req.method(), Method::GET | Method::HEAD) && !open_path {
+        if let Err(e) = crate::auth::authenticate_async(req.request()).await {
+            let resp = e.error_response();
+            return Ok(req.into_response(resp));
+        }
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let flags = Flags::new([(Flag::Export, false)]);
+        let shared = flags.clone();
+
+        assert!(flags.is_enabled(Flag::Webhooks));
+        assert!(matches!(flags.check(Flag::Export), Err(BookError::FeatureDisabled(Flag::Export))));
+
+        shared.set(Flag::Export, true);
+        assert!(flags.check(Flag::Export).is_ok());
+
+        assert_eq!("public_read".parse(), Ok(Flag::PublicRead));
+        assert!("enrichment".parse::<Flag>().is_err());
+    }
+}
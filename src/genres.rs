@@ -0,0 +1,199 @@
+//! 管理者が決めるジャンルの一覧。
+//!
+//! 自由に付けられるタグと違い、書籍の `genres` には一覧にあるジャンルしか付けられない
+//! (ないものを付けようとすると 400)。比べるときは `crate::normalize` で正規化し、保存するときは
+//! 一覧の表記にそろえる。一覧はデータファイルの隣 (`book.json` なら `book.genres.json`) に置き、
+//! `/admin/genres` で確認・変更する。書籍に付いているジャンルを一覧から外すことはできない (409)。
+//! `genre=` や検索式の `genre:` で絞り込め、`/genres` でジャンルごとの書籍数を返す。
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::normalize::fold;
+use crate::{Book, BookError};
+
+/// ジャンルの一覧。並びは登録した順。
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct Genres {
+    names: Vec<String>,
+    /// 正規化した名前 → 位置
+    by_name: HashMap<String, usize>,
+}
+
+impl Genres {
+    /// 一覧を読む。空の名前や、正規化すると同じになる名前があれば 400。
+    pub fn new(names: Vec<String>) -> Result<Self, BookError> {
+        let mut genres = Genres::default();
+
+        for name in names {
+            let name = name.trim().to_string();
+            if name.is_empty() {
+                return Err(BookError::BadRequest("genres: names must not be empty".to_string()));
+            }
+            if genres.by_name.insert(fold(&name), genres.names.len()).is_some() {
+                return Err(BookError::BadRequest(format!("genres: {:?} is listed more than once", name)));
+            }
+            genres.names.push(name);
+        }
+
+        Ok(genres)
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// 一覧での表記。一覧になければ `None`。
+    pub fn find(&self, genre: &str) -> Option<&str> {
+        self.by_name.get(&fold(genre.trim())).map(|&pos| self.names[pos].as_str())
+    }
+
+    /// 書籍のジャンルを一覧の表記にそろえ、重なりを除く。一覧にないジャンルがあれば 400。
+    pub fn canonical(&self, genres: &[String]) -> Result<Vec<String>, BookError> {
+        let mut canonical: Vec<String> = Vec::with_capacity(genres.len());
+
+        for genre in genres {
+            let name = self.find(genre).ok_or_else(|| {
+                BookError::BadRequest(format!("genres: unknown genre {:?} (use {})", genre, self.names.join(", ")))
+            })?;
+            if !canonical.iter().any(|g| g == name) {
+                canonical.push(name.to_string());
+            }
+        }
+
+        Ok(canonical)
+    }
+
+    /// `books` に付いているのに、この一覧にないジャンル。
+    pub fn missing<'a>(&self, books: &'a [Book]) -> Vec<&'a str> {
+        let mut missing: Vec<&str> = Vec::new();
+        for genre in books.iter().flat_map(|b| &b.genres) {
+            if self.find(genre).is_none() && !missing.contains(&genre.as_str()) {
+                missing.push(genre);
+            }
+        }
+        missing
+    }
+}
+
+impl TryFrom<Vec<String>> for Genres {
+    type Error = BookError;
+
+    fn try_from(names: Vec<String>) -> Result<Self, Self::Error> {
+        Genres::new(names)
+    }
+}
+
+impl From<Genres> for Vec<String> {
+    fn from(genres: Genres) -> Self {
+        genres.names
+    }
+}
+
+/// `book` に `genre` が付いているか。
+pub fn has_genre(book: &Book, genre: &str) -> bool {
+    let genre = fold(genre.trim());
+    book.genres.iter().any(|g| fold(g) == genre)
+}
+
+/// データファイルに対応する一覧のファイル。
+pub fn path_for(data_file: &Path) -> PathBuf {
+    data_file.with_extension("genres.json")
+}
+
+/// 一覧を読む。ファイルがなければ空。
+pub fn load(path: &Path) -> Result<Genres, BookError> {
+    match fs::read(path) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Genres::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct GenreCount {
+    pub genre: String,
+    pub count: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct GenreStats {
+    /// 一覧の順に、そのジャンルが付いた書籍数 (0 も含める)
+    pub genres: Vec<GenreCount>,
+    /// ジャンルが 1 つも付いていない書籍数
+    pub unassigned: usize,
+}
+
+/// ジャンルごとの書籍数。
+pub fn stats(genres: &Genres, books: &[Book]) -> GenreStats {
+    let mut counts = vec![0; genres.names.len()];
+    let mut unassigned = 0;
+
+    for book in books {
+        if book.genres.is_empty() {
+            unassigned += 1;
+        }
+        let mut seen = Vec::new();
+        for genre in &book.genres {
+            if let Some(&pos) = genres.by_name.get(&fold(genre)) {
+                if !seen.contains(&pos) {
+                    seen.push(pos);
+                    counts[pos] += 1;
+                }
+            }
+        }
+    }
+
+    GenreStats {
+        genres: genres.names.iter().zip(counts)
+            .map(|(genre, count)| GenreCount { genre: genre.clone(), count })
+            .collect(),
+        unassigned,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn genres(names: &[&str]) -> Genres {
+        Genres::new(names.iter().map(|n| n.to_string()).collect()).unwrap()
+    }
+
+    fn book(id: u32, genres: &[&str]) -> Book {
+        Book { id, genres: genres.iter().map(|g| g.to_string()).collect(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_canonical() {
+        let taxonomy = genres(&["Science Fiction", "Programming"]);
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(taxonomy.canonical(&names(&["programming", " PROGRAMMING ", "science fiction"])).unwrap(), names(&["Programming", "Science Fiction"]));
+        assert!(matches!(taxonomy.canonical(&names(&["Poetry"])), Err(BookError::BadRequest(_))));
+        assert!(taxonomy.canonical(&[]).unwrap().is_empty());
+
+        assert!(Genres::new(names(&["Poetry", "poetry"])).is_err());
+        assert!(Genres::new(names(&[" "])).is_err());
+    }
+
+    #[test]
+    fn test_stats_and_missing() {
+        let taxonomy = genres(&["Programming", "Poetry"]);
+        let books = [book(1, &["Programming"]), book(2, &[]), book(3, &["programming", "History"])];
+
+        assert_eq!(stats(&taxonomy, &books), GenreStats {
+            genres: vec![
+                GenreCount { genre: "Programming".to_string(), count: 2 },
+                GenreCount { genre: "Poetry".to_string(), count: 0 },
+            ],
+            unassigned: 1,
+        });
+        assert_eq!(taxonomy.missing(&books), vec!["History"]);
+        assert!(has_genre(&books[2], "PROGRAMMING"));
+    }
+}
@@ -0,0 +1,393 @@
+//! gRPC の書籍サービス。
+//!
+//! gRPC には認証がないので、呼び出し元は HTTP の認証なしのリクエストと同じに扱う。
+//! 見せる本は `anonymous_access` と本ごとの `visibility` で絞り、`unrestricted` 以外では書き込みを断る。
+//! proto にない項目 (`visibility` など) は、保存するときに保存済みの値を残す。
+//!
+//! HTTP のミドルウェアは通らないので、`ip_rules` と監査ログはここで当てる。`ip_rules` のパスは
+//! `/books.v1.BooksService/Upsert` のようなメソッドのパスと比べる。
+
+// tonic::Status は大きいが gRPC の戻り値型として固定されている
+#![allow(clippy::result_large_err)]
+
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use crate::anonymous::{self, Anonymous, AnonymousAccess};
+use crate::audit::{self, Audit, AuditEntry};
+use crate::events::{BookEvent, BookEventKind};
+use crate::ipfilter::IpFilter;
+use crate::limits;
+use crate::maintenance::Maintenance;
+use crate::storage::BookRepository;
+use crate::{Book, BookError, BookQuery};
+
+pub mod pb {
+    tonic::include_proto!("books.v1");
+}
+
+use pb::books_service_server::BooksService;
+pub use pb::books_service_server::BooksServiceServer;
+
+/// メソッドのパス (`ip_rules` と監査ログの `route`)。
+fn route(method: &str) -> String {
+    format!("/books.v1.BooksService/{}", method)
+}
+
+impl From<Book> for pb::Book {
+    fn from(book: Book) -> Self {
+        pb::Book {
+            id: book.id,
+            title: book.title,
+            content: book.content,
+            tags: book.tags,
+            authors: book.authors,
+            published_year: book.published_year,
+            publisher: book.publisher,
+            isbn: book.isbn,
+        }
+    }
+}
+
+impl pb::Book {
+    /// `stored` に proto の項目を重ねる。proto にない項目は `stored` のまま。
+    fn merge_into(self, stored: Book) -> Book {
+        Book {
+            id: self.id,
+            title: self.title,
+            content: self.content,
+            tags: self.tags,
+            authors: self.authors,
+            published_year: self.published_year,
+            publisher: self.publisher,
+            isbn: self.isbn,
+            ..stored
+        }
+    }
+}
+
+impl From<pb::Book> for Book {
+    fn from(book: pb::Book) -> Self {
+        book.merge_into(Book::default())
+    }
+}
+
+impl From<BookEvent> for pb::BookEvent {
+    fn from(event: BookEvent) -> Self {
+        let kind = match event.kind {
+            BookEventKind::Created => pb::book_event::Kind::Created,
+            BookEventKind::Updated => pb::book_event::Kind::Updated,
+            BookEventKind::Deleted => pb::book_event::Kind::Deleted,
+        };
+
+        pb::BookEvent {
+            kind: kind.into(),
+            seq: event.seq,
+            id: event.id,
+            title: event.title,
+        }
+    }
+}
+
+/// HTTP のステータスと同じ区別ができるよう、エラーの種類ごとに gRPC のステータスを選ぶ。
+impl From<BookError> for Status {
+    fn from(err: BookError) -> Self {
+        let message = err.to_string();
+        match err {
+            BookError::BadRequest(_) | BookError::InvalidPattern(_) | BookError::WeakPassword(_) | BookError::NotAcceptable => {
+                Status::invalid_argument(message)
+            }
+            BookError::NotFound => Status::not_found(message),
+            BookError::Conflict(_) => Status::aborted(message),
+            BookError::Gone(_) => Status::out_of_range(message),
+            BookError::Unauthorized => Status::unauthenticated(message),
+            BookError::Forbidden => Status::permission_denied(message),
+            BookError::Timeout => Status::deadline_exceeded(message),
+            BookError::PayloadTooLarge(_) | BookError::RateLimited(_) => Status::resource_exhausted(message),
+            BookError::Maintenance(_) => Status::unavailable(message),
+            BookError::FeatureDisabled(_) => Status::unimplemented(message),
+            BookError::FileReadError(_)
+            | BookError::JsonParseError(_)
+            | BookError::Serialize(_)
+            | BookError::Encryption(_)
+            | BookError::Blocking(_) => Status::internal(message),
+        }
+    }
+}
+
+pub struct GrpcBooks {
+    repository: BookRepository,
+    max_results: usize,
+    maintenance: Maintenance,
+    access: AnonymousAccess,
+    ip_filter: IpFilter,
+    audit: Option<Arc<Audit>>,
+}
+
+impl GrpcBooks {
+    pub fn new(repository: BookRepository) -> Self {
+        GrpcBooks {
+            repository,
+            max_results: limits::DEFAULT_MAX_RESULTS,
+            maintenance: Maintenance::default(),
+            access: AnonymousAccess::default(),
+            ip_filter: IpFilter::default(),
+            audit: None,
+        }
+    }
+
+    /// HTTP 側と同じ `ip_rules` で接続元を絞る。
+    pub fn with_ip_filter(mut self, ip_filter: IpFilter) -> Self {
+        self.ip_filter = ip_filter;
+        self
+    }
+
+    /// 書き込みを HTTP 側と同じ監査ログに記録する。
+    pub fn with_audit(mut self, audit: Arc<Audit>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    fn check_ip<T>(&self, request: &Request<T>, method: &str) -> Result<(), Status> {
+        let ip = request.remote_addr().map(|addr| addr.ip());
+        if self.ip_filter.allows(&route(method), ip) {
+            return Ok(());
+        }
+
+        log::warn!("Rejected gRPC {} from {:?} by ip_rules", method, ip);
+        Err(BookError::Forbidden.into())
+    }
+
+    /// 成功した書き込みを監査ログに追記する。記録に失敗しても、操作は済んでいるので応答はそのまま返す。
+    async fn record(&self, method: &str, change: audit::Change) {
+        let Some(audit) = self.audit.clone() else {
+            return;
+        };
+
+        let entry = AuditEntry::grpc(&route(method), change);
+        let appended = tokio::task::spawn_blocking(move || audit.append(&entry)).await;
+        if let Err(e) = appended.map_err(std::io::Error::other).and_then(|result| result) {
+            log::error!("Failed to write the audit log: {}", e);
+        }
+    }
+
+    /// HTTP 側と同じ `anonymous_access` で見せる本と書き込みを決める。
+    pub fn with_anonymous_access(mut self, access: AnonymousAccess) -> Self {
+        self.access = access;
+        self
+    }
+
+    fn viewer(&self) -> Anonymous {
+        Anonymous::new(self.access)
+    }
+
+    /// 一覧・検索で返す件数の上限を変える。
+    pub fn with_max_results(mut self, max_results: usize) -> Self {
+        self.max_results = max_results;
+        self
+    }
+
+    /// HTTP 側と同じメンテナンスモードの状態を使う。有効な間は書き込みを `UNAVAILABLE` で断る。
+    pub fn with_maintenance(mut self, maintenance: Maintenance) -> Self {
+        self.maintenance = maintenance;
+        self
+    }
+
+    fn check_writable(&self) -> Result<(), Status> {
+        if self.access != AnonymousAccess::Unrestricted {
+            return Err(Status::unauthenticated("writes require authentication"));
+        }
+        self.maintenance.check().map_err(|e| Status::unavailable(e.to_string()))
+    }
+
+    /// ファイル IO を伴うリポジトリ操作をブロッキング用スレッドで実行する。
+    async fn block<T, F>(&self, f: F) -> Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(&BookRepository) -> Result<T, BookError> + Send + 'static,
+    {
+        let repository = self.repository.clone();
+
+        tokio::task::spawn_blocking(move || f(&repository))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(Into::into)
+    }
+}
+
+type WatchStream = Pin<Box<dyn Stream<Item = Result<pb::BookEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl BooksService for GrpcBooks {
+    async fn list(&self, request: Request<pb::ListBooksRequest>) -> Result<Response<pb::ListBooksResponse>, Status> {
+        self.check_ip(&request, "List")?;
+
+        let viewer = self.viewer();
+        let mut books = self.block(|r| r.list()).await?;
+        books.retain(|book| viewer.lists(book));
+        let truncated = limits::truncate(&mut books, self.max_results);
+
+        Ok(Response::new(pb::ListBooksResponse {
+            books: books.into_iter().map(Into::into).collect(),
+            truncated,
+        }))
+    }
+
+    async fn get(&self, request: Request<pb::GetBookRequest>) -> Result<Response<pb::Book>, Status> {
+        self.check_ip(&request, "Get")?;
+
+        let id = request.into_inner().id;
+
+        let viewer = self.viewer();
+        match self.block(move |r| r.get(id)).await?.filter(|book| viewer.shows(book)) {
+            Some(book) => Ok(Response::new(book.into())),
+            None => Err(Status::not_found(format!("book {} not found", id))),
+        }
+    }
+
+    async fn search(&self, request: Request<pb::SearchBooksRequest>) -> Result<Response<pb::SearchBooksResponse>, Status> {
+        self.check_ip(&request, "Search")?;
+
+        let request = request.into_inner();
+        let query = BookQuery {
+            id: request.id,
+            tag: request.tag,
+            genre: None,
+            format: None,
+            lang: None,
+            q: request.q,
+            regex: false,
+        };
+
+        let viewer = self.viewer();
+        let mut books = self.block(move |r| r.search(&query)).await?;
+        books.retain(|book| viewer.lists(book));
+        let truncated = limits::truncate(&mut books, self.max_results);
+
+        Ok(Response::new(pb::SearchBooksResponse {
+            books: books.into_iter().map(Into::into).collect(),
+            truncated,
+        }))
+    }
+
+    async fn upsert(&self, request: Request<pb::UpsertBookRequest>) -> Result<Response<pb::UpsertBookResponse>, Status> {
+        self.check_ip(&request, "Upsert")?;
+        self.check_writable()?;
+
+        let book = request.into_inner()
+            .book
+            .ok_or_else(|| Status::invalid_argument("book is required"))?;
+
+        let (before, book, created) = self.block(move |r| {
+            let before = r.get(book.id)?;
+            let book = book.merge_into(before.clone().unwrap_or_default());
+            let (_, created) = r.upsert(book.clone())?;
+            Ok((before, book, created))
+        })
+        .await?;
+        self.record("Upsert", audit::Change::book(book.id, before.as_ref(), Some(&book))).await;
+
+        Ok(Response::new(pb::UpsertBookResponse {
+            book: Some(book.into()),
+            created,
+        }))
+    }
+
+    async fn delete(&self, request: Request<pb::DeleteBookRequest>) -> Result<Response<pb::DeleteBookResponse>, Status> {
+        self.check_ip(&request, "Delete")?;
+        self.check_writable()?;
+
+        let id = request.into_inner().id;
+
+        match self.block(move |r| r.delete(id)).await? {
+            Some(removed) => {
+                self.record("Delete", audit::Change::book(id, Some(&removed), None)).await;
+                Ok(Response::new(pb::DeleteBookResponse {}))
+            }
+            None => Err(Status::not_found(format!("book {} not found", id))),
+        }
+    }
+
+    type WatchStream = WatchStream;
+
+    async fn watch(&self, request: Request<pb::WatchBooksRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        self.check_ip(&request, "Watch")?;
+
+        // 取りこぼし (Lagged) は購読者側の問題なので読み飛ばして監視を続ける
+        let viewer = self.viewer();
+        let stream = BroadcastStream::new(self.repository.events().subscribe())
+            .filter_map(|event| event.ok())
+            .filter(move |event| anonymous::sees_event(Some(viewer), event))
+            .map(|event| Ok(event.into()));
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Visibility;
+
+    #[test]
+    fn test_merge_into() {
+        let stored = Book { id: 3, title: "Old".to_string(), visibility: Some(Visibility::Private), ..Default::default() };
+        let incoming = pb::Book { id: 3, title: "New".to_string(), ..Default::default() };
+
+        let merged = incoming.merge_into(stored);
+        assert_eq!(merged.title, "New");
+        assert_eq!(merged.visibility, Some(Visibility::Private));
+    }
+
+    #[actix_rt::test]
+    async fn test_writes_are_audited_and_filtered() {
+        let data_file = std::env::temp_dir().join(format!("books_backend_grpc_audit_{}.json", std::process::id()));
+        let audit_file = data_file.with_extension("jsonl");
+        std::fs::copy("src/data/book.json", &data_file).unwrap();
+        let _ = std::fs::remove_file(&audit_file);
+
+        let audit = Arc::new(Audit::new(&audit_file));
+        let service = GrpcBooks::new(BookRepository::new(&data_file)).with_audit(audit.clone());
+        let book = pb::Book { id: 900, title: "Over gRPC".to_string(), ..Default::default() };
+        service.upsert(Request::new(pb::UpsertBookRequest { book: Some(book) })).await.unwrap();
+        service.delete(Request::new(pb::DeleteBookRequest { id: 900 })).await.unwrap();
+
+        let entries = audit.query(None, &Default::default()).unwrap();
+        assert_eq!(entries.iter().map(|e| e.route.as_str()).collect::<Vec<_>>(), vec![
+            "/books.v1.BooksService/Delete",
+            "/books.v1.BooksService/Upsert",
+        ]);
+        assert_eq!(entries[1].change.after.as_ref().unwrap().title, "Over gRPC");
+
+        // 接続元の分からない呼び出しは、ルールの当たるメソッドでは断る
+        let rules = [crate::ipfilter::IpRule { allow: vec!["10.0.0.0/8".to_string()], ..Default::default() }];
+        let service = service.with_ip_filter(IpFilter::parse(&rules).unwrap());
+        let status = service.list(Request::new(pb::ListBooksRequest {})).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        std::fs::remove_file(&audit_file).unwrap();
+        std::fs::remove_file(data_file.with_extension("changes.jsonl")).unwrap();
+        std::fs::remove_file(&data_file).unwrap();
+    }
+
+    #[test]
+    fn test_status_from_book_error() {
+        use tonic::Code;
+
+        let code = |err: BookError| Status::from(err).code();
+        assert_eq!(code(BookError::BadRequest("title".to_string())), Code::InvalidArgument);
+        assert_eq!(code(BookError::InvalidPattern("(".to_string())), Code::InvalidArgument);
+        assert_eq!(code(BookError::WeakPassword(Vec::new())), Code::InvalidArgument);
+        assert_eq!(code(BookError::NotFound), Code::NotFound);
+        assert_eq!(code(BookError::Conflict("id".to_string())), Code::Aborted);
+        assert_eq!(code(BookError::Gone("seq".to_string())), Code::OutOfRange);
+        assert_eq!(code(BookError::Unauthorized), Code::Unauthenticated);
+        assert_eq!(code(BookError::Forbidden), Code::PermissionDenied);
+        assert_eq!(code(BookError::Timeout), Code::DeadlineExceeded);
+        assert_eq!(code(BookError::PayloadTooLarge(1024)), Code::ResourceExhausted);
+        assert_eq!(code(BookError::FileReadError(std::io::Error::other("disk"))), Code::Internal);
+    }
+}
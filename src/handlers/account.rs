@@ -0,0 +1,186 @@
+//! アカウントの登録とパスワードの変更。
+
+use actix_web::{http::header, post, put, web, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{authenticate, load_users_from, save_user_to, set_password_in, users_file_for, verify_password};
+use crate::invites::Invites;
+use crate::password::{PasswordPolicy, Rule, Violation};
+use crate::pwned::{PwnedCheck, PwnedMode};
+use crate::tenant::Tenant;
+use crate::{BookError, Role};
+
+/// 登録の受け付け方。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Signup {
+    /// 誰でも `POST /register` で登録できる。既定では閉じていて、招待トークンがいる。
+    pub open: bool,
+}
+
+/// ユーザー名に使える文字か。Basic 認証で区切りになる `:` と空白・制御文字は使えない。
+pub fn is_valid_username(username: &str) -> bool {
+    (1..=64).contains(&username.chars().count())
+        && !username.chars().any(|c| c == ':' || c.is_whitespace() || c.is_control())
+}
+
+/// 規則と漏洩チェックを確かめる。`warn` の設定で漏洩していたときは、レスポンスに付ける警告を返す。
+async fn check_password(
+    policy: Option<&PasswordPolicy>,
+    pwned: Option<&PwnedCheck>,
+    username: &str,
+    password: &str,
+) -> Result<Option<String>, BookError> {
+    let mut violations = match policy {
+        Some(policy) => policy.check(username, password),
+        None => PasswordPolicy::default().check(username, password),
+    };
+    if !violations.is_empty() {
+        return Err(BookError::WeakPassword(violations));
+    }
+
+    let Some(pwned) = pwned.filter(|pwned| pwned.mode != PwnedMode::Off) else {
+        return Ok(None);
+    };
+    let count = match pwned.times_pwned(password).await {
+        Some(count) if count > 0 => count,
+        _ => return Ok(None),
+    };
+
+    let message = format!("has appeared in {} data breaches", count);
+    match pwned.mode {
+        PwnedMode::Reject => {
+            violations.push(Violation { rule: Rule::Pwned, message });
+            Err(BookError::WeakPassword(violations))
+        }
+        _ => {
+            log::warn!("User {} chose a password that {}", username, message);
+            Ok(Some(format!("299 - \"password {}\"", message)))
+        }
+    }
+}
+
+fn with_warning(mut response: HttpResponseBuilder, warning: Option<String>) -> HttpResponseBuilder {
+    if let Some(warning) = warning {
+        response.insert_header((header::WARNING, warning));
+    }
+    response
+}
+
+#[derive(Deserialize)]
+pub struct Registration {
+    username: String,
+    password: String,
+    /// `POST /admin/invites` で発行した招待トークン。登録が閉じているときに使う。
+    #[serde(default)]
+    invite: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Registered {
+    username: String,
+    role: Role,
+}
+
+/// ユーザーを登録する。規則を満たさないパスワードは 422 で、満たさない規則を並べて返す。
+/// 登録が閉じていれば招待トークンがいり、招待の `role` で登録する。
+#[post("/register")]
+pub async fn register(
+    req: HttpRequest,
+    signup: Option<web::Data<Signup>>,
+    invites: Option<web::Data<Invites>>,
+    policy: Option<web::Data<PasswordPolicy>>,
+    pwned: Option<web::Data<PwnedCheck>>,
+    body: web::Json<Registration>,
+) -> Result<impl Responder, BookError> {
+    let Registration { username, password, invite } = body.into_inner();
+    let open = signup.is_some_and(|signup| signup.open);
+    if !open && (invite.is_none() || invites.is_none()) {
+        return Err(BookError::Forbidden);
+    }
+
+    if !is_valid_username(&username) {
+        return Err(BookError::BadRequest("username must be 1-64 characters without ':' or spaces".to_string()));
+    }
+    let warning = check_password(
+        policy.as_ref().map(|p| p.get_ref()),
+        pwned.as_ref().map(|p| p.get_ref()),
+        &username,
+        &password,
+    ).await?;
+
+    let users_file = users_file_for(&req);
+    if load_users_from(&users_file).iter().any(|u| u.username == username) {
+        return Err(BookError::Conflict(format!("user {} already exists", username)));
+    }
+
+    // 招待があれば登録が開いていても使い、招待の役割で登録する
+    let role = match (invite, invites) {
+        (Some(token), Some(invites)) => {
+            let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+            let invite = invites.redeem(&token, tenant.as_deref())?.ok_or(BookError::Forbidden)?;
+            log::info!("Invite {} used by {}", invite.id, username);
+            invite.role
+        }
+        _ => Role::User,
+    };
+
+    let name = username.clone();
+    let created = web::block(move || save_user_to(&users_file, &name, &password, role)).await??;
+    if !created {
+        return Err(BookError::Conflict(format!("user {} already exists", username)));
+    }
+
+    log::info!("Registered {} user {}", role.as_str(), username);
+    Ok(with_warning(HttpResponse::Created(), warning).json(Registered { username, role }))
+}
+
+#[derive(Deserialize)]
+pub struct PasswordChange {
+    current_password: String,
+    new_password: String,
+}
+
+/// 自分のパスワードを変える。トークンで認証していても、今のパスワードを確かめる。
+#[put("/account/password")]
+pub async fn change_password(
+    req: HttpRequest,
+    policy: Option<web::Data<PasswordPolicy>>,
+    pwned: Option<web::Data<PwnedCheck>>,
+    body: web::Json<PasswordChange>,
+) -> Result<impl Responder, BookError> {
+    let user = authenticate(&req)?;
+    let PasswordChange { current_password, new_password } = body.into_inner();
+
+    if !verify_password(&user.password, &current_password) {
+        return Err(BookError::Forbidden);
+    }
+    let warning = check_password(
+        policy.as_ref().map(|p| p.get_ref()),
+        pwned.as_ref().map(|p| p.get_ref()),
+        &user.username,
+        &new_password,
+    ).await?;
+
+    let users_file = users_file_for(&req);
+    let username = user.username.clone();
+    if !web::block(move || set_password_in(&users_file, &username, &new_password)).await?? {
+        return Err(BookError::Unauthorized);
+    }
+
+    log::info!("User {} changed their password", user.username);
+    Ok(with_warning(HttpResponse::NoContent(), warning).finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_username() {
+        assert!(is_valid_username("alice"));
+        assert!(is_valid_username("山田"));
+        assert!(!is_valid_username(""));
+        assert!(!is_valid_username("a:b"));
+        assert!(!is_valid_username("a b"));
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::BTreeMap;
+use actix_web::{get, post, put, web, HttpRequest, HttpResponse, Responder};
+use maud::{html, Markup, DOCTYPE};
+use serde::{Deserialize, Serialize};
+
+use super::block;
+use crate::audit;
+use crate::auth::{AdminUser, OperatorUser};
+use crate::auth::{load_users_from, users_file_for};
+use crate::flags::Flag;
+use crate::reload::Reloader;
+use crate::genres::Genres;
+use crate::seed::{SeedEndpoint, SeedOptions};
+use crate::synonyms::Synonyms;
+use crate::{AppState, Book, BookError, Visibility};
+
+fn layout(title: &str, body: Markup) -> Markup {
+    html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="utf-8";
+                title { (title) " | Books Admin" }
+                style {
+                    "body { font-family: sans-serif; margin: 2rem; }"
+                    "table { border-collapse: collapse; }"
+                    "th, td { border: 1px solid #ccc; padding: 0.3rem 0.6rem; text-align: left; }"
+                    "label { display: block; margin-top: 0.8rem; }"
+                }
+            }
+            body {
+                nav {
+                    a href="/admin" { "Books" } " | "
+                    a href="/admin/users" { "Users" }
+                }
+                h1 { (title) }
+                (body)
+            }
+        }
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[get("/admin")]
+pub async fn dashboard(_admin: AdminUser, data: web::Data<AppState>) -> Result<Markup, BookError> {
+    let books = block(&data.repository, |r| r.list()).await?;
+
+    Ok(layout("Books", html! {
+        form method="post" action="/admin/backup" {
+            button type="submit" { "Back up data file" }
+        }
+        p { a href="/admin/books/new" { "Add a book" } }
+        table {
+            thead { tr { th { "ID" } th { "Title" } th { "Authors" } th { "Tags" } th {} } }
+            tbody {
+                @for book in &books {
+                    tr {
+                        td { (book.id) }
+                        td { (book.title) }
+                        td { (book.authors.join(", ")) }
+                        td { (book.tags.join(", ")) }
+                        td { a href={ "/admin/books/" (book.id) "/edit" } { "Edit" } }
+                    }
+                }
+            }
+        }
+    }))
+}
+
+fn book_form(book: &Book, is_new: bool) -> Markup {
+    html! {
+        form method="post" action="/admin/books" {
+            label { "ID" input type="number" name="id" value=(book.id) readonly[!is_new] required; }
+            label { "Title" input type="text" name="title" value=(book.title) required; }
+            label { "Authors (comma separated)" input type="text" name="authors" value=(book.authors.join(", ")); }
+            label { "Tags (comma separated)" input type="text" name="tags" value=(book.tags.join(", ")); }
+            label { "Content" textarea name="content" rows="10" cols="80" { (book.content) } }
+            label {
+                "Visibility without login "
+                select name="visibility" {
+                    option value="" selected[book.visibility.is_none()] { "(default)" }
+                    @for visibility in [Visibility::Public, Visibility::Unlisted, Visibility::Private] {
+                        option value=(visibility.as_str()) selected[book.visibility == Some(visibility)] { (visibility.as_str()) }
+                    }
+                }
+            }
+            p { button type="submit" { "Save" } }
+        }
+    }
+}
+
+#[get("/admin/books/new")]
+pub async fn new_book(_admin: AdminUser, data: web::Data<AppState>) -> Result<Markup, BookError> {
+    let next_id = block(&data.repository, |r| r.list()).await?
+        .iter()
+        .map(|b| b.id)
+        .max()
+        .unwrap_or(0) + 1;
+
+    let book = Book { id: next_id, ..Default::default() };
+
+    Ok(layout("New book", book_form(&book, true)))
+}
+
+#[get("/admin/books/{id}/edit")]
+pub async fn edit_book(
+    _admin: AdminUser,
+    data: web::Data<AppState>,
+    id: web::Path<u32>,
+) -> Result<Markup, BookError> {
+    let id = id.into_inner();
+    let book = block(&data.repository, move |r| r.get(id)).await?.ok_or(BookError::NotFound)?;
+
+    Ok(layout(&format!("Edit \"{}\"", book.title), book_form(&book, false)))
+}
+
+#[derive(Deserialize)]
+pub struct BookForm {
+    id: u32,
+    title: String,
+    content: String,
+    tags: String,
+    authors: String,
+    /// 空なら指定なし。項目ごとないフォームからは、既存の値を引き継ぐ。
+    visibility: Option<String>,
+}
+
+#[post("/admin/books")]
+pub async fn save_book(
+    req: HttpRequest,
+    _admin: AdminUser,
+    data: web::Data<AppState>,
+    form: web::Form<BookForm>,
+) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+    let form = form.into_inner();
+
+    // フォームで扱わない項目 (貸出情報など) は既存の値を引き継ぐ
+    let before = block(repository, move |r| r.get(form.id)).await?;
+    let mut book = before.clone().unwrap_or_default();
+    book.id = form.id;
+    book.title = form.title;
+    book.content = form.content;
+    book.tags = split_list(&form.tags);
+    book.authors = split_list(&form.authors);
+    if let Some(visibility) = form.visibility {
+        book.visibility = match visibility.as_str() {
+            "" => None,
+            value => Some(value.parse().map_err(|_| BookError::BadRequest(format!("unknown visibility {:?}", value)))?),
+        };
+    }
+
+    audit::note(&req, audit::Change::book(book.id, before.as_ref(), Some(&book)));
+    block(repository, |r| r.upsert(book)).await?;
+
+    let location = crate::proxy::absolute_url(&req, "/admin");
+
+    Ok(HttpResponse::SeeOther().insert_header(("Location", location)).finish())
+}
+
+#[get("/admin/users")]
+pub async fn users(req: HttpRequest, _admin: AdminUser) -> Markup {
+    let users = load_users_from(&users_file_for(&req));
+
+    layout("Users", html! {
+        table {
+            thead { tr { th { "Username" } th { "Role" } } }
+            tbody {
+                @for user in &users {
+                    tr {
+                        td { (user.username) }
+                        td { (user.role.as_str()) }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[post("/admin/backup")]
+pub async fn backup(_admin: AdminUser, data: web::Data<AppState>) -> Result<Markup, BookError> {
+    let path = block(&data.repository, |r| r.backup()).await?;
+
+    Ok(layout("Backup", html! {
+        p { "Backup written to " code { (path.display()) } }
+        p { a href="/admin" { "Back to books" } }
+    }))
+}
+
+#[derive(Serialize)]
+pub struct SwapResult {
+    previous: usize,
+    current: usize,
+    backup: String,
+}
+
+/// アップロードされた JSON (書籍の配列) でデータファイルを丸ごと差し替える。
+/// 差し替え前のファイルは `backups/` に残す。サーバーの再起動は要らない。
+#[post("/admin/data/swap")]
+pub async fn swap_data(
+    req: HttpRequest,
+    _admin: AdminUser,
+    data: web::Data<AppState>,
+    body: web::Bytes,
+) -> Result<impl Responder, BookError> {
+    let books: Vec<Book> = serde_json::from_slice(&body)
+        .map_err(|e| BookError::BadRequest(format!("Invalid dataset: {}", e)))?;
+    let current = books.len();
+
+    let (backup_path, previous) = block(&data.repository, move |r| {
+        let path = r.backup()?;
+        Ok((path, r.replace(books)?))
+    }).await?;
+    audit::note(&req, audit::Change::summary(format!("replaced {} books with {}", previous, current)));
+
+    Ok(HttpResponse::Ok().json(SwapResult {
+        previous,
+        current,
+        backup: backup_path.display().to_string(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceRequest {
+    enabled: bool,
+    retry_after_secs: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct MaintenanceStatus {
+    enabled: bool,
+    retry_after_secs: Option<u64>,
+}
+
+/// メンテナンスモードを切り替える。有効な間は書き込みを 503 で断り、読み取りだけを受け付ける。
+/// すべてのテナントに効くので、既定の書庫の管理者だけが切り替えられる。
+#[post("/admin/maintenance")]
+pub async fn set_maintenance(
+    admin: OperatorUser,
+    data: web::Data<AppState>,
+    body: web::Json<MaintenanceRequest>,
+) -> impl Responder {
+    let maintenance = data.maintenance();
+
+    if body.enabled {
+        maintenance.enable(body.retry_after_secs.unwrap_or(crate::maintenance::DEFAULT_RETRY_AFTER));
+        log::warn!("Maintenance mode enabled by {}", admin.0.username);
+    } else {
+        maintenance.disable();
+        log::warn!("Maintenance mode disabled by {}", admin.0.username);
+    }
+
+    let retry_after_secs = maintenance.retry_after();
+    HttpResponse::Ok().json(MaintenanceStatus { enabled: retry_after_secs.is_some(), retry_after_secs })
+}
+
+/// 開発用のダミーデータを生成する。`books seed` と同じ。`seed_endpoint` が有効でなければ 404。
+#[post("/admin/seed")]
+pub async fn seed(
+    req: HttpRequest,
+    _admin: AdminUser,
+    data: web::Data<AppState>,
+    endpoint: Option<web::Data<SeedEndpoint>>,
+    options: web::Json<SeedOptions>,
+) -> Result<impl Responder, BookError> {
+    if !endpoint.is_some_and(|endpoint| endpoint.enabled) {
+        return Err(BookError::NotFound);
+    }
+    let options = options.into_inner();
+    let users_file = users_file_for(&req);
+    let report = block(&data.repository, move |r| crate::seed::run(r, &options, &users_file)).await?;
+    audit::note(&req, audit::Change::summary(format!("seeded {} books and {} users", report.books, report.users)));
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// データファイルの整合性を調べて結果を返す。問題があっても 200 で、`ok` が false になる。
+#[get("/admin/check")]
+pub async fn check(_admin: AdminUser, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    let report = block(&data.repository, crate::integrity::check).await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// データファイル・バックアップの大きさと空き容量。
+#[get("/admin/storage")]
+pub async fn storage(_admin: AdminUser, data: web::Data<AppState>, req: HttpRequest) -> Result<impl Responder, BookError> {
+    let users_file = users_file_for(&req);
+    let usage = block(&data.repository, move |r| crate::usage::usage(r, &users_file)).await?;
+
+    Ok(HttpResponse::Ok().json(usage))
+}
+
+/// 設定ファイルと環境変数を読み直す。SIGHUP を送るのと同じ。既定の書庫の管理者だけが使える。
+#[post("/admin/reload-config")]
+pub async fn reload_config(admin: OperatorUser, reloader: Option<web::Data<Reloader>>) -> Result<impl Responder, BookError> {
+    let reloader = reloader.ok_or(BookError::NotFound)?;
+
+    reloader.reload().map_err(|e| BookError::BadRequest(e.to_string()))?;
+    log::warn!("Configuration reloaded by {}", admin.0.username);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// 機能フラグの一覧。
+#[get("/admin/flags")]
+pub async fn list_flags(_admin: AdminUser, data: web::Data<AppState>) -> impl Responder {
+    let flags: BTreeMap<Flag, bool> = data.flags().snapshot().into_iter().collect();
+
+    HttpResponse::Ok().json(flags)
+}
+
+#[derive(Deserialize)]
+pub struct FlagUpdate {
+    enabled: bool,
+}
+
+/// 機能フラグを切り替える。再起動すると設定の値に戻る。既定の書庫の管理者だけが切り替えられる。
+#[put("/admin/flags/{name}")]
+pub async fn set_flag(
+    admin: OperatorUser,
+    data: web::Data<AppState>,
+    name: web::Path<String>,
+    body: web::Json<FlagUpdate>,
+) -> Result<impl Responder, BookError> {
+    let flag: Flag = name.parse().map_err(|_| BookError::NotFound)?;
+
+    data.flags().set(flag, body.enabled);
+    log::warn!("Feature flag {} set to {} by {}", flag, body.enabled, admin.0.username);
+
+    let flags: BTreeMap<Flag, bool> = data.flags().snapshot().into_iter().collect();
+    Ok(HttpResponse::Ok().json(flags))
+}
+
+/// 登録しているタグの同義語 (同じ意味のタグのグループの配列)。
+#[get("/admin/synonyms")]
+pub async fn get_synonyms(_admin: AdminUser, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    let synonyms = block(&data.repository, |r| Ok(r.synonyms())).await?;
+
+    Ok(HttpResponse::Ok().json(&*synonyms))
+}
+
+/// タグの同義語を丸ごと差し替える。空の配列を送ると同義語をなくす。
+#[put("/admin/synonyms")]
+pub async fn set_synonyms(
+    req: HttpRequest,
+    admin: AdminUser,
+    data: web::Data<AppState>,
+    body: web::Json<Vec<Vec<String>>>,
+) -> Result<impl Responder, BookError> {
+    let synonyms = Synonyms::new(body.into_inner())?;
+    let groups = synonyms.groups().len();
+
+    let saved = synonyms.clone();
+    block(&data.repository, move |r| r.set_synonyms(saved)).await?;
+    audit::note(&req, audit::Change::summary(format!("set {} tag synonym groups", groups)));
+    log::info!("Tag synonyms updated by {} ({} groups)", admin.0.username, groups);
+
+    Ok(HttpResponse::Ok().json(synonyms))
+}
+
+/// 送った同義語を登録したら、グループごとにどの書籍がまとまるか。登録はしない。
+#[post("/admin/synonyms/preview")]
+pub async fn preview_synonyms(
+    _admin: AdminUser,
+    data: web::Data<AppState>,
+    body: web::Json<Vec<Vec<String>>>,
+) -> Result<impl Responder, BookError> {
+    let synonyms = Synonyms::new(body.into_inner())?;
+    let preview = block(&data.repository, move |r| r.preview_synonyms(&synonyms)).await?;
+
+    Ok(HttpResponse::Ok().json(preview))
+}
+
+/// ジャンルの一覧。
+#[get("/admin/genres")]
+pub async fn get_genres(_admin: AdminUser, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    let genres = block(&data.repository, |r| Ok(r.genres())).await?;
+
+    Ok(HttpResponse::Ok().json(&*genres))
+}
+
+/// ジャンルの一覧を丸ごと差し替える。書籍に付いているジャンルを外そうとすると 409。
+#[put("/admin/genres")]
+pub async fn set_genres(
+    req: HttpRequest,
+    admin: AdminUser,
+    data: web::Data<AppState>,
+    body: web::Json<Vec<String>>,
+) -> Result<impl Responder, BookError> {
+    let genres = Genres::new(body.into_inner())?;
+    let count = genres.names().len();
+
+    let saved = genres.clone();
+    block(&data.repository, move |r| r.set_genres(saved)).await?;
+    audit::note(&req, audit::Change::summary(format!("set {} genres", count)));
+    log::info!("Genres updated by {} ({} genres)", admin.0.username, count);
+
+    Ok(HttpResponse::Ok().json(genres))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_list() {
+        assert_eq!(split_list(" rust, async ,,tokio "), vec!["rust", "async", "tokio"]);
+        assert!(split_list("").is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+
+use super::block;
+use crate::authors::{AuthorInput, Edit};
+use crate::registry::{self, Summary};
+use crate::{anonymous, audit, AppState, BookError};
+
+/// 著者の一覧。各著者に書籍数を添える。認証していなければ、一覧に出す本だけで数える。
+#[get("/authors")]
+#[tracing::instrument(skip_all)]
+pub async fn list_authors(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    let (authors, books) = block(&data.repository, |r| Ok((r.authors(), r.list()?))).await?;
+    let books = anonymous::listed(&req, books);
+
+    let summaries: Vec<Summary<_>> = authors.list().iter()
+        .map(|author| Summary::new(author, &books))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(summaries))
+}
+
+#[get("/authors/{id}")]
+#[tracing::instrument(skip_all)]
+pub async fn get_author(req: HttpRequest, data: web::Data<AppState>, id: web::Path<u32>) -> Result<impl Responder, BookError> {
+    let id = id.into_inner();
+    let (authors, books) = block(&data.repository, |r| Ok((r.authors(), r.list()?))).await?;
+    let books = anonymous::listed(&req, books);
+
+    let author = authors.get(id).ok_or(BookError::NotFound)?;
+    let summary = Summary::new(author, &books);
+
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+/// 著者の書籍。名前か別名で書かれた書籍を返す。
+#[get("/authors/{id}/books")]
+#[tracing::instrument(skip_all)]
+pub async fn get_author_books(req: HttpRequest, data: web::Data<AppState>, id: web::Path<u32>) -> Result<impl Responder, BookError> {
+    let id = id.into_inner();
+    let (authors, books) = block(&data.repository, |r| Ok((r.authors(), r.list()?))).await?;
+    let books = anonymous::listed(&req, books);
+
+    let author = authors.get(id).ok_or(BookError::NotFound)?;
+    let books: Vec<_> = registry::books_of(author, &books).collect();
+
+    Ok(HttpResponse::Ok().json(books))
+}
+
+#[post("/authors")]
+#[tracing::instrument(skip_all)]
+pub async fn create_author(data: web::Data<AppState>, input: web::Json<AuthorInput>) -> Result<impl Responder, BookError> {
+    let input = input.into_inner();
+    let created = block(&data.repository, move |r| r.edit_authors(Edit::Create(input))).await?;
+
+    Ok(HttpResponse::Created().json(&created[0]))
+}
+
+/// 著者を更新する。名前を変えると、その著者の書籍の著者名も書き換える。
+#[put("/authors/{id}")]
+#[tracing::instrument(skip_all)]
+pub async fn update_author(data: web::Data<AppState>, id: web::Path<u32>, input: web::Json<AuthorInput>) -> Result<impl Responder, BookError> {
+    let (id, input) = (id.into_inner(), input.into_inner());
+    let updated = block(&data.repository, move |r| r.edit_authors(Edit::Update(id, input))).await?;
+
+    Ok(HttpResponse::Ok().json(&updated[0]))
+}
+
+/// 著者の記録を消す。書籍の著者名はそのまま残る。
+#[delete("/authors/{id}")]
+#[tracing::instrument(skip_all)]
+pub async fn delete_author(data: web::Data<AppState>, id: web::Path<u32>) -> Result<impl Responder, BookError> {
+    let id = id.into_inner();
+    block(&data.repository, move |r| r.edit_authors(Edit::Delete(id))).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize)]
+pub struct MergeRequest {
+    /// まとめて消す著者の id
+    from: Vec<u32>,
+}
+
+/// `from` の著者を `{id}` にまとめる。まとめた著者の名前は別名として残し、書籍の著者名も書き換える。
+#[post("/authors/{id}/merge")]
+#[tracing::instrument(skip_all)]
+pub async fn merge_authors(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    id: web::Path<u32>,
+    body: web::Json<MergeRequest>,
+) -> Result<impl Responder, BookError> {
+    let into = id.into_inner();
+    let from = body.into_inner().from;
+    if from.is_empty() {
+        return Err(BookError::BadRequest("from: needs at least one author id".to_string()));
+    }
+
+    let summary = format!("merged authors {:?} into {}", from, into);
+    let merged = block(&data.repository, move |r| r.edit_authors(Edit::Merge { into, from })).await?;
+    audit::note(&req, audit::Change::summary(summary));
+
+    Ok(HttpResponse::Ok().json(&merged[0]))
+}
+
+/// 書籍にあって記録のない著者名をまとめて記録にする。作った記録を返す。
+#[post("/authors/import")]
+#[tracing::instrument(skip_all)]
+pub async fn import_authors(data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    let created = block(&data.repository, |r| r.edit_authors(Edit::Import)).await?;
+
+    Ok(HttpResponse::Ok().json(created))
+}
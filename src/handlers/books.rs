@@ -0,0 +1,242 @@
+use actix_web::{get, post, put, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{anonymous, audit, spelling};
+use crate::facets::{FacetOptions, Facets};
+use crate::limits::{self, ResultLimits};
+use crate::links::{self, ListedBook, Pagination};
+use crate::sort::SortOptions;
+use crate::storage::BookRepository;
+use super::block;
+use crate::{conditional, negotiate, AppState, Book, BookError, BookQuery};
+
+#[get("/")]
+pub async fn hello() -> impl Responder {
+    HttpResponse::Ok().body("Hello world!")
+}
+
+#[derive(Deserialize)]
+pub struct ListOptions {
+    /// 一覧でも本文を返すか。本文は大きいので既定では省く。
+    #[serde(default)]
+    include_content: bool,
+}
+
+/// 全文検索の結果の 1 件。本文は `include_content` のときだけ返す。
+#[derive(Serialize)]
+struct ListedHit<'a> {
+    book: ListedBook<'a>,
+    score: f32,
+    snippet: &'a str,
+    snippets: &'a [String],
+}
+
+#[get("/books")]
+#[tracing::instrument(skip_all)]
+pub async fn get_books(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    pagination: web::Query<Pagination>,
+    options: web::Query<ListOptions>,
+    sort: web::Query<SortOptions>,
+) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+    let sort = sort.parse()?;
+
+    let modified = block(repository, |r| r.last_modified()).await?;
+    if let Some(resp) = conditional::not_modified(&req, modified) {
+        return Ok(resp);
+    }
+
+    let mut books = anonymous::listed(&req, block(repository, |r| r.list()).await?);
+    sort.apply(&mut books);
+    let page = pagination.apply(&req, books);
+
+    let mut resp = negotiate::respond_books(&req, &page.items, options.include_content)?;
+    page.insert_headers(&mut resp);
+    conditional::insert_headers(&req, &mut resp, modified);
+
+    Ok(resp)
+}
+
+/// 1 行 1 冊の NDJSON で全件を流す。大量のエクスポートでもメモリ使用量が増えない。
+#[get("/books/stream")]
+#[tracing::instrument(skip_all)]
+pub async fn stream_books(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+    let viewer = anonymous::viewer(&req);
+
+    let books = block(repository, |r| r.iter()).await?;
+    let lines = books.filter(move |book| viewer.is_none_or(|anonymous| anonymous.lists(book))).map(move |mut book| {
+        if let Some(anonymous) = viewer {
+            anonymous.redact(&mut book);
+        }
+        let mut line = serde_json::to_vec(&book)?;
+        line.push(b'\n');
+        Ok::<_, BookError>(web::Bytes::from(line))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(futures_util::stream::iter(lines)))
+}
+
+#[post("/books")]
+#[tracing::instrument(skip_all)]
+pub async fn add_or_update_book(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    new_book: web::Json<Book>,
+) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+    let book = new_book.into_inner();
+
+    let id = book.id;
+    let before = block(repository, move |r| r.get(id)).await?;
+    audit::note(&req, audit::Change::book(id, before.as_ref(), Some(&book)));
+    let (books, _) = block(repository, move |r| r.upsert(book)).await?;
+
+    Ok(HttpResponse::Ok().json(books))
+}
+
+#[derive(Deserialize)]
+pub struct Progress {
+    position_seconds: u32,
+}
+
+/// オーディオブックの再生位置を記録する。聴いた割合を添えた `audio` を返す。
+#[put("/books/{id}/progress")]
+#[tracing::instrument(skip_all)]
+pub async fn set_progress(data: web::Data<AppState>, id: web::Path<u32>, progress: web::Json<Progress>) -> Result<impl Responder, BookError> {
+    let (id, seconds) = (id.into_inner(), progress.position_seconds);
+    let audio = block(&data.repository, move |r| r.set_progress(id, seconds)).await?;
+
+    Ok(HttpResponse::Ok().json(audio))
+}
+
+#[get("/books/search")]
+#[tracing::instrument(skip_all)]
+pub async fn get_book_with_query(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    query: web::Query<BookQuery>,
+    pagination: web::Query<Pagination>,
+    options: web::Query<ListOptions>,
+    facet_options: web::Query<FacetOptions>,
+    sort: web::Query<SortOptions>,
+) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+    let sort = sort.parse()?;
+
+    let modified = block(repository, |r| r.last_modified()).await?;
+    if let Some(resp) = conditional::not_modified(&req, modified) {
+        return Ok(resp);
+    }
+
+    // 正規表現の検索では「もしかして」を探さない
+    let q = if query.regex { String::new() } else { query.q.clone().unwrap_or_default() };
+    let mut books = anonymous::listed(&req, block(repository, move |r| r.search(&query)).await?);
+    sort.apply(&mut books);
+    let did_you_mean = did_you_mean(&req, repository, q, books.is_empty()).await?;
+    // ファセットはページに切り出す前の全件で数える
+    let facets = facet_options.facets.then(|| Facets::count(&books, OffsetDateTime::now_utc().date()));
+    let page = pagination.apply(&req, books);
+
+    let mut resp = match &facets {
+        Some(facets) => negotiate::respond_faceted(&req, &page.items, options.include_content, facets)?,
+        None => negotiate::respond_books(&req, &page.items, options.include_content)?,
+    };
+    page.insert_headers(&mut resp);
+    spelling::insert_header(&mut resp, &did_you_mean);
+    conditional::insert_headers(&req, &mut resp, modified);
+
+    Ok(resp)
+}
+
+/// 1 件も見つからなかったときだけ、直した検索式を探す。
+async fn did_you_mean(req: &HttpRequest, repository: &BookRepository, q: String, empty: bool) -> Result<Vec<String>, BookError> {
+    if !empty || q.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // 認証していなければ、一覧に出す本の語だけを使う
+    let viewer = anonymous::viewer(req);
+    block(repository, move |r| r.did_you_mean(&q, |book| viewer.is_none_or(|anonymous| anonymous.lists(book)))).await
+}
+
+#[derive(Deserialize)]
+pub struct FullTextQuery {
+    q: String,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    20
+}
+
+/// スコアとスニペット付きの全文検索。`"..."` でフレーズ検索になる。
+/// 本文は抜粋 (`snippets`) で足りるので、`include_content=true` のときだけ返す。
+#[get("/books/fulltext")]
+#[tracing::instrument(skip_all)]
+pub async fn full_text_search(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    query: web::Query<FullTextQuery>,
+    options: web::Query<ListOptions>,
+) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+
+    let modified = block(repository, |r| r.last_modified()).await?;
+    if let Some(resp) = conditional::not_modified(&req, modified) {
+        return Ok(resp);
+    }
+
+    let max = ResultLimits::of(&req).max_results;
+    let limit = query.limit;
+    let q = query.q.clone();
+    let mut hits = block(repository, move |r| r.full_text(&query.q)).await?;
+    if let Some(anonymous) = anonymous::viewer(&req) {
+        hits.retain(|hit| anonymous.lists(&hit.book));
+        hits.iter_mut().for_each(|hit| anonymous.redact(&mut hit.book));
+    }
+    let truncated = limits::truncate(&mut hits, limit.min(max)) && limit > max;
+    let did_you_mean = did_you_mean(&req, repository, q, hits.is_empty()).await?;
+
+    let hits: Vec<ListedHit> = hits.iter()
+        .map(|hit| ListedHit {
+            book: ListedBook::new(&hit.book, options.include_content),
+            score: hit.score,
+            snippet: &hit.snippet,
+            snippets: &hit.snippets,
+        })
+        .collect();
+    let mut resp = HttpResponse::Ok().json(hits);
+    limits::insert_truncated_header(&mut resp, truncated);
+    spelling::insert_header(&mut resp, &did_you_mean);
+    conditional::insert_headers(&req, &mut resp, modified);
+
+    Ok(resp)
+}
+
+#[get("/books/id/{id}")]
+#[tracing::instrument(skip_all)]
+pub async fn get_book_by_id(req: HttpRequest, data: web::Data<AppState>, id: web::Path<u32>) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+    let id = id.into_inner();
+
+    let modified = block(repository, |r| r.last_modified()).await?;
+    if let Some(resp) = conditional::not_modified(&req, modified) {
+        return Ok(resp);
+    }
+
+    let filtered_book: Vec<Book> = anonymous::shown(&req, block(repository, move |r| r.get(id)).await?)
+        .into_iter()
+        .collect();
+
+    let mut resp = HttpResponse::Ok().json(links::with_links(&filtered_book, true));
+    conditional::insert_headers(&req, &mut resp, modified);
+
+    Ok(resp)
+}
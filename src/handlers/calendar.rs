@@ -0,0 +1,138 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use time::macros::format_description;
+use time::{Date, OffsetDateTime};
+
+use super::block;
+use crate::anonymous;
+use crate::flags::Flag;
+use crate::{AppState, Book, BookError};
+
+/// RFC 5545 の 1 行の上限 (オクテット)。
+const MAX_LINE_OCTETS: usize = 75;
+
+/// TEXT の値をエスケープする。CR が残るとプロパティを書き足せてしまうので、改行と同じ `\n` にする。
+fn escape_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace('\n', "\\n")
+}
+
+/// 75 オクテットを超える行を折り返す (RFC 5545 §3.1)。続きの行は空白で始め、UTF-8 の文字の途中では切らない。
+fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + line.len() / MAX_LINE_OCTETS * 3);
+    let mut octets = 0;
+
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            folded.push_str("\r\n ");
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+
+    folded
+}
+
+fn format_date(date: Date) -> String {
+    date.format(format_description!("[year][month][day]")).unwrap_or_default()
+}
+
+/// 貸出中の書籍の返却期限を終日イベントとして並べた iCalendar を作る。
+pub fn books_to_ics(books: &[Book], now: OffsetDateTime) -> String {
+    let stamp = now
+        .format(format_description!("[year][month][day]T[hour][minute][second]Z"))
+        .unwrap_or_default();
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//books_backend//Library//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "X-WR-CALNAME:Library".to_string(),
+    ];
+
+    for book in books {
+        let Some(loan) = &book.loan else {
+            continue;
+        };
+
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:loan-{}@books_backend", book.id),
+            format!("DTSTAMP:{}", stamp),
+            format!("DTSTART;VALUE=DATE:{}", format_date(loan.due)),
+            format!("DTEND;VALUE=DATE:{}", format_date(loan.due.next_day().unwrap_or(loan.due))),
+            format!("SUMMARY:{}", escape_text(&format!("Due: {} (lent to {})", book.title, loan.borrower))),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold_line(line) + "\r\n").collect()
+}
+
+#[get("/calendar.ics")]
+pub async fn calendar(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    data.flags().check(Flag::Export)?;
+    let repository = &data.repository;
+
+    let books = anonymous::listed(&req, block(repository, |r| r.list()).await?);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(books_to_ics(&books, OffsetDateTime::now_utc())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Loan;
+    use time::macros::{date, datetime};
+
+    #[test]
+    fn test_books_to_ics_lists_loans() {
+        let books = vec![
+            Book {
+                id: 1,
+                title: "Rust Basics".to_string(),
+                loan: Some(Loan {
+                    borrower: "Tanaka, Ken".to_string(),
+                    due: date!(2026 - 10 - 31),
+                }),
+                ..Default::default()
+            },
+            Book {
+                id: 2,
+                title: "Async in Rust".to_string(),
+                ..Default::default()
+            },
+        ];
+
+        let ics = books_to_ics(&books, datetime!(2026-10-15 09:30 UTC));
+
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.contains("UID:loan-1@books_backend\r\nDTSTAMP:20261015T093000Z\r\n"));
+        assert!(ics.contains("DTSTART;VALUE=DATE:20261031\r\nDTEND;VALUE=DATE:20261101\r\n"));
+        assert!(ics.contains("SUMMARY:Due: Rust Basics (lent to Tanaka\\, Ken)\r\n"));
+        assert!(!ics.contains("loan-2"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+    }
+
+    #[test]
+    fn test_escape_and_fold() {
+        assert_eq!(escape_text("a\r\nb\rDTSTART:1"), "a\\nb\\nDTSTART:1");
+
+        let line = format!("SUMMARY:{}", "あ".repeat(30));
+        let folded = fold_line(&line);
+        assert!(folded.split("\r\n").all(|l| l.len() <= MAX_LINE_OCTETS));
+        assert!(folded.split("\r\n").skip(1).all(|l| l.starts_with(' ')));
+        assert_eq!(folded.replace("\r\n ", ""), line);
+        assert_eq!(fold_line("END:VEVENT"), "END:VEVENT");
+    }
+}
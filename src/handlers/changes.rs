@@ -0,0 +1,46 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+
+use super::block;
+use crate::events::BookEventKind;
+use crate::storage::Since;
+use crate::sync::SyncRequest;
+use crate::{anonymous, AppState, BookError};
+
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    since: String,
+}
+
+/// `since` (前回の `cursor` か RFC 3339 の時刻) より後に作成・更新・削除された書籍を返す。
+/// 初めて同期するクライアントは、全件を取得する前に `since=<今の時刻>` で `cursor` を受け取っておく。
+/// 記録が残っていない位置からなら 410 で、全件を取り直してもらう。
+#[get("/books/changes")]
+#[tracing::instrument(skip_all)]
+pub async fn changes(req: HttpRequest, data: web::Data<AppState>, query: web::Query<ChangesQuery>) -> Result<impl Responder, BookError> {
+    let since: Since = query.since.parse()
+        .map_err(|_| BookError::BadRequest("since: expected a cursor or an RFC 3339 timestamp".to_string()))?;
+
+    let mut changes = block(&data.repository, move |r| r.changes(since)).await?;
+
+    // 認証していなければ、一覧に出さない本はなくなったものとして伝える
+    if let Some(anonymous) = anonymous::viewer(&req) {
+        for change in &mut changes.changes {
+            if change.book.as_ref().is_some_and(|book| !anonymous.lists(book)) {
+                change.kind = BookEventKind::Deleted;
+                change.book = None;
+            }
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(changes))
+}
+
+/// オフラインで編集した変更を送り、衝突の解決結果とサーバー側の変更を受け取る (`crate::sync`)。
+#[post("/sync")]
+#[tracing::instrument(skip_all)]
+pub async fn sync(data: web::Data<AppState>, request: web::Json<SyncRequest>) -> Result<impl Responder, BookError> {
+    let response = block(&data.repository, move |r| r.sync(request.into_inner())).await?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
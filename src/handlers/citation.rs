@@ -0,0 +1,192 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+
+use super::block;
+use crate::{anonymous, AppState, Book, BookError};
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CitationStyle {
+    #[default]
+    Apa,
+    Mla,
+    Chicago,
+}
+
+#[derive(Deserialize)]
+pub struct CitationQuery {
+    #[serde(default)]
+    style: CitationStyle,
+}
+
+/// "Steve Klabnik" を (姓, 名) に分ける。1 語だけならそれを姓とみなす。
+fn split_name(name: &str) -> (&str, &str) {
+    match name.trim().rsplit_once(' ') {
+        Some((given, family)) => (family, given.trim()),
+        None => (name.trim(), ""),
+    }
+}
+
+fn inverted(name: &str) -> String {
+    match split_name(name) {
+        (family, "") => family.to_string(),
+        (family, given) => format!("{}, {}", family, given),
+    }
+}
+
+fn apa_name(name: &str) -> String {
+    let (family, given) = split_name(name);
+
+    let initials: Vec<String> = given.split_whitespace()
+        .filter_map(|part| part.chars().next())
+        .map(|c| format!("{}.", c))
+        .collect();
+
+    if initials.is_empty() {
+        family.to_string()
+    } else {
+        format!("{}, {}", family, initials.join(" "))
+    }
+}
+
+fn apa_authors(authors: &[String]) -> String {
+    let names: Vec<String> = authors.iter().map(|a| apa_name(a)).collect();
+
+    match names.as_slice() {
+        [] => String::new(),
+        [only] => only.clone(),
+        [rest @ .., last] => format!("{}, & {}", rest.join(", "), last),
+    }
+}
+
+/// 筆頭著者だけ「姓, 名」にし、以降は通常の順で並べる (MLA / Chicago 共通)。
+fn bibliography_authors(authors: &[String], et_al_from: usize) -> String {
+    match authors {
+        [] => String::new(),
+        [only] => inverted(only),
+        [first, second] => format!("{}, and {}", inverted(first), second),
+        [first, ..] if authors.len() >= et_al_from => format!("{}, et al", inverted(first)),
+        [first, middle @ .., last] => {
+            let mut names = vec![inverted(first)];
+            names.extend(middle.iter().cloned());
+            format!("{}, and {}", names.join(", "), last)
+        }
+    }
+}
+
+fn sentence(text: &str) -> String {
+    let text = text.trim();
+
+    if text.ends_with(['.', '?', '!']) {
+        text.to_string()
+    } else {
+        format!("{}.", text)
+    }
+}
+
+pub fn format_citation(book: &Book, style: CitationStyle) -> String {
+    let mut parts = Vec::new();
+
+    match style {
+        CitationStyle::Apa => {
+            if !book.authors.is_empty() {
+                parts.push(apa_authors(&book.authors));
+            }
+            parts.push(match book.published_year {
+                Some(year) => format!("({}).", year),
+                None => "(n.d.).".to_string(),
+            });
+            parts.push(sentence(&book.title));
+            if let Some(publisher) = &book.publisher {
+                parts.push(sentence(publisher));
+            }
+        }
+        CitationStyle::Mla | CitationStyle::Chicago => {
+            // MLA は 3 名以上で et al. にまとめ、Chicago は全員を並べる
+            let et_al_from = if style == CitationStyle::Mla { 3 } else { usize::MAX };
+
+            if !book.authors.is_empty() {
+                parts.push(sentence(&bibliography_authors(&book.authors, et_al_from)));
+            }
+            parts.push(sentence(&book.title));
+
+            let imprint: Vec<String> = book.publisher.iter().cloned()
+                .chain(book.published_year.map(|y| y.to_string()))
+                .collect();
+            if !imprint.is_empty() {
+                parts.push(sentence(&imprint.join(", ")));
+            }
+        }
+    }
+
+    parts.join(" ")
+}
+
+#[get("/books/{id}/citation")]
+pub async fn get_citation(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    id: web::Path<u32>,
+    query: web::Query<CitationQuery>,
+) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+
+    let id = id.into_inner();
+    let book = anonymous::shown(&req, block(repository, move |r| r.get(id)).await?).ok_or(BookError::NotFound)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(format_citation(&book, query.style)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rust_book(authors: &[&str]) -> Book {
+        Book {
+            id: 1,
+            title: "The Rust Programming Language".to_string(),
+            authors: authors.iter().map(|a| a.to_string()).collect(),
+            published_year: Some(2018),
+            publisher: Some("No Starch Press".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_format_citation_styles() {
+        let book = rust_book(&["Steve Klabnik", "Carol Nichols"]);
+
+        assert_eq!(
+            format_citation(&book, CitationStyle::Apa),
+            "Klabnik, S., & Nichols, C. (2018). The Rust Programming Language. No Starch Press."
+        );
+        assert_eq!(
+            format_citation(&book, CitationStyle::Mla),
+            "Klabnik, Steve, and Carol Nichols. The Rust Programming Language. No Starch Press, 2018."
+        );
+
+        let book = rust_book(&["Steve Klabnik", "Carol Nichols", "Chris Krycho"]);
+
+        assert_eq!(
+            format_citation(&book, CitationStyle::Mla),
+            "Klabnik, Steve, et al. The Rust Programming Language. No Starch Press, 2018."
+        );
+        assert_eq!(
+            format_citation(&book, CitationStyle::Chicago),
+            "Klabnik, Steve, Carol Nichols, and Chris Krycho. The Rust Programming Language. No Starch Press, 2018."
+        );
+    }
+
+    #[test]
+    fn test_format_citation_without_metadata() {
+        let book = Book {
+            title: "Rust Basics".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(format_citation(&book, CitationStyle::Apa), "(n.d.). Rust Basics.");
+        assert_eq!(format_citation(&book, CitationStyle::Chicago), "Rust Basics.");
+    }
+}
@@ -0,0 +1,208 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use rust_xlsxwriter::{Format, Workbook, XlsxError};
+use serde::Deserialize;
+
+use super::block;
+use crate::anonymous;
+use crate::flags::Flag;
+use crate::limits::{self, ResultLimits};
+use crate::{AppState, Book, BookError, BookQuery};
+
+const XLSX_CONTENT_TYPE: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+impl From<XlsxError> for BookError {
+    fn from(err: XlsxError) -> Self {
+        BookError::Serialize(err.to_string())
+    }
+}
+
+/// 1 冊 1 行のスプレッドシートを作る。
+pub fn books_to_xlsx(books: &[Book]) -> Result<Vec<u8>, XlsxError> {
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+    sheet.set_name("Books")?;
+
+    let bold = Format::new().set_bold();
+    for (col, header) in ["ID", "Title", "Authors", "Tags"].iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, *header, &bold)?;
+    }
+
+    for (i, book) in books.iter().enumerate() {
+        let row = i as u32 + 1;
+
+        sheet.write_number(row, 0, book.id)?;
+        sheet.write_string(row, 1, &book.title)?;
+        sheet.write_string(row, 2, book.authors.join(", "))?;
+        sheet.write_string(row, 3, book.tags.join(", "))?;
+    }
+
+    sheet.set_freeze_panes(1, 0)?;
+    sheet.autofit();
+
+    workbook.save_to_buffer()
+}
+
+#[get("/export/xlsx")]
+pub async fn export_xlsx(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    data.flags().check(Flag::Export)?;
+    let repository = &data.repository;
+
+    let mut books = anonymous::listed(&req, block(repository, |r| r.list()).await?);
+    let truncated = limits::truncate(&mut books, ResultLimits::of(&req).max_export);
+    let body = books_to_xlsx(&books)?;
+
+    let mut resp = HttpResponse::Ok()
+        .content_type(XLSX_CONTENT_TYPE)
+        .insert_header(("Content-Disposition", "attachment; filename=\"books.xlsx\""))
+        .body(body);
+    limits::insert_truncated_header(&mut resp, truncated);
+
+    Ok(resp)
+}
+
+fn escape_bibtex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// 引用キーは「筆頭著者の姓 + 出版年 + _id」。id を含めることで重複を避ける。
+fn citation_key(book: &Book) -> String {
+    let surname = book.authors
+        .first()
+        .and_then(|a| a.split_whitespace().last())
+        .unwrap_or("book");
+
+    let mut key: String = surname.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if key.is_empty() {
+        key.push_str("book");
+    }
+    if let Some(year) = book.published_year {
+        key.push_str(&year.to_string());
+    }
+
+    format!("{}_{}", key, book.id)
+}
+
+pub fn books_to_bibtex(books: &[Book]) -> String {
+    books.iter()
+        .map(|book| {
+            let mut fields = vec![("title", book.title.clone())];
+
+            if !book.authors.is_empty() {
+                fields.push(("author", book.authors.join(" and ")));
+            }
+            if let Some(year) = book.published_year {
+                fields.push(("year", year.to_string()));
+            }
+            if let Some(publisher) = &book.publisher {
+                fields.push(("publisher", publisher.clone()));
+            }
+            if let Some(isbn) = &book.isbn {
+                fields.push(("isbn", isbn.clone()));
+            }
+
+            let body: String = fields.iter()
+                .map(|(name, value)| format!("  {} = {{{}}},\n", name, escape_bibtex(value)))
+                .collect();
+
+            format!("@book{{{},\n{}}}\n", citation_key(book), body)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Deserialize)]
+pub struct BibtexQuery {
+    tag: Option<String>,
+}
+
+#[get("/export/bibtex")]
+pub async fn export_bibtex(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    query: web::Query<BibtexQuery>,
+) -> Result<impl Responder, BookError> {
+    data.flags().check(Flag::Export)?;
+    let repository = &data.repository;
+
+    let query = BookQuery {
+        tag: query.into_inner().tag,
+        ..Default::default()
+    };
+    let mut books = anonymous::listed(&req, block(repository, move |r| r.search(&query)).await?);
+    let truncated = limits::truncate(&mut books, ResultLimits::of(&req).max_export);
+
+    let mut resp = HttpResponse::Ok()
+        .content_type("application/x-bibtex; charset=utf-8")
+        .insert_header(("Content-Disposition", "attachment; filename=\"books.bib\""))
+        .body(books_to_bibtex(&books));
+    limits::insert_truncated_header(&mut resp, truncated);
+
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_books_to_bibtex() {
+        let books = vec![
+            Book {
+                id: 1,
+                title: "The Rust Programming Language".to_string(),
+                authors: vec!["Steve Klabnik".to_string(), "Carol Nichols".to_string()],
+                published_year: Some(2018),
+                publisher: Some("No Starch Press".to_string()),
+                isbn: Some("978-1593278281".to_string()),
+                ..Default::default()
+            },
+            Book {
+                id: 2,
+                title: "Pattern Matching & match".to_string(),
+                ..Default::default()
+            },
+        ];
+
+        assert_eq!(
+            books_to_bibtex(&books),
+            "@book{klabnik2018_1,\n  title = {The Rust Programming Language},\n  author = {Steve Klabnik and Carol Nichols},\n  year = {2018},\n  publisher = {No Starch Press},\n  isbn = {978-1593278281},\n}\n\n@book{book_2,\n  title = {Pattern Matching \\& match},\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_escape_bibtex() {
+        assert_eq!(escape_bibtex("C:\\~user^2 {50%}"), "C:\\textbackslash{}\\textasciitilde{}user\\textasciicircum{}2 \\{50\\%\\}");
+    }
+
+    #[test]
+    fn test_books_to_xlsx_is_zip_archive() {
+        let books = vec![Book {
+            id: 1,
+            title: "Rust Basics".to_string(),
+            ..Default::default()
+        }];
+
+        let bytes = books_to_xlsx(&books).unwrap();
+
+        // xlsx は zip コンテナ
+        assert_eq!(&bytes[..2], b"PK");
+    }
+}
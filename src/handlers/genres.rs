@@ -0,0 +1,25 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+
+use super::block;
+use crate::{anonymous, conditional, genres, AppState, BookError};
+
+/// ジャンルの一覧の順に、各ジャンルが付いた書籍数と、ジャンルのない書籍数。
+#[get("/genres")]
+#[tracing::instrument(skip_all)]
+pub async fn get_genres(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+
+    let modified = block(repository, |r| r.last_modified()).await?;
+    if let Some(resp) = conditional::not_modified(&req, modified) {
+        return Ok(resp);
+    }
+
+    // 認証していなければ、一覧に出す本だけで数える
+    let (taxonomy, books) = block(repository, |r| Ok((r.genres(), r.list()?))).await?;
+    let books = anonymous::listed(&req, books);
+
+    let mut resp = HttpResponse::Ok().json(genres::stats(&taxonomy, &books));
+    conditional::insert_headers(&req, &mut resp, modified);
+
+    Ok(resp)
+}
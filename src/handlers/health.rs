@@ -0,0 +1,49 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+
+use super::block;
+use crate::{AppState, BookError};
+
+#[derive(Serialize)]
+struct Check {
+    name: &'static str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Status {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    checks: Vec<Check>,
+}
+
+fn check<T>(name: &'static str, result: Result<T, BookError>) -> Check {
+    match result {
+        Ok(_) => Check { name, ok: true, error: None },
+        Err(e) => Check { name, ok: false, error: Some(e.to_string()) },
+    }
+}
+
+#[get("/healthz")]
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(Status { status: "ok", checks: Vec::new() })
+}
+
+#[get("/readyz")]
+pub async fn readyz(data: web::Data<AppState>) -> impl Responder {
+    let (repository, webhooks) = (&data.repository, &data.webhooks);
+
+    // データファイルが読めてパースできるかを確認する
+    let checks = vec![
+        check("books", block(repository, |r| r.list()).await),
+        check("webhooks", webhooks.list()),
+    ];
+
+    if checks.iter().all(|c| c.ok) {
+        HttpResponse::Ok().json(Status { status: "ready", checks })
+    } else {
+        HttpResponse::ServiceUnavailable().json(Status { status: "unavailable", checks })
+    }
+}
@@ -0,0 +1,13 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::ratelimit::RateLimits;
+
+/// Prometheus 形式の集計値。
+#[get("/metrics")]
+pub async fn metrics(limits: Option<web::Data<RateLimits>>) -> impl Responder {
+    let body = limits.map(|l| l.render_metrics()).unwrap_or_default();
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
@@ -0,0 +1,130 @@
+use actix_web::web;
+
+use crate::storage::BookRepository;
+use crate::{api_keys, audit, data_export, erasure, invites, jwt, share, webhooks, BookError};
+
+pub mod account;
+pub mod admin;
+pub mod authors;
+pub mod books;
+pub mod calendar;
+pub mod changes;
+pub mod citation;
+pub mod export;
+pub mod genres;
+pub mod health;
+pub mod metrics;
+pub mod publishers;
+pub mod quotes;
+pub mod sse;
+pub mod stats;
+pub mod suggest;
+pub mod tags;
+pub mod version;
+pub mod works;
+pub mod ws;
+pub mod zotero;
+
+/// ファイルの読み書きを伴うリポジトリ操作をブロッキング用スレッドで実行し、
+/// 遅いディスクや大きなデータファイルがワーカーを止めないようにする。
+pub(crate) async fn block<T, F>(repository: &BookRepository, f: F) -> Result<T, BookError>
+where
+    T: Send + 'static,
+    F: FnOnce(&BookRepository) -> Result<T, BookError> + Send + 'static,
+{
+    let repository = repository.clone();
+    web::block(move || f(&repository)).await?
+}
+
+/// 全ルートを登録する。`App::configure` に渡して使う。
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(books::hello)
+        .service(health::healthz)
+        .service(health::readyz)
+        .service(version::version)
+        .service(metrics::metrics)
+        .service(books::get_books)
+        .service(books::stream_books)
+        .service(books::get_book_by_id)
+        .service(books::get_book_with_query)
+        .service(books::full_text_search)
+        .service(changes::changes)
+        .service(changes::sync)
+        .service(citation::get_citation)
+        .service(share::share_book)
+        .service(share::list_shares)
+        .service(share::revoke_share)
+        .service(share::get_shared_book)
+        .service(tags::get_tags)
+        .service(tags::get_tag_tree)
+        .service(tags::bulk_tags)
+        .service(genres::get_genres)
+        .service(stats::get_stats)
+        .service(authors::list_authors)
+        .service(authors::import_authors)
+        .service(authors::create_author)
+        .service(authors::get_author)
+        .service(authors::get_author_books)
+        .service(authors::update_author)
+        .service(authors::delete_author)
+        .service(authors::merge_authors)
+        .service(publishers::list_publishers)
+        .service(publishers::create_publisher)
+        .service(publishers::get_publisher)
+        .service(publishers::get_publisher_books)
+        .service(publishers::update_publisher)
+        .service(publishers::delete_publisher)
+        .service(works::get_editions)
+        .service(quotes::list_quotes)
+        .service(quotes::delete_quote)
+        .service(quotes::get_book_quotes)
+        .service(quotes::add_quote)
+        .service(suggest::suggest)
+        .service(books::add_or_update_book)
+        .service(books::set_progress)
+        .service(ws::book_events_ws)
+        .service(sse::book_events_sse)
+        .service(export::export_xlsx)
+        .service(export::export_bibtex)
+        .service(calendar::calendar)
+        .service(zotero::import_zotero)
+        .service(admin::dashboard)
+        .service(admin::new_book)
+        .service(admin::edit_book)
+        .service(admin::save_book)
+        .service(admin::users)
+        .service(admin::backup)
+        .service(admin::swap_data)
+        .service(admin::set_maintenance)
+        .service(admin::seed)
+        .service(admin::check)
+        .service(admin::storage)
+        .service(admin::reload_config)
+        .service(admin::list_flags)
+        .service(admin::set_flag)
+        .service(admin::get_synonyms)
+        .service(admin::set_synonyms)
+        .service(admin::preview_synonyms)
+        .service(admin::get_genres)
+        .service(admin::set_genres)
+        .service(webhooks::list_webhooks)
+        .service(webhooks::create_webhook)
+        .service(webhooks::list_deliveries)
+        .service(webhooks::delete_webhook)
+        .service(account::register)
+        .service(account::change_password)
+        .service(data_export::start_export)
+        .service(data_export::download_export)
+        .service(jwt::issue_token)
+        .service(invites::create_invite)
+        .service(invites::list_invites)
+        .service(api_keys::create_api_key)
+        .service(api_keys::list_api_keys)
+        .service(api_keys::revoke_api_key)
+        .service(audit::list_audit)
+        .service(erasure::erase_user)
+        .service(jwt::impersonate)
+        .service(jwt::list_keys)
+        .service(jwt::rotate_keys);
+}
@@ -0,0 +1,76 @@
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+
+use super::block;
+use crate::publishers::{Edit, PublisherInput};
+use crate::registry::{self, Summary};
+use crate::{anonymous, AppState, BookError};
+
+/// 出版社の一覧。各出版社に書籍数を添える。認証していなければ、一覧に出す本だけで数える。
+#[get("/publishers")]
+#[tracing::instrument(skip_all)]
+pub async fn list_publishers(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    let (publishers, books) = block(&data.repository, |r| Ok((r.publishers(), r.list()?))).await?;
+    let books = anonymous::listed(&req, books);
+
+    let summaries: Vec<Summary<_>> = publishers.list().iter()
+        .map(|publisher| Summary::new(publisher, &books))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(summaries))
+}
+
+#[get("/publishers/{id}")]
+#[tracing::instrument(skip_all)]
+pub async fn get_publisher(req: HttpRequest, data: web::Data<AppState>, id: web::Path<u32>) -> Result<impl Responder, BookError> {
+    let id = id.into_inner();
+    let (publishers, books) = block(&data.repository, |r| Ok((r.publishers(), r.list()?))).await?;
+    let books = anonymous::listed(&req, books);
+
+    let publisher = publishers.get(id).ok_or(BookError::NotFound)?;
+    let summary = Summary::new(publisher, &books);
+
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+/// 出版社の書籍。名前か別名で書かれた書籍を返す。
+#[get("/publishers/{id}/books")]
+#[tracing::instrument(skip_all)]
+pub async fn get_publisher_books(req: HttpRequest, data: web::Data<AppState>, id: web::Path<u32>) -> Result<impl Responder, BookError> {
+    let id = id.into_inner();
+    let (publishers, books) = block(&data.repository, |r| Ok((r.publishers(), r.list()?))).await?;
+    let books = anonymous::listed(&req, books);
+
+    let publisher = publishers.get(id).ok_or(BookError::NotFound)?;
+    let books: Vec<_> = registry::books_of(publisher, &books).collect();
+
+    Ok(HttpResponse::Ok().json(books))
+}
+
+#[post("/publishers")]
+#[tracing::instrument(skip_all)]
+pub async fn create_publisher(data: web::Data<AppState>, input: web::Json<PublisherInput>) -> Result<impl Responder, BookError> {
+    let input = input.into_inner();
+    let created = block(&data.repository, move |r| r.edit_publishers(Edit::Create(input))).await?;
+
+    Ok(HttpResponse::Created().json(created))
+}
+
+/// 出版社を更新する。名前を変えると、その出版社の書籍の出版社名も書き換える。
+#[put("/publishers/{id}")]
+#[tracing::instrument(skip_all)]
+pub async fn update_publisher(data: web::Data<AppState>, id: web::Path<u32>, input: web::Json<PublisherInput>) -> Result<impl Responder, BookError> {
+    let (id, input) = (id.into_inner(), input.into_inner());
+    let updated = block(&data.repository, move |r| r.edit_publishers(Edit::Update(id, input))).await?;
+
+    Ok(HttpResponse::Ok().json(updated))
+}
+
+/// 出版社の記録を消す。書籍に付いていれば 409。
+#[delete("/publishers/{id}")]
+#[tracing::instrument(skip_all)]
+pub async fn delete_publisher(data: web::Data<AppState>, id: web::Path<u32>) -> Result<impl Responder, BookError> {
+    let id = id.into_inner();
+    block(&data.repository, move |r| r.edit_publishers(Edit::Delete(id))).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
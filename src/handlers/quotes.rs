@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Responder};
+use rand::Rng;
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+use super::block;
+use crate::normalize::fold;
+use crate::quotes::{Edit, QuoteInput, QuoteView};
+use crate::{anonymous, AppState, BookError};
+
+#[derive(Deserialize)]
+pub struct QuoteQuery {
+    /// 本文とメモの検索
+    q: Option<String>,
+    book: Option<u32>,
+    /// 一致したものから 1 件だけ選んで返す
+    #[serde(default)]
+    random: bool,
+}
+
+/// 引用の一覧。認証していなければ、一覧に出す本の引用だけを返す。
+#[get("/quotes")]
+#[tracing::instrument(skip_all)]
+pub async fn list_quotes(req: HttpRequest, data: web::Data<AppState>, query: web::Query<QuoteQuery>) -> Result<impl Responder, BookError> {
+    let (quotes, books) = block(&data.repository, |r| Ok((r.quotes(), r.list()?))).await?;
+    let titles: HashMap<u32, String> = anonymous::listed(&req, books).into_iter().map(|b| (b.id, b.title)).collect();
+
+    let q = query.q.as_deref().map(|q| fold(q.trim())).filter(|q| !q.is_empty());
+    let mut found: Vec<QuoteView> = quotes.list().iter()
+        .filter(|quote| query.book.is_none_or(|id| quote.book_id == id))
+        .filter(|quote| q.as_deref().is_none_or(|q| quote.matches(q)))
+        .filter_map(|quote| {
+            let book_title = titles.get(&quote.book_id)?.clone();
+            Some(QuoteView { quote: quote.clone(), book_title })
+        })
+        .collect();
+
+    if query.random {
+        if found.is_empty() {
+            return Err(BookError::NotFound);
+        }
+        let pick = rand::thread_rng().gen_range(0..found.len());
+        return Ok(HttpResponse::Ok().json(found.swap_remove(pick)));
+    }
+
+    Ok(HttpResponse::Ok().json(found))
+}
+
+/// 書籍の引用。書籍が見えなければ 404。
+#[get("/books/{id}/quotes")]
+#[tracing::instrument(skip_all)]
+pub async fn get_book_quotes(req: HttpRequest, data: web::Data<AppState>, id: web::Path<u32>) -> Result<impl Responder, BookError> {
+    let id = id.into_inner();
+    let (quotes, book) = block(&data.repository, move |r| Ok((r.quotes(), r.get(id)?))).await?;
+    let book = anonymous::shown(&req, book).ok_or(BookError::NotFound)?;
+
+    let found: Vec<QuoteView> = quotes.list().iter()
+        .filter(|quote| quote.book_id == id)
+        .map(|quote| QuoteView { quote: quote.clone(), book_title: book.title.clone() })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(found))
+}
+
+#[post("/books/{id}/quotes")]
+#[tracing::instrument(skip_all)]
+pub async fn add_quote(data: web::Data<AppState>, id: web::Path<u32>, input: web::Json<QuoteInput>) -> Result<impl Responder, BookError> {
+    let edit = Edit::Add { book_id: id.into_inner(), input: input.into_inner(), now: OffsetDateTime::now_utc().unix_timestamp() };
+    let quote = block(&data.repository, move |r| r.edit_quotes(edit)).await?;
+
+    Ok(HttpResponse::Created().json(quote))
+}
+
+#[delete("/quotes/{id}")]
+#[tracing::instrument(skip_all)]
+pub async fn delete_quote(data: web::Data<AppState>, id: web::Path<u32>) -> Result<impl Responder, BookError> {
+    let id = id.into_inner();
+    block(&data.repository, move |r| r.edit_quotes(Edit::Delete(id))).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
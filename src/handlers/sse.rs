@@ -0,0 +1,94 @@
+use std::convert::Infallible;
+use std::time::Duration;
+use actix_web::{get, web, web::Bytes, HttpRequest, HttpResponse};
+use tokio_stream::wrappers::{BroadcastStream, IntervalStream};
+use tokio_stream::StreamExt;
+
+use crate::events::{BookEvent, Replay};
+use crate::{anonymous, AppState};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+fn format_event(event: &BookEvent) -> Bytes {
+    let data = serde_json::to_string(event).unwrap_or_default();
+
+    Bytes::from(format!("id: {}\nevent: {}\ndata: {}\n\n", event.seq, event.kind.as_str(), data))
+}
+
+/// `Last-Event-ID` からのイベントを送り直せないときに送る。クライアントは一覧を取り直す。
+fn format_reset(last_seq: u64) -> Bytes {
+    Bytes::from(format!("id: {}\nevent: reset\ndata: {{}}\n\n", last_seq))
+}
+
+#[get("/events")]
+pub async fn book_events_sse(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    let events = data.repository.events().clone();
+    let viewer = anonymous::viewer(&req);
+    let visible = move |event: &BookEvent| anonymous::sees_event(viewer, event);
+
+    let last_event_id = req.headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok());
+
+    // 取りこぼしを防ぐため、履歴を取る前に購読を開始しておく
+    let live = events.subscribe();
+
+    let (replay, replayed_up_to) = match last_event_id.map(|seq| (seq, events.since(seq))) {
+        None => (Vec::new(), 0),
+        Some((seq, Replay::Events(backlog))) => {
+            let replayed_up_to = backlog.last().map_or(seq, |e| e.seq);
+            let frames = backlog.iter().filter(|event| visible(event)).map(format_event).collect();
+            (frames, replayed_up_to)
+        }
+        Some((_, Replay::Reset { last_seq })) => (vec![format_reset(last_seq)], last_seq),
+    };
+
+    let replay = tokio_stream::iter(replay);
+
+    let live = BroadcastStream::new(live)
+        .filter_map(|event| event.ok())
+        .filter(move |event| event.seq > replayed_up_to && visible(event))
+        .map(|event| format_event(&event));
+
+    let heartbeat = IntervalStream::new(tokio::time::interval(HEARTBEAT_INTERVAL))
+        .map(|_| Bytes::from_static(b": heartbeat\n\n"));
+
+    let stream = replay
+        .chain(live.merge(heartbeat))
+        .map(Ok::<_, Infallible>);
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::BookEventKind;
+
+    #[test]
+    fn test_format_event() {
+        let event = BookEvent {
+            seq: 7,
+            kind: BookEventKind::Updated,
+            id: 1,
+            title: "Rust Basics".to_string(),
+            visibility: None,
+        };
+
+        let frame = format_event(&event);
+
+        assert_eq!(
+            frame,
+            Bytes::from("id: 7\nevent: updated\ndata: {\"seq\":7,\"kind\":\"updated\",\"id\":1,\"title\":\"Rust Basics\"}\n\n")
+        );
+    }
+
+    #[test]
+    fn test_format_reset() {
+        assert_eq!(format_reset(12), Bytes::from("id: 12\nevent: reset\ndata: {}\n\n"));
+    }
+}
@@ -0,0 +1,23 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+
+use super::block;
+use crate::{anonymous, conditional, stats, AppState, BookError};
+
+/// 蔵書全体の集計。認証していなければ、一覧に出す本だけで数える。
+#[get("/stats")]
+#[tracing::instrument(skip_all)]
+pub async fn get_stats(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+
+    let modified = block(repository, |r| r.last_modified()).await?;
+    if let Some(resp) = conditional::not_modified(&req, modified) {
+        return Ok(resp);
+    }
+
+    let books = anonymous::listed(&req, block(repository, |r| r.list()).await?);
+
+    let mut resp = HttpResponse::Ok().json(stats::stats(&books));
+    conditional::insert_headers(&req, &mut resp, modified);
+
+    Ok(resp)
+}
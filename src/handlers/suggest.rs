@@ -0,0 +1,43 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+
+use super::block;
+use crate::{anonymous, conditional, AppState, BookError};
+
+/// 返す候補の数の上限。
+const MAX_LIMIT: usize = 50;
+
+#[derive(Deserialize)]
+pub struct SuggestQuery {
+    q: String,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    10
+}
+
+/// 入力途中の語に続くタイトル・タグ・著者名の候補。`type` で種類を示す。
+#[get("/suggest")]
+#[tracing::instrument(skip_all)]
+pub async fn suggest(req: HttpRequest, data: web::Data<AppState>, query: web::Query<SuggestQuery>) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+
+    let modified = block(repository, |r| r.last_modified()).await?;
+    if let Some(resp) = conditional::not_modified(&req, modified) {
+        return Ok(resp);
+    }
+
+    // 認証していなければ、一覧に出す本の値だけを候補にする
+    let viewer = anonymous::viewer(&req);
+    let limit = query.limit.min(MAX_LIMIT);
+    let suggestions = block(repository, move |r| {
+        r.suggest(&query.q, limit, |book| viewer.is_none_or(|anonymous| anonymous.lists(book)))
+    }).await?;
+
+    let mut resp = HttpResponse::Ok().json(suggestions);
+    conditional::insert_headers(&req, &mut resp, modified);
+
+    Ok(resp)
+}
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+
+use super::block;
+use crate::bulk_tags::{BulkTagRequest, Target};
+use crate::{anonymous, audit, conditional, tag_tree, AppState, Book, BookError, BookQuery};
+
+#[derive(Serialize)]
+struct TagCount {
+    tag: String,
+    count: usize,
+}
+
+fn count_tags(books: &[Book]) -> Vec<(String, usize)> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for book in books {
+        for tag in &book.tags {
+            *counts.entry(tag.clone()).or_default() += 1;
+        }
+    }
+    counts.into_iter().collect()
+}
+
+/// タグと、そのタグが付いた書籍数の一覧。
+#[get("/tags")]
+#[tracing::instrument(skip_all)]
+pub async fn get_tags(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+
+    let modified = block(repository, |r| r.last_modified()).await?;
+    if let Some(resp) = conditional::not_modified(&req, modified) {
+        return Ok(resp);
+    }
+
+    // 認証していなければ、一覧に出す本だけで数え直す
+    let tags = match anonymous::viewer(&req) {
+        None => block(repository, |r| r.tags()).await?,
+        Some(_) => count_tags(&anonymous::listed(&req, block(repository, |r| r.list()).await?)),
+    };
+    let tags: Vec<TagCount> = tags
+        .into_iter()
+        .map(|(tag, count)| TagCount { tag, count })
+        .collect();
+
+    let mut resp = HttpResponse::Ok().json(tags);
+    conditional::insert_headers(&req, &mut resp, modified);
+
+    Ok(resp)
+}
+
+/// タグを `/` の階層に組み直した木。各ノードに、そのタグと子孫のタグが付いた書籍数を付ける。
+#[get("/tags/tree")]
+#[tracing::instrument(skip_all)]
+pub async fn get_tag_tree(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+
+    let modified = block(repository, |r| r.last_modified()).await?;
+    if let Some(resp) = conditional::not_modified(&req, modified) {
+        return Ok(resp);
+    }
+
+    let books = anonymous::listed(&req, block(repository, |r| r.list()).await?);
+
+    let mut resp = HttpResponse::Ok().json(tag_tree::tree(&books));
+    conditional::insert_headers(&req, &mut resp, modified);
+
+    Ok(resp)
+}
+
+/// 書籍のタグをまとめて付け外しする。対象は `ids` か検索式 `q` で選ぶ。
+#[post("/books/tags/bulk")]
+#[tracing::instrument(skip_all)]
+pub async fn bulk_tags(req: HttpRequest, data: web::Data<AppState>, body: web::Json<BulkTagRequest>) -> Result<impl Responder, BookError> {
+    let (target, change) = body.into_inner().validate()?;
+    let summary = format!("added {:?} and removed {:?} in bulk", change.add, change.remove);
+
+    let report = block(&data.repository, move |r| {
+        let ids = match target {
+            Target::Ids(ids) => ids,
+            Target::Query(q) => r.search(&BookQuery { q: Some(q), ..Default::default() })?.iter().map(|b| b.id).collect(),
+        };
+        r.bulk_tags(ids, change)
+    }).await?;
+    audit::note(&req, audit::Change::summary(summary));
+
+    Ok(HttpResponse::Ok().json(report))
+}
@@ -0,0 +1,33 @@
+use actix_web::{get, HttpResponse, Responder};
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::storage::BookRepository;
+
+#[derive(Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    git_commit: &'static str,
+    build_timestamp: String,
+    storage_backend: &'static str,
+}
+
+fn build_timestamp() -> String {
+    env!("BUILD_TIMESTAMP")
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+        .and_then(|t| t.format(&Rfc3339).ok())
+        .unwrap_or_default()
+}
+
+#[get("/version")]
+pub async fn version() -> impl Responder {
+    HttpResponse::Ok().json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("GIT_COMMIT"),
+        build_timestamp: build_timestamp(),
+        storage_backend: BookRepository::BACKEND,
+    })
+}
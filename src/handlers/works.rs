@@ -0,0 +1,28 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+
+use super::block;
+use crate::{anonymous, conditional, works, AppState, BookError};
+
+/// 作品の版。作品の id は最初の版の書籍の id。見せる版が 1 つもなければ 404。
+#[get("/works/{id}/editions")]
+#[tracing::instrument(skip_all)]
+pub async fn get_editions(req: HttpRequest, data: web::Data<AppState>, id: web::Path<u32>) -> Result<impl Responder, BookError> {
+    let repository = &data.repository;
+    let id = id.into_inner();
+
+    let modified = block(repository, |r| r.last_modified()).await?;
+    if let Some(resp) = conditional::not_modified(&req, modified) {
+        return Ok(resp);
+    }
+
+    let books = anonymous::listed(&req, block(repository, |r| r.list()).await?);
+    let editions: Vec<_> = works::editions(&books, id).collect();
+    if editions.is_empty() {
+        return Err(BookError::NotFound);
+    }
+
+    let mut resp = HttpResponse::Ok().json(editions);
+    conditional::insert_headers(&req, &mut resp, modified);
+
+    Ok(resp)
+}
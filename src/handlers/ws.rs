@@ -0,0 +1,62 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use actix_ws::Message;
+use futures_util::StreamExt;
+use log::error;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{anonymous, AppState};
+
+#[get("/ws")]
+pub async fn book_events_ws(
+    req: HttpRequest,
+    body: web::Payload,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut events = data.repository.events().subscribe();
+    let viewer = anonymous::viewer(&req);
+
+    let (response, mut session, mut msg_stream) = actix_ws::handle(&req, body)?;
+
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) if !anonymous::sees_event(viewer, &event) => continue,
+                    Ok(event) => {
+                        let json = match serde_json::to_string(&event) {
+                            Ok(json) => json,
+                            Err(e) => {
+                                error!("Failed to serialize book event: {}", e);
+                                continue;
+                            }
+                        };
+
+                        if session.text(json).await.is_err() {
+                            return;
+                        }
+                    }
+                    // 取りこぼしたイベントは諦めて次から配信する
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                msg = msg_stream.next() => match msg {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(Message::Close(reason))) => {
+                        let _ = session.close(reason).await;
+                        return;
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) | None => break,
+                },
+            }
+        }
+
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
@@ -0,0 +1,397 @@
+use std::collections::{BTreeMap, HashMap};
+use actix_web::{http::header, post, web, HttpRequest, HttpResponse, Responder};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::Serialize;
+
+use super::block;
+use crate::audit;
+use crate::flags::Flag;
+use crate::{AppState, Book, BookError};
+
+/// Zotero の item type のうち書籍として取り込むもの
+const BOOK_ITEM_TYPE: &str = "book";
+
+#[derive(Default, Debug)]
+struct ZoteroItem {
+    item_type: String,
+    title: String,
+    authors: Vec<String>,
+    tags: Vec<String>,
+    year: Option<i32>,
+    publisher: Option<String>,
+    isbn: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ImportReport {
+    imported: usize,
+    created: usize,
+    updated: usize,
+    /// 取り込まなかった item type ごとの件数
+    unmapped_item_types: BTreeMap<String, usize>,
+}
+
+fn parse_year(value: &str) -> Option<i32> {
+    value.as_bytes()
+        .windows(4)
+        .find(|w| w.iter().all(u8::is_ascii_digit))
+        .and_then(|w| std::str::from_utf8(w).ok())
+        .and_then(|y| y.parse().ok())
+}
+
+fn first_isbn(value: &str) -> Option<String> {
+    value.split_whitespace()
+        .find(|s| s.chars().any(|c| c.is_ascii_digit()))
+        .map(|s| s.to_string())
+}
+
+fn split_tags(value: &str) -> impl Iterator<Item = String> + '_ {
+    value.split(';')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+}
+
+/// Zotero の CSV エクスポート ("Last, First; Last, First") を解釈する。
+fn parse_csv(body: &[u8]) -> Result<Vec<ZoteroItem>, BookError> {
+    let mut reader = csv::Reader::from_reader(body);
+
+    let headers = reader.headers()
+        .map_err(|e| BookError::BadRequest(format!("Invalid Zotero CSV: {}", e)))?
+        .clone();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+
+    let item_type = column("Item Type")
+        .ok_or_else(|| BookError::BadRequest("Zotero CSV is missing the Item Type column".to_string()))?;
+    let (title, author, year, publisher, isbn) = (
+        column("Title"),
+        column("Author"),
+        column("Publication Year"),
+        column("Publisher"),
+        column("ISBN"),
+    );
+    let tag_columns: Vec<usize> = ["Manual Tags", "Automatic Tags"].iter().filter_map(|n| column(n)).collect();
+
+    let mut items = Vec::new();
+
+    for record in reader.records() {
+        let record = record.map_err(|e| BookError::BadRequest(format!("Invalid Zotero CSV: {}", e)))?;
+        let field = |index: Option<usize>| index.and_then(|i| record.get(i)).unwrap_or("").trim();
+
+        items.push(ZoteroItem {
+            item_type: field(Some(item_type)).to_string(),
+            title: field(title).to_string(),
+            authors: field(author)
+                .split(';')
+                .map(str::trim)
+                .filter(|a| !a.is_empty())
+                .map(|a| match a.split_once(',') {
+                    Some((family, given)) => format!("{} {}", given.trim(), family.trim()),
+                    None => a.to_string(),
+                })
+                .collect(),
+            tags: tag_columns.iter().flat_map(|&i| split_tags(field(Some(i)))).collect(),
+            year: parse_year(field(year)),
+            publisher: Some(field(publisher).to_string()).filter(|p| !p.is_empty()),
+            isbn: first_isbn(field(isbn)),
+        });
+    }
+
+    Ok(items)
+}
+
+fn attribute(e: &quick_xml::events::BytesStart, name: &[u8]) -> Option<String> {
+    e.attributes()
+        .flatten()
+        .find(|a| a.key.as_ref() == name)
+        .and_then(|a| a.unescape_value().ok())
+        .map(|v| v.into_owned())
+}
+
+/// Zotero RDF を解釈する。コレクションに含まれるアイテムにはコレクション名をタグとして付ける。
+fn parse_rdf(body: &str) -> Result<Vec<ZoteroItem>, BookError> {
+    let invalid = |e: quick_xml::Error| BookError::BadRequest(format!("Invalid Zotero RDF: {}", e));
+
+    let mut reader = Reader::from_str(body);
+    reader.config_mut().trim_text(true);
+
+    let mut items: Vec<(String, ZoteroItem)> = Vec::new();
+    let mut collections: Vec<(String, Vec<String>)> = Vec::new();
+
+    // rdf:RDF 直下の要素を 1 アイテムとして扱う
+    let mut path: Vec<String> = Vec::new();
+    let mut current: Option<(String, ZoteroItem)> = None;
+    let mut collection: Option<(String, Vec<String>)> = None;
+    let mut person: (String, String) = (String::new(), String::new());
+
+    loop {
+        match reader.read_event().map_err(invalid)? {
+            Event::Start(e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+
+                if path.len() == 1 {
+                    let about = attribute(&e, b"rdf:about").unwrap_or_default();
+
+                    if name == "z:Collection" {
+                        collection = Some((String::new(), Vec::new()));
+                    } else {
+                        // z:itemType がない要素 (bib:Memo など) は要素名から種類を推定する
+                        let local = name.rsplit(':').next().unwrap_or(&name);
+                        let item_type = match local {
+                            "Memo" => "note".to_string(),
+                            other => other.to_ascii_lowercase(),
+                        };
+                        current = Some((about, ZoteroItem { item_type, ..Default::default() }));
+                    }
+                }
+
+                path.push(name);
+            }
+            Event::Empty(e) if e.name().as_ref() == b"dcterms:hasPart" => {
+                if let (Some((_, members)), Some(resource)) = (collection.as_mut(), attribute(&e, b"rdf:resource")) {
+                    members.push(resource);
+                }
+            }
+            Event::Text(text) => {
+                let text = text.unescape().map_err(invalid)?.trim().to_string();
+                let element = path.last().map(String::as_str).unwrap_or("");
+                let in_element = |name: &str| path.iter().any(|p| p == name);
+
+                if let Some((title, _)) = collection.as_mut() {
+                    if element == "dc:title" && path.len() == 3 {
+                        *title = text;
+                    }
+                    continue;
+                }
+
+                let Some((_, item)) = current.as_mut() else {
+                    continue;
+                };
+
+                match element {
+                    "z:itemType" => item.item_type = text,
+                    "dc:title" if path.len() == 3 => item.title = text,
+                    "foaf:surname" if in_element("bib:authors") => person.0 = text,
+                    "foaf:givenName" if in_element("bib:authors") => person.1 = text,
+                    "foaf:name" if in_element("dc:publisher") => item.publisher = Some(text),
+                    "dc:subject" => item.tags.push(text),
+                    "rdf:value" if in_element("dc:subject") => item.tags.push(text),
+                    "dc:date" if path.len() == 3 => item.year = parse_year(&text),
+                    "dc:identifier" => {
+                        if let Some(isbn) = text.strip_prefix("ISBN") {
+                            item.isbn = first_isbn(isbn);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(e) => {
+                path.pop();
+
+                if e.name().as_ref() == b"foaf:Person" && path.iter().any(|p| p == "bib:authors") {
+                    let (family, given) = std::mem::take(&mut person);
+                    if let Some((_, item)) = current.as_mut() {
+                        item.authors.push(format!("{} {}", given, family).trim().to_string());
+                    }
+                }
+
+                if path.len() == 1 {
+                    if let Some(item) = current.take() {
+                        items.push(item);
+                    }
+                    if let Some(c) = collection.take() {
+                        collections.push(c);
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let index: HashMap<String, usize> = items.iter()
+        .enumerate()
+        .map(|(i, (about, _))| (about.clone(), i))
+        .collect();
+
+    for (title, members) in collections {
+        for member in members {
+            if let Some(&i) = index.get(&member) {
+                items[i].1.tags.push(title.clone());
+            }
+        }
+    }
+
+    Ok(items.into_iter().map(|(_, item)| item).collect())
+}
+
+fn is_rdf(req: &HttpRequest, body: &[u8]) -> bool {
+    let content_type = req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+
+    if content_type.contains("xml") {
+        return true;
+    }
+    if content_type.starts_with("text/csv") {
+        return false;
+    }
+
+    let start = body.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(0);
+    body[start..].starts_with(b"<")
+}
+
+/// 取り込み対象を Book に変換する。ISBN が一致する既存の書籍は上書きし、それ以外は新しい id を振る。
+fn into_books(items: Vec<ZoteroItem>, existing: &[Book], report: &mut ImportReport) -> Vec<Book> {
+    let mut next_id = existing.iter().map(|b| b.id).max().unwrap_or(0) + 1;
+    let mut books = Vec::new();
+
+    for mut item in items {
+        if item.item_type != BOOK_ITEM_TYPE {
+            *report.unmapped_item_types.entry(item.item_type).or_insert(0) += 1;
+            continue;
+        }
+
+        item.tags.sort();
+        item.tags.dedup();
+
+        let existing = item.isbn.as_ref()
+            .and_then(|isbn| existing.iter().find(|b| b.isbn.as_ref() == Some(isbn)));
+
+        let book = match existing {
+            Some(book) => Book {
+                title: item.title,
+                tags: item.tags,
+                authors: item.authors,
+                published_year: item.year,
+                publisher: item.publisher,
+                isbn: item.isbn,
+                ..book.clone()
+            },
+            None => {
+                next_id += 1;
+                Book {
+                    id: next_id - 1,
+                    title: item.title,
+                    tags: item.tags,
+                    authors: item.authors,
+                    published_year: item.year,
+                    publisher: item.publisher,
+                    isbn: item.isbn,
+                    ..Default::default()
+                }
+            }
+        };
+
+        books.push(book);
+    }
+
+    books
+}
+
+#[post("/import/zotero")]
+pub async fn import_zotero(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    body: web::Bytes,
+) -> Result<impl Responder, BookError> {
+    data.flags().check(Flag::Import)?;
+    let repository = &data.repository;
+
+    let items = if is_rdf(&req, &body) {
+        let text = std::str::from_utf8(&body)
+            .map_err(|_| BookError::BadRequest("Zotero RDF must be UTF-8".to_string()))?;
+        parse_rdf(text)?
+    } else {
+        parse_csv(&body)?
+    };
+
+    let mut report = ImportReport::default();
+    let existing = block(repository, |r| r.list()).await?;
+    let books = into_books(items, &existing, &mut report);
+
+    report.imported = books.len();
+    report.created = block(repository, |r| r.upsert_many(books)).await?;
+    report.updated = report.imported - report.created;
+    audit::note(&req, audit::Change::summary(format!(
+        "imported {} books ({} created, {} updated)",
+        report.imported, report.created, report.updated,
+    )));
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let csv = "\"Key\",\"Item Type\",\"Publication Year\",\"Author\",\"Title\",\"ISBN\",\"Publisher\",\"Manual Tags\",\"Automatic Tags\"\n\
+                   \"AB12\",\"book\",\"2018\",\"Klabnik, Steve; Nichols, Carol\",\"The Rust Programming Language\",\"978-1593278281 1593278284\",\"No Starch Press\",\"rust; programming\",\"\"\n\
+                   \"CD34\",\"journalArticle\",\"2020\",\"Doe, Jane\",\"Ownership Types\",\"\",\"\",\"\",\"\"\n";
+
+        let items = parse_csv(csv.as_bytes()).unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].authors, vec!["Steve Klabnik", "Carol Nichols"]);
+        assert_eq!(items[0].tags, vec!["rust", "programming"]);
+        assert_eq!(items[0].year, Some(2018));
+        assert_eq!(items[0].isbn.as_deref(), Some("978-1593278281"));
+        assert_eq!(items[1].item_type, "journalArticle");
+    }
+
+    #[test]
+    fn test_parse_rdf_maps_collections_to_tags() {
+        let rdf = r##"<?xml version="1.0"?>
+<rdf:RDF
+ xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+ xmlns:z="http://www.zotero.org/namespaces/export#"
+ xmlns:dc="http://purl.org/dc/elements/1.1/"
+ xmlns:dcterms="http://purl.org/dc/terms/"
+ xmlns:foaf="http://xmlns.com/foaf/0.1/"
+ xmlns:bib="http://purl.org/net/biblio#">
+    <bib:Book rdf:about="urn:isbn:978-1593278281">
+        <z:itemType>book</z:itemType>
+        <dc:publisher>
+            <foaf:Organization><foaf:name>No Starch Press</foaf:name></foaf:Organization>
+        </dc:publisher>
+        <bib:authors>
+            <rdf:Seq>
+                <rdf:li><foaf:Person><foaf:surname>Klabnik</foaf:surname><foaf:givenName>Steve</foaf:givenName></foaf:Person></rdf:li>
+            </rdf:Seq>
+        </bib:authors>
+        <dc:subject>rust</dc:subject>
+        <dc:subject><z:AutomaticTag><rdf:value>programming</rdf:value></z:AutomaticTag></dc:subject>
+        <dc:title>The Rust Programming Language</dc:title>
+        <dc:date>2018-06-26</dc:date>
+        <dc:identifier>ISBN 978-1593278281</dc:identifier>
+    </bib:Book>
+    <bib:Memo rdf:about="#item_2"><rdf:value>note</rdf:value></bib:Memo>
+    <z:Collection rdf:about="#collection_1">
+        <dc:title>Languages</dc:title>
+        <dcterms:hasPart rdf:resource="urn:isbn:978-1593278281"/>
+    </z:Collection>
+</rdf:RDF>"##;
+
+        let items = parse_rdf(rdf).unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].item_type, "book");
+        assert_eq!(items[0].title, "The Rust Programming Language");
+        assert_eq!(items[0].authors, vec!["Steve Klabnik"]);
+        assert_eq!(items[0].publisher.as_deref(), Some("No Starch Press"));
+        assert_eq!(items[0].tags, vec!["rust", "programming", "Languages"]);
+        assert_eq!(items[0].year, Some(2018));
+        assert_eq!(items[1].item_type, "note");
+
+        let mut report = ImportReport::default();
+        let books = into_books(items, &[Book { id: 7, ..Default::default() }], &mut report);
+
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].id, 8);
+        assert_eq!(report.unmapped_item_types.get("note"), Some(&1));
+    }
+}
@@ -0,0 +1,312 @@
+//! `Idempotency-Key` による POST の再送対策。
+//!
+//! `Idempotency-Key` ヘッダー付きの POST は、最初のレスポンスを `window` の間覚えておき、同じキーで
+//! 再送されたらハンドラーを呼ばずにそのレスポンスを返す (`Idempotent-Replayed: true` を付ける)。
+//! 通信の不安定なクライアントが、同じ本を二重に登録しないようにするため。
+//!
+//! キーは送ってきた相手 (`Authorization` / `X-Api-Key` の値) とテナントごとに分ける。同じキーで
+//! 別のパスや本文を送ってきたら 422、最初のリクエストをまだ処理している間に再送されたら 409 を返す。
+//! 5xx のレスポンスやストリームは覚えず、再送で改めて処理する。覚えた内容はメモリにだけ置く。
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use actix_web::body::{self, BodySize, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, HttpResponse};
+use sha2::{Digest, Sha256};
+
+use crate::invites::hash_token;
+use crate::tenant::Tenant;
+use crate::BookError;
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// 覚えておく既定の時間 (24 時間)。
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(24 * 3600);
+
+/// キーの長さの上限。
+const MAX_KEY_LEN: usize = 255;
+
+/// 覚えておくレスポンスの数とボディの大きさの上限。超えたものは覚えない。
+const MAX_ENTRIES: usize = 10_000;
+const MAX_BODY: u64 = 1024 * 1024;
+
+/// 送ってきた相手・テナント・キー。
+type Key = (Option<String>, String, String);
+
+#[derive(Clone)]
+struct Stored {
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: web::Bytes,
+}
+
+struct Entry {
+    /// メソッド・パス・クエリ・本文の SHA-256
+    fingerprint: String,
+    created_at: Instant,
+    /// `None` なら処理中
+    response: Option<Stored>,
+}
+
+/// 覚えたレスポンス。`window` が 0 なら何もしない。
+pub struct Idempotency {
+    window: Duration,
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Idempotency::new(DEFAULT_WINDOW)
+    }
+}
+
+enum Lookup {
+    /// 初めてのキー。処理中として登録した。
+    Started,
+    Replay(Stored),
+    InProgress,
+    Mismatch,
+    /// 覚えきれないので、そのまま処理する
+    Full,
+}
+
+impl Idempotency {
+    pub fn new(window: Duration) -> Self {
+        Idempotency { window, entries: Mutex::new(HashMap::new()) }
+    }
+
+    fn begin(&self, key: &Key, fingerprint: &str) -> Lookup {
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(entry) = entries.get(key).filter(|e| e.created_at.elapsed() < self.window) {
+            return match &entry.response {
+                _ if entry.fingerprint != fingerprint => Lookup::Mismatch,
+                Some(stored) => Lookup::Replay(stored.clone()),
+                None => Lookup::InProgress,
+            };
+        }
+
+        if entries.len() >= MAX_ENTRIES {
+            let window = self.window;
+            entries.retain(|_, e| e.created_at.elapsed() < window);
+            if entries.len() >= MAX_ENTRIES {
+                return Lookup::Full;
+            }
+        }
+
+        entries.insert(key.clone(), Entry {
+            fingerprint: fingerprint.to_string(),
+            created_at: Instant::now(),
+            response: None,
+        });
+        Lookup::Started
+    }
+
+    /// 処理の結果を覚える。`None` なら登録を消して、再送で改めて処理させる。
+    fn finish(&self, key: &Key, response: Option<Stored>) {
+        let mut entries = self.entries.lock().unwrap();
+        match response {
+            Some(response) => {
+                if let Some(entry) = entries.get_mut(key) {
+                    entry.response = Some(response);
+                }
+            }
+            None => {
+                entries.remove(key);
+            }
+        }
+    }
+}
+
+/// 処理の途中でタイムアウトなどによりリクエストが捨てられたら、処理中の登録を消す。
+struct Pending<'a> {
+    idempotency: &'a Idempotency,
+    key: &'a Key,
+    done: bool,
+}
+
+impl Pending<'_> {
+    fn finish(mut self, response: Option<Stored>) {
+        self.done = true;
+        self.idempotency.finish(self.key, response);
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.idempotency.finish(self.key, None);
+        }
+    }
+}
+
+/// 覚えておくヘッダー。接続ごとに決まるものやリクエスト ID は除く。
+fn stored_headers(headers: &HeaderMap) -> Vec<(HeaderName, HeaderValue)> {
+    headers.iter()
+        .filter(|(name, _)| {
+            !matches!(name.as_str(), "content-length" | "connection" | "date" | "set-cookie" | "x-request-id")
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+fn credentials(req: &ServiceRequest) -> String {
+    let value = req.headers().get(header::AUTHORIZATION)
+        .or_else(|| req.headers().get("x-api-key"))
+        .map(|v| v.as_bytes())
+        .unwrap_or_default();
+    hash_token(&String::from_utf8_lossy(value))
+}
+
+/// `middleware::from_fn` に渡すミドルウェア。設定は `web::Data<Idempotency>` から読む。
+pub async fn replay(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let idempotency = req.app_data::<web::Data<Idempotency>>().cloned();
+    let key = req.headers().get(IDEMPOTENCY_KEY).map(|v| v.to_str().map(str::to_string));
+
+    let (Some(idempotency), Some(key), &Method::POST) = (idempotency, key, req.method()) else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    if idempotency.window.is_zero() {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+    let key = match key {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key,
+        _ => return Err(BookError::BadRequest(format!("Idempotency-Key must be 1-{} visible ASCII characters", MAX_KEY_LEN)).into()),
+    };
+
+    // 本文を読んで指紋を取り、ハンドラーのために戻しておく
+    let payload = req.extract::<web::Bytes>().await?;
+    let mut hasher = Sha256::new();
+    hasher.update(req.path().as_bytes());
+    hasher.update(b"?");
+    hasher.update(req.query_string().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(&payload);
+    let fingerprint: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+    req.set_payload(payload.into());
+
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+    let key = (tenant, credentials(&req), key);
+
+    match idempotency.begin(&key, &fingerprint) {
+        Lookup::Started => {}
+        Lookup::Full => return Ok(next.call(req).await?.map_into_boxed_body()),
+        Lookup::Replay(stored) => {
+            let mut res = HttpResponse::build(stored.status);
+            for (name, value) in stored.headers {
+                res.append_header((name, value));
+            }
+            res.insert_header((IDEMPOTENT_REPLAYED, "true"));
+            return Ok(req.into_response(res.body(stored.body)));
+        }
+        Lookup::InProgress => {
+            return Err(BookError::Conflict("a request with this Idempotency-Key is still in progress".to_string()).into());
+        }
+        Lookup::Mismatch => {
+            let res = HttpResponse::UnprocessableEntity()
+                .body("Idempotency-Key was already used for a different request");
+            return Ok(req.into_response(res));
+        }
+    }
+
+    let pending = Pending { idempotency: &idempotency, key: &key, done: false };
+    let res = next.call(req).await?;
+
+    let cacheable = !res.status().is_server_error()
+        && matches!(res.response().body().size(), BodySize::Sized(n) if n <= MAX_BODY);
+    if !cacheable {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let bytes = body::to_bytes(body)
+        .await
+        .map_err(|e| {
+            let e: Box<dyn std::error::Error> = e.into();
+            actix_web::error::ErrorInternalServerError(e.to_string())
+        })?;
+
+    pending.finish(Some(Stored {
+        status: res.status(),
+        headers: stored_headers(res.headers()),
+        body: bytes.clone(),
+    }));
+
+    Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(bytes))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use actix_web::{middleware, test as actix_test, App};
+
+    #[actix_rt::test]
+    async fn test_replay() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Idempotency::default()))
+                .wrap(middleware::from_fn(replay))
+                .route("/books", web::post().to(move |body: String| {
+                    let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                    async move { HttpResponse::Created().body(format!("{} {}", n, body)) }
+                })),
+        ).await;
+
+        let post = |key: Option<&str>, body: &str| {
+            let mut req = actix_test::TestRequest::post().uri("/books").set_payload(body.to_string());
+            if let Some(key) = key {
+                req = req.insert_header((IDEMPOTENCY_KEY, key.to_string()));
+            }
+            req.to_request()
+        };
+
+        let resp = actix_test::call_service(&app, post(Some("k1"), "dune")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert!(resp.headers().get(IDEMPOTENT_REPLAYED).is_none());
+        assert_eq!(actix_test::read_body(resp).await, "1 dune");
+
+        // 再送はハンドラーを呼ばずに同じレスポンスを返す
+        let resp = actix_test::call_service(&app, post(Some("k1"), "dune")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers().get(IDEMPOTENT_REPLAYED).unwrap(), "true");
+        assert_eq!(actix_test::read_body(resp).await, "1 dune");
+
+        // 同じキーで別の本文は 422
+        let resp = actix_test::call_service(&app, post(Some("k1"), "emma")).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        assert_eq!(actix_test::read_body(actix_test::call_service(&app, post(Some("k2"), "emma")).await).await, "2 emma");
+        assert_eq!(actix_test::read_body(actix_test::call_service(&app, post(None, "emma")).await).await, "3 emma");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_in_progress_and_expiry() {
+        let idempotency = Idempotency::new(Duration::from_millis(20));
+        let key = (None, hash_token(""), "k".to_string());
+
+        assert!(matches!(idempotency.begin(&key, "a"), Lookup::Started));
+        assert!(matches!(idempotency.begin(&key, "a"), Lookup::InProgress));
+
+        // 失敗したら忘れて、再送で改めて処理する
+        idempotency.finish(&key, None);
+        assert!(matches!(idempotency.begin(&key, "a"), Lookup::Started));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(matches!(idempotency.begin(&key, "b"), Lookup::Started));
+    }
+}
@@ -0,0 +1,145 @@
+//! データファイルの整合性チェック。
+//!
+//! `GET /admin/check` と `books check` で使う。読み込み時には黙って扱われてしまう
+//! 重複した id や未知の項目も見つけられるよう、キャッシュではなくファイルの JSON を直接調べる。
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::storage::BookRepository;
+use crate::{Book, BookError};
+
+/// `Book` の項目名。`Book` に項目を足したらここにも足す。
+const KNOWN_FIELDS: [&str; 16] = [
+    "id", "title", "content", "tags", "genres", "authors", "published_year", "publisher", "isbn", "language", "content_stats",
+    "formats", "audio", "work", "loan", "visibility",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// `Book` として読めない
+    InvalidRecord,
+    DuplicateId,
+    EmptyTitle,
+    /// 空・前後の空白・制御文字・カンマを含むタグや、1 冊の中で重複したタグ
+    MalformedTag,
+    UnknownField,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Issue {
+    pub kind: IssueKind,
+    /// データファイル内の位置 (0 始まり)
+    pub index: usize,
+    pub id: Option<u32>,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub books: usize,
+    pub ok: bool,
+    pub issues: Vec<Issue>,
+}
+
+fn tag_problem(tag: &str) -> Option<&'static str> {
+    if tag.trim().is_empty() {
+        Some("empty tag")
+    } else if tag.trim() != tag {
+        Some("leading or trailing whitespace")
+    } else if tag.chars().any(|c| c.is_control() || c == ',') {
+        Some("control character or comma")
+    } else {
+        None
+    }
+}
+
+/// 読み込んだ JSON の配列を調べる。
+pub fn check_values(values: &[Value]) -> Report {
+    let mut issues = Vec::new();
+    let mut first_seen: HashMap<u32, usize> = HashMap::new();
+
+    for (index, value) in values.iter().enumerate() {
+        let id = value.get("id").and_then(Value::as_u64).and_then(|id| u32::try_from(id).ok());
+        let mut issue = |kind, detail: String| issues.push(Issue { kind, index, id, detail });
+
+        if let Some(object) = value.as_object() {
+            for key in object.keys().filter(|key| !KNOWN_FIELDS.contains(&key.as_str())) {
+                issue(IssueKind::UnknownField, format!("unknown field {:?}", key));
+            }
+        }
+
+        let book: Book = match serde_json::from_value(value.clone()) {
+            Ok(book) => book,
+            Err(e) => {
+                issue(IssueKind::InvalidRecord, e.to_string());
+                continue;
+            }
+        };
+
+        match first_seen.entry(book.id) {
+            Entry::Occupied(first) => {
+                issue(IssueKind::DuplicateId, format!("id {} is also used at index {}", book.id, first.get()));
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(index);
+            }
+        }
+
+        if book.title.trim().is_empty() {
+            issue(IssueKind::EmptyTitle, "title is empty".to_string());
+        }
+
+        for (pos, tag) in book.tags.iter().enumerate() {
+            if let Some(problem) = tag_problem(tag) {
+                issue(IssueKind::MalformedTag, format!("tag {:?}: {}", tag, problem));
+            } else if book.tags[..pos].contains(tag) {
+                issue(IssueKind::MalformedTag, format!("tag {:?}: duplicated", tag));
+            }
+        }
+    }
+
+    Report { books: values.len(), ok: issues.is_empty(), issues }
+}
+
+/// 書き込み待ちの変更を書き出してから、データファイルを調べる。
+pub fn check(repository: &BookRepository) -> Result<Report, BookError> {
+    repository.flush()?;
+
+    let values: Vec<Value> = serde_json::from_slice(&repository.read_contents()?)?;
+
+    Ok(check_values(&values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_check_values() {
+        let report = check_values(&[
+            json!({ "id": 1, "title": "Rust Basics", "content": "", "tags": ["rust"] }),
+            json!({ "id": 1, "title": " ", "content": "", "tags": ["rust", "rust", " async", ""] }),
+            json!({ "id": 2, "title": "Extra", "content": "", "tags": [], "cover": "2.png" }),
+            json!({ "id": "3", "title": "Bad id", "tags": [] }),
+        ]);
+
+        let kinds: Vec<(usize, IssueKind)> = report.issues.iter().map(|i| (i.index, i.kind)).collect();
+        assert_eq!(kinds, vec![
+            (1, IssueKind::DuplicateId),
+            (1, IssueKind::EmptyTitle),
+            (1, IssueKind::MalformedTag),
+            (1, IssueKind::MalformedTag),
+            (1, IssueKind::MalformedTag),
+            (2, IssueKind::UnknownField),
+            (3, IssueKind::InvalidRecord),
+        ]);
+        assert!(!report.ok);
+
+        assert!(check_values(&[json!({ "id": 1, "title": "OK", "content": "", "tags": ["a"] })]).ok);
+    }
+}
@@ -0,0 +1,229 @@
+//! 招待による登録。
+//!
+//! 登録を閉じていても、管理者が `POST /admin/invites` で発行した招待トークンがあれば `POST /register` で
+//! 登録できる。トークンは 1 回しか使えず、発行したテナントでしか使えない。ファイルにはハッシュだけを残す。
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+use crate::auth::AdminUser;
+use crate::tenant::Tenant;
+use crate::{BookError, Role};
+
+pub const DEFAULT_INVITES_FILE: &str = "src/users/invites.json";
+pub const DEFAULT_INVITE_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Invite {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub role: Role,
+    pub created_by: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredInvite {
+    /// トークンの SHA-256 (16 進)
+    token_hash: String,
+    #[serde(flatten)]
+    invite: Invite,
+}
+
+/// 未使用の招待の一覧。
+pub struct Invites {
+    file: PathBuf,
+    ttl: Duration,
+    invites: RwLock<Vec<StoredInvite>>,
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn write_invites(path: &Path, invites: &[StoredInvite]) -> io::Result<()> {
+    crate::private_file::write_private(path, serde_json::to_string_pretty(invites)?.as_bytes())
+}
+
+impl Invites {
+    /// 招待のファイルを読む。なければ空で始める。
+    pub fn open(file: impl Into<PathBuf>, ttl: Duration) -> io::Result<Self> {
+        let file = file.into();
+
+        let invites = match fs::read_to_string(&file) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Invites { file, ttl, invites: RwLock::new(invites) })
+    }
+
+    /// 招待を発行し、トークンと招待を返す。トークンはここでしか分からない。
+    pub fn create(&self, tenant: Option<&str>, role: Role, created_by: &str) -> Result<(String, Invite), BookError> {
+        let mut bytes = [0u8; 24];
+        OsRng.fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+
+        let now = now();
+        let invite = Invite {
+            id: format!("{:016x}", OsRng.next_u64()),
+            tenant: tenant.map(str::to_string),
+            role,
+            created_by: created_by.to_string(),
+            created_at: now,
+            expires_at: now + self.ttl.as_secs() as i64,
+        };
+
+        let mut invites = self.invites.write().unwrap();
+        invites.retain(|i| i.invite.expires_at > now);
+        invites.push(StoredInvite { token_hash: hash_token(&token), invite: invite.clone() });
+        write_invites(&self.file, &invites)?;
+
+        Ok((token, invite))
+    }
+
+    /// `tenant` の期限内の招待。
+    pub fn list(&self, tenant: Option<&str>) -> Vec<Invite> {
+        let now = now();
+
+        self.invites.read().unwrap()
+            .iter()
+            .map(|i| &i.invite)
+            .filter(|i| i.tenant.as_deref() == tenant && i.expires_at > now)
+            .cloned()
+            .collect()
+    }
+
+    /// `tenant` で `username` が発行した招待の発行者を `[erased]` に置き換え、件数を返す。
+    /// `write` が false なら数えるだけ。ユーザーの消去に使う。
+    pub fn erase_creator(&self, tenant: Option<&str>, username: &str, write: bool) -> Result<usize, BookError> {
+        let mut invites = self.invites.write().unwrap();
+        let mut count = 0;
+        for invite in invites.iter_mut().map(|i| &mut i.invite) {
+            if invite.tenant.as_deref() == tenant && invite.created_by == username {
+                if write {
+                    invite.created_by = crate::audit::ERASED_ACTOR.to_string();
+                }
+                count += 1;
+            }
+        }
+
+        if write && count > 0 {
+            write_invites(&self.file, &invites)?;
+        }
+        Ok(count)
+    }
+
+    /// トークンを使う。使えれば招待を消して返す。別のテナントの招待や期限切れのものは `None`。
+    pub fn redeem(&self, token: &str, tenant: Option<&str>) -> Result<Option<Invite>, BookError> {
+        let hash = hash_token(token);
+        let now = now();
+
+        let mut invites = self.invites.write().unwrap();
+        let Some(index) = invites.iter()
+            .position(|i| i.token_hash == hash && i.invite.tenant.as_deref() == tenant && i.invite.expires_at > now)
+        else {
+            return Ok(None);
+        };
+
+        let invite = invites.remove(index).invite;
+        write_invites(&self.file, &invites)?;
+
+        Ok(Some(invite))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct InviteRequest {
+    #[serde(default)]
+    role: Option<Role>,
+}
+
+#[derive(Serialize)]
+pub struct InviteResponse {
+    token: String,
+    #[serde(flatten)]
+    invite: Invite,
+}
+
+/// 招待を発行する。ボディは省略でき、`role` を指定しなければ一般ユーザー。
+#[post("/admin/invites")]
+pub async fn create_invite(
+    req: HttpRequest,
+    admin: AdminUser,
+    invites: Option<web::Data<Invites>>,
+    body: Option<web::Json<InviteRequest>>,
+) -> Result<impl Responder, BookError> {
+    let invites = invites.ok_or(BookError::NotFound)?;
+    let role = body.and_then(|body| body.into_inner().role).unwrap_or(Role::User);
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+
+    let (token, invite) = invites.create(tenant.as_deref(), role, &admin.0.username)?;
+    log::info!("Invite {} ({}) created by {}", invite.id, role.as_str(), admin.0.username);
+
+    Ok(HttpResponse::Created().json(InviteResponse { token, invite }))
+}
+
+#[get("/admin/invites")]
+pub async fn list_invites(
+    req: HttpRequest,
+    _admin: AdminUser,
+    invites: Option<web::Data<Invites>>,
+) -> Result<impl Responder, BookError> {
+    let invites = invites.ok_or(BookError::NotFound)?;
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+
+    Ok(HttpResponse::Ok().json(invites.list(tenant.as_deref())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redeem_once() {
+        let path = std::env::temp_dir().join(format!("books_backend_invites_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let invites = Invites::open(&path, DEFAULT_INVITE_TTL).unwrap();
+
+        let (token, invite) = invites.create(Some("acme"), Role::User, "admin").unwrap();
+        assert_eq!(invites.list(Some("acme")).len(), 1);
+        assert!(invites.list(None).is_empty());
+
+        // 別のテナントでは使えない
+        assert!(invites.redeem(&token, None).unwrap().is_none());
+
+        // ファイルから読み直しても使え、1 回しか使えない
+        let reopened = Invites::open(&path, DEFAULT_INVITE_TTL).unwrap();
+        assert_eq!(reopened.redeem(&token, Some("acme")).unwrap().unwrap().id, invite.id);
+        assert!(reopened.redeem(&token, Some("acme")).unwrap().is_none());
+        assert!(!fs::read_to_string(&path).unwrap().contains(&token));
+
+        fs::remove_file(&path).unwrap();
+
+        // 期限切れの招待は使えない
+        let invites = Invites::open(&path, Duration::ZERO).unwrap();
+        let (token, _) = invites.create(None, Role::User, "admin").unwrap();
+        assert!(invites.redeem(&token, None).unwrap().is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
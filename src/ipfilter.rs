@@ -0,0 +1,127 @@
+//! 接続元 IP による許可・拒否。
+//!
+//! `[[ip_rules]]` ごとにパスの接頭辞と CIDR の一覧を書く。パスが一致したルールのうち、`deny` に
+//! 含まれるか、`allow` があるのにどれにも含まれない接続元は 403 にする。管理画面を VPN の範囲だけに
+//! 絞る、といった使い方をする。接続元は `trusted_proxies` を踏まえて `proxy::client_ip` で決める。
+//! パスはルーティングと同じく、パーセントエンコードを戻したもので比べる。
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, ResponseError};
+use serde::Deserialize;
+
+use crate::proxy::Cidr;
+use crate::BookError;
+
+/// 設定ファイルの `[[ip_rules]]`。
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IpRule {
+    /// このパスとその下に当てはめる。省略するとすべてのパス。
+    #[serde(default = "root")]
+    pub path: String,
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+fn root() -> String {
+    "/".to_string()
+}
+
+#[derive(Clone, Debug)]
+struct CompiledRule {
+    path: String,
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl CompiledRule {
+    fn applies_to(&self, path: &str) -> bool {
+        let prefix = self.path.trim_end_matches('/');
+        prefix.is_empty()
+            || path == prefix
+            || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+    }
+}
+
+/// 読み込んだルール。空なら何もしない。
+#[derive(Clone, Debug, Default)]
+pub struct IpFilter(Vec<CompiledRule>);
+
+impl IpFilter {
+    /// 読めない CIDR があれば、その文字列を `Err` で返す。
+    pub fn parse(rules: &[IpRule]) -> Result<Self, String> {
+        let cidrs = |entries: &[String]| {
+            entries.iter()
+                .map(|entry| Cidr::parse(entry).ok_or_else(|| entry.clone()))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        rules.iter()
+            .map(|rule| Ok(CompiledRule { path: rule.path.clone(), allow: cidrs(&rule.allow)?, deny: cidrs(&rule.deny)? }))
+            .collect::<Result<_, _>>()
+            .map(IpFilter)
+    }
+
+    /// `path` への `ip` からのアクセスを許すか。接続元が分からなければ、ルールの当たるパスは拒否する。
+    pub fn allows(&self, path: &str, ip: Option<std::net::IpAddr>) -> bool {
+        self.0.iter()
+            .filter(|rule| rule.applies_to(path))
+            .all(|rule| match ip {
+                Some(ip) => {
+                    !rule.deny.iter().any(|cidr| cidr.contains(ip))
+                        && (rule.allow.is_empty() || rule.allow.iter().any(|cidr| cidr.contains(ip)))
+                }
+                None => false,
+            })
+    }
+}
+
+/// `middleware::from_fn` に渡すミドルウェア。ルールは `web::Data<IpFilter>` から読む。
+pub async fn filter(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if let Some(filter) = req.app_data::<web::Data<IpFilter>>() {
+        let ip = crate::proxy::client_ip(req.request());
+
+        // ルーティングと同じく、パーセントエンコードを戻したパスで判定する (`/%61dmin` も `/admin`)
+        if !filter.allows(req.match_info().as_str(), ip) {
+            log::warn!("Rejected {} {} from {:?} by ip_rules", req.method(), req.path(), ip);
+            let resp = BookError::Forbidden.error_response();
+            return Ok(req.into_response(resp));
+        }
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows() {
+        let filter = IpFilter::parse(&[
+            IpRule { deny: vec!["203.0.113.0/24".to_string()], ..Default::default() },
+            IpRule { path: "/admin".to_string(), allow: vec!["10.8.0.0/16".to_string()], ..Default::default() },
+        ])
+        .unwrap();
+
+        let ip = |value: &str| Some(value.parse().unwrap());
+        assert!(filter.allows("/books", ip("198.51.100.7")));
+        assert!(!filter.allows("/books", ip("203.0.113.9")));
+        assert!(filter.allows("/admin/check", ip("10.8.3.4")));
+        assert!(!filter.allows("/admin", ip("198.51.100.7")));
+        assert!(filter.allows("/administrators", ip("198.51.100.7")));
+        assert!(!filter.allows("/admin", None));
+
+        assert_eq!(
+            IpFilter::parse(&[IpRule { allow: vec!["10.0.0.0/33".to_string()], ..Default::default() }]).unwrap_err(),
+            "10.0.0.0/33",
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeSet;
+use serde::Serialize;
+
+use crate::facets::Facets;
+use crate::Book;
+
+pub const MEDIA_TYPE: &str = "application/vnd.api+json";
+
+#[derive(Serialize)]
+struct ResourceIdentifier {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+}
+
+#[derive(Serialize)]
+struct Relationship {
+    data: Vec<ResourceIdentifier>,
+}
+
+#[derive(Serialize)]
+struct BookRelationships {
+    tags: Relationship,
+    authors: Relationship,
+}
+
+#[derive(Serialize)]
+struct BookAttributes<'a> {
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct BookResource<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+    attributes: BookAttributes<'a>,
+    relationships: BookRelationships,
+}
+
+#[derive(Serialize)]
+struct NameAttributes<'a> {
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct IncludedResource<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: &'a str,
+    attributes: NameAttributes<'a>,
+}
+
+#[derive(Serialize)]
+struct Meta<'a> {
+    facets: &'a Facets,
+}
+
+#[derive(Serialize)]
+pub struct Document<'a> {
+    data: Vec<BookResource<'a>>,
+    included: Vec<IncludedResource<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta<'a>>,
+}
+
+impl<'a> Document<'a> {
+    /// ファセットを `meta.facets` に入れる。
+    pub fn with_facets(mut self, facets: &'a Facets) -> Self {
+        self.meta = Some(Meta { facets });
+        self
+    }
+}
+
+fn identifiers(kind: &'static str, names: &[String]) -> Relationship {
+    Relationship {
+        data: names.iter()
+            .map(|name| ResourceIdentifier { kind, id: name.clone() })
+            .collect(),
+    }
+}
+
+/// 書籍一覧を JSON:API のドキュメントに変換する。タグと著者は `included` にまとめる。
+/// 本文は `include_content` のときだけ入れる。
+pub fn document(books: &[Book], include_content: bool) -> Document<'_> {
+    let data = books.iter()
+        .map(|b| BookResource {
+            kind: "books",
+            id: b.id.to_string(),
+            attributes: BookAttributes {
+                title: &b.title,
+                content: include_content.then_some(b.content.as_str()),
+            },
+            relationships: BookRelationships {
+                tags: identifiers("tags", &b.tags),
+                authors: identifiers("authors", &b.authors),
+            },
+        })
+        .collect();
+
+    let tags: BTreeSet<&str> = books.iter().flat_map(|b| b.tags.iter().map(String::as_str)).collect();
+    let authors: BTreeSet<&str> = books.iter().flat_map(|b| b.authors.iter().map(String::as_str)).collect();
+
+    let included = tags.into_iter()
+        .map(|name| IncludedResource { kind: "tags", id: name, attributes: NameAttributes { name } })
+        .chain(authors.into_iter()
+            .map(|name| IncludedResource { kind: "authors", id: name, attributes: NameAttributes { name } }))
+        .collect();
+
+    Document { data, included, meta: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_includes_tags_and_authors_once() {
+        let books = vec![
+            Book {
+                id: 1,
+                title: "Rust Basics".to_string(),
+                tags: vec!["beginner".to_string()],
+                authors: vec!["Steve Klabnik".to_string()],
+                ..Default::default()
+            },
+            Book {
+                id: 2,
+                title: "Async in Rust".to_string(),
+                tags: vec!["beginner".to_string(), "async".to_string()],
+                ..Default::default()
+            },
+        ];
+
+        let json = serde_json::to_value(document(&books, true)).unwrap();
+
+        assert_eq!(json["data"][0]["type"], "books");
+        assert_eq!(json["data"][0]["id"], "1");
+        assert_eq!(json["data"][0]["attributes"]["title"], "Rust Basics");
+        assert_eq!(json["data"][1]["relationships"]["tags"]["data"][1]["id"], "async");
+
+        let included = json["included"].as_array().unwrap();
+        assert_eq!(included.len(), 3);
+        assert_eq!(included[2]["type"], "authors");
+    }
+}
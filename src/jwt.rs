@@ -0,0 +1,453 @@
+//! 署名付きトークン (JWT, HS256)。
+//!
+//! `POST /auth/token` に Basic 認証で問い合わせると、`Authorization: Bearer` で使えるトークンを返す。
+//! 署名鍵は `kid` で区別し、`POST /admin/keys/rotate` で新しい鍵に切り替える。切り替え前の鍵も
+//! トークンの有効期限が切れるまでは検証に使うので、発行済みのトークンはそのまま使える。
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use time::OffsetDateTime;
+
+use crate::auth::{AdminUser, OperatorUser};
+use crate::ldap::DirectoryUser;
+use crate::tenant::Tenant;
+use crate::{BookError, Role};
+
+pub const DEFAULT_KEYS_FILE: &str = "src/users/keys.json";
+pub const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(3600);
+
+/// `POST /admin/impersonate` で発行するトークンの有効期間。`token_ttl` の方が短ければそちらに従う。
+pub const IMPERSONATION_TTL: Duration = Duration::from_secs(15 * 60);
+
+const ALGORITHM: &str = "HS256";
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SigningKey {
+    kid: String,
+    /// base64 で書いた 32 バイトの秘密鍵
+    secret: String,
+    created_at: i64,
+    /// 新しい鍵に切り替えた時刻。ここから `ttl` の間は検証にだけ使う。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retired_at: Option<i64>,
+}
+
+impl SigningKey {
+    fn generate(now: i64) -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+
+        SigningKey {
+            kid: format!("{:016x}", OsRng.next_u64()),
+            secret: STANDARD.encode(secret),
+            created_at: now,
+            retired_at: None,
+        }
+    }
+
+    fn mac(&self) -> Result<Hmac<Sha256>, BookError> {
+        let secret = STANDARD.decode(&self.secret)
+            .map_err(|_| BookError::Serialize(format!("signing key {} is not valid base64", self.kid)))?;
+
+        Hmac::new_from_slice(&secret).map_err(|e| BookError::Serialize(e.to_string()))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    typ: String,
+    kid: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    /// ユーザー名
+    pub sub: String,
+    /// テナントで発行したトークンは、そのテナントでしか使えない
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub iat: i64,
+    pub exp: i64,
+    /// なりすましのトークンなら、発行した管理者 (RFC 8693 の `act`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    /// LDAP で認証したユーザーの役割。ユーザーファイルにいないので、トークンに書いておく
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: String,
+}
+
+/// 鍵の状態。秘密鍵そのものは含めない。
+#[derive(Debug, Serialize)]
+pub struct KeyStatus {
+    /// 新しいトークンの署名に使う鍵
+    pub active: String,
+    /// 検証に使える鍵 (`active` を含む)
+    pub verification: Vec<String>,
+}
+
+/// 署名鍵の一覧。ファイルに保存し、テナント間で共有する。
+pub struct Keys {
+    file: PathBuf,
+    ttl: Duration,
+    keys: RwLock<Vec<SigningKey>>,
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+fn write_keys(path: &Path, keys: &[SigningKey]) -> io::Result<()> {
+    crate::private_file::write_private(path, serde_json::to_string_pretty(keys)?.as_bytes())
+}
+
+impl Keys {
+    /// 鍵のファイルを読む。なければ最初の鍵を作って保存する。
+    pub fn open(file: impl Into<PathBuf>, ttl: Duration) -> io::Result<Self> {
+        let file = file.into();
+
+        let keys: Vec<SigningKey> = match fs::read_to_string(&file) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        let keys = if keys.iter().any(|k| k.retired_at.is_none()) {
+            keys
+        } else {
+            let keys = vec![SigningKey::generate(now())];
+            write_keys(&file, &keys)?;
+            keys
+        };
+
+        Ok(Keys { file, ttl, keys: RwLock::new(keys) })
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn is_usable(&self, key: &SigningKey, now: i64) -> bool {
+        key.retired_at.is_none_or(|retired| retired + self.ttl.as_secs() as i64 > now)
+    }
+
+    pub fn status(&self) -> KeyStatus {
+        let keys = self.keys.read().unwrap();
+        let now = now();
+
+        KeyStatus {
+            active: keys.iter().rev().find(|k| k.retired_at.is_none()).map(|k| k.kid.clone()).unwrap_or_default(),
+            verification: keys.iter().filter(|k| self.is_usable(k, now)).map(|k| k.kid.clone()).collect(),
+        }
+    }
+
+    /// `username` のトークンを発行する。
+    pub fn issue(&self, username: &str, tenant: Option<&str>) -> Result<String, BookError> {
+        let iat = now();
+
+        self.sign(&Claims {
+            sub: username.to_string(),
+            tenant: tenant.map(str::to_string),
+            iat,
+            exp: iat + self.ttl.as_secs() as i64,
+            act: None,
+            role: None,
+        })
+    }
+
+    /// LDAP で認証したユーザーのトークンを、役割を付けて発行する。既定のテナントでだけ使う。
+    pub fn issue_directory(&self, username: &str, role: Role) -> Result<String, BookError> {
+        let iat = now();
+
+        self.sign(&Claims {
+            sub: username.to_string(),
+            tenant: None,
+            iat,
+            exp: iat + self.ttl.as_secs() as i64,
+            act: None,
+            role: Some(role),
+        })
+    }
+
+    /// 管理者 `admin` が `username` として振る舞うトークンを発行する。有効期間は `impersonation_ttl`。
+    pub fn impersonate(&self, username: &str, tenant: Option<&str>, admin: &str) -> Result<String, BookError> {
+        let iat = now();
+
+        self.sign(&Claims {
+            sub: username.to_string(),
+            tenant: tenant.map(str::to_string),
+            iat,
+            exp: iat + self.impersonation_ttl().as_secs() as i64,
+            act: Some(Actor { sub: admin.to_string() }),
+            role: None,
+        })
+    }
+
+    pub fn impersonation_ttl(&self) -> Duration {
+        self.ttl.min(IMPERSONATION_TTL)
+    }
+
+    /// クレームに署名してトークンにする。
+    fn sign(&self, claims: &Claims) -> Result<String, BookError> {
+        let keys = self.keys.read().unwrap();
+        let key = keys.iter().rev().find(|k| k.retired_at.is_none()).ok_or(BookError::Unauthorized)?;
+
+        let header = Header { alg: ALGORITHM.to_string(), typ: "JWT".to_string(), kid: key.kid.clone() };
+
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?),
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?),
+        );
+
+        let mut mac = key.mac()?;
+        mac.update(signing_input.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+
+        Ok(format!("{}.{}", signing_input, signature))
+    }
+
+    /// 署名と有効期限を確かめる。どこがおかしくても 401 にする。
+    pub fn verify(&self, token: &str) -> Result<Claims, BookError> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(claims), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(BookError::Unauthorized);
+        };
+
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| BookError::Unauthorized);
+        let parsed: Header = serde_json::from_slice(&decode(header)?).map_err(|_| BookError::Unauthorized)?;
+        if parsed.alg != ALGORITHM {
+            return Err(BookError::Unauthorized);
+        }
+
+        let now = now();
+        {
+            let keys = self.keys.read().unwrap();
+            let key = keys.iter()
+                .find(|k| k.kid == parsed.kid && self.is_usable(k, now))
+                .ok_or(BookError::Unauthorized)?;
+
+            let mut mac = key.mac()?;
+            mac.update(header.as_bytes());
+            mac.update(b".");
+            mac.update(claims.as_bytes());
+            mac.verify_slice(&decode(signature)?).map_err(|_| BookError::Unauthorized)?;
+        }
+
+        let claims: Claims = serde_json::from_slice(&decode(claims)?).map_err(|_| BookError::Unauthorized)?;
+        if claims.exp <= now {
+            return Err(BookError::Unauthorized);
+        }
+
+        Ok(claims)
+    }
+
+    /// 新しい鍵に切り替える。それまでの鍵は `ttl` の間だけ検証に残し、期限の過ぎた鍵は捨てる。
+    pub fn rotate(&self) -> Result<KeyStatus, BookError> {
+        {
+            let mut keys = self.keys.write().unwrap();
+            let now = now();
+
+            for key in keys.iter_mut().filter(|k| k.retired_at.is_none()) {
+                key.retired_at = Some(now);
+            }
+            keys.retain(|k| self.is_usable(k, now));
+            keys.push(SigningKey::generate(now));
+
+            write_keys(&self.file, &keys)?;
+        }
+
+        Ok(self.status())
+    }
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: u64,
+}
+
+/// Basic 認証で受け取ったユーザーのトークンを発行する。
+/// なりすましのトークンからは発行しない (期限と監査の印が外れるため 403)。
+#[post("/auth/token")]
+pub async fn issue_token(req: HttpRequest, keys: Option<web::Data<Keys>>) -> Result<impl Responder, BookError> {
+    let keys = keys.ok_or(BookError::NotFound)?;
+    let user = crate::auth::authenticate(&req)?;
+    if req.extensions().contains::<crate::auth::Impersonator>() {
+        return Err(BookError::Forbidden);
+    }
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+    let access_token = match req.extensions().get::<DirectoryUser>() {
+        Some(_) => keys.issue_directory(&user.username, user.role)?,
+        None => keys.issue(&user.username, tenant.as_deref())?,
+    };
+
+    Ok(HttpResponse::Ok().json(TokenResponse {
+        access_token,
+        token_type: "Bearer",
+        expires_in: keys.ttl().as_secs(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ImpersonationRequest {
+    username: String,
+    /// 監査ログに残す理由 (問い合わせの番号など)
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ImpersonationResponse {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: u64,
+    impersonating: String,
+}
+
+/// 管理者が他のユーザーとして振る舞うための短命なトークンを発行する。管理者にはなりすませない。
+/// このトークンでのリクエストは、読み取りも含めてすべて監査ログに管理者の名前付きで残る。
+#[post("/admin/impersonate")]
+pub async fn impersonate(
+    req: HttpRequest,
+    admin: AdminUser,
+    keys: Option<web::Data<Keys>>,
+    body: web::Json<ImpersonationRequest>,
+) -> Result<impl Responder, BookError> {
+    let keys = keys.ok_or(BookError::NotFound)?;
+    let ImpersonationRequest { username, reason } = body.into_inner();
+
+    let users_file = crate::auth::users_file_for(&req);
+    let target = web::block(move || crate::auth::load_users_from(&users_file)).await?
+        .into_iter()
+        .find(|u| u.username == username)
+        .ok_or(BookError::NotFound)?;
+    if target.role == Role::Admin {
+        return Err(BookError::Forbidden);
+    }
+
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+    let token = keys.impersonate(&target.username, tenant.as_deref(), &admin.0.username)?;
+
+    let reason = reason.map(|r| format!(": {}", r)).unwrap_or_default();
+    crate::audit::note(&req, crate::audit::Change::about(&target.username, format!("impersonating {}{}", target.username, reason)));
+    log::warn!("{} is impersonating {}{}", admin.0.username, target.username, reason);
+
+    Ok(HttpResponse::Ok().json(ImpersonationResponse {
+        access_token: token,
+        token_type: "Bearer",
+        expires_in: keys.impersonation_ttl().as_secs(),
+        impersonating: target.username,
+    }))
+}
+
+#[get("/admin/keys")]
+pub async fn list_keys(_admin: AdminUser, keys: Option<web::Data<Keys>>) -> Result<impl Responder, BookError> {
+    let keys = keys.ok_or(BookError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(keys.status()))
+}
+
+/// 署名鍵を切り替える。発行済みのトークンは有効期限まで使える。鍵はすべてのテナントで共有するので、
+/// 既定の書庫の管理者だけが切り替えられる。
+#[post("/admin/keys/rotate")]
+pub async fn rotate_keys(admin: OperatorUser, keys: Option<web::Data<Keys>>) -> Result<impl Responder, BookError> {
+    let keys = keys.ok_or(BookError::NotFound)?;
+
+    let status = keys.rotate()?;
+    log::warn!("Signing key rotated to {} by {}", status.active, admin.0.username);
+
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_keys(name: &str, ttl: Duration) -> (PathBuf, Keys) {
+        let path = std::env::temp_dir().join(format!("books_backend_keys_{}_{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        let keys = Keys::open(&path, ttl).unwrap();
+        (path, keys)
+    }
+
+    #[test]
+    fn test_issue_and_verify() {
+        let (path, keys) = temp_keys("verify", DEFAULT_TOKEN_TTL);
+
+        let token = keys.issue("alice", Some("acme")).unwrap();
+        let claims = keys.verify(&token).unwrap();
+        assert_eq!((claims.sub.as_str(), claims.tenant.as_deref()), ("alice", Some("acme")));
+
+        // 署名やペイロードを書き換えたものは通さない
+        let (signing_input, _) = token.rsplit_once('.').unwrap();
+        assert!(keys.verify(&format!("{}.AAAA", signing_input)).is_err());
+        let forged = URL_SAFE_NO_PAD.encode(r#"{"sub":"admin","iat":0,"exp":9999999999}"#);
+        let parts: Vec<&str> = token.split('.').collect();
+        assert!(keys.verify(&format!("{}.{}.{}", parts[0], forged, parts[2])).is_err());
+        assert!(keys.verify("not-a-token").is_err());
+
+        // 同じファイルを開けば同じ鍵で検証できる
+        assert!(Keys::open(&path, DEFAULT_TOKEN_TTL).unwrap().verify(&token).is_ok());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_impersonation_token() {
+        let (path, keys) = temp_keys("impersonate", DEFAULT_TOKEN_TTL);
+
+        let claims = keys.verify(&keys.impersonate("alice", None, "root").unwrap()).unwrap();
+        assert_eq!(claims.act, Some(Actor { sub: "root".to_string() }));
+        assert_eq!(claims.exp - claims.iat, IMPERSONATION_TTL.as_secs() as i64);
+        assert!(keys.verify(&keys.issue("alice", None).unwrap()).unwrap().act.is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rotate_keeps_old_tokens_valid() {
+        let (path, keys) = temp_keys("rotate", DEFAULT_TOKEN_TTL);
+        let before = keys.status();
+        let old_token = keys.issue("alice", None).unwrap();
+
+        let after = keys.rotate().unwrap();
+        assert_ne!(before.active, after.active);
+        assert_eq!(after.verification, vec![before.active.clone(), after.active.clone()]);
+
+        assert!(keys.verify(&old_token).is_ok());
+        let new_token = keys.issue("alice", None).unwrap();
+        assert!(new_token.starts_with(&URL_SAFE_NO_PAD.encode(format!(
+            r#"{{"alg":"HS256","typ":"JWT","kid":"{}"}}"#,
+            after.active
+        ))));
+
+        fs::remove_file(&path).unwrap();
+
+        // 有効期限が 0 なら、切り替えた時点で古い鍵は使えなくなる
+        let (path, keys) = temp_keys("rotate_expired", Duration::ZERO);
+        let old_token = keys.issue("alice", None).unwrap();
+        keys.rotate().unwrap();
+        assert!(keys.verify(&old_token).is_err());
+        assert_eq!(keys.status().verification.len(), 1);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
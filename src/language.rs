@@ -0,0 +1,87 @@
+//! 書籍の言語の推定 (`language`、`?lang=`)。
+//!
+//! 保存するときに title と content の文字の種類を数えて、ISO 639-1 の言語コードを決める。
+//! かなを含めば日本語 (`ja`)、ハングルが多ければ韓国語 (`ko`)、かなのない漢字だけなら中国語 (`zh`)、
+//! ラテン文字が多ければ英語 (`en`) とみなす。蔵書は日本語と英語がほとんどなので、ラテン文字の
+//! 言語どうしは区別しない。文字が少なすぎて決められなければ `None`。content の HTML タグは数えない。
+//! `language` を保存する前に書き込まれた書籍は、絞り込むときにその場で推定する。
+
+use crate::Book;
+
+/// これより文字が少なければ推定しない。
+const MIN_LETTERS: usize = 2;
+
+#[derive(Default)]
+struct Counts {
+    kana: usize,
+    han: usize,
+    hangul: usize,
+    latin: usize,
+}
+
+impl Counts {
+    fn add(&mut self, text: &str) {
+        let mut in_tag = false;
+        for c in text.chars() {
+            match c {
+                '<' => in_tag = true,
+                '>' => in_tag = false,
+                _ if in_tag => {}
+                '\u{3040}'..='\u{30ff}' | '\u{ff66}'..='\u{ff9f}' => self.kana += 1,
+                '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' | '\u{f900}'..='\u{faff}' => self.han += 1,
+                '\u{1100}'..='\u{11ff}' | '\u{3130}'..='\u{318f}' | '\u{ac00}'..='\u{d7af}' => self.hangul += 1,
+                _ if c.is_alphabetic() && (c.is_ascii() || ('\u{00c0}'..='\u{024f}').contains(&c)) => self.latin += 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// title と content から言語を推定する。
+pub fn detect(title: &str, content: &str) -> Option<&'static str> {
+    let mut counts = Counts::default();
+    counts.add(title);
+    counts.add(content);
+
+    let cjk = counts.kana + counts.han;
+    if cjk + counts.hangul + counts.latin < MIN_LETTERS {
+        return None;
+    }
+
+    // 日本語の文には英単語が混じるので、かながあれば漢字と合わせて比べる
+    Some(if counts.kana > 0 && cjk >= counts.hangul && cjk * 2 >= counts.latin {
+        "ja"
+    } else if counts.hangul >= cjk && counts.hangul >= counts.latin {
+        "ko"
+    } else if counts.han > counts.latin {
+        "zh"
+    } else {
+        "en"
+    })
+}
+
+/// `book` の言語が `lang` か。大文字小文字は区別しない。
+pub fn matches(book: &Book, lang: &str) -> bool {
+    book.language.as_deref()
+        .or_else(|| detect(&book.title, &book.content))
+        .is_some_and(|language| language.eq_ignore_ascii_case(lang.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect("The Rust Programming Language", ""), Some("en"));
+        assert_eq!(detect("プログラミングRust", "<p>Rust の所有権とライフタイムについて</p>"), Some("ja"));
+        assert_eq!(detect("Async in Rust", "<p>非同期処理を学ぶ</p>"), Some("ja"));
+        assert_eq!(detect("러스트 프로그래밍", ""), Some("ko"));
+        assert_eq!(detect("程序设计语言", ""), Some("zh"));
+        assert_eq!(detect("1984", "<p></p>"), None);
+        let book = Book { title: "Rust Basics".to_string(), ..Default::default() };
+        assert!(matches(&book, "EN"));
+        assert!(!matches(&Book { language: Some("ja".to_string()), ..book }, "en"));
+        assert!(!matches(&Book::default(), "en"));
+    }
+}
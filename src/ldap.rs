@@ -0,0 +1,883 @@
+//! LDAP / Active Directory による認証。
+//!
+//! `[ldap]` を設定すると、ユーザーファイルにいないユーザーの Basic 認証を LDAP サーバーで確かめる。
+//! サービスアカウント (`bind_dn`) で `user_filter` に合うエントリを探し、見つかった DN と送られた
+//! パスワードで bind し直す。エントリの `group_attribute` (既定 `memberOf`) で役割を決め、
+//! `admin_groups` のどれかに入っていれば管理者にする。`user_groups` を指定したときは、
+//! そのどれか (か `admin_groups`) に入っていないユーザーを拒否する。既定のテナントだけで使う。
+//!
+//! LDAPv3 の simple bind と検索だけを BER で直接書いている。`ldap3` クレートは使わない。`ldap3` は同期の
+//! API でも中で tokio のランタイムを動かし、TLS も別の実装か版を持ち込むが、ここで要るのは 1 つの接続での
+//! bind 2 回と検索 1 回だけで、TLS は既に使っている rustls で足りる。その代わり、サーバーからの応答は
+//! 信用しない。受け取るメッセージは `MAX_MESSAGE_LEN` までにし、長さは切り出す前に必ず確かめ、範囲外の
+//! 整数や壊れた・途中で切れた応答はパニックせずにエラーにする (テストでランダムな入力を流して確かめている)。
+//! `ldaps://` では `ca_file` の CA でサーバー証明書を確かめる。問い合わせはブロックするので、ワーカーを止めないように `web::block` で
+//! 別のスレッドで行う。通らなかったユーザー名とパスワードの組はしばらく覚えておき、問い合わせ直さない。
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use actix_web::{web, HttpMessage, HttpRequest};
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::pki_types::pem::PemObject;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use serde::Deserialize;
+
+use crate::tenant::Tenant;
+use crate::{BookError, Role, User};
+
+/// `user_filter` の中で、ログインするユーザー名に置き換える文字列。
+pub const USERNAME_PLACEHOLDER: &str = "{username}";
+
+/// 受け取るメッセージの上限。グループの多いユーザーでも足りる大きさ。
+const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+const SUCCESS: u32 = 0;
+
+/// 通らなかった組を覚えておく時間。
+const REJECTED_TTL: Duration = Duration::from_secs(60);
+
+/// 覚えておく組の上限。超えたら古いものから捨てる代わりに全部忘れる。
+const MAX_REJECTED: usize = 10_000;
+
+fn default_user_filter() -> String {
+    "(uid={username})".to_string()
+}
+
+fn default_group_attribute() -> String {
+    "memberOf".to_string()
+}
+
+fn default_timeout_secs() -> u64 {
+    5
+}
+
+/// 設定ファイルの `[ldap]`。
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LdapConfig {
+    /// `ldap://host[:port]` か `ldaps://host[:port]`
+    pub url: String,
+    /// `ldaps://` でサーバー証明書を確かめる CA (PEM)
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+    /// 検索に使うサービスアカウント。省略すると匿名で検索する。
+    #[serde(default)]
+    pub bind_dn: Option<String>,
+    #[serde(default)]
+    pub bind_password: Option<String>,
+    pub base_dn: String,
+    /// 例: Active Directory なら `(&(objectClass=user)(sAMAccountName={username}))`
+    #[serde(default = "default_user_filter")]
+    pub user_filter: String,
+    #[serde(default = "default_group_attribute")]
+    pub group_attribute: String,
+    #[serde(default)]
+    pub admin_groups: Vec<String>,
+    #[serde(default)]
+    pub user_groups: Vec<String>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl LdapConfig {
+    pub fn new(url: impl Into<String>, base_dn: impl Into<String>) -> Self {
+        LdapConfig {
+            url: url.into(),
+            ca_file: None,
+            bind_dn: None,
+            bind_password: None,
+            base_dn: base_dn.into(),
+            user_filter: default_user_filter(),
+            group_attribute: default_group_attribute(),
+            admin_groups: Vec::new(),
+            user_groups: Vec::new(),
+            timeout_secs: default_timeout_secs(),
+        }
+    }
+
+    /// ファイルを読まずに確かめられるところを確かめる。
+    pub fn check(&self) -> Result<(), String> {
+        let (tls, _, _) = parse_url(&self.url)?;
+        if tls && self.ca_file.is_none() {
+            return Err("ldaps:// needs ca_file".to_string());
+        }
+        if self.base_dn.trim().is_empty() {
+            return Err("base_dn is empty".to_string());
+        }
+        if !self.user_filter.contains(USERNAME_PLACEHOLDER) {
+            return Err(format!("user_filter must contain {}", USERNAME_PLACEHOLDER));
+        }
+        parse_filter(&self.user_filter)?;
+
+        Ok(())
+    }
+}
+
+/// パスワードを出さない。
+impl fmt::Debug for LdapConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LdapConfig")
+            .field("url", &self.url)
+            .field("bind_dn", &self.bind_dn)
+            .field("base_dn", &self.base_dn)
+            .field("user_filter", &self.user_filter)
+            .field("admin_groups", &self.admin_groups)
+            .field("user_groups", &self.user_groups)
+            .finish_non_exhaustive()
+    }
+}
+
+/// `(scheme が ldaps か, ホスト, ポート)`
+fn parse_url(url: &str) -> Result<(bool, String, u16), String> {
+    let (tls, rest) = if let Some(rest) = url.strip_prefix("ldaps://") {
+        (true, rest)
+    } else if let Some(rest) = url.strip_prefix("ldap://") {
+        (false, rest)
+    } else {
+        return Err(format!("{:?} must start with ldap:// or ldaps://", url));
+    };
+    let rest = rest.trim_end_matches('/');
+
+    let (host, port) = match rest.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => {
+            (host, port.parse().map_err(|_| format!("invalid port in {:?}", url))?)
+        }
+        _ => (rest, if tls { 636 } else { 389 }),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(format!("no host in {:?}", url));
+    }
+
+    Ok((tls, host.to_string(), port))
+}
+
+/// 検索フィルター (RFC 4515) のうち、等号・存在・`&`・`|`・`!` だけ。
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    Equal(String, String),
+    Present(String),
+}
+
+fn parse_filter(filter: &str) -> Result<Filter, String> {
+    let (parsed, rest) = parse_filter_at(filter.trim())?;
+    if !rest.is_empty() {
+        return Err(format!("unexpected {:?} after the filter", rest));
+    }
+    Ok(parsed)
+}
+
+fn parse_filter_at(s: &str) -> Result<(Filter, &str), String> {
+    let s = s.strip_prefix('(').ok_or_else(|| format!("expected '(' at {:?}", s))?;
+
+    if let Some(rest) = s.strip_prefix('&') {
+        let (filters, rest) = parse_filter_list(rest)?;
+        return Ok((Filter::And(filters), rest));
+    }
+    if let Some(rest) = s.strip_prefix('|') {
+        let (filters, rest) = parse_filter_list(rest)?;
+        return Ok((Filter::Or(filters), rest));
+    }
+    if let Some(rest) = s.strip_prefix('!') {
+        let (filter, rest) = parse_filter_at(rest)?;
+        let rest = rest.strip_prefix(')').ok_or("expected ')' after a negation")?;
+        return Ok((Filter::Not(Box::new(filter)), rest));
+    }
+
+    let end = s.find(')').ok_or("unterminated filter")?;
+    let (item, rest) = (&s[..end], &s[end + 1..]);
+    let (attribute, value) = item.split_once('=').ok_or_else(|| format!("{:?} has no '='", item))?;
+    if attribute.is_empty() || attribute.ends_with(['~', '<', '>', ':']) {
+        return Err(format!("{:?} is not supported; use attribute=value", item));
+    }
+
+    if value == "*" {
+        return Ok((Filter::Present(attribute.to_string()), rest));
+    }
+    if value.contains('*') {
+        return Err(format!("{:?}: substring filters are not supported", item));
+    }
+
+    Ok((Filter::Equal(attribute.to_string(), unescape(value)?), rest))
+}
+
+/// `)` までのフィルターを並べて読む。
+fn parse_filter_list(mut s: &str) -> Result<(Vec<Filter>, &str), String> {
+    let mut filters = Vec::new();
+    while !s.starts_with(')') {
+        if s.is_empty() {
+            return Err("unterminated filter".to_string());
+        }
+        let (filter, rest) = parse_filter_at(s)?;
+        filters.push(filter);
+        s = rest;
+    }
+    Ok((filters, &s[1..]))
+}
+
+/// `\2a` のような 16 進のエスケープを戻す。
+fn unescape(value: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'\\' {
+            let hex = tail.get(..2).ok_or("incomplete escape")?;
+            // from_str_radix は "+f" も通すので、16 進の数字だけを受け付ける
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return Err(format!("invalid escape \\{}", String::from_utf8_lossy(hex)));
+            }
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).unwrap(), 16).unwrap());
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| "escaped value is not UTF-8".to_string())
+}
+
+// BER (X.690) の書き出し
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn integer(tag: u8, value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // 先頭の 0 を削る。ただし次のバイトの最上位ビットが立っていれば、負の数にならないように残す
+    let mut start = 0;
+    while start < 3 && bytes[start] == 0 && bytes[start + 1] & 0x80 == 0 {
+        start += 1;
+    }
+    tlv(tag, &bytes[start..])
+}
+
+fn octets(value: &[u8]) -> Vec<u8> {
+    tlv(0x04, value)
+}
+
+fn encode_filter(filter: &Filter, username: &str) -> Vec<u8> {
+    match filter {
+        Filter::And(filters) => tlv(0xa0, &filters.iter().flat_map(|f| encode_filter(f, username)).collect::<Vec<_>>()),
+        Filter::Or(filters) => tlv(0xa1, &filters.iter().flat_map(|f| encode_filter(f, username)).collect::<Vec<_>>()),
+        Filter::Not(filter) => tlv(0xa2, &encode_filter(filter, username)),
+        Filter::Equal(attribute, value) => {
+            // 置き換えた値は BER の中にそのまま入るので、フィルターの構文を壊せない
+            let value = value.replace(USERNAME_PLACEHOLDER, username);
+            tlv(0xa3, &[octets(attribute.as_bytes()), octets(value.as_bytes())].concat())
+        }
+        Filter::Present(attribute) => tlv(0x87, attribute.as_bytes()),
+    }
+}
+
+fn bind_request(dn: &str, password: &str) -> Vec<u8> {
+    tlv(0x60, &[integer(0x02, 3), octets(dn.as_bytes()), tlv(0x80, password.as_bytes())].concat())
+}
+
+fn search_request(base_dn: &str, filter: Vec<u8>, attribute: &str, time_limit: u32) -> Vec<u8> {
+    tlv(0x63, &[
+        octets(base_dn.as_bytes()),
+        integer(0x0a, 2), // wholeSubtree
+        integer(0x0a, 0), // neverDerefAliases
+        integer(0x02, 2), // 2 件見つかれば曖昧なのでそれ以上は要らない
+        integer(0x02, time_limit),
+        tlv(0x01, &[0x00]),
+        filter,
+        tlv(0x30, &octets(attribute.as_bytes())),
+    ].concat())
+}
+
+// BER の読み取り
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("LDAP: {}", message))
+}
+
+struct Ber<'a>(&'a [u8]);
+
+impl<'a> Ber<'a> {
+    fn next(&mut self) -> io::Result<(u8, &'a [u8])> {
+        let (&tag, rest) = self.0.split_first().ok_or_else(|| invalid("truncated element"))?;
+        let (&first, mut rest) = rest.split_first().ok_or_else(|| invalid("truncated length"))?;
+
+        let len = if first < 0x80 {
+            first as usize
+        } else {
+            let n = (first & 0x7f) as usize;
+            if n == 0 || n > 4 || rest.len() < n {
+                return Err(invalid("unsupported length"));
+            }
+            let len = rest[..n].iter().fold(0usize, |len, b| len << 8 | *b as usize);
+            rest = &rest[n..];
+            len
+        };
+
+        if rest.len() < len {
+            return Err(invalid("truncated content"));
+        }
+        self.0 = &rest[len..];
+        Ok((tag, &rest[..len]))
+    }
+
+    fn expect(&mut self, tag: u8) -> io::Result<&'a [u8]> {
+        match self.next()? {
+            (t, content) if t == tag => Ok(content),
+            (t, _) => Err(invalid(&format!("expected tag {:#04x}, got {:#04x}", tag, t))),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// 0 以上で `u32` に収まる INTEGER / ENUMERATED。桁あふれで別の値 (resultCode の 0 など) に化けないよう、
+/// 収まらない値は切り詰めずにエラーにする。
+fn read_integer(content: &[u8]) -> io::Result<u32> {
+    if content.first().is_none_or(|b| b & 0x80 != 0) {
+        return Err(invalid("expected a non-negative integer"));
+    }
+    let digits = &content[content.iter().position(|&b| b != 0).unwrap_or(content.len())..];
+    if digits.len() > 4 {
+        return Err(invalid("integer out of range"));
+    }
+    Ok(digits.iter().fold(0u32, |n, b| n << 8 | *b as u32))
+}
+
+/// LDAPResult の resultCode。
+fn result_code(content: &[u8]) -> io::Result<u32> {
+    read_integer(Ber(content).expect(0x0a)?)
+}
+
+trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+struct Connection {
+    stream: Box<dyn Stream>,
+    next_id: u32,
+}
+
+struct Entry {
+    dn: String,
+    groups: Vec<String>,
+}
+
+impl Connection {
+    fn send(&mut self, op: Vec<u8>) -> io::Result<u32> {
+        self.next_id += 1;
+        let message = tlv(0x30, &[integer(0x02, self.next_id), op].concat());
+        self.stream.write_all(&message)?;
+        self.stream.flush()?;
+        Ok(self.next_id)
+    }
+
+    /// 次のメッセージの (messageID, protocolOp のタグ, 中身)。
+    fn receive(&mut self) -> io::Result<(u32, u8, Vec<u8>)> {
+        let mut head = [0u8; 2];
+        self.stream.read_exact(&mut head)?;
+        if head[0] != 0x30 {
+            return Err(invalid("expected an LDAPMessage"));
+        }
+
+        let len = if head[1] < 0x80 {
+            head[1] as usize
+        } else {
+            let n = (head[1] & 0x7f) as usize;
+            if n == 0 || n > 4 {
+                return Err(invalid("unsupported length"));
+            }
+            let mut bytes = [0u8; 4];
+            self.stream.read_exact(&mut bytes[4 - n..])?;
+            u32::from_be_bytes(bytes) as usize
+        };
+        if len > MAX_MESSAGE_LEN {
+            return Err(invalid("message too large"));
+        }
+
+        let mut content = vec![0u8; len];
+        self.stream.read_exact(&mut content)?;
+
+        let mut message = Ber(&content);
+        let id = read_integer(message.expect(0x02)?)?;
+        let (tag, op) = message.next()?;
+        Ok((id, tag, op.to_vec()))
+    }
+
+    fn bind(&mut self, dn: &str, password: &str) -> io::Result<u32> {
+        let id = self.send(bind_request(dn, password))?;
+        loop {
+            match self.receive()? {
+                (got, 0x61, op) if got == id => return result_code(&op),
+                (0, _, _) => return Err(invalid("the server closed the connection")),
+                _ => continue,
+            }
+        }
+    }
+
+    fn search(&mut self, request: Vec<u8>, attribute: &str) -> io::Result<Vec<Entry>> {
+        let id = self.send(request)?;
+        let mut entries = Vec::new();
+
+        loop {
+            let (got, tag, op) = self.receive()?;
+            if got != id {
+                continue;
+            }
+            match tag {
+                0x64 => {
+                    let mut entry = Ber(&op);
+                    let dn = String::from_utf8_lossy(entry.expect(0x04)?).to_string();
+                    let mut groups = Vec::new();
+
+                    let mut attributes = Ber(entry.expect(0x30)?);
+                    while !attributes.is_empty() {
+                        let mut pair = Ber(attributes.expect(0x30)?);
+                        let name = pair.expect(0x04)?;
+                        let mut values = Ber(pair.expect(0x31)?);
+                        while !values.is_empty() {
+                            let value = values.expect(0x04)?;
+                            if name.eq_ignore_ascii_case(attribute.as_bytes()) {
+                                groups.push(String::from_utf8_lossy(value).to_string());
+                            }
+                        }
+                    }
+
+                    entries.push(Entry { dn, groups });
+                }
+                // 参照先は追わない
+                0x73 => {}
+                0x65 => {
+                    return match result_code(&op)? {
+                        SUCCESS => Ok(entries),
+                        // sizeLimitExceeded: 2 件以上見つかった
+                        4 => Ok(entries),
+                        code => Err(invalid(&format!("search failed with result code {}", code))),
+                    };
+                }
+                _ => return Err(invalid(&format!("unexpected response {:#04x}", tag))),
+            }
+        }
+    }
+
+    fn unbind(mut self) {
+        let _ = self.send(tlv(0x42, &[]));
+    }
+}
+
+/// DN の比較用に、区切りの前後の空白を除いて小文字にする。
+fn normalize_dn(dn: &str) -> String {
+    dn.split(',').map(str::trim).collect::<Vec<_>>().join(",").to_lowercase()
+}
+
+/// 設定を読み込んだ LDAP の接続先。
+pub struct Ldap {
+    config: LdapConfig,
+    host: String,
+    port: u16,
+    tls: Option<Arc<ClientConfig>>,
+    filter: Filter,
+    /// 通らなかったユーザー名とパスワードの組のハッシュと、そのときの時刻
+    rejected: Mutex<HashMap<String, Instant>>,
+}
+
+/// LDAP で認証したユーザー。トークンに役割を書き込むのに使う。
+#[derive(Clone, Copy, Debug)]
+pub struct DirectoryUser;
+
+impl Ldap {
+    pub fn new(config: LdapConfig) -> Result<Self, String> {
+        config.check()?;
+        let (tls, host, port) = parse_url(&config.url)?;
+        let filter = parse_filter(&config.user_filter)?;
+
+        let tls = match (tls, &config.ca_file) {
+            (true, Some(ca_file)) => {
+                let mut roots = RootCertStore::empty();
+                for cert in CertificateDer::pem_file_iter(ca_file).map_err(|e| format!("{}: {}", ca_file.display(), e))? {
+                    let cert = cert.map_err(|e| format!("{}: {}", ca_file.display(), e))?;
+                    roots.add(cert).map_err(|e| format!("{}: {}", ca_file.display(), e))?;
+                }
+
+                let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                    .with_safe_default_protocol_versions()
+                    .map_err(|e| e.to_string())?
+                    .with_root_certificates(roots)
+                    .with_no_client_auth();
+                Some(Arc::new(config))
+            }
+            _ => None,
+        };
+
+        Ok(Ldap { config, host, port, tls, filter, rejected: Mutex::default() })
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+
+        let mut last_error = None;
+        let tcp = (self.host.as_str(), self.port).to_socket_addrs()?
+            .find_map(|addr| TcpStream::connect_timeout(&addr, timeout).map_err(|e| last_error = Some(e)).ok())
+            .ok_or_else(|| last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address")))?;
+        tcp.set_read_timeout(Some(timeout))?;
+        tcp.set_write_timeout(Some(timeout))?;
+
+        let stream: Box<dyn Stream> = match &self.tls {
+            Some(config) => {
+                let name = ServerName::try_from(self.host.clone()).map_err(|e| invalid(&e.to_string()))?;
+                let connection = ClientConnection::new(config.clone(), name).map_err(io::Error::other)?;
+                Box::new(StreamOwned::new(connection, tcp))
+            }
+            None => Box::new(tcp),
+        };
+
+        Ok(Connection { stream, next_id: 0 })
+    }
+
+    /// ユーザー名とパスワードを確かめ、役割を返す。認証できなければ `Ok(None)`。
+    /// サーバーにつながらないときなどは `Err`。
+    pub fn authenticate(&self, username: &str, password: &str) -> io::Result<Option<Role>> {
+        // 空のパスワードの bind は匿名の bind として成功してしまう
+        if username.is_empty() || password.is_empty() {
+            return Ok(None);
+        }
+
+        let mut connection = self.connect()?;
+        let code = connection.bind(
+            self.config.bind_dn.as_deref().unwrap_or_default(),
+            self.config.bind_password.as_deref().unwrap_or_default(),
+        )?;
+        if code != SUCCESS {
+            return Err(invalid(&format!("service bind failed with result code {}", code)));
+        }
+
+        let request = search_request(
+            &self.config.base_dn,
+            encode_filter(&self.filter, username),
+            &self.config.group_attribute,
+            self.config.timeout_secs as u32,
+        );
+        let entries = connection.search(request, &self.config.group_attribute)?;
+        let [entry] = entries.as_slice() else {
+            if entries.len() > 1 {
+                log::warn!("LDAP: {} entries match user {}; rejecting", entries.len(), username);
+            }
+            connection.unbind();
+            return Ok(None);
+        };
+
+        let code = connection.bind(&entry.dn, password)?;
+        let role = (code == SUCCESS).then(|| self.role(&entry.groups)).flatten();
+        connection.unbind();
+
+        Ok(role)
+    }
+
+    /// `authenticate` と同じ。最近通らなかった組はサーバーに問い合わせずに `Ok(None)` を返す。
+    pub fn authenticate_cached(&self, username: &str, password: &str) -> io::Result<Option<Role>> {
+        let key = crate::invites::hash_token(&format!("{}\0{}", username, password));
+        if self.rejected.lock().unwrap().get(&key).is_some_and(|at| at.elapsed() < REJECTED_TTL) {
+            return Ok(None);
+        }
+
+        let role = self.authenticate(username, password)?;
+        if role.is_none() {
+            let mut rejected = self.rejected.lock().unwrap();
+            rejected.retain(|_, at| at.elapsed() < REJECTED_TTL);
+            if rejected.len() >= MAX_REJECTED {
+                rejected.clear();
+            }
+            rejected.insert(key, Instant::now());
+        }
+
+        Ok(role)
+    }
+
+    /// グループから役割を決める。`user_groups` を指定していて、どれにも入っていなければ `None`。
+    fn role(&self, groups: &[String]) -> Option<Role> {
+        let groups: Vec<String> = groups.iter().map(|g| normalize_dn(g)).collect();
+        let member = |list: &[String]| list.iter().any(|g| groups.contains(&normalize_dn(g)));
+
+        if member(&self.config.admin_groups) {
+            Some(Role::Admin)
+        } else if self.config.user_groups.is_empty() || member(&self.config.user_groups) {
+            Some(Role::User)
+        } else {
+            None
+        }
+    }
+}
+
+/// ユーザーファイルにいないユーザーを LDAP で認証する。テナントのリクエストや、LDAP を設定して
+/// いないときは 401。問い合わせは `web::block` で別のスレッドで行う。
+pub(crate) async fn authenticate(req: &HttpRequest, username: &str, password: &str) -> Result<User, BookError> {
+    if req.extensions().contains::<Tenant>() {
+        return Err(BookError::Unauthorized);
+    }
+    let ldap = req.app_data::<web::Data<Ldap>>().ok_or(BookError::Unauthorized)?.clone();
+
+    let (name, secret) = (username.to_string(), password.to_string());
+    let result = web::block(move || ldap.authenticate_cached(&name, &secret))
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e.to_string())));
+
+    match result {
+        Ok(Some(role)) => {
+            req.extensions_mut().insert(DirectoryUser);
+            Ok(User { username: username.to_string(), password: String::new(), role })
+        }
+        Ok(None) => Err(BookError::Unauthorized),
+        Err(e) => {
+            log::error!("LDAP authentication for {} failed: {}", username, e);
+            Err(BookError::Unauthorized)
+        }
+    }
+}
+
+/// トークンに書かれた役割で、LDAP のユーザーを作り直す。LDAP を設定していなければ `None`。
+pub(crate) fn directory_user(req: &HttpRequest, username: &str, role: Role) -> Option<User> {
+    req.app_data::<web::Data<Ldap>>()?;
+    if req.extensions().contains::<Tenant>() {
+        return None;
+    }
+
+    Some(User { username: username.to_string(), password: String::new(), role })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(parse_url("ldap://dc.example.com").unwrap(), (false, "dc.example.com".to_string(), 389));
+        assert_eq!(parse_url("ldaps://dc.example.com:3269/").unwrap(), (true, "dc.example.com".to_string(), 3269));
+        assert_eq!(parse_url("ldap://[::1]").unwrap(), (false, "::1".to_string(), 389));
+        assert!(parse_url("http://dc.example.com").is_err());
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(
+            parse_filter("(&(objectClass=user)(sAMAccountName={username})(!(cn=a\\29b)))").unwrap(),
+            Filter::And(vec![
+                Filter::Equal("objectClass".to_string(), "user".to_string()),
+                Filter::Equal("sAMAccountName".to_string(), "{username}".to_string()),
+                Filter::Not(Box::new(Filter::Equal("cn".to_string(), "a)b".to_string()))),
+            ]),
+        );
+        assert_eq!(parse_filter("(mail=*)").unwrap(), Filter::Present("mail".to_string()));
+        assert!(parse_filter("(cn=a*b)").is_err());
+        assert!(parse_filter("(cn=a)(cn=b)").is_err());
+        assert!(parse_filter("(&(cn=a)").is_err());
+    }
+
+    #[test]
+    fn test_ber() {
+        assert_eq!(integer(0x02, 3), vec![0x02, 0x01, 0x03]);
+        assert_eq!(integer(0x02, 128), vec![0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(integer(0x02, 0), vec![0x02, 0x01, 0x00]);
+        assert_eq!(&tlv(0x04, &[0u8; 200])[..3], &[0x04, 0x81, 200]);
+
+        // ユーザー名に括弧や * を入れても、フィルターの意味は変わらない
+        let filter = encode_filter(&parse_filter("(uid={username})").unwrap(), "*)(uid=*");
+        let mut ber = Ber(&filter);
+        let mut item = Ber(ber.expect(0xa3).unwrap());
+        assert_eq!(item.expect(0x04).unwrap(), b"uid");
+        assert_eq!(item.expect(0x04).unwrap(), b"*)(uid=*");
+    }
+
+    #[test]
+    fn test_read_integer() {
+        assert_eq!(read_integer(&[0x00, 0x80]).unwrap(), 128);
+        assert_eq!(read_integer(&[0x00, 0xff, 0xff, 0xff, 0xff]).unwrap(), u32::MAX);
+        assert!(read_integer(&[]).is_err());
+        assert!(read_integer(&[0xff]).is_err());
+        // 切り詰めると 0 (success) に化ける値
+        assert!(read_integer(&[0x01, 0x00, 0x00, 0x00, 0x00]).is_err());
+        assert!(unescape("\\+f").is_err());
+    }
+
+    /// 決まった応答を返し、送ったものは捨てるストリーム。
+    struct Replay(io::Cursor<Vec<u8>>);
+
+    impl Read for Replay {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Replay {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn search(response: Vec<u8>) -> io::Result<Vec<Entry>> {
+        let mut connection = Connection { stream: Box::new(Replay(io::Cursor::new(response))), next_id: 0 };
+        connection.search(tlv(0x63, &[]), "memberOf")
+    }
+
+    /// 1 件見つかったときの検索の応答。
+    fn search_response() -> Vec<u8> {
+        let groups = tlv(0x31, &octets(b"cn=readers,dc=example,dc=com"));
+        let attributes = tlv(0x30, &tlv(0x30, &[octets(b"memberOf"), groups].concat()));
+        [
+            tlv(0x30, &[integer(0x02, 1), tlv(0x64, &[octets(b"uid=alice,dc=example,dc=com"), attributes].concat())].concat()),
+            tlv(0x30, &[integer(0x02, 1), tlv(0x65, &[integer(0x0a, 0), octets(b""), octets(b"")].concat())].concat()),
+        ].concat()
+    }
+
+    /// 壊れた応答やでたらめな入力でもパニックせず、エラーか結果を返す。
+    #[test]
+    fn test_malformed_responses() {
+        let response = search_response();
+        let entries = search(response.clone()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].groups, vec!["cn=readers,dc=example,dc=com"]);
+
+        for len in 0..response.len() {
+            assert!(search(response[..len].to_vec()).is_err(), "prefix of {} bytes", len);
+        }
+
+        let mut rng = StdRng::seed_from_u64(175);
+        let alphabet = ['(', ')', '&', '|', '!', '=', '*', '\\', '2', 'a', 'é'];
+        for _ in 0..5000 {
+            let mut mutated = response.clone();
+            for _ in 0..rng.gen_range(1..4) {
+                let i = rng.gen_range(0..mutated.len());
+                mutated[i] = rng.gen();
+            }
+            let _ = search(mutated);
+
+            let random: Vec<u8> = (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect();
+            let _ = search(random.clone());
+            let mut ber = Ber(&random);
+            while !ber.is_empty() && ber.next().is_ok() {}
+
+            let filter: String = (0..rng.gen_range(0..24)).map(|_| alphabet[rng.gen_range(0..alphabet.len())]).collect();
+            if let Ok(parsed) = parse_filter(&filter) {
+                encode_filter(&parsed, "alice");
+            }
+        }
+    }
+
+    /// bind と検索だけに答える LDAP サーバー。`alice` / `secret` だけを通す。
+    fn fake_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { return };
+                let mut connection = Connection { stream: Box::new(stream), next_id: 0 };
+                let mut bound_dn = String::new();
+
+                while let Ok((id, tag, op)) = connection.receive() {
+                    let reply = match tag {
+                        0x60 => {
+                            let mut bind = Ber(&op);
+                            bind.expect(0x02).unwrap();
+                            let dn = String::from_utf8(bind.expect(0x04).unwrap().to_vec()).unwrap();
+                            let password = bind.expect(0x80).unwrap().to_vec();
+                            let ok = match dn.as_str() {
+                                "cn=svc,dc=example,dc=com" => password == b"svc-password",
+                                "uid=alice,ou=people,dc=example,dc=com" => password == b"secret",
+                                _ => false,
+                            };
+                            bound_dn = dn;
+                            vec![tlv(0x61, &[integer(0x0a, if ok { 0 } else { 49 }), octets(b""), octets(b"")].concat())]
+                        }
+                        0x63 => {
+                            assert_eq!(bound_dn, "cn=svc,dc=example,dc=com");
+                            let mut search = Ber(&op);
+                            search.expect(0x04).unwrap();
+                            for _ in 0..5 {
+                                search.next().unwrap();
+                            }
+                            let mut filter = Ber(search.expect(0xa3).unwrap());
+                            filter.expect(0x04).unwrap();
+                            let uid = filter.expect(0x04).unwrap().to_vec();
+
+                            let mut replies = Vec::new();
+                            if uid == b"alice" {
+                                let groups = tlv(0x31, &[
+                                    octets(b"cn=readers,ou=groups,dc=example,dc=com"),
+                                    octets(b"cn=Books Admins, ou=groups, dc=example, dc=com"),
+                                ].concat());
+                                let attributes = tlv(0x30, &tlv(0x30, &[octets(b"memberOf"), groups].concat()));
+                                replies.push(tlv(0x64, &[octets(b"uid=alice,ou=people,dc=example,dc=com"), attributes].concat()));
+                            }
+                            replies.push(tlv(0x65, &[integer(0x0a, 0), octets(b""), octets(b"")].concat()));
+                            replies
+                        }
+                        _ => break,
+                    };
+
+                    for op in reply {
+                        let message = tlv(0x30, &[integer(0x02, id), op].concat());
+                        connection.stream.write_all(&message).unwrap();
+                    }
+                }
+            }
+        });
+
+        port
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let port = fake_server();
+        let mut config = LdapConfig::new(format!("ldap://127.0.0.1:{}", port), "dc=example,dc=com");
+        config.bind_dn = Some("cn=svc,dc=example,dc=com".to_string());
+        config.bind_password = Some("svc-password".to_string());
+        config.admin_groups = vec!["cn=books admins,ou=groups,dc=example,dc=com".to_string()];
+        let ldap = Ldap::new(config.clone()).unwrap();
+
+        assert_eq!(ldap.authenticate("alice", "secret").unwrap(), Some(Role::Admin));
+        assert_eq!(ldap.authenticate("alice", "wrong").unwrap(), None);
+        assert_eq!(ldap.authenticate("alice", "").unwrap(), None);
+        assert_eq!(ldap.authenticate("bob", "secret").unwrap(), None);
+
+        assert_eq!(ldap.authenticate_cached("bob", "secret").unwrap(), None);
+        assert_eq!(ldap.rejected.lock().unwrap().len(), 1);
+        assert_eq!(ldap.authenticate_cached("alice", "secret").unwrap(), Some(Role::Admin));
+        assert_eq!(ldap.rejected.lock().unwrap().len(), 1);
+
+        config.admin_groups.clear();
+        config.user_groups = vec!["cn=writers,ou=groups,dc=example,dc=com".to_string()];
+        assert_eq!(Ldap::new(config.clone()).unwrap().authenticate("alice", "secret").unwrap(), None);
+
+        config.bind_password = Some("wrong".to_string());
+        assert!(Ldap::new(config).unwrap().authenticate("alice", "secret").is_err());
+    }
+}
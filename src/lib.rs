@@ -0,0 +1,472 @@
+use std::path::Path;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::middleware;
+use actix_web::http::KeepAlive;
+use actix_web::{web, App, HttpServer};
+use log::error;
+
+pub mod analysis;
+pub mod anonymous;
+pub mod api_keys;
+pub mod audio;
+pub mod audit;
+pub mod authors;
+pub mod auth;
+pub mod bench;
+pub mod bulk_tags;
+pub mod cli;
+pub mod compress;
+pub mod config;
+mod conditional;
+mod cors;
+pub mod data_export;
+pub mod erasure;
+pub mod error;
+pub mod events;
+pub mod facets;
+pub mod flags;
+pub mod genres;
+pub mod grpc;
+pub mod handlers;
+pub mod idempotency;
+pub mod integrity;
+pub mod invites;
+pub mod ipfilter;
+pub mod jwt;
+pub mod language;
+pub mod ldap;
+mod jsonapi;
+mod limits;
+mod links;
+pub mod logging;
+pub mod maintenance;
+pub mod models;
+pub mod password;
+pub mod pattern;
+pub mod private_file;
+pub mod proxy;
+pub mod publishers;
+pub mod pwned;
+pub mod query;
+pub mod quotes;
+pub mod ratelimit;
+pub mod reading;
+pub mod registry;
+pub mod reload;
+pub mod repair;
+pub mod request_id;
+mod negotiate;
+pub mod normalize;
+pub mod sanitize;
+pub mod search;
+pub mod seed;
+pub mod share;
+pub mod sort;
+pub mod spelling;
+pub mod stats;
+pub mod storage;
+pub mod suggest;
+pub mod sync;
+pub mod synonyms;
+pub mod tag_tree;
+pub mod telemetry;
+pub mod tenant;
+pub mod timeout;
+pub mod tls;
+#[cfg(unix)]
+mod systemd;
+#[cfg(unix)]
+mod uds;
+pub mod usage;
+pub mod webhooks;
+pub mod works;
+
+pub use config::Config;
+pub use error::BookError;
+pub use models::{Book, BookQuery, Format, Loan, Role, User, Visibility};
+
+use auth::save_user;
+use pwned::PwnedCheck;
+use storage::BookRepository;
+use webhooks::Webhooks;
+
+/// ハンドラー間で共有する状態。実行中に切り替わるのはメンテナンスモードと機能フラグだけで、
+/// 書籍データ自体はリポジトリ内の `RwLock` 付きキャッシュが持つ。
+pub struct AppState {
+    repository: BookRepository,
+    webhooks: Webhooks,
+    cors_origins: cors::AllowedOrigins,
+    maintenance: maintenance::Maintenance,
+    flags: flags::Flags,
+}
+
+impl AppState {
+    pub fn new(repository: BookRepository, webhooks: Webhooks) -> Self {
+        AppState {
+            repository,
+            webhooks,
+            cors_origins: cors::AllowedOrigins::new(&config::DEFAULT_CORS_ORIGINS.map(String::from)),
+            maintenance: maintenance::Maintenance::default(),
+            flags: flags::Flags::default(),
+        }
+    }
+
+    /// CORS で許可するオリジンを変える。`https://*.example.com` のようなワイルドカードも書ける。
+    /// 読めないパターンは無視する。
+    pub fn with_cors_origins(mut self, origins: Vec<String>) -> Self {
+        self.cors_origins = cors::AllowedOrigins::new(&origins);
+        self
+    }
+
+    /// 機能フラグを差し替える。`Flags` は複製しても同じ状態を指すので、手元に残した方から切り替えられる。
+    pub fn with_flags(mut self, flags: flags::Flags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// 機能フラグの現在の値。
+    pub fn flags(&self) -> &flags::Flags {
+        &self.flags
+    }
+
+    /// メンテナンスモードを他の `AppState` (既定のテナントなど) と共有する。
+    pub fn with_maintenance(mut self, maintenance: maintenance::Maintenance) -> Self {
+        self.maintenance = maintenance;
+        self
+    }
+
+    /// メンテナンスモードの状態。
+    pub fn maintenance(&self) -> &maintenance::Maintenance {
+        &self.maintenance
+    }
+}
+
+/// ミドルウェアと全ルートを組み込んだ `App` を作る。結合テストや組み込み用途でも使える。
+pub fn app(
+    state: web::Data<AppState>,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let cors_origins = state.cors_origins.clone();
+
+    App::new()
+        .app_data(state)
+        .app_data(limits::json_config(limits::DEFAULT_JSON_LIMIT))
+        .app_data(limits::form_config(limits::DEFAULT_JSON_LIMIT))
+        .app_data(limits::payload_config(limits::DEFAULT_UPLOAD_LIMIT))
+        .wrap(middleware::from_fn(audit::record))
+        .wrap(middleware::from_fn(idempotency::replay))
+        .wrap(middleware::from_fn(flags::require_read_auth))
+        .wrap(middleware::from_fn(api_keys::require_scope))
+        .wrap(middleware::from_fn(anonymous::restrict))
+        .wrap(middleware::from_fn(maintenance::reject_writes))
+        .wrap(middleware::from_fn(logging::log_bodies))
+        .wrap(middleware::from_fn(timeout::timeout))
+        .wrap(middleware::from_fn(compress::compress))
+        .wrap(middleware::from_fn(ratelimit::rate_limit))
+        .wrap(middleware::from_fn(ipfilter::filter))
+        .wrap(middleware::from_fn(tenant::resolve))
+        .wrap(cors::cors(cors_origins))
+        .wrap(middleware::from_fn(logging::access_log))
+        .wrap(tracing_actix_web::TracingLogger::default())
+        .wrap(middleware::from_fn(request_id::request_id))
+        .configure(handlers::configure)
+}
+
+/// データファイルと Webhook のファイルを置くディレクトリを作り、データファイルがなければ用意する。
+/// 鍵があれば平文のデータファイルを暗号化する。
+fn prepare_files(config: &Config, data_file: &Path, webhooks_file: &Path) -> std::io::Result<()> {
+    if storage::bootstrap(data_file, config.initial_data)? {
+        log::info!("Created {} ({:?})", data_file.display(), config.initial_data);
+    }
+    if let Some(dir) = webhooks_file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    if let Some(key) = &config.encryption_key {
+        match storage::encrypt_file(data_file, key) {
+            Ok(true) => log::warn!("Encrypted {}; existing backups are left as they are", data_file.display()),
+            Ok(false) => {}
+            Err(e) => log::error!("Failed to encrypt {}: {}", data_file.display(), e),
+        }
+    }
+
+    Ok(())
+}
+
+/// どのアドレスで失敗したか分かるようにする。ポートが使用中なら対処法も添える。
+fn bind_error(host: &str, port: u16, e: std::io::Error) -> std::io::Error {
+    let hint = if e.kind() == std::io::ErrorKind::AddrInUse {
+        " (is another instance running? set BIND_ADDR or server.bind to use a different port)"
+    } else {
+        ""
+    };
+
+    std::io::Error::new(e.kind(), format!("Failed to bind {}:{}: {}{}", host, port, e, hint))
+}
+
+/// HTTP サーバーと gRPC サーバーを起動し、HTTP サーバーが止まるまで待つ。
+///
+/// SIGTERM / SIGINT を受けると新しい接続の受け付けをやめ、処理中のリクエストを待ってから
+/// gRPC サーバーを止め、書き込みスレッドに残っている変更をファイルに書き出して終了する。
+pub async fn serve(config: Config) -> std::io::Result<()> {
+    prepare_files(&config, &config.data_file, &config.webhooks_file)?;
+    for tenant in config.tenants.values() {
+        prepare_files(&config, &tenant.data_file, &tenant.webhooks_file)?;
+    }
+
+    let repository = BookRepository::with_options(&config.data_file, config.storage_options());
+
+    // 最初のリクエストを待たずにデータファイルを読み、全文検索の索引を作っておく
+    match repository.list() {
+        Ok(books) => log::info!("Loaded {} books (search: {})", books.len(), repository.search_backend()),
+        Err(e @ BookError::JsonParseError(_)) => {
+            log::error!("Failed to parse {}: {}; run `books repair` to salvage it", config.data_file.display(), e)
+        }
+        Err(e) => log::warn!("Failed to load {}: {}", config.data_file.display(), e),
+    }
+
+    let webhooks = Webhooks::new(&config.webhooks_file);
+    let flags = flags::Flags::new(config.flags.clone());
+    webhooks.spawn_dispatcher(repository.events(), flags.clone());
+
+    let books = web::Data::new(
+        AppState::new(repository.clone(), webhooks)
+            .with_cors_origins(config.cors_origins.clone())
+            .with_flags(flags),
+    );
+
+    let mut tenants = tenant::Tenants::default();
+    for (name, tenant) in &config.tenants {
+        let repository = BookRepository::with_options(&tenant.data_file, config.storage_options());
+        match repository.list() {
+            Ok(books) => log::info!("Loaded {} books for tenant {}", books.len(), name),
+            Err(e) => log::warn!("Failed to load {} for tenant {}: {}", tenant.data_file.display(), name, e),
+        }
+
+        let webhooks = Webhooks::new(&tenant.webhooks_file);
+        webhooks.spawn_dispatcher(repository.events(), books.flags().clone());
+
+        let state = AppState::new(repository, webhooks)
+            .with_cors_origins(config.cors_origins.clone())
+            .with_flags(books.flags().clone())
+            .with_maintenance(books.maintenance().clone());
+        tenants.insert(name, tenant.users_file.clone(), web::Data::new(state));
+    }
+    let tenants = web::Data::new(tenants);
+
+    let password_policy = web::Data::new(config.password_policy().map_err(std::io::Error::other)?);
+
+    if let Some((username, password)) = &config.admin {
+        if !password_policy.check(username, password).is_empty() {
+            log::warn!("ADMIN_PASSWORD does not meet the password policy; change it with PUT /account/password");
+        }
+        save_user(username, password, Role::Admin)?;
+    }
+
+    let grpc_addr = config.grpc_addr;
+    let grpc_max_results = config.max_results;
+    let grpc_repository = repository.clone();
+    let grpc_maintenance = books.maintenance.clone();
+    let grpc_access = config.anonymous_access;
+    let ip_filter = web::Data::new(ipfilter::IpFilter::parse(&config.ip_rules).unwrap_or_default());
+    let audit = web::Data::new(audit::Audit::new(&config.audit_file));
+    let grpc_ip_filter = ip_filter.get_ref().clone();
+    let grpc_audit = audit.clone().into_inner();
+    let (stop_grpc, grpc_stopped) = tokio::sync::oneshot::channel::<()>();
+
+    let grpc = tokio::spawn(async move {
+        let service = grpc::BooksServiceServer::new(grpc::GrpcBooks::new(grpc_repository)
+            .with_max_results(grpc_max_results)
+            .with_maintenance(grpc_maintenance)
+            .with_anonymous_access(grpc_access)
+            .with_ip_filter(grpc_ip_filter)
+            .with_audit(grpc_audit));
+
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_shutdown(grpc_addr, async {
+                let _ = grpc_stopped.await;
+            })
+            .await
+        {
+            error!("gRPC server error: {}", e);
+        }
+    });
+
+    let compression = web::Data::new(compress::Compression(config.compression_level));
+
+    let rate_limits = web::Data::new(ratelimit::RateLimits::new(
+        config.rate_limit_per_ip,
+        config.rate_limit_per_api_key,
+    ));
+
+    let reloader = web::Data::new(reload::Reloader::new(
+        config.config_file.clone(),
+        books.cors_origins.clone(),
+        books.flags().clone(),
+        rate_limits.clone(),
+    ));
+    #[cfg(unix)]
+    reload::spawn_on_sighup(reloader.clone());
+
+    let request_timeout = web::Data::new(timeout::RequestTimeout(config.request_timeout));
+    let idempotency = web::Data::new(idempotency::Idempotency::new(config.idempotency_window));
+    let body_logging = web::Data::new(config.body_logging.clone());
+
+    let trusted_proxies = web::Data::new(
+        proxy::TrustedProxies::parse(&config.trusted_proxies).unwrap_or_default(),
+    );
+
+    let keys = web::Data::new(jwt::Keys::open(&config.keys_file, config.token_ttl)?);
+    let signup = web::Data::new(handlers::account::Signup { open: config.open_signup });
+    let seed_endpoint = web::Data::new(seed::SeedEndpoint { enabled: config.seed_endpoint });
+    let invites = web::Data::new(invites::Invites::open(&config.invites_file, config.invite_ttl)?);
+    let api_keys = web::Data::new(api_keys::ApiKeys::open(&config.api_keys_file)?);
+    let anonymous_access = web::Data::new(config.anonymous_access);
+    let shares = web::Data::new(share::Shares::open(&config.shares_file)?);
+    let data_exports = web::Data::new(data_export::DataExports::new(config.export_notify_hosts.clone()));
+    let pwned = web::Data::new(PwnedCheck::new(config.pwned_check, config.pwned_api_url.clone(), config.pwned_timeout));
+    let ldap = match &config.ldap {
+        Some(ldap) => Some(web::Data::new(ldap::Ldap::new(ldap.clone()).map_err(std::io::Error::other)?)),
+        None => None,
+    };
+
+    let result_limits = web::Data::new(limits::ResultLimits {
+        max_per_page: config.max_per_page,
+        max_results: config.max_results,
+        max_export: config.max_export,
+    });
+
+    let mut server = HttpServer::new(move || {
+        let app = app(books.clone())
+            .app_data(compression.clone())
+            .app_data(rate_limits.clone())
+            .app_data(request_timeout.clone())
+            .app_data(idempotency.clone())
+            .app_data(body_logging.clone())
+            .app_data(result_limits.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(ip_filter.clone())
+            .app_data(tenants.clone())
+            .app_data(reloader.clone())
+            .app_data(keys.clone())
+            .app_data(signup.clone())
+            .app_data(seed_endpoint.clone())
+            .app_data(invites.clone())
+            .app_data(api_keys.clone())
+            .app_data(anonymous_access.clone())
+            .app_data(shares.clone())
+            .app_data(audit.clone())
+            .app_data(data_exports.clone())
+            .app_data(password_policy.clone())
+            .app_data(pwned.clone())
+            .app_data(limits::json_config(config.json_limit))
+            .app_data(limits::form_config(config.json_limit))
+            .app_data(limits::payload_config(config.upload_limit));
+
+        match &ldap {
+            Some(ldap) => app.app_data(ldap.clone()),
+            None => app,
+        }
+    });
+
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    if let Some(keep_alive) = config.keep_alive {
+        server = server.keep_alive(if keep_alive.is_zero() { KeepAlive::Disabled } else { KeepAlive::Timeout(keep_alive) });
+    }
+    if let Some(client_timeout) = config.client_timeout {
+        server = server.client_request_timeout(client_timeout);
+    }
+    if let Some(max_connections) = config.max_connections {
+        server = server.max_connections(max_connections);
+    }
+
+    // 待ち受けに失敗したら、先に起動した gRPC サーバーも止めてから終了する
+    let bound = (|| -> std::io::Result<_> {
+        let tls = match (&config.tls_cert, &config.tls_key) {
+            (Some(cert), Some(key)) => Some(tls::server_config(cert, key)?),
+            _ => None,
+        };
+
+        // systemd から渡されたソケットがあれば、それだけで待ち受ける
+        #[cfg(unix)]
+        {
+            let activated = systemd::listeners()?;
+            if !activated.is_empty() {
+                for listener in activated {
+                    server = match listener {
+                        systemd::Listener::Tcp(listener) => {
+                            let addr = listener.local_addr()?;
+                            log::info!("Listening on {}://{} (socket activation)", if tls.is_some() { "https" } else { "http" }, addr);
+                            match &tls {
+                                Some(tls) => server.listen_rustls_0_23(listener, tls.clone())?,
+                                None => server.listen(listener)?,
+                            }
+                        }
+                        systemd::Listener::Unix(listener) => {
+                            let addr = listener.local_addr()?;
+                            let path = addr.as_pathname().map(|p| p.display().to_string()).unwrap_or_default();
+                            log::info!("Listening on unix:{} (socket activation)", path);
+                            server.listen_uds(listener)?
+                        }
+                    };
+                }
+                return Ok(server);
+            }
+        }
+
+        for (host, port) in &config.bind {
+            server = match &tls {
+                Some(tls) => server.bind_rustls_0_23((host.as_str(), *port), tls.clone()),
+                None => server.bind((host.as_str(), *port)),
+            }
+            .map_err(|e| bind_error(host, *port, e))?;
+
+            log::info!("Listening on {}://{}:{}", if tls.is_some() { "https" } else { "http" }, host, port);
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = &config.unix_socket {
+            uds::remove_stale(path)?;
+            server = server.bind_uds(path)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("Failed to bind {}: {}", path.display(), e)))?;
+            uds::set_permissions(path, config.unix_socket_mode, config.unix_socket_uid, config.unix_socket_gid)?;
+
+            log::info!("Listening on unix:{}", path.display());
+        }
+
+        Ok(server)
+    })();
+
+    let server = match bound {
+        Ok(server) => server,
+        Err(e) => {
+            let _ = stop_grpc.send(());
+            return Err(e);
+        }
+    };
+
+    server.run().await?;
+
+    log::info!("HTTP server stopped, shutting down gRPC server");
+    let _ = stop_grpc.send(());
+    let _ = grpc.await;
+
+    match web::block(move || repository.flush()).await {
+        Ok(Ok(())) => log::info!("Pending writes flushed"),
+        Ok(Err(e)) => error!("Failed to flush pending writes: {}", e),
+        Err(e) => error!("Failed to flush pending writes: {}", e),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,95 @@
+//! リクエストボディの大きさの上限。
+//!
+//! 上限を超えたボディは読み切る前に打ち切り、上限値を示した 413 を返す。
+//! JSON とフォームは書籍 1 冊分、取り込み用の生ボディ (Zotero) はファイル全体を想定した値にする。
+//!
+//! レスポンス側も、一覧・検索・エクスポートの件数に上限を設ける。上限で切り詰めたときは
+//! `X-Truncated: true` を付け、クライアントがページングに切り替えられるようにする。
+
+use actix_web::error::{JsonPayloadError, UrlencodedError};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::BookError;
+
+/// JSON / フォームの既定の上限 (1 MiB)。
+pub const DEFAULT_JSON_LIMIT: usize = 1024 * 1024;
+
+/// 取り込み用の生ボディの既定の上限 (16 MiB)。
+pub const DEFAULT_UPLOAD_LIMIT: usize = 16 * 1024 * 1024;
+
+pub fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(|err, _req| match err {
+            JsonPayloadError::Overflow { limit } | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+                BookError::PayloadTooLarge(limit).into()
+            }
+            other => BookError::BadRequest(other.to_string()).into(),
+        })
+}
+
+pub fn form_config(limit: usize) -> web::FormConfig {
+    web::FormConfig::default()
+        .limit(limit)
+        .error_handler(|err, _req| match err {
+            UrlencodedError::Overflow { limit, .. } => BookError::PayloadTooLarge(limit).into(),
+            other => BookError::BadRequest(other.to_string()).into(),
+        })
+}
+
+pub fn payload_config(limit: usize) -> web::PayloadConfig {
+    web::PayloadConfig::new(limit)
+}
+
+/// 1 ページあたりの件数の既定の上限。
+pub const DEFAULT_MAX_PER_PAGE: usize = 100;
+
+/// ページを指定しない一覧・検索で返す件数の既定の上限。
+pub const DEFAULT_MAX_RESULTS: usize = 1000;
+
+/// エクスポートで書き出す件数の既定の上限。
+pub const DEFAULT_MAX_EXPORT: usize = 10_000;
+
+/// 上限で結果を切り詰めたときに付けるヘッダー。
+pub const TRUNCATED_HEADER: &str = "x-truncated";
+
+/// 1 回のレスポンスで返す件数の上限。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResultLimits {
+    pub max_per_page: usize,
+    pub max_results: usize,
+    pub max_export: usize,
+}
+
+impl Default for ResultLimits {
+    fn default() -> Self {
+        ResultLimits {
+            max_per_page: DEFAULT_MAX_PER_PAGE,
+            max_results: DEFAULT_MAX_RESULTS,
+            max_export: DEFAULT_MAX_EXPORT,
+        }
+    }
+}
+
+impl ResultLimits {
+    /// アプリに登録された上限を返す。登録されていなければ既定値。
+    pub fn of(req: &HttpRequest) -> Self {
+        req.app_data::<web::Data<ResultLimits>>()
+            .map(|limits| *limits.get_ref())
+            .unwrap_or_default()
+    }
+}
+
+/// `items` を `max` 件までに切り詰め、切り詰めたかどうかを返す。
+pub fn truncate<T>(items: &mut Vec<T>, max: usize) -> bool {
+    let truncated = items.len() > max;
+    items.truncate(max);
+    truncated
+}
+
+pub fn insert_truncated_header(resp: &mut HttpResponse, truncated: bool) {
+    if truncated {
+        resp.headers_mut().insert(HeaderName::from_static(TRUNCATED_HEADER), HeaderValue::from_static("true"));
+    }
+}
@@ -0,0 +1,279 @@
+use actix_web::http::header::{HeaderValue, LINK};
+use actix_web::{HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::audio::Audio;
+use crate::limits::{self, ResultLimits};
+use crate::reading::ContentStats;
+use crate::{Book, Format, Loan, Visibility};
+
+const DEFAULT_PER_PAGE: usize = 20;
+
+#[derive(Serialize)]
+pub struct BookLinks {
+    #[serde(rename = "self")]
+    self_link: String,
+    /// 同じタグを持つ書籍の検索結果
+    related: Vec<String>,
+}
+
+fn is_empty(items: &&[String]) -> bool {
+    items.is_empty()
+}
+
+/// 本文を省いた本。`Book` の形は変えずに、一覧のレスポンスだけで使う。
+#[derive(Serialize)]
+pub struct BookSummary<'a> {
+    id: u32,
+    title: &'a str,
+    tags: &'a [String],
+    #[serde(skip_serializing_if = "is_empty")]
+    genres: &'a [String],
+    #[serde(skip_serializing_if = "is_empty")]
+    authors: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    published_year: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publisher: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    isbn: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_stats: Option<&'a ContentStats>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    formats: &'a [Format],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audio: Option<&'a Audio>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    work: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    loan: Option<&'a Loan>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    visibility: Option<Visibility>,
+}
+
+impl<'a> From<&'a Book> for BookSummary<'a> {
+    fn from(book: &'a Book) -> Self {
+        BookSummary {
+            id: book.id,
+            title: &book.title,
+            tags: &book.tags,
+            genres: &book.genres,
+            authors: &book.authors,
+            published_year: book.published_year,
+            publisher: book.publisher.as_deref(),
+            isbn: book.isbn.as_deref(),
+            language: book.language.as_deref(),
+            content_stats: book.content_stats.as_ref(),
+            formats: &book.formats,
+            audio: book.audio.as_ref(),
+            work: book.work,
+            loan: book.loan.as_ref(),
+            visibility: book.visibility,
+        }
+    }
+}
+
+/// 一覧の 1 冊。本文は大きいので、`include_content` を指定されたときだけ含める。
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum ListedBook<'a> {
+    Full(&'a Book),
+    Summary(BookSummary<'a>),
+}
+
+impl<'a> ListedBook<'a> {
+    pub fn new(book: &'a Book, include_content: bool) -> Self {
+        if include_content {
+            ListedBook::Full(book)
+        } else {
+            ListedBook::Summary(BookSummary::from(book))
+        }
+    }
+}
+
+pub fn listed(books: &[Book], include_content: bool) -> Vec<ListedBook<'_>> {
+    books.iter().map(|book| ListedBook::new(book, include_content)).collect()
+}
+
+#[derive(Serialize)]
+pub struct LinkedBook<'a> {
+    #[serde(flatten)]
+    book: ListedBook<'a>,
+    links: BookLinks,
+}
+
+fn tag_search_url(tag: &str) -> String {
+    let query = serde_urlencoded::to_string([("tag", tag)]).unwrap_or_default();
+
+    format!("/books/search?{}", query)
+}
+
+pub fn book_links(book: &Book) -> BookLinks {
+    BookLinks {
+        self_link: format!("/books/id/{}", book.id),
+        related: book.tags.iter().map(|t| tag_search_url(t)).collect(),
+    }
+}
+
+pub fn with_links(books: &[Book], include_content: bool) -> Vec<LinkedBook<'_>> {
+    books.iter()
+        .map(|book| LinkedBook { book: ListedBook::new(book, include_content), links: book_links(book) })
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct Pagination {
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+pub struct Page {
+    pub items: Vec<Book>,
+    link: Option<String>,
+    total: usize,
+    /// 上限のために要求より少ない件数しか返していないか
+    truncated: bool,
+}
+
+impl Page {
+    /// `Link` ヘッダー (RFC 8288)・総件数・切り詰めの有無をレスポンスに付与する。
+    pub fn insert_headers(&self, resp: &mut HttpResponse) {
+        limits::insert_truncated_header(resp, self.truncated);
+
+        if self.link.is_none() && !self.truncated {
+            return;
+        }
+
+        if let Some(value) = self.link.as_deref().and_then(|link| HeaderValue::from_str(link).ok()) {
+            resp.headers_mut().insert(LINK, value);
+        }
+        resp.headers_mut().insert(
+            actix_web::http::header::HeaderName::from_static("x-total-count"),
+            HeaderValue::from(self.total),
+        );
+    }
+}
+
+fn page_url(req: &HttpRequest, page: usize, per_page: usize) -> String {
+    let mut params: Vec<(String, String)> = serde_urlencoded::from_str(req.query_string()).unwrap_or_default();
+    params.retain(|(k, _)| k != "page" && k != "per_page");
+    params.push(("page".to_string(), page.to_string()));
+    params.push(("per_page".to_string(), per_page.to_string()));
+
+    format!("{}{}?{}", crate::tenant::path_prefix(req), req.path(), serde_urlencoded::to_string(params).unwrap_or_default())
+}
+
+impl Pagination {
+    /// `page` が指定されたときだけ切り出す。指定がなければ先頭から `max_results` 件までを返す。
+    /// `per_page` は `max_per_page` までに抑える。
+    pub fn apply(&self, req: &HttpRequest, mut books: Vec<Book>) -> Page {
+        let limits = ResultLimits::of(req);
+        let total = books.len();
+
+        let Some(page) = self.page else {
+            let truncated = limits::truncate(&mut books, limits.max_results);
+            return Page { items: books, link: None, total, truncated };
+        };
+
+        let page = page.max(1);
+        let requested = self.per_page.unwrap_or(DEFAULT_PER_PAGE).max(1);
+        let per_page = requested.min(limits.max_per_page.max(1));
+        let last = total.div_ceil(per_page).max(1);
+
+        let items: Vec<Book> = books.into_iter()
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+            .collect();
+
+        let mut links = vec![
+            format!("<{}>; rel=\"first\"", page_url(req, 1, per_page)),
+            format!("<{}>; rel=\"last\"", page_url(req, last, per_page)),
+        ];
+        if page > 1 {
+            links.push(format!("<{}>; rel=\"prev\"", page_url(req, page - 1, per_page)));
+        }
+        if page < last {
+            links.push(format!("<{}>; rel=\"next\"", page_url(req, page + 1, per_page)));
+        }
+
+        let truncated = per_page < requested && items.len() == per_page;
+
+        Page { items, link: Some(links.join(", ")), total, truncated }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_book_links() {
+        let book = Book {
+            id: 9,
+            tags: vec!["data structures".to_string()],
+            ..Default::default()
+        };
+
+        let json = serde_json::to_value(with_links(std::slice::from_ref(&book), true)).unwrap();
+
+        assert_eq!(json[0]["id"], 9);
+        assert_eq!(json[0]["content"], "");
+        assert_eq!(json[0]["links"]["self"], "/books/id/9");
+        assert_eq!(json[0]["links"]["related"][0], "/books/search?tag=data+structures");
+    }
+
+    #[test]
+    fn test_summary_omits_only_content() {
+        let book = Book {
+            id: 3,
+            title: "Rust Basics".to_string(),
+            content: "Intro to Rust".to_string(),
+            tags: vec!["rust".to_string()],
+            authors: vec!["Steve Klabnik".to_string()],
+            publisher: Some("No Starch".to_string()),
+            visibility: Some(Visibility::Unlisted),
+            ..Default::default()
+        };
+
+        let mut full = serde_json::to_value(&book).unwrap();
+        full.as_object_mut().unwrap().remove("content");
+        assert_eq!(serde_json::to_value(ListedBook::new(&book, false)).unwrap(), full);
+    }
+
+    #[test]
+    fn test_pagination_links() {
+        let req = TestRequest::get().uri("/books/search?tag=rust&page=2&per_page=2").to_http_request();
+        let books: Vec<Book> = (1..=5).map(|id| Book { id, ..Default::default() }).collect();
+
+        let pagination = Pagination { page: Some(2), per_page: Some(2) };
+        let page = pagination.apply(&req, books);
+
+        assert_eq!(page.items.iter().map(|b| b.id).collect::<Vec<_>>(), vec![3, 4]);
+
+        let link = page.link.unwrap();
+        assert!(link.contains("</books/search?tag=rust&page=1&per_page=2>; rel=\"prev\""));
+        assert!(link.contains("</books/search?tag=rust&page=3&per_page=2>; rel=\"next\""));
+        assert!(link.contains("</books/search?tag=rust&page=3&per_page=2>; rel=\"last\""));
+        assert!(!page.truncated);
+    }
+
+    #[test]
+    fn test_pagination_caps() {
+        let books: Vec<Book> = (1..=1500).map(|id| Book { id, ..Default::default() }).collect();
+
+        let req = TestRequest::get().uri("/books?page=1&per_page=500").to_http_request();
+        let page = Pagination { page: Some(1), per_page: Some(500) }.apply(&req, books.clone());
+        assert_eq!(page.items.len(), limits::DEFAULT_MAX_PER_PAGE);
+        assert!(page.truncated);
+        assert!(page.link.unwrap().contains("per_page=100>; rel=\"next\""));
+
+        let req = TestRequest::get().uri("/books").to_http_request();
+        let page = Pagination { page: None, per_page: None }.apply(&req, books);
+        assert_eq!(page.items.len(), limits::DEFAULT_MAX_RESULTS);
+        assert_eq!(page.total, 1500);
+        assert!(page.truncated);
+    }
+}
@@ -0,0 +1,198 @@
+//! ログの出力形式とアクセスログ。
+//!
+//! 既定は env_logger のテキスト形式。`LOG_FORMAT=json` にすると `tracing-subscriber` で
+//! 1 行 1 つの JSON を出力し、Loki / ELK などにそのまま取り込める。
+//!
+//! ログ用のディレクトリを指定すると、アプリケーションのログを `books.log` に、アクセスログを
+//! `access.log` に書き、それぞれローテーションする。
+//!
+//! 調査用に、伏せるべき値を伏せたうえでボディも書ける (`bodies`)。
+
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage};
+use serde::Deserialize;
+
+use crate::auth::AuthenticatedUser;
+use crate::request_id::RequestId;
+
+pub use bodies::{log_bodies, BodyLogging};
+pub use file::{Rotation, RotationPolicy, RotatingFile};
+
+mod bodies;
+mod file;
+
+/// アプリケーションのログのファイル名。
+pub const APP_LOG_FILE: &str = "books.log";
+/// アクセスログのファイル名。
+pub const ACCESS_LOG_FILE: &str = "access.log";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+static ACCESS_FILE: OnceLock<Mutex<RotatingFile>> = OnceLock::new();
+
+/// JSON 形式で出力しているか。アクセスログの出し方を切り替えるのに使う。
+pub(crate) fn set_json(enabled: bool) {
+    JSON.store(enabled, Ordering::Relaxed);
+}
+
+/// アクセスログをアプリケーションのログとは別のファイルに書く。
+pub(crate) fn set_access_file(file: RotatingFile) {
+    let _ = ACCESS_FILE.set(Mutex::new(file));
+}
+
+/// アクセスログ 1 行分。
+struct AccessEntry {
+    method: String,
+    path: String,
+    client_ip: Option<String>,
+    status: u16,
+    latency: Duration,
+    user: Option<String>,
+    request_id: Option<String>,
+}
+
+impl AccessEntry {
+    fn log(&self) {
+        let latency_ms = self.latency.as_secs_f64() * 1000.0;
+        let json = JSON.load(Ordering::Relaxed);
+
+        if let Some(file) = ACCESS_FILE.get() {
+            let timestamp = time::OffsetDateTime::now_utc()
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_default();
+
+            let line = if json {
+                serde_json::json!({
+                    "timestamp": timestamp,
+                    "method": self.method,
+                    "path": self.path,
+                    "client_ip": self.client_ip,
+                    "status": self.status,
+                    "latency_ms": latency_ms,
+                    "user": self.user,
+                    "request_id": self.request_id,
+                })
+                .to_string()
+            } else {
+                format!("{} {}", timestamp, self)
+            };
+
+            if let Err(e) = writeln!(file.lock().unwrap(), "{}", line) {
+                log::error!("Failed to write access log: {}", e);
+            }
+            return;
+        }
+
+        if json {
+            // JSON ではフィールドをそのままキーにする
+            tracing::info!(
+                target: "access",
+                method = %self.method,
+                path = %self.path,
+                client_ip = self.client_ip.as_deref(),
+                status = self.status,
+                latency_ms,
+                user = self.user.as_deref(),
+                request_id = self.request_id.as_deref(),
+                "request completed",
+            );
+        } else {
+            log::info!(target: "access", "{}", self);
+        }
+    }
+}
+
+impl fmt::Display for AccessEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {:.3}ms user={} request_id={}",
+            self.client_ip.as_deref().unwrap_or("-"),
+            self.method,
+            self.path,
+            self.status,
+            self.latency.as_secs_f64() * 1000.0,
+            self.user.as_deref().unwrap_or("-"),
+            self.request_id.as_deref().unwrap_or("-"),
+        )
+    }
+}
+
+/// `middleware::from_fn` に渡すアクセスログのミドルウェア。
+pub async fn access_log(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let client_ip = crate::proxy::client_ip(req.request()).map(|ip| ip.to_string());
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+
+    let res = next.call(req).await?;
+
+    let user = res.request().extensions().get::<AuthenticatedUser>().map(|u| u.0.clone());
+
+    AccessEntry {
+        method,
+        path,
+        client_ip,
+        status: res.status().as_u16(),
+        latency: started.elapsed(),
+        user,
+        request_id,
+    }
+    .log();
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_entry_text() {
+        let entry = AccessEntry {
+            method: "GET".to_string(),
+            path: "/books".to_string(),
+            client_ip: Some("192.0.2.1".to_string()),
+            status: 200,
+            latency: Duration::from_micros(1500),
+            user: None,
+            request_id: Some("abc".to_string()),
+        };
+
+        assert_eq!(entry.to_string(), "192.0.2.1 GET /books 200 1.500ms user=- request_id=abc");
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
@@ -0,0 +1,281 @@
+//! 調査用の、リクエストとレスポンスのボディのログ。
+//!
+//! `[logging] log_bodies = true` (`LOG_BODIES`) で有効にする。`bodies` ターゲットの debug レベルで
+//! 出すので、`level = "info,bodies=debug"` のように絞れる。パスワードやトークンのような値は
+//! 伏せてから書く。`Authorization` などのヘッダー、JSON とフォームのキー、クエリのパラメーターの
+//! うち名前が `SECRET_NAMES` のどれかを含むか `SECRET_EXACT_NAMES` のどれかと同じものが対象。
+//! JSON の文字列に入った URL のクエリ (共有リンクの `sig` など) も伏せる。`skip_paths` に書いたパス (とその下) は
+//! ボディを書かない。JSON・フォーム・テキスト以外のボディは大きさだけ書く。
+
+use actix_web::body::{self, BodySize, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage};
+use serde_json::Value;
+
+use crate::request_id::RequestId;
+
+/// ログに書くボディの長さの上限 (バイト)。
+const MAX_LOGGED: usize = 4096;
+
+/// これより大きいレスポンスは読まずに大きさだけ書く。
+const MAX_BUFFERED: u64 = 1024 * 1024;
+
+const REDACTED: &str = "[redacted]";
+
+/// 名前にこれを含むヘッダー・キー・パラメーターの値を伏せる (小文字、`-` は `_` として比べる)。
+const SECRET_NAMES: [&str; 10] = [
+    "password", "passwd", "secret", "token", "authorization", "api_key", "apikey", "cookie", "credential", "private_key",
+];
+
+/// 名前がこれと同じときだけ伏せる。発行した API キー (`key`)、共有リンクの署名 (`sig`) と、
+/// `POST /register` で送る招待のトークン (`invite`)。
+const SECRET_EXACT_NAMES: [&str; 3] = ["key", "sig", "invite"];
+
+/// ボディのログの設定。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BodyLogging {
+    pub enabled: bool,
+    /// ボディを書かないパス
+    pub skip_paths: Vec<String>,
+}
+
+impl BodyLogging {
+    fn skips(&self, path: &str) -> bool {
+        self.skip_paths.iter().any(|prefix| {
+            let prefix = prefix.trim_end_matches('/');
+            prefix.is_empty() || path == prefix || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+}
+
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_lowercase().replace('-', "_");
+    SECRET_NAMES.iter().any(|secret| name.contains(secret)) || SECRET_EXACT_NAMES.contains(&name.as_str())
+}
+
+fn redact_headers(headers: &HeaderMap) -> String {
+    headers.iter()
+        .map(|(name, value)| {
+            let value = if is_secret(name.as_str()) { REDACTED } else { value.to_str().unwrap_or("<binary>") };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_secret(key) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        Value::String(text) => {
+            if let Some(url) = redact_url(text) {
+                *text = url;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// `a=1&password=x` のような並びの値を伏せる。クエリとフォームに使う。
+fn redact_pairs(pairs: &str) -> String {
+    pairs.split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if is_secret(name) => format!("{}={}", name, REDACTED),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// URL (`https://...` か `/...`) ならクエリの値を伏せる。URL でなければ `None`。
+fn redact_url(text: &str) -> Option<String> {
+    if !(text.contains("://") || text.starts_with('/')) {
+        return None;
+    }
+    let (base, query) = text.split_once('?')?;
+    Some(format!("{}?{}", base, redact_pairs(query)))
+}
+
+fn truncate(mut text: String) -> String {
+    if text.len() > MAX_LOGGED {
+        let mut end = MAX_LOGGED;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let total = text.len();
+        text.truncate(end);
+        text.push_str(&format!("... ({} bytes)", total));
+    }
+    text
+}
+
+/// ボディを伏せて書ける形にする。
+fn render_body(content_type: &str, bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "-".to_string();
+    }
+
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    let rendered = if mime == "application/json" || mime.ends_with("+json") {
+        match serde_json::from_slice::<Value>(bytes) {
+            Ok(mut value) => {
+                redact_json(&mut value);
+                value.to_string()
+            }
+            Err(_) => format!("<{} bytes of invalid JSON>", bytes.len()),
+        }
+    } else if mime == "application/x-www-form-urlencoded" {
+        redact_pairs(&String::from_utf8_lossy(bytes))
+    } else if mime.starts_with("text/") && mime != "text/event-stream" {
+        String::from_utf8_lossy(bytes).to_string()
+    } else {
+        format!("<{} bytes of {}>", bytes.len(), if mime.is_empty() { "unknown type" } else { &mime })
+    };
+
+    truncate(rendered)
+}
+
+fn content_type(headers: &HeaderMap) -> String {
+    headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string()
+}
+
+/// `middleware::from_fn` に渡すミドルウェア。設定は `web::Data<BodyLogging>` から読む。
+pub async fn log_bodies(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let enabled = req.app_data::<web::Data<BodyLogging>>()
+        .is_some_and(|logging| logging.enabled && !logging.skips(req.path()));
+    if !enabled || !log::log_enabled!(target: "bodies", log::Level::Debug) {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone()).unwrap_or_default();
+    let target = match req.query_string() {
+        "" => req.path().to_string(),
+        query => format!("{}?{}", req.path(), redact_pairs(query)),
+    };
+
+    let payload = req.extract::<web::Bytes>().await?;
+    log::debug!(
+        target: "bodies",
+        "request_id={} > {} {} [{}] {}",
+        request_id,
+        req.method(),
+        target,
+        redact_headers(req.headers()),
+        render_body(&content_type(req.headers()), &payload),
+    );
+    req.set_payload(payload.into());
+
+    let res = next.call(req).await?;
+    let status = res.status();
+    let headers = redact_headers(res.headers());
+
+    // ストリームや大きいレスポンスは読まない
+    let size = res.response().body().size();
+    if !matches!(size, BodySize::Sized(n) if n <= MAX_BUFFERED) {
+        let size = match size {
+            BodySize::Sized(n) => format!("<{} bytes>", n),
+            _ => "<stream>".to_string(),
+        };
+        log::debug!(target: "bodies", "request_id={} < {} [{}] {}", request_id, status, headers, size);
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let content_type = content_type(res.headers());
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let bytes = body::to_bytes(body)
+        .await
+        .map_err(|e| {
+            let e: Box<dyn std::error::Error> = e.into();
+            actix_web::error::ErrorInternalServerError(e.to_string())
+        })?;
+
+    log::debug!(
+        target: "bodies",
+        "request_id={} < {} [{}] {}",
+        request_id,
+        status,
+        headers,
+        render_body(&content_type, &bytes),
+    );
+
+    Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(bytes))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    #[test]
+    fn test_redact_json() {
+        let body = br#"{"username":"alice","password":"hunter2","nested":[{"access_token":"abc","title":"Dune"}]}"#;
+        let rendered = render_body("application/json; charset=utf-8", body);
+
+        assert!(!rendered.contains("hunter2"));
+        assert!(!rendered.contains("abc"));
+        assert!(rendered.contains("alice") && rendered.contains("Dune"));
+    }
+
+    #[test]
+    fn test_redact_pairs_and_headers() {
+        assert_eq!(redact_pairs("q=dune&api-key=k1&page=2"), "q=dune&api-key=[redacted]&page=2");
+        assert_eq!(render_body("application/x-www-form-urlencoded", b"username=a&new_password=b"), "username=a&new_password=[redacted]");
+        assert_eq!(render_body("image/png", &[0; 10]), "<10 bytes of image/png>");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Basic YWxpY2U6cHc="));
+        headers.insert(HeaderName::from_static("x-api-key"), HeaderValue::from_static("k1"));
+        headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+        let rendered = redact_headers(&headers);
+        assert!(!rendered.contains("YWxpY2U6cHc=") && !rendered.contains("k1"));
+        assert!(rendered.contains("accept: application/json"));
+    }
+
+    #[test]
+    fn test_redact_api_key_and_share() {
+        // POST /admin/api-keys のレスポンス
+        let created = br#"{"key":"bk_s3cr3t","id":"00ff","name":"ci","scopes":["books:read"],"created_by":"admin","created_at":0}"#;
+        let rendered = render_body("application/json", created);
+        assert!(!rendered.contains("bk_s3cr3t"));
+        assert!(rendered.contains("\"name\":\"ci\""));
+
+        // POST /books/{id}/share のレスポンスと、GET /shared のクエリ
+        let link = br#"{"url":"https://books.example.com/shared/ab12?expires=1700000000&sig=c2lnbmF0dXJl","id":"ab12","book_id":3}"#;
+        let rendered = render_body("application/json", link);
+        assert!(!rendered.contains("c2lnbmF0dXJl"));
+        assert!(rendered.contains("/shared/ab12?expires=1700000000&sig=[redacted]"));
+        assert_eq!(redact_pairs("expires=1700000000&sig=c2lnbmF0dXJl"), "expires=1700000000&sig=[redacted]");
+
+        // POST /register のリクエスト
+        let register = br#"{"username":"dana","password":"pw","invite":"aW52aXRl"}"#;
+        let rendered = render_body("application/json", register);
+        assert!(!rendered.contains("aW52aXRl"));
+        assert!(rendered.contains("\"username\":\"dana\""));
+
+        assert_eq!(redact_url("what? no"), None);
+        assert!(!is_secret("keywords"));
+    }
+
+    #[test]
+    fn test_skip_paths() {
+        let logging = BodyLogging { enabled: true, skip_paths: vec!["/admin/backup".to_string()] };
+        assert!(logging.skips("/admin/backup"));
+        assert!(logging.skips("/admin/backup/latest"));
+        assert!(!logging.skips("/admin/backups"));
+        assert!(!logging.skips("/books"));
+    }
+}
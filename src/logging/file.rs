@@ -0,0 +1,186 @@
+//! サイズと時間でローテーションするログファイル。
+//!
+//! 上限サイズを超えるか、日 (または時間) が変わったら現在のファイルを
+//! `books.log.20261015T120000` のような名前に変えて新しいファイルを開く。古いものは
+//! `max_files` 個だけ残して消す。
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+/// 時間によるローテーションの単位。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Never,
+    Hourly,
+    #[default]
+    Daily,
+}
+
+impl std::str::FromStr for Rotation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Rotation::Never),
+            "hourly" => Ok(Rotation::Hourly),
+            "daily" => Ok(Rotation::Daily),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Rotation {
+    /// 同じファイルに書き続けてよい期間を表すキー。キーが変わったらローテーションする。
+    fn period(self, now: OffsetDateTime) -> String {
+        match self {
+            Rotation::Never => String::new(),
+            Rotation::Hourly => format!("{}-{}", now.date(), now.hour()),
+            Rotation::Daily => now.date().to_string(),
+        }
+    }
+}
+
+/// ローテーションの設定。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// これを超えたらローテーションする (バイト)。0 ならサイズでは切り替えない。
+    pub max_size: u64,
+    pub rotation: Rotation,
+    /// 残しておくローテーション済みファイルの数。
+    pub max_files: usize,
+}
+
+pub struct RotatingFile {
+    path: PathBuf,
+    policy: RotationPolicy,
+    file: File,
+    size: u64,
+    period: String,
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl RotatingFile {
+    pub fn open(path: impl Into<PathBuf>, policy: RotationPolicy) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let file = open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            period: policy.rotation.period(OffsetDateTime::now_utc()),
+            path,
+            policy,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_name(&self, now: OffsetDateTime) -> PathBuf {
+        let stamp = now.format(format_description!("[year][month][day]T[hour][minute][second]"))
+            .unwrap_or_default();
+        let base = format!("{}.{}", self.path.display(), stamp);
+
+        // 同じ秒に 2 回ローテーションしたときは連番を付ける
+        let mut candidate = PathBuf::from(&base);
+        let mut n = 1;
+        while candidate.exists() {
+            candidate = PathBuf::from(format!("{}.{}", base, n));
+            n += 1;
+        }
+        candidate
+    }
+
+    fn rotate(&mut self, now: OffsetDateTime) -> io::Result<()> {
+        self.file.flush()?;
+        fs::rename(&self.path, self.rotated_name(now))?;
+
+        self.file = open(&self.path)?;
+        self.size = 0;
+        self.period = self.policy.rotation.period(now);
+
+        self.remove_old()
+    }
+
+    /// ローテーション済みのファイルを新しい順に `max_files` 個だけ残す。
+    fn remove_old(&self) -> io::Result<()> {
+        let (Some(dir), Some(name)) = (self.path.parent(), self.path.file_name().and_then(|n| n.to_str())) else {
+            return Ok(());
+        };
+        let prefix = format!("{}.", name);
+
+        let mut rotated: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_str().is_some_and(|n| n.starts_with(&prefix)))
+            .map(|entry| entry.path())
+            .collect();
+        // 日時の部分は辞書順で並ぶ
+        rotated.sort();
+
+        let excess = rotated.len().saturating_sub(self.policy.max_files);
+        for path in &rotated[..excess] {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let now = OffsetDateTime::now_utc();
+        let too_large = self.policy.max_size > 0 && self.size > 0 && self.size + buf.len() as u64 > self.policy.max_size;
+
+        if too_large || self.policy.rotation.period(now) != self.period {
+            self.rotate(now)?;
+        }
+
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotates_by_size_and_keeps_max_files() {
+        let dir = std::env::temp_dir().join(format!("books_backend_logs_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let policy = RotationPolicy { max_size: 10, rotation: Rotation::Never, max_files: 2 };
+        let mut file = RotatingFile::open(dir.join("books.log"), policy).unwrap();
+
+        for _ in 0..5 {
+            file.write_all(b"12345678\n").unwrap();
+        }
+
+        let mut names: Vec<String> = fs::read_dir(&dir).unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+
+        // 現在のファイルとローテーション済みの 2 つ
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0], "books.log");
+        assert_eq!(fs::read_to_string(dir.join("books.log")).unwrap(), "12345678\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+//! メンテナンス (読み取り専用) モード。
+//!
+//! 有効な間は書き込みのリクエストを 503 と `Retry-After` で断り、読み取りはそのまま通す。
+//! バックアップやデータファイルの差し替え、移行作業の間に使う。切り替えは
+//! `POST /admin/maintenance` から行い、再起動すると解除される。
+
+use std::sync::{Arc, RwLock};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, ResponseError};
+
+use crate::{AppState, BookError};
+
+/// `Retry-After` を指定せずに有効にしたときの秒数。
+pub const DEFAULT_RETRY_AFTER: u64 = 60;
+
+/// メンテナンス中でも受け付ける管理用の書き込み。
+const ALLOWED_PATHS: [&str; 4] = ["/admin/maintenance", "/admin/backup", "/admin/data/swap", "/admin/reload-config"];
+/// 同じく、機能フラグの切り替え。
+const ALLOWED_PREFIX: &str = "/admin/flags/";
+
+/// メンテナンスモードの状態。複製しても同じ状態を指すので、HTTP と gRPC で共有できる。
+#[derive(Clone, Debug, Default)]
+pub struct Maintenance(Arc<RwLock<Option<u64>>>);
+
+impl Maintenance {
+    /// 有効にする。`retry_after` は断るときに返す `Retry-After` の秒数。
+    pub fn enable(&self, retry_after: u64) {
+        *self.0.write().unwrap() = Some(retry_after);
+    }
+
+    pub fn disable(&self) {
+        *self.0.write().unwrap() = None;
+    }
+
+    /// 有効なら `Retry-After` の秒数を返す。
+    pub fn retry_after(&self) -> Option<u64> {
+        *self.0.read().unwrap()
+    }
+
+    /// メンテナンス中なら書き込みを断るエラーを返す。
+    pub fn check(&self) -> Result<(), BookError> {
+        match self.retry_after() {
+            Some(retry_after) => Err(BookError::Maintenance(retry_after)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn is_write(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// `middleware::from_fn` に渡すミドルウェア。メンテナンス中の書き込みを断る。
+pub async fn reject_writes(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if is_write(req.method()) && !ALLOWED_PATHS.contains(&req.path()) && !req.path().starts_with(ALLOWED_PREFIX) {
+        if let Some(state) = req.app_data::<web::Data<AppState>>() {
+            if let Err(e) = state.maintenance().check() {
+                let resp = e.error_response();
+                return Ok(req.into_response(resp));
+            }
+        }
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
@@ -0,0 +1,162 @@
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+use crate::audio::Audio;
+use crate::reading::ContentStats;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    #[default]
+    User,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::User => "user",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct User {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Loan {
+    pub borrower: String,
+    #[serde(with = "iso_date")]
+    pub due: time::Date,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Book {
+    pub id: u32,
+    pub title: String,
+    pub content: String,
+    pub tags: Vec<String>,
+    /// 管理者が決めた一覧 (`crate::genres`) から選ぶジャンル。
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub genres: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published_year: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isbn: Option<String>,
+    /// title と content から保存するときに推定した言語 (`crate::language`)。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// 本文の語数・文字数と読む時間の目安 (`crate::reading`)。保存するときに数える。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_stats: Option<ContentStats>,
+    /// 持っている形式。紙と電子書籍の両方を持っていれば両方。
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub formats: Vec<Format>,
+    /// オーディオブックの長さと聴いた長さ (`crate::audio`)。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio: Option<Audio>,
+    /// 同じ作品の版なら、その作品の id (`crate::works`)。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub work: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loan: Option<Loan>,
+    /// 認証なしのリクエストにどう見せるか。指定がなければ匿名アクセスの設定で決まる。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+}
+
+/// 認証なしのリクエストへの見せ方。認証したユーザーにはすべての本が見える。
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// 一覧・検索・エクスポートに出す
+    Public,
+    /// 一覧や検索には出さないが、ID を知っていれば見られる
+    Unlisted,
+    /// 認証なしには見せない
+    Private,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Unlisted => "unlisted",
+            Visibility::Private => "private",
+        }
+    }
+}
+
+impl FromStr for Visibility {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(Visibility::Public),
+            "unlisted" => Ok(Visibility::Unlisted),
+            "private" => Ok(Visibility::Private),
+            _ => Err(()),
+        }
+    }
+}
+
+/// 書籍の形式。
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Hardcover,
+    Paperback,
+    Ebook,
+    Audiobook,
+}
+
+impl Format {
+    pub const ALL: [Format; 4] = [Format::Hardcover, Format::Paperback, Format::Ebook, Format::Audiobook];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Hardcover => "hardcover",
+            Format::Paperback => "paperback",
+            Format::Ebook => "ebook",
+            Format::Audiobook => "audiobook",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Format::ALL.into_iter().find(|f| f.as_str() == s).ok_or(())
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct BookQuery {
+    pub id: Option<u32>,
+    pub tag: Option<String>,
+    /// ジャンルでの絞り込み (`genres` モジュールを参照)。
+    pub genre: Option<String>,
+    /// 持っている形式での絞り込み。
+    pub format: Option<Format>,
+    /// 言語での絞り込み (`ja`、`en` など)。
+    pub lang: Option<String>,
+    /// title / content / tags に対する全文検索。`tag:async year:>=2020` のような絞り込みも書ける
+    /// (`query` モジュールを参照)。
+    pub q: Option<String>,
+    /// `q` を正規表現として title / content に当てる (`pattern` モジュールを参照)。
+    #[serde(default)]
+    pub regex: bool,
+}
@@ -0,0 +1,244 @@
+use std::borrow::Cow;
+use actix_web::{http::header, HttpRequest, HttpResponse};
+use serde::Serialize;
+
+use crate::facets::Facets;
+use crate::{jsonapi, links, Book, BookError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    JsonApi,
+    Csv,
+    Xml,
+    MessagePack,
+}
+
+impl Format {
+    fn from_media_type(media_type: &str) -> Option<Format> {
+        match media_type {
+            "application/json" | "application/*" | "*/*" => Some(Format::Json),
+            jsonapi::MEDIA_TYPE => Some(Format::JsonApi),
+            "text/csv" => Some(Format::Csv),
+            "application/xml" | "text/xml" => Some(Format::Xml),
+            "application/msgpack" | "application/x-msgpack" => Some(Format::MessagePack),
+            _ => None,
+        }
+    }
+}
+
+/// Accept ヘッダーから q 値が最も高い対応フォーマットを選ぶ。ヘッダーがなければ JSON。
+pub fn preferred_format(req: &HttpRequest) -> Result<Format, BookError> {
+    let accept = match req.headers().get(header::ACCEPT).and_then(|v| v.to_str().ok()) {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return Ok(Format::Json),
+    };
+
+    let mut best: Option<(Format, f32)> = None;
+
+    for item in accept.split(',') {
+        let mut parts = item.split(';');
+        let media_type = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+
+        let quality = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if quality <= 0.0 {
+            continue;
+        }
+
+        if let Some(format) = Format::from_media_type(&media_type) {
+            if best.is_none_or(|(_, q)| quality > q) {
+                best = Some((format, quality));
+            }
+        }
+    }
+
+    best.map(|(format, _)| format).ok_or(BookError::NotAcceptable)
+}
+
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    id: u32,
+    title: Cow<'a, str>,
+    content: Cow<'a, str>,
+    tags: String,
+    authors: String,
+}
+
+/// 表計算ソフトで数式として扱われる先頭の文字。
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// 数式として評価されないよう、`'` を前に付ける (OWASP の CSV injection の対策)。
+fn csv_cell(value: &str) -> Cow<'_, str> {
+    if value.starts_with(FORMULA_PREFIXES) {
+        Cow::Owned(format!("'{}", value))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// `include_content` が false なら本文の列は空にする。
+fn to_csv(books: &[Book], include_content: bool) -> Result<Vec<u8>, BookError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    for book in books {
+        writer.serialize(CsvRow {
+            id: book.id,
+            title: csv_cell(&book.title),
+            content: if include_content { csv_cell(&book.content) } else { Cow::Borrowed("") },
+            tags: csv_cell(&book.tags.join(";")).into_owned(),
+            authors: csv_cell(&book.authors.join(";")).into_owned(),
+        }).map_err(|e| BookError::Serialize(e.to_string()))?;
+    }
+
+    writer.into_inner().map_err(|e| BookError::Serialize(e.to_string()))
+}
+
+#[derive(Serialize)]
+struct XmlTags<'a> {
+    tag: &'a [String],
+}
+
+#[derive(Serialize)]
+struct XmlAuthors<'a> {
+    author: &'a [String],
+}
+
+#[derive(Serialize)]
+struct XmlBook<'a> {
+    id: u32,
+    title: &'a str,
+    content: &'a str,
+    tags: XmlTags<'a>,
+    authors: XmlAuthors<'a>,
+}
+
+#[derive(Serialize)]
+struct XmlBooks<'a> {
+    book: Vec<XmlBook<'a>>,
+}
+
+/// `include_content` が false なら本文は空にする。
+fn to_xml(books: &[Book], include_content: bool) -> Result<String, BookError> {
+    let books = XmlBooks {
+        book: books.iter()
+            .map(|b| XmlBook {
+                id: b.id,
+                title: &b.title,
+                content: if include_content { &b.content } else { "" },
+                tags: XmlTags { tag: &b.tags },
+                authors: XmlAuthors { author: &b.authors },
+            })
+            .collect(),
+    };
+
+    let xml = quick_xml::se::to_string_with_root("books", &books)
+        .map_err(|e| BookError::Serialize(e.to_string()))?;
+
+    Ok(format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>{}", xml))
+}
+
+/// 書籍一覧をクライアントが要求したフォーマットでレスポンスにする。
+pub fn respond_books(req: &HttpRequest, books: &[Book], include_content: bool) -> Result<HttpResponse, BookError> {
+    let response = match preferred_format(req)? {
+        Format::Json => HttpResponse::Ok().json(links::with_links(books, include_content)),
+        Format::JsonApi => HttpResponse::Ok()
+            .content_type(jsonapi::MEDIA_TYPE)
+            .json(jsonapi::document(books, include_content)),
+        Format::Csv => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .body(to_csv(books, include_content)?),
+        Format::Xml => HttpResponse::Ok()
+            .content_type("application/xml; charset=utf-8")
+            .body(to_xml(books, include_content)?),
+        Format::MessagePack => HttpResponse::Ok()
+            .content_type("application/msgpack")
+            .body(rmp_serde::to_vec_named(&links::listed(books, include_content)).map_err(|e| BookError::Serialize(e.to_string()))?),
+    };
+
+    Ok(response)
+}
+
+#[derive(Serialize)]
+struct Faceted<'a, B: Serialize> {
+    books: B,
+    facets: &'a Facets,
+}
+
+/// 書籍一覧とファセットをまとめてレスポンスにする。JSON と MessagePack は `{"books", "facets"}`、
+/// JSON:API は `meta.facets` に入れる。CSV と XML では返せないので 406。
+pub fn respond_faceted(req: &HttpRequest, books: &[Book], include_content: bool, facets: &Facets) -> Result<HttpResponse, BookError> {
+    let response = match preferred_format(req)? {
+        Format::Json => HttpResponse::Ok().json(Faceted { books: links::with_links(books, include_content), facets }),
+        Format::JsonApi => HttpResponse::Ok()
+            .content_type(jsonapi::MEDIA_TYPE)
+            .json(jsonapi::document(books, include_content).with_facets(facets)),
+        Format::MessagePack => {
+            let books = links::listed(books, include_content);
+            HttpResponse::Ok()
+                .content_type("application/msgpack")
+                .body(rmp_serde::to_vec_named(&Faceted { books, facets }).map_err(|e| BookError::Serialize(e.to_string()))?)
+        }
+        Format::Csv | Format::Xml => return Err(BookError::NotAcceptable),
+    };
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn request_accepting(accept: &str) -> HttpRequest {
+        TestRequest::default()
+            .insert_header((header::ACCEPT, accept))
+            .to_http_request()
+    }
+
+    #[test]
+    fn test_preferred_format() {
+        assert_eq!(preferred_format(&TestRequest::default().to_http_request()).unwrap(), Format::Json);
+        assert_eq!(preferred_format(&request_accepting("text/csv")).unwrap(), Format::Csv);
+        assert_eq!(preferred_format(&request_accepting("application/vnd.api+json")).unwrap(), Format::JsonApi);
+        assert_eq!(
+            preferred_format(&request_accepting("application/json;q=0.5, application/msgpack")).unwrap(),
+            Format::MessagePack
+        );
+        assert_eq!(preferred_format(&request_accepting("text/html, */*;q=0.1")).unwrap(), Format::Json);
+        assert!(preferred_format(&request_accepting("text/html")).is_err());
+    }
+
+    #[test]
+    fn test_csv_and_xml_output() {
+        let books = vec![Book {
+            id: 1,
+            title: "Rust, Basics".to_string(),
+            content: "Intro <to> Rust".to_string(),
+            tags: vec!["beginner".to_string(), "syntax".to_string()],
+            authors: vec!["Steve Klabnik".to_string()],
+            ..Default::default()
+        }];
+
+        let csv = String::from_utf8(to_csv(&books, true).unwrap()).unwrap();
+        assert_eq!(csv, "id,title,content,tags,authors\n1,\"Rust, Basics\",Intro <to> Rust,beginner;syntax,Steve Klabnik\n");
+
+        let formulas = vec![Book {
+            id: 2,
+            title: "=HYPERLINK(\"http://evil.example\")".to_string(),
+            content: "-1+2".to_string(),
+            tags: vec!["@SUM(A1)".to_string()],
+            authors: vec!["Ada".to_string()],
+            ..Default::default()
+        }];
+        let csv = String::from_utf8(to_csv(&formulas, true).unwrap()).unwrap();
+        assert!(csv.ends_with("2,\"'=HYPERLINK(\"\"http://evil.example\"\")\",'-1+2,'@SUM(A1),Ada\n"));
+
+        let xml = to_xml(&books, true).unwrap();
+        assert!(xml.contains("<book><id>1</id><title>Rust, Basics</title><content>Intro &lt;to&gt; Rust</content>"));
+        assert!(xml.contains("<tags><tag>beginner</tag><tag>syntax</tag></tags><authors><author>Steve Klabnik</author></authors>"));
+    }
+}
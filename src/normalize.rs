@@ -0,0 +1,90 @@
+//! 検索で比べる前の文字列の正規化。
+//!
+//! NFKC で全角英数字・半角カナ・合成済みの文字をそろえてから小文字にする。`RUST`・`ｒｕｓｔ`・
+//! `rust` はどれも `rust` になる。タグの絞り込み・検索式・全文検索の索引と検索語・補完・
+//! 「もしかして」のすべてで、保存されたデータと入力の両方にかける。保存されるデータ自体は変えない。
+
+use icu_normalizer::ComposingNormalizer;
+
+/// 組み込みのデータを指すだけなので、呼ぶたびに作っても安い。
+fn nfkc(text: &str) -> String {
+    ComposingNormalizer::new_nfkc().normalize(text)
+}
+
+/// 前の文字と組み合わさる文字 (結合文字と、半角カナの濁点・半濁点)。
+fn is_mark(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036f}' | '\u{1ab0}'..='\u{1aff}' | '\u{1dc0}'..='\u{1dff}' | '\u{20d0}'..='\u{20ff}' |
+        '\u{fe20}'..='\u{fe2f}' | '\u{3099}'..='\u{309a}' | '\u{ff9e}'..='\u{ff9f}')
+}
+
+fn fold_cluster(cluster: &str, out: &mut String) {
+    if cluster.is_ascii() {
+        out.extend(cluster.chars().map(|c| c.to_ascii_lowercase()));
+    } else {
+        out.push_str(&nfkc(cluster).to_lowercase());
+    }
+}
+
+/// NFKC にして小文字にする。
+pub fn fold(text: &str) -> String {
+    if text.is_ascii() {
+        return text.to_ascii_lowercase();
+    }
+    nfkc(text).to_lowercase()
+}
+
+/// 正規化した文字列と、その各文字が元の文字列のどこ (バイト単位の範囲) から来たか。
+/// 正規化で文字数が変わっても、一致した箇所を元の文字列で示せるようにする。
+pub struct Folded {
+    pub text: String,
+    origins: Vec<(usize, usize)>,
+}
+
+impl Folded {
+    /// 基底の文字とそれに続く結合文字ごとに正規化する。
+    pub fn new(text: &str) -> Self {
+        let mut folded = String::with_capacity(text.len());
+        let mut origins = Vec::with_capacity(text.len());
+
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let mut end = start + c.len_utf8();
+            while let Some(&(i, mark)) = chars.peek().filter(|(_, c)| is_mark(*c)) {
+                end = i + mark.len_utf8();
+                chars.next();
+            }
+
+            let before = folded.len();
+            fold_cluster(&text[start..end], &mut folded);
+            origins.extend(folded[before..].chars().map(|_| (start, end)));
+        }
+
+        Folded { text: folded, origins }
+    }
+
+    /// 正規化した文字列の `n` 文字目が元の文字列のどこから来たか。
+    pub fn origin(&self, n: usize) -> (usize, usize) {
+        self.origins[n]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fold() {
+        assert_eq!(fold("RUST"), "rust");
+        assert_eq!(fold("ｒｕｓｔ"), "rust");
+        assert_eq!(fold("ＲＵＳＴ"), "rust");
+        assert_eq!(fold("ｶﾞｲﾄﾞ"), "ガイド");
+        assert_eq!(fold("Cafe\u{301}"), "café");
+
+        let folded = Folded::new("ＲＵＳＴとｶﾞｲﾄﾞ");
+        assert_eq!(folded.text, "rustとガイド");
+        // `ガ` は半角の `ｶﾞ` (2 文字、6 バイト) から来ている
+        assert_eq!(folded.origin(5), (15, 21));
+        assert_eq!(folded.origin(0), (0, 3));
+    }
+}
@@ -0,0 +1,142 @@
+//! パスワードの規則。
+//!
+//! 登録・パスワード変更・`books user add` で確かめ、満たさない規則をすべて返す。
+//! よく使われるパスワードの一覧は埋め込みのものに、設定したファイルの分を足して使う。
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::Serialize;
+
+pub const DEFAULT_MIN_LENGTH: usize = 8;
+pub const DEFAULT_MAX_LENGTH: usize = 128;
+
+/// 埋め込みの拒否リスト。漏洩したパスワードの集計で上位のもの。
+const COMMON_PASSWORDS: &str = "\
+123456 123456789 12345678 12345 1234567 1234567890 qwerty password password1 111111 123123
+000000 abc123 1q2w3e4r 1q2w3e4r5t qwerty123 qwertyuiop iloveyou admin admin123 welcome
+letmein monkey dragon football baseball sunshine princess shadow master superman
+trustno1 passw0rd p@ssw0rd changeme secret root toor default guest login 654321 666666
+987654321 121212 112233 123321 7777777 88888888 11111111 00000000 asdfghjkl zxcvbnm
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    MinLength,
+    MaxLength,
+    /// 拒否リストに載っている
+    Common,
+    /// ユーザー名と同じ
+    Username,
+    /// 漏洩したパスワードの一覧に載っている
+    Pwned,
+}
+
+/// 満たさなかった規則と、その説明。
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    pub rule: Rule,
+    pub message: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    deny_list: HashSet<String>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy::new(DEFAULT_MIN_LENGTH, DEFAULT_MAX_LENGTH)
+    }
+}
+
+impl PasswordPolicy {
+    /// 埋め込みの拒否リストを使う。
+    pub fn new(min_length: usize, max_length: usize) -> Self {
+        let deny_list = COMMON_PASSWORDS.split_whitespace().map(str::to_string).collect();
+        PasswordPolicy { min_length, max_length, deny_list }
+    }
+
+    /// 1 行に 1 つパスワードを書いたファイルを拒否リストに足す。`#` で始まる行は読み飛ばす。
+    pub fn with_deny_list_file(mut self, path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+
+        self.deny_list.extend(
+            contents.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_lowercase),
+        );
+        Ok(self)
+    }
+
+    /// 満たさない規則をすべて返す。空なら問題なし。
+    pub fn check(&self, username: &str, password: &str) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let length = password.chars().count();
+
+        if length < self.min_length {
+            violations.push(Violation {
+                rule: Rule::MinLength,
+                message: format!("must be at least {} characters", self.min_length),
+            });
+        }
+        if length > self.max_length {
+            violations.push(Violation {
+                rule: Rule::MaxLength,
+                message: format!("must be at most {} characters", self.max_length),
+            });
+        }
+        if self.deny_list.contains(&password.to_lowercase()) {
+            violations.push(Violation {
+                rule: Rule::Common,
+                message: "is too common".to_string(),
+            });
+        }
+        if !username.is_empty() && password.eq_ignore_ascii_case(username) {
+            violations.push(Violation {
+                rule: Rule::Username,
+                message: "must not be the same as the username".to_string(),
+            });
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let policy = PasswordPolicy::new(8, 16);
+
+        assert!(policy.check("alice", "correct horse").is_empty());
+
+        let rules = |password: &str| policy.check("alice", password).into_iter().map(|v| v.rule).collect::<Vec<_>>();
+        assert_eq!(rules("short"), vec![Rule::MinLength]);
+        assert_eq!(rules("this one is far too long"), vec![Rule::MaxLength]);
+        assert_eq!(rules("Password"), vec![Rule::Common]);
+        assert_eq!(rules("123456"), vec![Rule::MinLength, Rule::Common]);
+        assert_eq!(rules("ALICE"), vec![Rule::MinLength, Rule::Username]);
+
+        // 文字数はバイトではなく文字で数える
+        assert!(policy.check("alice", "パスワードです。長め").is_empty());
+    }
+
+    #[test]
+    fn test_deny_list_file() {
+        let path = std::env::temp_dir().join(format!("books_backend_deny_{}.txt", std::process::id()));
+        fs::write(&path, "# team-specific\nBooksBackend2024\n\n").unwrap();
+
+        let policy = PasswordPolicy::default().with_deny_list_file(&path).unwrap();
+        assert_eq!(policy.check("", "booksbackend2024")[0].rule, Rule::Common);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
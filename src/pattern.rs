@@ -0,0 +1,104 @@
+//! `regex=true` のときの `q=` (正規表現での検索)。
+//!
+//! `q=` 全体を正規表現として title と content に当てる。検索式 (`tag:` など) は読まない。
+//! `regex` クレートは後戻りしないので、1 冊の照合には本文の長さに比例した時間しかかからない。
+//! それでも長すぎるパターンは `MAX_PATTERN_LEN`、コンパイル後に大きくなりすぎるものは
+//! `SIZE_LIMIT` で断り、全件の走査には `TIME_LIMIT` の期限を設ける。
+//! 読めないパターンや大きすぎるパターンは 422、期限を過ぎたら 504 を返す。
+
+use std::time::{Duration, Instant};
+use regex::{Regex, RegexBuilder};
+
+use crate::{Book, BookError};
+
+/// パターンの長さ (文字数) の上限。
+pub const MAX_PATTERN_LEN: usize = 256;
+
+/// コンパイルしたプログラムと遅延 DFA のそれぞれに使ってよいメモリ量。
+const SIZE_LIMIT: usize = 1 << 20;
+
+/// 全件の走査にかけてよい時間。
+pub const TIME_LIMIT: Duration = Duration::from_secs(2);
+
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self, BookError> {
+        if pattern.is_empty() {
+            return Err(BookError::BadRequest("regex=true needs a pattern in q".to_string()));
+        }
+        if pattern.chars().count() > MAX_PATTERN_LEN {
+            return Err(BookError::InvalidPattern(format!("pattern is longer than {} characters", MAX_PATTERN_LEN)));
+        }
+
+        RegexBuilder::new(pattern)
+            .size_limit(SIZE_LIMIT)
+            .dfa_size_limit(SIZE_LIMIT)
+            .build()
+            .map(Pattern)
+            .map_err(|e| match e {
+                regex::Error::CompiledTooBig(_) => BookError::InvalidPattern("pattern is too complex".to_string()),
+                e => BookError::InvalidPattern(e.to_string()),
+            })
+    }
+
+    /// title か content に一致するか。
+    pub fn matches(&self, book: &Book) -> bool {
+        self.0.is_match(&book.title) || self.0.is_match(&book.content)
+    }
+
+    /// `books` のうち一致するものを元の順で返す。`deadline` を過ぎたら `BookError::Timeout`。
+    pub fn filter<'a>(&self, books: impl IntoIterator<Item = &'a Book>, deadline: Instant) -> Result<Vec<Book>, BookError> {
+        let mut matched = Vec::new();
+        for book in books {
+            if Instant::now() > deadline {
+                return Err(BookError::Timeout);
+            }
+            if self.matches(book) {
+                matched.push(book.clone());
+            }
+        }
+        Ok(matched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(id: u32, title: &str, content: &str) -> Book {
+        Book { id, title: title.to_string(), content: content.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_filter() {
+        let books = [
+            book(1, "Rust Basics", "fn main() {}"),
+            book(2, "Async in Rust", "async fn run() -> Result<(), Error>"),
+            book(3, "Python", "def main(): pass"),
+        ];
+        let ids = |pattern: &str| {
+            Pattern::new(pattern).unwrap()
+                .filter(&books, Instant::now() + TIME_LIMIT)
+                .unwrap()
+                .iter()
+                .map(|b| b.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(r"\bfn \w+\("), vec![1, 2]);
+        assert_eq!(ids("(?i)^python$"), vec![3]);
+        assert!(ids("^Rust$").is_empty());
+
+        let expired = Pattern::new("fn").unwrap().filter(&books, Instant::now() - Duration::from_secs(1));
+        assert!(matches!(expired, Err(BookError::Timeout)));
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        assert!(matches!(Pattern::new("(unclosed"), Err(BookError::InvalidPattern(_))));
+        assert!(matches!(Pattern::new(&"a".repeat(MAX_PATTERN_LEN + 1)), Err(BookError::InvalidPattern(_))));
+        assert!(matches!(Pattern::new(r"\w{1000}\w{1000}"), Err(BookError::InvalidPattern(message)) if message == "pattern is too complex"));
+        assert!(matches!(Pattern::new(""), Err(BookError::BadRequest(_))));
+    }
+}
@@ -0,0 +1,58 @@
+//! 鍵やトークンのように、他のユーザーに読ませないファイルの書き込み。
+//!
+//! 同じディレクトリの一時ファイルを最初から 0600 で作って書き、fsync してから差し替える。
+//! 書いている途中で落ちても元のファイルが残り、権限を変えるまでの間に読まれることもない。
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// `path` を `contents` で差し替える。ディレクトリがなければ作る。
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    // 前に残った一時ファイルの権限を引き継がないように、作り直す
+    match fs::remove_file(&tmp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_private() {
+        let path = std::env::temp_dir().join(format!("books_backend_private_{}.json", std::process::id()));
+        fs::write(&path, "old").unwrap();
+
+        write_private(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+//! リバースプロキシ越しの接続元・スキーム・ホスト。
+//!
+//! 信頼するプロキシ (`trusted_proxies`) から届いたリクエストに限り、`Forwarded` /
+//! `X-Forwarded-For` / `X-Forwarded-Proto` / `X-Forwarded-Host` を使う。それ以外の接続元が
+//! 付けた転送ヘッダーは偽装できるので無視する。
+
+use std::net::{IpAddr, SocketAddr};
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::{web, HttpRequest};
+
+/// `10.0.0.0/8` のようなアドレス範囲。プレフィックスを省略すると 1 アドレスだけ。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let (addr, prefix) = match value.trim().split_once('/') {
+            Some((addr, prefix)) => (addr.parse().ok()?, Some(prefix.parse().ok()?)),
+            None => (value.trim().parse().ok()?, None),
+        };
+
+        let max = if matches!(addr, IpAddr::V4(_)) { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+
+        (prefix <= max).then_some(Cidr { addr, prefix })
+    }
+
+    pub(crate) fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// 転送ヘッダーを信頼するプロキシのアドレス。既定では空で、どのヘッダーも使わない。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrustedProxies(Vec<Cidr>);
+
+impl TrustedProxies {
+    /// 読めない項目があれば、その文字列を `Err` で返す。
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        entries.iter()
+            .map(|entry| Cidr::parse(entry).ok_or_else(|| entry.clone()))
+            .collect::<Result<_, _>>()
+            .map(TrustedProxies)
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /// アプリに登録された設定。なければ何も信頼しない。
+    fn of(req: &HttpRequest) -> TrustedProxies {
+        req.app_data::<web::Data<TrustedProxies>>()
+            .map(|proxies| proxies.get_ref().clone())
+            .unwrap_or_default()
+    }
+}
+
+/// `Forwarded` の値から `name=` の項目を要素ごとに取り出す。
+fn forwarded_params<'a>(headers: &'a HeaderMap, name: &'a str) -> Vec<&'a str> {
+    headers.get_all(header::FORWARDED)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case(name).then(|| value.trim_matches('"'))
+            })
+        })
+        .collect()
+}
+
+fn header_list<'a>(headers: &'a HeaderMap, name: &str) -> Vec<&'a str> {
+    headers.get_all(name)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect()
+}
+
+/// `192.0.2.1`・`192.0.2.1:4711`・`[2001:db8::1]:4711` のいずれかから IP を取り出す。
+fn parse_node(value: &str) -> Option<IpAddr> {
+    value.parse::<IpAddr>().ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| value.trim_start_matches('[').trim_end_matches(']').parse().ok())
+}
+
+/// 信頼するプロキシからのリクエストなら、その転送ヘッダーの値を返す。
+fn trusted_headers(req: &HttpRequest) -> Option<(&HeaderMap, TrustedProxies)> {
+    let proxies = TrustedProxies::of(req);
+    let peer = req.peer_addr()?.ip();
+
+    proxies.is_trusted(peer).then(|| (req.headers(), proxies))
+}
+
+/// 接続元のクライアントの IP。
+///
+/// 転送経路を右 (自分に近い側) からたどり、最初に現れた信頼しないアドレスを返す。
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let peer = req.peer_addr().map(|addr| addr.ip());
+
+    let Some((headers, proxies)) = trusted_headers(req) else {
+        return peer;
+    };
+
+    let mut chain = forwarded_params(headers, "for");
+    if chain.is_empty() {
+        chain = header_list(headers, "x-forwarded-for");
+    }
+
+    let chain: Vec<IpAddr> = chain.into_iter().filter_map(parse_node).collect();
+
+    chain.iter()
+        .rev()
+        .find(|ip| !proxies.is_trusted(**ip))
+        .or(chain.first())
+        .copied()
+        .or(peer)
+}
+
+/// クライアントから見たスキーム (`http` / `https`)。
+pub fn scheme(req: &HttpRequest) -> String {
+    let forwarded = trusted_headers(req).and_then(|(headers, _)| {
+        forwarded_params(headers, "proto").first().copied()
+            .or_else(|| header_list(headers, "x-forwarded-proto").first().copied())
+            .map(str::to_ascii_lowercase)
+    });
+
+    forwarded.unwrap_or_else(|| if req.app_config().secure() { "https" } else { "http" }.to_string())
+}
+
+/// クライアントから見たホスト名 (ポートを含む)。
+pub fn host(req: &HttpRequest) -> String {
+    let forwarded = trusted_headers(req).and_then(|(headers, _)| {
+        forwarded_params(headers, "host").first().copied()
+            .or_else(|| header_list(headers, "x-forwarded-host").first().copied())
+            .map(str::to_string)
+    });
+
+    forwarded
+        .or_else(|| req.headers().get(header::HOST).and_then(|v| v.to_str().ok()).map(str::to_string))
+        .unwrap_or_else(|| req.app_config().host().to_string())
+}
+
+/// `path` をクライアントから見た絶対 URL にする。
+pub fn absolute_url(req: &HttpRequest, path: &str) -> String {
+    format!("{}://{}{}", scheme(req), host(req), path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn request(peer: &str, headers: &[(&str, &str)]) -> HttpRequest {
+        let proxies = TrustedProxies::parse(&["10.0.0.0/8".to_string(), "::1".to_string()]).unwrap();
+
+        let mut req = TestRequest::default()
+            .peer_addr(peer.parse().unwrap())
+            .insert_header((header::HOST, "internal:8080"))
+            .app_data(web::Data::new(proxies));
+        for header in headers {
+            req = req.append_header(*header);
+        }
+        req.to_http_request()
+    }
+
+    #[test]
+    fn test_client_ip() {
+        // 信頼しない接続元のヘッダーは無視する
+        let req = request("203.0.113.9:5000", &[("X-Forwarded-For", "198.51.100.1")]);
+        assert_eq!(client_ip(&req), Some("203.0.113.9".parse().unwrap()));
+
+        // 右からたどり、信頼するプロキシを飛ばす
+        let req = request("10.0.0.2:5000", &[("X-Forwarded-For", "1.2.3.4, 198.51.100.1, 10.0.0.3")]);
+        assert_eq!(client_ip(&req), Some("198.51.100.1".parse().unwrap()));
+
+        let req = request("[::1]:5000", &[("Forwarded", "for=\"[2001:db8::1]:4711\";proto=https;host=books.example.com")]);
+        assert_eq!(client_ip(&req), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(absolute_url(&req, "/books/id/1"), "https://books.example.com/books/id/1");
+    }
+
+    #[test]
+    fn test_absolute_url_without_proxy() {
+        let req = request("203.0.113.9:5000", &[("X-Forwarded-Proto", "https"), ("X-Forwarded-Host", "evil.test")]);
+        assert_eq!(absolute_url(&req, "/admin"), "http://internal:8080/admin");
+
+        assert!(TrustedProxies::parse(&["10.0.0.0/33".to_string()]).is_err());
+        assert!(TrustedProxies::parse(&["not-an-ip".to_string()]).is_err());
+    }
+}
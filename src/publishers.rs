@@ -0,0 +1,187 @@
+//! 出版社の記録 (`/publishers`)。
+//!
+//! 書籍の `publisher` はこれまでどおり名前の文字列で持ち、出版社の記録とは名前か別名で結び付ける
+//! (`crate::registry`)。書籍を書き込むたびに、記録のない出版社名 (Zotero の取り込みなどで入ったもの) は
+//! 記録を作る。書籍を保存するときに別名で書かれた出版社は記録の名前にそろえ、記録の名前を変えると
+//! 書籍の出版社名も書き換える。書籍に付いている出版社の記録は消せない (409)。
+//! 記録はデータファイルの隣 (`book.json` なら `book.publishers.json`) に置く。
+
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::registry::{self, Named, Registry};
+use crate::{Book, BookError};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Publisher {
+    pub id: u32,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub website: String,
+    /// 同じ出版社の別の表記
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+}
+
+impl Named for Publisher {
+    const KIND: &'static str = "publisher";
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn aliases(&self) -> &[String] {
+        &self.aliases
+    }
+
+    fn names_in(book: &Book) -> impl Iterator<Item = &str> {
+        book.publisher.as_deref().into_iter()
+    }
+
+    fn rename_in(book: &mut Book, renames: &[(String, String)]) -> bool {
+        let Some(renamed) = book.publisher.as_deref().and_then(|name| registry::renamed(name, renames)) else {
+            return false;
+        };
+        book.publisher = Some(renamed.to_string());
+        true
+    }
+}
+
+/// 出版社を作る・更新するときの内容。
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct PublisherInput {
+    pub name: String,
+    #[serde(default)]
+    pub website: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+impl PublisherInput {
+    fn into_publisher(self, id: u32) -> Result<Publisher, BookError> {
+        let (name, aliases) = registry::clean_names(&self.name, &self.aliases)?;
+
+        Ok(Publisher { id, name, website: self.website.trim().to_string(), aliases })
+    }
+}
+
+/// 出版社の記録の一覧。
+pub type Publishers = Registry<Publisher>;
+
+/// 記録への変更。
+#[derive(Debug)]
+pub enum Edit {
+    Create(PublisherInput),
+    Update(u32, PublisherInput),
+    Delete(u32),
+}
+
+/// 作成・更新した記録。削除なら削除した記録。
+pub type Plan = registry::Plan<Publisher, Publisher>;
+
+impl Registry<Publisher> {
+    /// 書籍の出版社名を記録の名前にそろえる。記録のない名前は前後の空白を落とすだけ。
+    pub fn canonical(&self, name: &str) -> String {
+        self.find(name).map_or_else(|| name.trim().to_string(), |p| p.name.clone())
+    }
+
+    /// `edit` を適用した結果を求める。記録も書籍もまだ変えない。
+    pub fn plan(&self, edit: Edit, books: &[Book]) -> Result<Plan, BookError> {
+        let mut publishers = self.clone();
+        let mut renames = Vec::new();
+
+        let result = match edit {
+            Edit::Create(input) => {
+                let publisher = input.into_publisher(self.next_id())?;
+                publishers.push(publisher.clone());
+                publisher
+            }
+            Edit::Update(id, input) => {
+                let publisher = input.into_publisher(id)?;
+                publishers.update(publisher.clone(), &mut renames)?;
+                publisher
+            }
+            Edit::Delete(id) => {
+                let removed = publishers.remove(id)?;
+                // 消しても次の書き込みで作り直されるだけなので、付いている間は断る
+                let count = registry::books_of(&removed, books).count();
+                if count > 0 {
+                    return Err(BookError::Conflict(format!("publisher {} is still assigned to {} books", id, count)));
+                }
+                removed
+            }
+        };
+
+        publishers.check()?;
+        Ok(Plan { records: publishers, renames, result })
+    }
+
+    /// `books` にあって記録のない出版社名を記録にした一覧。新しく作るものがなければ `None`。
+    pub fn register(&self, books: &[Book]) -> Option<Publishers> {
+        let mut publishers: Option<Publishers> = None;
+
+        for name in books.iter().filter_map(|b| b.publisher.as_deref()).map(str::trim) {
+            let current = publishers.as_ref().unwrap_or(self);
+            if name.is_empty() || current.find(name).is_some() {
+                continue;
+            }
+            let publisher = Publisher { id: current.next_id(), name: name.to_string(), website: String::new(), aliases: Vec::new() };
+            publishers.get_or_insert_with(|| self.clone()).push(publisher);
+        }
+
+        publishers
+    }
+}
+
+/// データファイルに対応する記録のファイル。
+pub fn path_for(data_file: &Path) -> PathBuf {
+    data_file.with_extension("publishers.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(name: &str) -> PublisherInput {
+        PublisherInput { name: name.to_string(), ..Default::default() }
+    }
+
+    fn book(id: u32, publisher: Option<&str>) -> Book {
+        Book { id, publisher: publisher.map(str::to_string), ..Default::default() }
+    }
+
+    #[test]
+    fn test_register() {
+        let books = [book(1, Some("No Starch Press")), book(2, None), book(3, Some(" no starch press ")), book(4, Some("O'Reilly"))];
+
+        let publishers = Publishers::default().register(&books).unwrap();
+        assert_eq!(publishers.list().iter().map(|p| (p.id, p.name.as_str())).collect::<Vec<_>>(), vec![(1, "No Starch Press"), (2, "O'Reilly")]);
+        assert!(publishers.register(&books).is_none());
+        assert_eq!(registry::books_of(publishers.get(1).unwrap(), &books).map(|b| b.id).collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_edit() {
+        let books = [book(1, Some("O'Reilly"))];
+        let publishers = Publishers::default().plan(Edit::Create(input("O'Reilly")), &books).unwrap().records;
+        let publishers = publishers.plan(Edit::Create(input("Manning")), &books).unwrap().records;
+
+        let renamed = PublisherInput { aliases: vec!["O'Reilly".to_string()], ..input("O'Reilly Media") };
+        let plan = publishers.plan(Edit::Update(1, renamed), &books).unwrap();
+        let [mut first] = books.clone();
+        assert!(Publisher::rename_in(&mut first, &plan.renames));
+        assert_eq!(first.publisher.as_deref(), Some("O'Reilly Media"));
+        assert_eq!(plan.records.canonical("o'reilly"), "O'Reilly Media");
+        assert_eq!(plan.records.canonical(" Pragmatic "), "Pragmatic");
+
+        let taken = PublisherInput { aliases: vec!["manning".to_string()], ..input("O'Reilly") };
+        assert!(matches!(publishers.plan(Edit::Update(1, taken), &books), Err(BookError::Conflict(_))));
+        assert!(matches!(publishers.plan(Edit::Delete(1), &books), Err(BookError::Conflict(_))));
+        assert_eq!(publishers.plan(Edit::Delete(2), &books).unwrap().result.name, "Manning");
+        assert!(matches!(publishers.plan(Edit::Create(input(" ")), &books), Err(BookError::BadRequest(_))));
+    }
+}
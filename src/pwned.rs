@@ -0,0 +1,136 @@
+//! Have I Been Pwned のパスワード漏洩チェック。
+//!
+//! SHA-1 の先頭 5 文字だけを range API に送り (k-anonymity)、返ってきた一覧に残りが載っているかを見る。
+//! API に届かない・時間切れのときは、登録を止めないように確かめずに通す。
+
+use std::str::FromStr;
+use std::time::Duration;
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use serde::Deserialize;
+
+pub const DEFAULT_API_URL: &str = "https://api.pwnedpasswords.com/range/";
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 漏洩したパスワードの扱い。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PwnedMode {
+    /// 確かめない
+    #[default]
+    Off,
+    /// 受け付けるが、ログとレスポンスの `Warning` ヘッダーで知らせる
+    Warn,
+    /// 422 で拒否する
+    Reject,
+}
+
+impl FromStr for PwnedMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(PwnedMode::Off),
+            "warn" => Ok(PwnedMode::Warn),
+            "reject" => Ok(PwnedMode::Reject),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PwnedCheck {
+    pub mode: PwnedMode,
+    api_url: String,
+    client: reqwest::Client,
+}
+
+impl PwnedCheck {
+    /// `api_url` の後ろにハッシュの先頭 5 文字を付けて問い合わせる。
+    pub fn new(mode: PwnedMode, api_url: impl Into<String>, timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_default();
+
+        PwnedCheck { mode, api_url: api_url.into(), client }
+    }
+
+    /// 漏洩した回数。API に届かなければ `None`。
+    pub async fn times_pwned(&self, password: &str) -> Option<u64> {
+        let (prefix, suffix) = split_hash(password);
+        let url = format!("{}{}", self.api_url, prefix);
+
+        let response = self.client.get(&url)
+            .header("Add-Padding", "true")
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+
+        let body = match response {
+            Ok(response) => response.text().await,
+            Err(e) => Err(e),
+        };
+
+        match body {
+            Ok(body) => Some(count_in(&body, &suffix)),
+            Err(e) => {
+                log::warn!("Skipping the compromised password check: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// SHA-1 の 16 進 (大文字) を、送る先頭 5 文字と残りに分ける。
+fn split_hash(password: &str) -> (String, String) {
+    let hash: String = digest(&SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect();
+
+    let (prefix, suffix) = hash.split_at(5);
+    (prefix.to_string(), suffix.to_string())
+}
+
+/// `SUFFIX:COUNT` の行から `suffix` の回数を探す。パディングの行は回数が 0 なので数えない。
+fn count_in(body: &str, suffix: &str) -> u64 {
+    body.lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .find(|(candidate, _)| candidate.eq_ignore_ascii_case(suffix))
+        .and_then(|(_, count)| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_hash() {
+        // SHA-1("password") = 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        let (prefix, suffix) = split_hash("password");
+        assert_eq!(prefix, "5BAA6");
+        assert_eq!(suffix, "1E4C9B93F3F0682250B6CF8331B7EE68FD8");
+    }
+
+    #[test]
+    fn test_count_in() {
+        let body = "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9659365\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD9:0\r\n";
+
+        assert_eq!(count_in(body, "1E4C9B93F3F0682250B6CF8331B7EE68FD8"), 9659365);
+        assert_eq!(count_in(body, "1E4C9B93F3F0682250B6CF8331B7EE68FD9"), 0);
+        assert_eq!(count_in(body, "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_unreachable_api_is_skipped() {
+        // 何も待ち受けていないポート
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/range/", listener.local_addr().unwrap());
+        drop(listener);
+
+        let check = PwnedCheck::new(PwnedMode::Reject, url, Duration::from_millis(500));
+        assert_eq!(check.times_pwned("password").await, None);
+    }
+}
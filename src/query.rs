@@ -0,0 +1,245 @@
+//! `q=` の検索式。
+//!
+//! `title:rust tag:async -tag:beginner year:>=2020` のように、`フィールド:値` で絞り込みを書ける。
+//! 先頭の `-` は否定、値に空白を含めるときは `title:"rust book"` のように引用符で囲む。
+//! 使えるフィールドは `title` / `author` / `publisher` (部分一致)、`tag` / `genre` / `format` / `isbn` / `id` (完全一致)、
+//! `year` (`year:2020`、`year:>=2020`、`year:2018..2020`)。全角・半角や大文字小文字は区別しない。
+//! `tag:` は `crate::synonyms` の同義語のタグや、`crate::tag_tree` の子孫のタグにも一致する。
+//! 知らないフィールド名の語やフィールドのない語は、これまでどおり全文検索の語として扱う。
+//! `-rust` のように否定した語は、title と content のどちらにも含まない書籍に絞り込む。
+
+use crate::normalize::fold;
+use crate::synonyms::Synonyms;
+use crate::{Book, BookError, Format};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Condition {
+    Title(String),
+    Author(String),
+    Publisher(String),
+    Tag(String),
+    Genre(String),
+    Format(Format),
+    Isbn(String),
+    Id(u32),
+    /// 出版年の範囲 (両端を含む)
+    Year(Option<i32>, Option<i32>),
+    /// title か content に含む語
+    Text(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Filter {
+    negated: bool,
+    condition: Condition,
+}
+
+/// 全文検索の語と絞り込みに分けた検索式。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// 全文検索に渡す語。なければ空。
+    pub text: String,
+    filters: Vec<Filter>,
+}
+
+/// 引用符の内側の空白では区切らずに、空白で語に分ける。
+pub(crate) fn split_words(q: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+
+    for c in q.chars() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        if c.is_whitespace() && !quoted {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.replace('"', "").split_whitespace().collect::<Vec<_>>().join(" ");
+    fold(&value)
+}
+
+fn parse_year(value: &str) -> Result<Condition, BookError> {
+    let year = |s: &str| {
+        s.parse::<i32>().map_err(|_| BookError::BadRequest(format!("year: {:?} is not a year", value)))
+    };
+
+    let condition = if let Some((from, to)) = value.split_once("..") {
+        let from = if from.is_empty() { None } else { Some(year(from)?) };
+        let to = if to.is_empty() { None } else { Some(year(to)?) };
+        Condition::Year(from, to)
+    } else if let Some(n) = value.strip_prefix(">=") {
+        Condition::Year(Some(year(n)?), None)
+    } else if let Some(n) = value.strip_prefix("<=") {
+        Condition::Year(None, Some(year(n)?))
+    } else if let Some(n) = value.strip_prefix('>') {
+        Condition::Year(Some(year(n)?.saturating_add(1)), None)
+    } else if let Some(n) = value.strip_prefix('<') {
+        Condition::Year(None, Some(year(n)?.saturating_sub(1)))
+    } else {
+        let n = year(value.strip_prefix('=').unwrap_or(value))?;
+        Condition::Year(Some(n), Some(n))
+    };
+
+    Ok(condition)
+}
+
+fn normalize_isbn(isbn: &str) -> String {
+    isbn.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_lowercase()
+}
+
+fn contains(haystack: &str, needle: &str) -> bool {
+    fold(haystack).contains(needle)
+}
+
+impl SearchQuery {
+    /// 検索式を読む。フィールドの値が空だったり、年や id として読めなかったりすれば 400。
+    pub fn parse(q: &str) -> Result<Self, BookError> {
+        let mut text = Vec::new();
+        let mut filters = Vec::new();
+
+        for word in split_words(q) {
+            let (negated, body) = match word.strip_prefix('-') {
+                Some(rest) if !rest.is_empty() => (true, rest),
+                _ => (false, word.as_str()),
+            };
+
+            let field = body.split_once(':').map(|(name, value)| (name.to_ascii_lowercase(), value));
+            let condition = match field {
+                Some((name, value)) if matches!(name.as_str(), "title" | "author" | "publisher" | "tag" | "genre" | "format" | "isbn" | "id" | "year") => {
+                    let value = unquote(value);
+                    if value.is_empty() {
+                        return Err(BookError::BadRequest(format!("{}: needs a value", name)));
+                    }
+                    match name.as_str() {
+                        "title" => Condition::Title(value),
+                        "author" => Condition::Author(value),
+                        "publisher" => Condition::Publisher(value),
+                        "tag" => Condition::Tag(value),
+                        "genre" => Condition::Genre(value),
+                        "format" => Condition::Format(value.parse().map_err(|_| {
+                            BookError::BadRequest(format!("format: {:?} is not a format", value))
+                        })?),
+                        "isbn" => Condition::Isbn(normalize_isbn(&value)),
+                        "id" => Condition::Id(value.parse().map_err(|_| {
+                            BookError::BadRequest(format!("id: {:?} is not a book id", value))
+                        })?),
+                        _ => parse_year(&value)?,
+                    }
+                }
+                _ if negated => Condition::Text(unquote(body)),
+                _ => {
+                    text.push(word);
+                    continue;
+                }
+            };
+
+            filters.push(Filter { negated, condition });
+        }
+
+        Ok(SearchQuery { text: text.join(" "), filters })
+    }
+
+    /// 絞り込みの条件をすべて満たすか。全文検索の語は見ない。
+    pub fn matches(&self, book: &Book, synonyms: &Synonyms) -> bool {
+        self.filters.iter().all(|filter| {
+            let matched = match &filter.condition {
+                Condition::Title(title) => contains(&book.title, title),
+                Condition::Author(author) => book.authors.iter().any(|a| contains(a, author)),
+                Condition::Publisher(publisher) => book.publisher.as_deref().is_some_and(|p| contains(p, publisher)),
+                Condition::Tag(tag) => synonyms.matches(&book.tags, tag),
+                Condition::Genre(genre) => book.genres.iter().any(|g| fold(g) == *genre),
+                Condition::Format(format) => book.formats.contains(format),
+                Condition::Isbn(isbn) => book.isbn.as_deref().is_some_and(|i| normalize_isbn(i) == *isbn),
+                Condition::Id(id) => book.id == *id,
+                Condition::Year(from, to) => book.published_year.is_some_and(|year| {
+                    from.is_none_or(|from| year >= from) && to.is_none_or(|to| year <= to)
+                }),
+                Condition::Text(text) => contains(&book.title, text) || contains(&book.content, text),
+            };
+            matched != filter.negated
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(id: u32, title: &str, tags: &[&str], year: Option<i32>) -> Book {
+        Book {
+            id,
+            title: title.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            published_year: year,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let query = SearchQuery::parse("title:\"rust  Book\" memory -tag:beginner year:>=2020 \"async fn\" c++:x").unwrap();
+        assert_eq!(query.text, "memory \"async fn\" c++:x");
+        assert_eq!(query.filters, vec![
+            Filter { negated: false, condition: Condition::Title("rust book".to_string()) },
+            Filter { negated: true, condition: Condition::Tag("beginner".to_string()) },
+            Filter { negated: false, condition: Condition::Year(Some(2020), None) },
+        ]);
+
+        assert_eq!(parse_year("<2000").unwrap(), Condition::Year(None, Some(1999)));
+        assert_eq!(parse_year("2018..2020").unwrap(), Condition::Year(Some(2018), Some(2020)));
+        assert!(SearchQuery::parse("year:recent").is_err());
+        assert!(SearchQuery::parse("tag:").is_err());
+        assert!(SearchQuery::parse("id:x").is_err());
+        assert!(SearchQuery::parse("format:scroll").is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let books = [
+            book(1, "Rust Basics", &["beginner", "syntax"], Some(2018)),
+            Book {
+                genres: vec!["Systems Programming".to_string()],
+                formats: vec![Format::Paperback, Format::Ebook],
+                ..book(2, "Async in Rust", &["async", "tokio"], Some(2021))
+            },
+            book(3, "Async Python", &["async"], None),
+        ];
+        let ids = |q: &str| {
+            let query = SearchQuery::parse(q).unwrap();
+            books.iter().filter(|b| query.matches(b, &Synonyms::default())).map(|b| b.id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids("title:rust"), vec![1, 2]);
+        assert_eq!(ids("title:rust tag:ASYNC -tag:beginner year:>=2020"), vec![2]);
+        assert_eq!(ids("year:2018..2019"), vec![1]);
+        assert_eq!(ids("-year:2018"), vec![2, 3]);
+        assert_eq!(ids("-python"), vec![1, 2]);
+        assert_eq!(ids("title:ｒｕｓｔ tag:ＴＯＫＩＯ"), vec![2]);
+        assert_eq!(ids("genre:\"Systems Programming\""), vec![2]);
+        assert_eq!(ids("-genre:\"systems programming\""), vec![1, 3]);
+        assert_eq!(ids("format:EBOOK"), vec![2]);
+
+        let synonyms = Synonyms::new(vec![vec!["async".to_string(), "concurrency".to_string()]]).unwrap();
+        let query = SearchQuery::parse("tag:concurrency").unwrap();
+        assert_eq!(books.iter().filter(|b| query.matches(b, &synonyms)).map(|b| b.id).collect::<Vec<_>>(), vec![2, 3]);
+
+        // 親のタグは子孫のタグにも一致する
+        let nested = [book(4, "Tokio", &["programming/rust/async"], None), book(5, "Rusty", &["programming/rusty"], None)];
+        let query = SearchQuery::parse("tag:Programming/Rust").unwrap();
+        assert_eq!(nested.iter().filter(|b| query.matches(b, &Synonyms::default())).map(|b| b.id).collect::<Vec<_>>(), vec![4]);
+    }
+}
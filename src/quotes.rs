@@ -0,0 +1,149 @@
+//! 書籍から抜き出した引用 (`/books/{id}/quotes`、`/quotes`)。
+//!
+//! 引用は本文とページや位置 (電子書籍の位置番号など) を持ち、データファイルの隣
+//! (`book.json` なら `book.quotes.json`) に置く。`/quotes?q=` で本文とメモを検索でき、
+//! `random=true` なら一致したものから 1 件を選んで返す。書籍を消しても引用は残るが、
+//! 一覧には見える書籍の引用だけを返す。
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::normalize::fold;
+use crate::BookError;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Quote {
+    pub id: u32,
+    pub book_id: u32,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+    /// ページのない本での位置 (`Loc 1234` など)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub note: String,
+    /// 登録した時刻 (UNIX 秒)
+    pub created_at: i64,
+}
+
+impl Quote {
+    /// 本文かメモに `q` (正規化済み) を含むか。
+    pub fn matches(&self, q: &str) -> bool {
+        fold(&self.text).contains(q) || fold(&self.note).contains(q)
+    }
+}
+
+/// 引用を登録するときの内容。
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct QuoteInput {
+    pub text: String,
+    #[serde(default)]
+    pub page: Option<u32>,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub note: String,
+}
+
+/// 引用の一覧。並びは登録した順。
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Quotes(Vec<Quote>);
+
+/// 引用への変更。
+#[derive(Debug)]
+pub enum Edit {
+    Add { book_id: u32, input: QuoteInput, now: i64 },
+    Delete(u32),
+}
+
+impl Quotes {
+    pub fn list(&self) -> &[Quote] {
+        &self.0
+    }
+
+    /// `edit` を適用した一覧と、登録・削除した引用。
+    pub fn apply(&self, edit: Edit) -> Result<(Quotes, Quote), BookError> {
+        let mut quotes = self.clone();
+
+        let quote = match edit {
+            Edit::Add { book_id, input, now } => {
+                let text = input.text.trim().to_string();
+                if text.is_empty() {
+                    return Err(BookError::BadRequest("text: must not be empty".to_string()));
+                }
+                let quote = Quote {
+                    id: self.0.iter().map(|q| q.id).max().unwrap_or(0) + 1,
+                    book_id,
+                    text,
+                    page: input.page,
+                    location: input.location.map(|l| l.trim().to_string()).filter(|l| !l.is_empty()),
+                    note: input.note.trim().to_string(),
+                    created_at: now,
+                };
+                quotes.0.push(quote.clone());
+                quote
+            }
+            Edit::Delete(id) => {
+                let pos = self.0.iter().position(|q| q.id == id).ok_or(BookError::NotFound)?;
+                quotes.0.remove(pos)
+            }
+        };
+
+        Ok((quotes, quote))
+    }
+}
+
+/// データファイルに対応する引用のファイル。
+pub fn path_for(data_file: &Path) -> PathBuf {
+    data_file.with_extension("quotes.json")
+}
+
+/// 引用を読む。ファイルがなければ空。
+pub fn load(path: &Path) -> Result<Quotes, BookError> {
+    match fs::read(path) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Quotes::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 一覧で返す引用。書籍の題名を添える。
+#[derive(Serialize, Debug)]
+pub struct QuoteView {
+    #[serde(flatten)]
+    pub quote: Quote,
+    pub book_title: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(quotes: &Quotes, book_id: u32, text: &str) -> Quotes {
+        let input = QuoteInput { text: text.to_string(), location: Some(" ".to_string()), ..Default::default() };
+        quotes.apply(Edit::Add { book_id, input, now: 1_700_000_000 }).unwrap().0
+    }
+
+    #[test]
+    fn test_apply() {
+        let quotes = add(&add(&Quotes::default(), 1, "Fearless concurrency"), 2, " Ownership is Rust's most unique feature ");
+        assert_eq!(quotes.list().iter().map(|q| (q.id, q.book_id)).collect::<Vec<_>>(), vec![(1, 1), (2, 2)]);
+        assert_eq!(quotes.list()[1].text, "Ownership is Rust's most unique feature");
+        assert_eq!(quotes.list()[0].location, None);
+        assert!(quotes.list()[1].matches("ownership"));
+        assert!(!quotes.list()[0].matches("ownership"));
+
+        let (rest, removed) = quotes.apply(Edit::Delete(1)).unwrap();
+        assert_eq!(removed.text, "Fearless concurrency");
+        assert_eq!(rest.list().len(), 1);
+        assert_eq!(add(&rest, 1, "Zero-cost").list().last().unwrap().id, 3);
+
+        assert!(matches!(quotes.apply(Edit::Delete(9)), Err(BookError::NotFound)));
+        let empty = QuoteInput { text: " ".to_string(), ..Default::default() };
+        assert!(matches!(quotes.apply(Edit::Add { book_id: 1, input: empty, now: 0 }), Err(BookError::BadRequest(_))));
+    }
+}
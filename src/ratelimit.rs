@@ -0,0 +1,274 @@
+//! IP アドレスごと・API キーごとのレート制限 (トークンバケット)。
+//!
+//! `X-Api-Key` ヘッダーが発行済みのキーならキー単位、それ以外は接続元 IP 単位で数える。
+//! 知らないキーは IP 単位にするので、キーを毎回変えても IP の上限は逃れられない。
+//! 超過したリクエストには 429 と `Retry-After` を返す。
+//!
+//! 制限をかけたレスポンスには、許可されたものも含めて `X-RateLimit-Limit` (1 分あたりの上限)・
+//! `X-RateLimit-Remaining` (残り)・`X-RateLimit-Reset` (上限まで回復するまでの秒数) を付ける。
+
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, ResponseError};
+use governor::clock::{Clock, DefaultClock};
+use governor::middleware::StateInformationMiddleware;
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+
+use crate::api_keys::ApiKeys;
+use crate::tenant::Tenant;
+use crate::BookError;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+pub const RATE_LIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+pub const RATE_LIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+pub const RATE_LIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+/// 使われなくなったバケットを掃除する間隔 (リクエスト数)。
+const RETAIN_EVERY: u64 = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum KeyKind {
+    Ip,
+    ApiKey,
+}
+
+impl KeyKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            KeyKind::Ip => "ip",
+            KeyKind::ApiKey => "api_key",
+        }
+    }
+}
+
+type Limiter = DefaultKeyedRateLimiter<String, StateInformationMiddleware>;
+
+/// 1 分あたりの上限と、それに対応するリミッター。
+struct Limiters {
+    per_ip_per_minute: u32,
+    per_key_per_minute: u32,
+    per_ip: Option<Limiter>,
+    per_key: Option<Limiter>,
+}
+
+/// ある接続元の、その時点での使用状況。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Usage {
+    limit: u32,
+    remaining: u32,
+    /// 上限まで回復するまでの秒数
+    reset: u64,
+}
+
+impl Usage {
+    fn new(limit: u32, remaining: u32) -> Self {
+        // 1 分で `limit` 回分回復する
+        let used = u64::from(limit.saturating_sub(remaining));
+        Usage { limit, remaining, reset: (used * 60).div_ceil(u64::from(limit)) }
+    }
+
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(RATE_LIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(RATE_LIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATE_LIMIT_RESET, HeaderValue::from(self.reset));
+    }
+}
+
+pub struct RateLimits {
+    limiters: RwLock<Limiters>,
+    allowed: AtomicU64,
+    rejected_ip: AtomicU64,
+    rejected_key: AtomicU64,
+}
+
+fn limiter(per_minute: u32) -> Option<Limiter> {
+    NonZeroU32::new(per_minute).map(|n| RateLimiter::keyed(Quota::per_minute(n)).with_middleware())
+}
+
+impl RateLimits {
+    /// 1 分あたりの上限を指定する。0 ならその種類の制限をかけない。
+    pub fn new(per_ip_per_minute: u32, per_key_per_minute: u32) -> Self {
+        RateLimits {
+            limiters: RwLock::new(Limiters {
+                per_ip_per_minute,
+                per_key_per_minute,
+                per_ip: limiter(per_ip_per_minute),
+                per_key: limiter(per_key_per_minute),
+            }),
+            allowed: AtomicU64::new(0),
+            rejected_ip: AtomicU64::new(0),
+            rejected_key: AtomicU64::new(0),
+        }
+    }
+
+    /// 上限を変える。変わった種類だけリミッターを作り直すので、そのバケットは空に戻る。
+    pub fn set_limits(&self, per_ip_per_minute: u32, per_key_per_minute: u32) {
+        let mut limiters = self.limiters.write().unwrap();
+
+        if limiters.per_ip_per_minute != per_ip_per_minute {
+            limiters.per_ip_per_minute = per_ip_per_minute;
+            limiters.per_ip = limiter(per_ip_per_minute);
+        }
+        if limiters.per_key_per_minute != per_key_per_minute {
+            limiters.per_key_per_minute = per_key_per_minute;
+            limiters.per_key = limiter(per_key_per_minute);
+        }
+    }
+
+    /// 許可されれば使用状況 (制限がなければ `None`) を、超過していれば使用状況と次に受け付けられる
+    /// までの時間を返す。
+    fn check(&self, kind: KeyKind, key: String) -> Result<Option<Usage>, (Usage, Duration)> {
+        let limiters = self.limiters.read().unwrap();
+        let (limiter, limit) = match kind {
+            KeyKind::Ip => (&limiters.per_ip, limiters.per_ip_per_minute),
+            KeyKind::ApiKey => (&limiters.per_key, limiters.per_key_per_minute),
+        };
+        let Some(limiter) = limiter else {
+            return Ok(None);
+        };
+
+        match limiter.check_key(&key) {
+            Ok(snapshot) => {
+                if self.allowed.fetch_add(1, Ordering::Relaxed).is_multiple_of(RETAIN_EVERY) {
+                    limiter.retain_recent();
+                }
+                Ok(Some(Usage::new(limit, snapshot.remaining_burst_capacity())))
+            }
+            Err(not_until) => {
+                match kind {
+                    KeyKind::Ip => self.rejected_ip.fetch_add(1, Ordering::Relaxed),
+                    KeyKind::ApiKey => self.rejected_key.fetch_add(1, Ordering::Relaxed),
+                };
+                Err((Usage::new(limit, 0), not_until.wait_time_from(DefaultClock::default().now())))
+            }
+        }
+    }
+
+    /// Prometheus のテキスト形式で集計値を返す。
+    pub fn render_metrics(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP books_rate_limit_allowed_total Requests accepted by the rate limiter.\n");
+        out.push_str("# TYPE books_rate_limit_allowed_total counter\n");
+        out.push_str(&format!("books_rate_limit_allowed_total {}\n", self.allowed.load(Ordering::Relaxed)));
+
+        out.push_str("# HELP books_rate_limit_rejected_total Requests rejected with 429.\n");
+        out.push_str("# TYPE books_rate_limit_rejected_total counter\n");
+        for (kind, count) in [(KeyKind::Ip, &self.rejected_ip), (KeyKind::ApiKey, &self.rejected_key)] {
+            out.push_str(&format!(
+                "books_rate_limit_rejected_total{{key=\"{}\"}} {}\n",
+                kind.as_str(),
+                count.load(Ordering::Relaxed),
+            ));
+        }
+
+        out
+    }
+}
+
+fn client_key(req: &ServiceRequest) -> (KeyKind, String) {
+    let secret = req.headers().get(API_KEY_HEADER).and_then(|v| v.to_str().ok());
+    if let (Some(secret), Some(keys)) = (secret, req.app_data::<web::Data<ApiKeys>>()) {
+        let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+        if let Some(key) = keys.find(secret.trim(), tenant.as_deref()) {
+            return (KeyKind::ApiKey, key.id);
+        }
+    }
+
+    // X-Forwarded-For は偽装できるので、信頼するプロキシから来たときだけたどる
+    let ip = crate::proxy::client_ip(req.request()).map(|ip| ip.to_string()).unwrap_or_default();
+    (KeyKind::Ip, ip)
+}
+
+/// `middleware::from_fn` に渡すレート制限ミドルウェア。設定は `web::Data<RateLimits>` から読む。
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let Some(limits) = req.app_data::<web::Data<RateLimits>>() else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    let (kind, key) = client_key(&req);
+
+    match limits.check(kind, key) {
+        Ok(usage) => {
+            let mut res = next.call(req).await?;
+            if let Some(usage) = usage {
+                usage.insert_headers(res.headers_mut());
+            }
+            Ok(res.map_into_boxed_body())
+        }
+        Err((usage, wait)) => {
+            let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            let mut resp = BookError::RateLimited(retry_after.max(1)).error_response();
+            usage.insert_headers(resp.headers_mut());
+            Ok(req.into_response(resp))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_per_key() {
+        let limits = RateLimits::new(1, 0);
+
+        assert!(limits.check(KeyKind::Ip, "10.0.0.1".to_string()).is_ok());
+        assert!(limits.check(KeyKind::Ip, "10.0.0.1".to_string()).is_err());
+        assert!(limits.check(KeyKind::Ip, "10.0.0.2".to_string()).is_ok());
+
+        // API キーの上限は 0 (無制限)
+        for _ in 0..10 {
+            assert!(limits.check(KeyKind::ApiKey, "key".to_string()).is_ok());
+        }
+
+        assert!(limits.render_metrics().contains("books_rate_limit_rejected_total{key=\"ip\"} 1"));
+
+        assert_eq!(limits.check(KeyKind::Ip, "10.0.0.3".to_string()), Ok(Some(Usage { limit: 1, remaining: 0, reset: 60 })));
+        assert_eq!(limits.check(KeyKind::ApiKey, "key".to_string()), Ok(None));
+
+        // 上限を変えるとバケットも作り直す
+        limits.set_limits(2, 1);
+        assert!(limits.check(KeyKind::Ip, "10.0.0.1".to_string()).is_ok());
+        assert!(limits.check(KeyKind::ApiKey, "key".to_string()).is_ok());
+        assert!(limits.check(KeyKind::ApiKey, "key".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_client_key() {
+        let file = std::env::temp_dir().join(format!("books_backend_ratelimit_keys_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        let keys = ApiKeys::open(&file).unwrap();
+        let (secret, key) = keys.create("ci", Vec::new(), None, "admin").unwrap();
+        let keys = web::Data::new(keys);
+
+        let request = |secret: &str| {
+            actix_web::test::TestRequest::default()
+                .insert_header((API_KEY_HEADER, secret))
+                .peer_addr("10.0.0.1:4000".parse().unwrap())
+                .app_data(keys.clone())
+                .to_srv_request()
+        };
+        assert_eq!(client_key(&request(&secret)), (KeyKind::ApiKey, key.id));
+        // 知らないキーは接続元 IP で数える
+        assert_eq!(client_key(&request("bk_random")), (KeyKind::Ip, "10.0.0.1".to_string()));
+
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_usage() {
+        assert_eq!(Usage::new(600, 599).reset, 1);
+        assert_eq!(Usage::new(600, 300).reset, 30);
+        assert_eq!(Usage::new(7, 0).reset, 60);
+    }
+}
@@ -0,0 +1,82 @@
+//! 本文の語数・文字数と、読み終えるまでの目安の時間。
+//!
+//! 保存するときに content から HTML タグを除いて数え、`content_stats` に入れる。
+//! 読む時間は、日本語と中国語は 1 分に 500 文字、それ以外は 1 分に 230 語で見積もり、分単位で切り上げる。
+//! 語数は空白で区切って数えるので、空白のない日本語の文はほぼ文字数で見ることになる。
+//! `content_stats` を保存する前に書き込まれた書籍は、集計するときにその場で数える。
+
+use serde::{Deserialize, Serialize};
+
+use crate::{language, Book};
+
+const WORDS_PER_MINUTE: usize = 230;
+const CHARACTERS_PER_MINUTE: usize = 500;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ContentStats {
+    pub words: usize,
+    /// 空白を除いた文字数
+    pub characters: usize,
+    pub reading_minutes: usize,
+}
+
+/// HTML タグの外の文字。タグは語の区切りにする。
+fn text(content: &str) -> String {
+    let mut text = String::with_capacity(content.len());
+    let mut in_tag = false;
+    for c in content.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// `content` を数える。本文がなければ `None`。
+pub fn measure(content: &str, language: Option<&str>) -> Option<ContentStats> {
+    let text = text(content);
+    let words = text.split_whitespace().filter(|w| w.chars().any(char::is_alphanumeric)).count();
+    let characters = text.chars().filter(|c| !c.is_whitespace()).count();
+    if characters == 0 {
+        return None;
+    }
+
+    let reading_minutes = match language {
+        Some("ja" | "zh") => characters.div_ceil(CHARACTERS_PER_MINUTE),
+        _ => words.div_ceil(WORDS_PER_MINUTE),
+    };
+
+    Some(ContentStats { words, characters, reading_minutes: reading_minutes.max(1) })
+}
+
+/// `book` の語数・文字数。保存されていなければ数える。
+pub fn of(book: &Book) -> Option<ContentStats> {
+    book.content_stats.clone().or_else(|| {
+        let language = book.language.as_deref().or_else(|| language::detect(&book.title, &book.content));
+        measure(&book.content, language)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure() {
+        let content = format!("<p>{}</p>", vec!["word"; 461].join(" "));
+        assert_eq!(measure(&content, Some("en")), Some(ContentStats { words: 461, characters: 461 * 4, reading_minutes: 3 }));
+
+        let stats = measure("<p>所有権と借用を学ぶ</p>", Some("ja")).unwrap();
+        assert_eq!((stats.words, stats.characters, stats.reading_minutes), (1, 9, 1));
+
+        assert_eq!(measure("<p>one</p><p>two</p>", None).unwrap().words, 2);
+        assert_eq!(measure("<p> </p>", None), None);
+        assert_eq!(of(&Book { content: "Intro to Rust".to_string(), ..Default::default() }).unwrap().words, 3);
+    }
+}
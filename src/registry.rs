@@ -0,0 +1,204 @@
+//! 名前と別名で書籍と結び付ける記録 (著者・出版社) の共通部分。
+//!
+//! 書籍には名前の文字列だけを持ち、記録とは名前か別名 (`aliases`) で結び付ける。比べるときは
+//! `crate::normalize` で正規化する。名前と別名は記録どうしで重ならないようにし、記録の名前を
+//! 変えたら書籍の名前も書き換える。記録の種類ごとの違い (書籍のどの項目か、どんな変更があるか) は
+//! `crate::authors` と `crate::publishers` に置く。
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::normalize::fold;
+use crate::{Book, BookError};
+
+/// 名前と別名を持つ記録。
+pub trait Named: Clone {
+    /// エラーの文言に使う記録の種類 (`author` など)
+    const KIND: &'static str;
+
+    fn id(&self) -> u32;
+    fn name(&self) -> &str;
+    fn aliases(&self) -> &[String];
+
+    /// 書籍に書かれた、この種類の名前。
+    fn names_in(book: &Book) -> impl Iterator<Item = &str>;
+
+    /// `renames` に従って書籍の名前を書き換える。変わったら true。
+    fn rename_in(book: &mut Book, renames: &[(String, String)]) -> bool;
+
+    /// 名前と別名 (正規化済み)。
+    fn keys(&self) -> impl Iterator<Item = String> + '_ {
+        std::iter::once(self.name()).chain(self.aliases().iter().map(String::as_str)).map(fold)
+    }
+
+    /// 名前か別名が `name` か。
+    fn is_named(&self, name: &str) -> bool {
+        let name = fold(name.trim());
+        self.keys().any(|key| key == name)
+    }
+}
+
+/// 入力の名前と別名の前後の空白を落とし、名前と同じ別名や重なった別名を除く。名前が空なら 400。
+pub fn clean_names(name: &str, aliases: &[String]) -> Result<(String, Vec<String>), BookError> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(BookError::BadRequest("name: must not be empty".to_string()));
+    }
+
+    let mut cleaned: Vec<String> = Vec::new();
+    for alias in aliases.iter().map(|a| a.trim()).filter(|a| !a.is_empty()) {
+        if fold(alias) != fold(&name) && !cleaned.iter().any(|a| fold(a) == fold(alias)) {
+            cleaned.push(alias.to_string());
+        }
+    }
+
+    Ok((name, cleaned))
+}
+
+/// `renames` での `name` の新しい名前。書き換えないなら `None`。
+pub fn renamed<'a>(name: &str, renames: &'a [(String, String)]) -> Option<&'a str> {
+    let folded = fold(name.trim());
+    renames.iter().find(|(from, _)| *from == folded).map(|(_, to)| to.as_str())
+}
+
+/// 記録の一覧。
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Registry<T>(Vec<T>);
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Registry(Vec::new())
+    }
+}
+
+/// 変更を適用したあとの記録と、書籍の名前の書き換え。
+#[derive(Debug)]
+pub struct Plan<T, R> {
+    pub records: Registry<T>,
+    /// (正規化した元の名前, 新しい名前)
+    pub renames: Vec<(String, String)>,
+    /// 変更の結果として返す記録
+    pub result: R,
+}
+
+impl<T: Named> Registry<T> {
+    pub fn list(&self) -> &[T] {
+        &self.0
+    }
+
+    pub fn get(&self, id: u32) -> Option<&T> {
+        self.0.iter().find(|r| r.id() == id)
+    }
+
+    /// 名前か別名が `name` の記録。
+    pub fn find(&self, name: &str) -> Option<&T> {
+        self.0.iter().find(|r| r.is_named(name))
+    }
+
+    pub(crate) fn next_id(&self) -> u32 {
+        self.0.iter().map(|r| r.id()).max().unwrap_or(0) + 1
+    }
+
+    fn position(&self, id: u32) -> Result<usize, BookError> {
+        self.0.iter().position(|r| r.id() == id).ok_or(BookError::NotFound)
+    }
+
+    pub(crate) fn push(&mut self, record: T) {
+        self.0.push(record);
+    }
+
+    /// 同じ id の記録を `record` に置き換え、元の記録を返す。名前が変わったら書籍の書き換えを `renames` に足す。
+    pub(crate) fn update(&mut self, record: T, renames: &mut Vec<(String, String)>) -> Result<T, BookError> {
+        let pos = self.position(record.id())?;
+        if self.0[pos].name() != record.name() {
+            renames.push((fold(self.0[pos].name()), record.name().to_string()));
+        }
+        Ok(std::mem::replace(&mut self.0[pos], record))
+    }
+
+    pub(crate) fn remove(&mut self, id: u32) -> Result<T, BookError> {
+        let pos = self.position(id)?;
+        Ok(self.0.remove(pos))
+    }
+
+    /// 名前と別名が記録どうしで重なっていないか。重なっていれば 409。
+    pub(crate) fn check(&self) -> Result<(), BookError> {
+        let mut owners: HashMap<String, u32> = HashMap::new();
+        for record in &self.0 {
+            for key in record.keys() {
+                if let Some(other) = owners.insert(key, record.id()).filter(|&other| other != record.id()) {
+                    return Err(BookError::Conflict(format!(
+                        "{kind} {} and {kind} {} share the name {:?}", other, record.id(), record.name(), kind = T::KIND
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 記録を読む。ファイルがなければ空。
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<Registry<T>, BookError> {
+    match fs::read(path) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Registry::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// `record` の書籍。名前か別名で書かれたものを返す。
+pub fn books_of<'a, T: Named>(record: &'a T, books: &'a [Book]) -> impl Iterator<Item = &'a Book> + 'a {
+    books.iter().filter(move |b| T::names_in(b).any(|name| record.is_named(name)))
+}
+
+/// 一覧で返す記録。書籍の数を添える。
+#[derive(Serialize, Debug)]
+pub struct Summary<T> {
+    #[serde(flatten)]
+    pub record: T,
+    pub book_count: usize,
+}
+
+impl<T: Named> Summary<T> {
+    pub fn new(record: &T, books: &[Book]) -> Self {
+        Summary { record: record.clone(), book_count: books_of(record, books).count() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publishers::Publisher;
+
+    fn publisher(id: u32, name: &str, aliases: &[&str]) -> Publisher {
+        Publisher { id, name: name.to_string(), website: String::new(), aliases: aliases.iter().map(|a| a.to_string()).collect() }
+    }
+
+    #[test]
+    fn test_clean_names() {
+        let aliases = vec![" ORM ".to_string(), "orm".to_string(), "O'Reilly".to_string(), " ".to_string()];
+        assert_eq!(clean_names(" O'Reilly ", &aliases).unwrap(), ("O'Reilly".to_string(), vec!["ORM".to_string()]));
+        assert!(matches!(clean_names("  ", &[]), Err(BookError::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_check_and_books_of() {
+        let mut records = Registry::default();
+        records.push(publisher(1, "O'Reilly", &["ORM"]));
+        records.push(publisher(2, "Manning", &[]));
+        assert!(records.check().is_ok());
+        assert_eq!(records.find(" orm ").map(|p| p.id), Some(1));
+
+        let books = [Book { id: 1, publisher: Some("orm".to_string()), ..Default::default() }, Book { id: 2, ..Default::default() }];
+        assert_eq!(books_of(records.get(1).unwrap(), &books).map(|b| b.id).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(Summary::new(records.get(2).unwrap(), &books).book_count, 0);
+
+        records.push(publisher(3, "Manning Publications", &["manning"]));
+        assert!(matches!(records.check(), Err(BookError::Conflict(message)) if message.starts_with("publisher 2 and publisher 3")));
+    }
+}
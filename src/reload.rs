@@ -0,0 +1,103 @@
+//! 設定の再読み込み。
+//!
+//! SIGHUP か `POST /admin/reload-config` で設定ファイルと環境変数を読み直し、CORS のオリジン・
+//! 機能フラグ・レート制限・ログのフィルターだけを差し替える。接続は切らないので、
+//! アップロード中のリクエストもそのまま続く。待ち受けアドレスやデータファイルなど
+//! それ以外の項目を変えるには再起動が要る。
+
+use std::path::PathBuf;
+use actix_web::web;
+
+use crate::config::{Config, ConfigError};
+use crate::cors::AllowedOrigins;
+use crate::flags::Flags;
+use crate::ratelimit::RateLimits;
+use crate::telemetry;
+
+/// 差し替えられる設定への参照をまとめたもの。
+pub struct Reloader {
+    config_file: Option<PathBuf>,
+    cors_origins: AllowedOrigins,
+    flags: Flags,
+    rate_limits: web::Data<RateLimits>,
+}
+
+impl Reloader {
+    pub fn new(
+        config_file: Option<PathBuf>,
+        cors_origins: AllowedOrigins,
+        flags: Flags,
+        rate_limits: web::Data<RateLimits>,
+    ) -> Self {
+        Reloader { config_file, cors_origins, flags, rate_limits }
+    }
+
+    /// 設定を読み直して反映する。読めなければ何も変えずにエラーを返す。
+    pub fn reload(&self) -> Result<(), ConfigError> {
+        let config = Config::load(self.config_file.as_deref(), None)?;
+
+        self.cors_origins.set(&config.cors_origins);
+        self.flags.reset(config.flags.clone());
+        self.rate_limits.set_limits(config.rate_limit_per_ip, config.rate_limit_per_api_key);
+        telemetry::reload_level(&config);
+
+        log::info!(
+            "Reloaded configuration from {}",
+            self.config_file.as_deref().map(|p| p.display().to_string()).unwrap_or_else(|| "environment".to_string()),
+        );
+        Ok(())
+    }
+}
+
+/// SIGHUP を受けるたびに設定を読み直すタスクを起動する。
+#[cfg(unix)]
+pub fn spawn_on_sighup(reloader: web::Data<Reloader>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log::error!("Failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            if let Err(e) = reloader.reload() {
+                log::error!("Failed to reload configuration, keeping the current one: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::Flag;
+
+    #[test]
+    fn test_reload() {
+        let path = std::env::temp_dir().join(format!("books_backend_reload_{}.toml", std::process::id()));
+        std::fs::write(&path, "[cors]\nallowed_origins = [\"https://a.example.com\"]\n").unwrap();
+
+        let origins = AllowedOrigins::default();
+        let flags = Flags::default();
+        let reloader = Reloader::new(Some(path.clone()), origins.clone(), flags.clone(), web::Data::new(RateLimits::new(1, 1)));
+
+        reloader.reload().unwrap();
+        assert!(origins.matches("https://a.example.com"));
+
+        // 読めない設定なら何も変えない
+        std::fs::write(&path, "[flags]\nexport = false\n[cors]\nallowed_origins = [\"nope\"]\n").unwrap();
+        assert!(reloader.reload().is_err());
+        assert!(flags.is_enabled(Flag::Export));
+        assert!(origins.matches("https://a.example.com"));
+
+        std::fs::write(&path, "[flags]\nexport = false\n").unwrap();
+        reloader.reload().unwrap();
+        assert!(!flags.is_enabled(Flag::Export));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
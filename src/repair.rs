@@ -0,0 +1,213 @@
+//! 壊れたデータファイルの修復。
+//!
+//! `books repair` で使う。途中で切れたり一部のレコードが壊れたりしたファイルから読める書籍だけを
+//! 取り出し、データファイルを書き直す。読めなかった部分は元のファイルと一緒に `backups/` へ退避する。
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use serde::Serialize;
+
+use crate::storage::BookRepository;
+use crate::{Book, BookError};
+
+/// 取り出せなかった部分。
+#[derive(Debug, Serialize)]
+pub struct Fragment {
+    /// 元のファイル内の位置 (バイト)
+    pub offset: usize,
+    pub error: String,
+    pub text: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Salvage {
+    pub books: Vec<Book>,
+    pub quarantined: Vec<Fragment>,
+}
+
+impl Salvage {
+    fn quarantine(&mut self, offset: usize, error: impl Into<String>, text: &str) {
+        self.quarantined.push(Fragment { offset, error: error.into(), text: text.to_string() });
+    }
+}
+
+#[derive(Debug)]
+pub struct RepairReport {
+    pub recovered: usize,
+    pub quarantined: usize,
+    /// 修復前のファイルの複製。修復が要らなかったときや `dry_run` では `None`。
+    pub backup: Option<PathBuf>,
+    pub quarantine_file: Option<PathBuf>,
+}
+
+/// `start` の `{` に対応する `}` の直後の位置。文字列中の括弧は数えない。閉じていなければ `None`。
+fn object_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (pos, &b) in bytes.iter().enumerate().skip(start) {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match b {
+            b'"' => in_string = true,
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(pos + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// 配列の要素を 1 つずつ読み、`Book` として読めたものと読めなかった部分に分ける。
+/// 同じ id が複数あれば先頭のものを残す。
+pub fn salvage(contents: &str) -> Salvage {
+    let bytes = contents.as_bytes();
+    let mut salvage = Salvage::default();
+    let mut seen = HashSet::new();
+
+    let Some(open) = contents.find('[') else {
+        if !contents.trim().is_empty() {
+            salvage.quarantine(0, "not a JSON array", contents);
+        }
+        return salvage;
+    };
+    if !contents[..open].trim().is_empty() {
+        salvage.quarantine(0, "unexpected data before the array", &contents[..open]);
+    }
+
+    let mut pos = open + 1;
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b if b.is_ascii_whitespace() || b == b',' => pos += 1,
+            b']' => {
+                let rest = &contents[pos + 1..];
+                if !rest.trim().is_empty() {
+                    salvage.quarantine(pos + 1, "unexpected data after the array", rest);
+                }
+                return salvage;
+            }
+            b'{' => {
+                let Some(end) = object_end(bytes, pos) else {
+                    salvage.quarantine(pos, "truncated record", &contents[pos..]);
+                    return salvage;
+                };
+
+                let text = &contents[pos..end];
+                match serde_json::from_str::<Book>(text) {
+                    Ok(book) if !seen.insert(book.id) => {
+                        salvage.quarantine(pos, format!("duplicate id {}", book.id), text);
+                    }
+                    Ok(book) => salvage.books.push(book),
+                    Err(e) => salvage.quarantine(pos, e.to_string(), text),
+                }
+                pos = end;
+            }
+            _ => {
+                // オブジェクト以外の値は次のレコードか配列の終わりまで読み飛ばす
+                let end = contents[pos..].find(['{', ']']).map_or(bytes.len(), |n| pos + n);
+                let text = contents[pos..end].trim_end_matches(|c: char| c.is_whitespace() || c == ',');
+                salvage.quarantine(pos, "not a book record", text);
+                pos = end;
+            }
+        }
+    }
+
+    salvage.quarantine(bytes.len(), "missing closing bracket", "");
+    salvage
+}
+
+/// データファイルが読めなければ修復する。`dry_run` ならファイルには触れない。
+pub fn repair(repository: &BookRepository, dry_run: bool) -> Result<RepairReport, BookError> {
+    let raw = repository.read_contents()?;
+    let contents = String::from_utf8_lossy(&raw);
+
+    if let Ok(books) = serde_json::from_str::<Vec<Book>>(&contents) {
+        return Ok(RepairReport { recovered: books.len(), quarantined: 0, backup: None, quarantine_file: None });
+    }
+
+    let salvage = salvage(&contents);
+    let mut report = RepairReport {
+        recovered: salvage.books.len(),
+        quarantined: salvage.quarantined.len(),
+        backup: None,
+        quarantine_file: None,
+    };
+    if dry_run {
+        return Ok(report);
+    }
+
+    let backup = repository.backup()?;
+    let quarantine_file = backup.with_extension("quarantine.json");
+    fs::write(&quarantine_file, repository.seal(serde_json::to_vec_pretty(&salvage.quarantined)?)?)?;
+
+    // 書き込みスレッドは読めないファイルを相手にできないので、直接書き換える
+    repository.write_contents(serde_json::to_vec_pretty(&salvage.books)?)?;
+
+    report.backup = Some(backup);
+    report.quarantine_file = Some(quarantine_file);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_salvage_truncated_file() {
+        let contents = r#"[
+  {"id": 1, "title": "A {braced} \"title\"", "content": "", "tags": ["x"]},
+  {"id": "two", "title": "Bad id", "content": "", "tags": []},
+  42,
+  {"id": 1, "title": "Duplicate", "content": "", "tags": []},
+  {"id": 3, "title": "Ok", "content": "", "tags": []},
+  {"id": 4, "title": "Cut off", "cont"#;
+
+        let salvage = salvage(contents);
+
+        assert_eq!(salvage.books.iter().map(|b| b.id).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(salvage.books[0].title, "A {braced} \"title\"");
+
+        let errors: Vec<&str> = salvage.quarantined.iter().map(|f| f.error.as_str()).collect();
+        assert_eq!(errors.len(), 4);
+        assert_eq!(&errors[1..], ["not a book record", "duplicate id 1", "truncated record"]);
+        assert_eq!(salvage.quarantined[1].text, "42");
+    }
+
+    #[test]
+    fn test_repair_rewrites_data_file() {
+        let dir = std::env::temp_dir().join(format!("books_backend_repair_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("book.json");
+        fs::write(&path, r#"[{"id": 1, "title": "Kept", "content": "", "tags": []}, {"id": 2, "ti"#).unwrap();
+
+        let repository = BookRepository::new(&path);
+        assert!(repository.list().is_err());
+
+        let report = repair(&repository, true).unwrap();
+        assert_eq!((report.recovered, report.quarantined), (1, 1));
+        assert!(report.backup.is_none());
+
+        let report = repair(&repository, false).unwrap();
+        assert!(report.backup.unwrap().exists());
+        assert!(report.quarantine_file.unwrap().exists());
+        assert_eq!(repository.list().unwrap().len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+//! リクエスト ID。
+//!
+//! `X-Request-Id` が付いていればそれを引き継ぎ、なければ生成する。ID はアクセスログに出し、
+//! レスポンスヘッダーにも付ける。エラーの本文にも添えるので、フロントエンドの不具合報告と
+//! サーバーのログを突き合わせられる。テキストの本文には末尾に、JSON のオブジェクトの本文には
+//! `request_id` として足す。圧縮済みの本文には手を付けない。
+
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage};
+use rand::Rng;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// 引き継ぐ ID の最大長。これより長いものや使えない文字を含むものは捨てて生成し直す。
+const MAX_LEN: usize = 128;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn generate() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// ID を添えられるエラーの本文の種類。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ErrorBody {
+    Text,
+    Json,
+}
+
+fn error_body(res: &ServiceResponse<impl MessageBody>) -> Option<ErrorBody> {
+    if res.status().as_u16() < 400 || res.headers().contains_key(header::CONTENT_ENCODING) {
+        return None;
+    }
+
+    let content_type = res.headers().get(header::CONTENT_TYPE).map(|v| v.to_str().unwrap_or(""));
+    let essence = content_type.map(|ct| ct.split(';').next().unwrap_or("").trim().to_ascii_lowercase());
+    match essence.as_deref() {
+        None | Some("text/plain") => Some(ErrorBody::Text),
+        Some(ct) if ct == "application/json" || ct.ends_with("+json") => Some(ErrorBody::Json),
+        _ => None,
+    }
+}
+
+/// 本文に ID を添える。JSON がオブジェクトでなければそのまま返す。
+fn with_id(kind: ErrorBody, mut bytes: Vec<u8>, id: &str) -> Vec<u8> {
+    match kind {
+        ErrorBody::Text => {
+            if !bytes.is_empty() {
+                bytes.extend_from_slice(format!(" (request id: {})", id).as_bytes());
+            }
+            bytes
+        }
+        ErrorBody::Json => match serde_json::from_slice::<serde_json::Value>(&bytes) {
+            Ok(serde_json::Value::Object(mut object)) => {
+                object.insert("request_id".to_string(), id.into());
+                serde_json::to_vec(&object).unwrap_or(bytes)
+            }
+            _ => bytes,
+        },
+    }
+}
+
+/// `middleware::from_fn` に渡すリクエスト ID のミドルウェア。
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let id = req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(generate);
+
+    req.extensions_mut().insert(RequestId(id.clone()));
+
+    let res = next.call(req).await?;
+
+    let mut res = if let Some(kind) = error_body(&res) {
+        let (req, res) = res.into_parts();
+        let (res, body) = res.into_parts();
+
+        let bytes = body::to_bytes(body).await.map_err(|e| {
+            let e: Box<dyn std::error::Error> = e.into();
+            actix_web::error::ErrorInternalServerError(e.to_string())
+        })?.to_vec();
+
+        ServiceResponse::new(req, res.set_body(BoxBody::new(with_id(kind, bytes, &id))))
+    } else {
+        res.map_into_boxed_body()
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid() {
+        assert!(is_valid("3f2a-91_c.0"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("has space"));
+        assert!(!is_valid(&"a".repeat(MAX_LEN + 1)));
+        assert!(is_valid(&generate()));
+    }
+
+    #[test]
+    fn test_with_id() {
+        assert_eq!(with_id(ErrorBody::Text, b"Not found".to_vec(), "abc"), b"Not found (request id: abc)");
+        assert!(with_id(ErrorBody::Text, Vec::new(), "abc").is_empty());
+
+        let body: serde_json::Value = serde_json::from_slice(&with_id(ErrorBody::Json, br#"{"error":"x"}"#.to_vec(), "abc")).unwrap();
+        assert_eq!(body, serde_json::json!({ "error": "x", "request_id": "abc" }));
+        assert_eq!(with_id(ErrorBody::Json, b"[1]".to_vec(), "abc"), b"[1]");
+    }
+}
@@ -0,0 +1,290 @@
+//! 本の `content` に書かれた HTML の無害化。
+//!
+//! フロントエンドが `content` を HTML として表示しても、スクリプトを動かせないようにする。
+//! 許可したタグと属性だけを書き直して残し、`script` や `iframe` などは中身ごと捨てる。
+//! それ以外のタグは外して中のテキストだけ残す。タグにならない `<` は `&lt;` にする。
+//! リンクは http / https / mailto と相対 URL だけを許し、`rel="noopener noreferrer"` を付ける。
+//! 属性値は文字参照をブラウザーと同じ規則で戻してから判定し、戻した値を書き直して残す。
+//!
+//! 無害化した結果をもう一度無害化しても変わらないので、保存のたびにかけてよい。
+
+/// 残すタグ。
+const ALLOWED_TAGS: [&str; 34] = [
+    "a", "abbr", "b", "blockquote", "br", "code", "del", "div", "em", "h1", "h2", "h3", "h4", "h5", "h6",
+    "hr", "i", "ins", "li", "ol", "p", "pre", "s", "small", "span", "strong", "sub", "sup", "table",
+    "tbody", "td", "th", "thead", "tr",
+];
+
+/// 中身ごと捨てるタグ。
+const DROPPED_TAGS: [&str; 14] = [
+    "script", "style", "iframe", "object", "embed", "noscript", "template", "textarea", "title", "xmp",
+    "noembed", "noframes", "svg", "math",
+];
+
+/// どのタグにも残す属性。
+const GLOBAL_ATTRIBUTES: [&str; 2] = ["title", "lang"];
+
+const LINK_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/// 読み取ったタグ。
+struct Tag<'a> {
+    name: String,
+    closing: bool,
+    attributes: Vec<(String, &'a str)>,
+    /// `>` の次の位置
+    end: usize,
+}
+
+/// `input[start..]` が `<` で始まるタグなら読み取る。タグとして読めなければ `None`。
+fn parse_tag(input: &str, start: usize) -> Option<Tag<'_>> {
+    let bytes = input.as_bytes();
+    let mut pos = start + 1;
+
+    let closing = bytes.get(pos) == Some(&b'/');
+    if closing {
+        pos += 1;
+    }
+
+    let name_start = pos;
+    if !bytes.get(pos)?.is_ascii_alphabetic() {
+        return None;
+    }
+    while bytes.get(pos).is_some_and(|b| b.is_ascii_alphanumeric()) {
+        pos += 1;
+    }
+    let name = input[name_start..pos].to_ascii_lowercase();
+
+    let mut attributes = Vec::new();
+    loop {
+        while bytes.get(pos).is_some_and(|b| b.is_ascii_whitespace() || *b == b'/') {
+            pos += 1;
+        }
+        if *bytes.get(pos)? == b'>' {
+            return Some(Tag { name, closing, attributes, end: pos + 1 });
+        }
+
+        let attr_start = pos;
+        while bytes.get(pos).is_some_and(|b| !b.is_ascii_whitespace() && !matches!(b, b'=' | b'>' | b'/')) {
+            pos += 1;
+        }
+        let attr = input[attr_start..pos].to_ascii_lowercase();
+
+        while bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+            pos += 1;
+        }
+        let mut value = "";
+        if bytes.get(pos) == Some(&b'=') {
+            pos += 1;
+            while bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+                pos += 1;
+            }
+            match bytes.get(pos)? {
+                quote @ (b'"' | b'\'') => {
+                    let len = input[pos + 1..].find(*quote as char)?;
+                    value = &input[pos + 1..pos + 1 + len];
+                    pos += len + 2;
+                }
+                _ => {
+                    let value_start = pos;
+                    while bytes.get(pos).is_some_and(|b| !b.is_ascii_whitespace() && *b != b'>') {
+                        pos += 1;
+                    }
+                    value = &input[value_start..pos];
+                }
+            }
+        }
+
+        if !attr.is_empty() {
+            attributes.push((attr, value));
+        }
+    }
+}
+
+/// 属性値の文字参照を、ブラウザーと同じ規則で戻す。
+///
+/// 数値参照は `;` がなくても、先頭に 0 がいくつ続いても読む。範囲外の値は U+FFFD にする。
+/// 名前の参照は URL の判定に関わるものだけを読み、`&amp` のほかは `;` を要る。
+fn decode_entities(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp + 1..];
+
+        if let Some(number) = rest.strip_prefix('#') {
+            let (radix, digits) = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => (16, hex),
+                None => (10, number),
+            };
+            let len = digits.find(|c: char| !c.is_digit(radix)).unwrap_or(digits.len());
+            if len == 0 {
+                out.push('&');
+                continue;
+            }
+            let code = digits[..len].chars().try_fold(0u32, |code, c| {
+                code.checked_mul(radix)?.checked_add(c.to_digit(radix)?)
+            });
+            out.push(code.filter(|c| *c != 0).and_then(char::from_u32).unwrap_or('\u{fffd}'));
+            rest = &digits[len..];
+            rest = rest.strip_prefix(';').unwrap_or(rest);
+            continue;
+        }
+
+        let len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+        let terminated = rest[len..].starts_with(';');
+        let decoded = match rest[..len].to_ascii_lowercase().as_str() {
+            "colon" if terminated => Some(':'),
+            "tab" if terminated => Some('\t'),
+            "newline" if terminated => Some('\n'),
+            "amp" if terminated || !rest[len..].starts_with('=') => Some('&'),
+            _ => None,
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[len + usize::from(terminated)..];
+            }
+            None => out.push('&'),
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// 文字参照を戻したリンク先として許すか。スキームがなければ相対 URL として許す。
+fn is_safe_url(value: &str) -> bool {
+    // ブラウザーは空白や制御文字を読み飛ばすので、除いてから判定する
+    let url: String = value
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    match url.find([':', '/', '?', '#']) {
+        Some(i) if url.as_bytes()[i] == b':' => LINK_SCHEMES.contains(&&url[..i]),
+        _ => true,
+    }
+}
+
+fn escape_attribute(value: &str) -> String {
+    value.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn write_tag(out: &mut String, tag: &Tag) {
+    if tag.closing {
+        out.push_str(&format!("</{}>", tag.name));
+        return;
+    }
+
+    out.push('<');
+    out.push_str(&tag.name);
+    for (attr, value) in &tag.attributes {
+        // 判定した値そのものをブラウザーが読むように、戻した値を書き直す
+        let value = decode_entities(value);
+        let allowed = GLOBAL_ATTRIBUTES.contains(&attr.as_str())
+            || (tag.name == "a" && attr == "href" && is_safe_url(&value));
+        if allowed {
+            out.push_str(&format!(" {}=\"{}\"", attr, escape_attribute(&value)));
+        }
+    }
+    if tag.name == "a" {
+        out.push_str(" rel=\"noopener noreferrer\"");
+    }
+    out.push('>');
+}
+
+/// `name` の終了タグの後ろの位置。なければ `None`。
+fn find_closing(input: &str, from: usize, name: &str) -> Option<usize> {
+    let lower = input[from..].to_ascii_lowercase();
+    let needle = format!("</{}", name);
+    let mut offset = 0;
+
+    while let Some(i) = lower[offset..].find(&needle) {
+        let after = offset + i + needle.len();
+        if lower.as_bytes().get(after).is_none_or(|b| b.is_ascii_whitespace() || matches!(b, b'>' | b'/')) {
+            return Some(from + after + lower[after..].find('>').map_or(lower.len() - after, |end| end + 1));
+        }
+        offset = after;
+    }
+
+    None
+}
+
+/// HTML を無害化する。
+pub fn sanitize_html(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut pos = 0;
+
+    while let Some(lt) = input[pos..].find('<') {
+        let start = pos + lt;
+        out.push_str(&input[pos..start]);
+
+        // コメントは捨てる
+        if input[start..].starts_with("<!--") {
+            pos = input[start + 4..].find("-->").map_or(input.len(), |end| start + 4 + end + 3);
+            continue;
+        }
+
+        let Some(tag) = parse_tag(input, start) else {
+            out.push_str("&lt;");
+            pos = start + 1;
+            continue;
+        };
+
+        pos = tag.end;
+        if DROPPED_TAGS.contains(&tag.name.as_str()) {
+            // `<script/>` も開始タグとして扱われるので、終了タグまで捨てる
+            if !tag.closing {
+                pos = find_closing(input, pos, &tag.name).unwrap_or(input.len());
+            }
+        } else if ALLOWED_TAGS.contains(&tag.name.as_str()) {
+            write_tag(&mut out, &tag);
+        }
+    }
+
+    out.push_str(&input[pos..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_html() {
+        let cases = [
+            ("<p>Hello <b>world</b></p>", "<p>Hello <b>world</b></p>"),
+            ("a < b && c > d", "a &lt; b && c > d"),
+            ("<script>alert(1)</script>ok", "ok"),
+            ("<SCRIPT src=x>alert(1)</script >ok", "ok"),
+            ("<script/>alert(1)", ""),
+            ("<iframe src=\"https://evil.test\"></iframe>", ""),
+            ("<img src=x onerror=alert(1)>", ""),
+            ("<p onclick=\"alert(1)\" title='t'>x</p>", "<p title=\"t\">x</p>"),
+            ("<font color=red>text</font>", "text"),
+            ("<!-- <script>alert(1)</script> -->after", "after"),
+            ("<a href=\"https://example.com\">x</a>", "<a href=\"https://example.com\" rel=\"noopener noreferrer\">x</a>"),
+            ("<a href=\"javascript:alert(1)\">x</a>", "<a rel=\"noopener noreferrer\">x</a>"),
+            ("<a href=\"jav&#x09;ascript&colon;alert(1)\">x</a>", "<a rel=\"noopener noreferrer\">x</a>"),
+            ("<a href=\"&#106avascript:alert(1)\">x</a>", "<a rel=\"noopener noreferrer\">x</a>"),
+            ("<a href=\"&#00000000106;avascript:alert(1)\">x</a>", "<a rel=\"noopener noreferrer\">x</a>"),
+            ("<a href=\"&#x6A&#x61vascript&#58alert(1)\">x</a>", "<a rel=\"noopener noreferrer\">x</a>"),
+            ("<a href=\"javascript&#0000058;alert(1)\">x</a>", "<a rel=\"noopener noreferrer\">x</a>"),
+            ("<a href=\"data:text/html,x\">x</a>", "<a rel=\"noopener noreferrer\">x</a>"),
+            ("<a href=\"/search?q=a&amp;b=&#99\" title=\"&#9999999999;\">x</a>",
+             "<a href=\"/search?q=a&amp;b=c\" title=\"\u{fffd}\" rel=\"noopener noreferrer\">x</a>"),
+            ("<a href=\"/books/1?x=1\" rel=opener>x</a>", "<a href=\"/books/1?x=1\" rel=\"noopener noreferrer\">x</a>"),
+            ("<p title=\"a\" <b>x", "<p title=\"a\">x"),
+            ("<p title=\"unterminated>x", "&lt;p title=\"unterminated>x"),
+        ];
+
+        for (input, expected) in cases {
+            let sanitized = sanitize_html(input);
+            assert_eq!(sanitized, expected, "{}", input);
+            // 何度かけても変わらない
+            assert_eq!(sanitize_html(&sanitized), sanitized, "{}", input);
+        }
+    }
+}
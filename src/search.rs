@@ -0,0 +1,633 @@
+//! `q=` による全文検索。
+//!
+//! `fulltext` フィーチャーを有効にすると Tantivy のインメモリ索引で
+//! スコア順・フレーズ検索・スニペット付きの検索を行う。無効なとき
+//! (または索引の構築に失敗したとき) は title / content の単純な走査にフォールバックする。
+//! どちらも `crate::analysis` のアナライザーで語を切り出せる。索引する文字列と検索語は
+//! `crate::normalize` で正規化してから比べるので、全角・半角や大文字小文字を区別しない。
+//!
+//! スコアは title > タグ > content の順に重くし (`TITLE_WEIGHT` など)、出版年が新しい本ほど
+//! 上乗せする (`recency`)。`/books/fulltext` は各件の `score` を返し、`/books/search` の `q=` も
+//! このスコアの順に並べる。タグの欄には `crate::synonyms` の同義語も含めて索引する。
+
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::analysis::{Analyzer, Token};
+use crate::normalize::{fold, Folded};
+use crate::synonyms::Synonyms;
+use crate::Book;
+
+/// スニペットの前後に含める文字数。
+const SNIPPET_CONTEXT: usize = 40;
+
+/// 1 冊あたりに返す本文の抜粋の数の上限。
+pub const MAX_SNIPPETS: usize = 3;
+
+/// 語が現れた欄ごとの重み。
+const TITLE_WEIGHT: f32 = 3.0;
+const TAGS_WEIGHT: f32 = 2.0;
+const CONTENT_WEIGHT: f32 = 1.0;
+
+/// 今年出版された本のスコアに上乗せする割合。`RECENCY_HALF_LIFE` 年ごとに半分になる。
+const RECENCY_BOOST: f32 = 0.5;
+const RECENCY_HALF_LIFE: f32 = 5.0;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchHit {
+    pub book: Book,
+    pub score: f32,
+    /// 一致箇所を `<b>` で囲んだ HTML 断片。
+    pub snippet: String,
+    /// content の一致箇所ごとの抜粋 (`snippet` と同じ形式)。近くの一致はまとめ、最大 `MAX_SNIPPETS` 件。
+    pub snippets: Vec<String>,
+}
+
+#[derive(Clone)]
+pub enum SearchIndex {
+    Scan(Analyzer),
+    #[cfg(feature = "fulltext")]
+    Tantivy(std::sync::Arc<fulltext::TantivyIndex>),
+}
+
+impl SearchIndex {
+    /// 使える中で最も高機能な索引を作る。
+    pub fn new(analyzer: Analyzer) -> Self {
+        #[cfg(feature = "fulltext")]
+        match fulltext::TantivyIndex::new(analyzer) {
+            Ok(index) => return SearchIndex::Tantivy(std::sync::Arc::new(index)),
+            Err(e) => log::warn!("Failed to create full-text index, falling back to scan: {}", e),
+        }
+
+        SearchIndex::Scan(analyzer)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SearchIndex::Scan(_) => "scan",
+            #[cfg(feature = "fulltext")]
+            SearchIndex::Tantivy(_) => "tantivy",
+        }
+    }
+
+    /// 全件を索引し直す。起動時とデータファイルの再読み込み時に呼ぶ。
+    #[cfg_attr(not(feature = "fulltext"), allow(unused_variables))]
+    pub fn rebuild(&self, books: &[Book], synonyms: &Synonyms) {
+        #[cfg(feature = "fulltext")]
+        if let SearchIndex::Tantivy(index) = self {
+            if let Err(e) = index.rebuild(books, synonyms) {
+                log::error!("Failed to rebuild full-text index: {}", e);
+            }
+        }
+    }
+
+    #[cfg_attr(not(feature = "fulltext"), allow(unused_variables))]
+    pub fn upsert(&self, book: &Book, synonyms: &Synonyms) {
+        #[cfg(feature = "fulltext")]
+        if let SearchIndex::Tantivy(index) = self {
+            if let Err(e) = index.upsert(book, synonyms) {
+                log::error!("Failed to index book {}: {}", book.id, e);
+            }
+        }
+    }
+
+    #[cfg_attr(not(feature = "fulltext"), allow(unused_variables))]
+    pub fn delete(&self, id: u32) {
+        #[cfg(feature = "fulltext")]
+        if let SearchIndex::Tantivy(index) = self {
+            if let Err(e) = index.delete(id) {
+                log::error!("Failed to remove book {} from full-text index: {}", id, e);
+            }
+        }
+    }
+
+    /// スコアの高い順に一致した書籍を返す。`lookup` で id から書籍本体を引く。
+    pub fn search<'a>(&self, books: &'a [Book], lookup: impl Fn(u32) -> Option<&'a Book>, q: &str, synonyms: &Synonyms) -> Vec<SearchHit> {
+        let mut hits = self.find(books, lookup, q, synonyms);
+        boost_recent(&mut hits, OffsetDateTime::now_utc().year());
+        hits
+    }
+
+    #[cfg_attr(not(feature = "fulltext"), allow(unused_variables))]
+    fn find<'a>(&self, books: &'a [Book], lookup: impl Fn(u32) -> Option<&'a Book>, q: &str, synonyms: &Synonyms) -> Vec<SearchHit> {
+        let analyzer = match self {
+            SearchIndex::Scan(analyzer) => *analyzer,
+            #[cfg(feature = "fulltext")]
+            SearchIndex::Tantivy(index) => {
+                match index.search(q, books.len().max(1)) {
+                    Ok(hits) => {
+                        // 抜粋は索引と同じアナライザーで切り出した語から作る
+                        let terms = analyzed_terms(q, index.analyzer);
+                        return hits.into_iter()
+                            .filter_map(|(id, score, snippet)| {
+                                lookup(id).map(|book| {
+                                    let tokens = index.analyzer.tokens(&book.content);
+                                    let ranges: Vec<(usize, usize)> = terms.iter()
+                                        .flat_map(|term| find_tokens(&tokens, term))
+                                        .collect();
+                                    let snippets = excerpts(&book.content, &ranges);
+                                    SearchHit { book: book.clone(), score, snippet, snippets }
+                                })
+                            })
+                            .collect();
+                    }
+                    Err(e) => log::warn!("Full-text query {:?} failed, falling back to scan: {}", q, e),
+                }
+                index.analyzer
+            }
+        };
+
+        if analyzer.is_enabled() {
+            scan_analyzed(books, q, analyzer, synonyms)
+        } else {
+            scan(books, q, synonyms)
+        }
+    }
+}
+
+/// 出版年に応じたスコアの倍率。出版年がなければ 1。
+fn recency(year: Option<i32>, this_year: i32) -> f32 {
+    match year {
+        Some(year) => {
+            let age = this_year.saturating_sub(year).max(0) as f32;
+            1.0 + RECENCY_BOOST * 0.5_f32.powf(age / RECENCY_HALF_LIFE)
+        }
+        None => 1.0,
+    }
+}
+
+/// 新しい本ほどスコアを上げて並べ直す。同点なら元の順序を保つ。
+fn boost_recent(hits: &mut [SearchHit], this_year: i32) {
+    for hit in hits.iter_mut() {
+        hit.score *= recency(hit.book.published_year, this_year);
+    }
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+}
+
+impl Default for SearchIndex {
+    fn default() -> Self {
+        SearchIndex::new(Analyzer::default())
+    }
+}
+
+/// `rust "async fn"` のような文字列を語とフレーズに分ける。
+fn parse_terms(q: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+
+    for (i, part) in q.split('"').enumerate() {
+        if i % 2 == 1 {
+            // 引用符の内側はフレーズとしてそのまま扱う
+            let phrase = part.split_whitespace().collect::<Vec<_>>().join(" ");
+            if !phrase.is_empty() {
+                terms.push(fold(&phrase));
+            }
+        } else {
+            terms.extend(part.split_whitespace().map(fold));
+        }
+    }
+
+    terms
+}
+
+/// `term` が現れる位置 (文字単位) をすべて返す。どちらも正規化したものを渡す。
+fn find_all(text: &[char], term: &[char]) -> Vec<usize> {
+    if term.is_empty() || term.len() > text.len() {
+        return Vec::new();
+    }
+
+    (0..=text.len() - term.len())
+        .filter(|&i| text[i..i + term.len()].iter().zip(term).all(|(a, b)| a == b))
+        .collect()
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// 正規化した `text` で `term` が現れる箇所を、元の文字列のバイト単位の範囲で返す。
+fn find_folded(text: &Folded, chars: &[char], term: &[char]) -> Vec<(usize, usize)> {
+    find_all(chars, term).into_iter()
+        .map(|i| (text.origin(i).0, text.origin(i + term.len() - 1).1))
+        .collect()
+}
+
+/// `chars[start..end]` の `ranges` (文字単位) を `<b>` で囲む。重なる範囲やはみ出す範囲は囲まない。
+fn render(chars: &[char], start: usize, end: usize, ranges: &[(usize, usize)]) -> String {
+    let mut out = String::new();
+    let mut pos = start;
+    for &(from, to) in ranges {
+        if from < pos || to > end {
+            continue;
+        }
+        out.push_str(&escape_html(&chars[pos..from].iter().collect::<String>()));
+        out.push_str("<b>");
+        out.push_str(&escape_html(&chars[from..to].iter().collect::<String>()));
+        out.push_str("</b>");
+        pos = to;
+    }
+    out.push_str(&escape_html(&chars[pos..end].iter().collect::<String>()));
+    out
+}
+
+/// 一致箇所 (元の文字列のバイト単位の範囲) の前後を切り出し、一致した語を `<b>` で囲む。
+/// 前の抜粋に収まる一致箇所はその抜粋で囲み、収まらなければ次の抜粋にする。最大 `MAX_SNIPPETS` 件。
+fn excerpts(text: &str, ranges: &[(usize, usize)]) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let index = |byte: usize| text[..byte].chars().count();
+    let mut ranges: Vec<(usize, usize)> = ranges.iter().map(|&(from, to)| (index(from), index(to))).collect();
+    ranges.sort();
+    ranges.dedup();
+
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < ranges.len() && out.len() < MAX_SNIPPETS {
+        let first = ranges[i].0;
+        let start = first.saturating_sub(SNIPPET_CONTEXT);
+        let end = (first + SNIPPET_CONTEXT).min(chars.len());
+
+        let mut next = i + 1;
+        while next < ranges.len() && ranges[next].1 <= end {
+            next += 1;
+        }
+        out.push(render(&chars, start, end, &ranges[i..next]));
+        i = next;
+    }
+
+    out
+}
+
+/// content の抜粋と、最初の抜粋 (content に一致がなければ title の抜粋)。
+fn snippets(book: &Book, title_ranges: &[(usize, usize)], content_ranges: &[(usize, usize)]) -> (String, Vec<String>) {
+    let snippets = excerpts(&book.content, content_ranges);
+    let snippet = snippets.first().cloned()
+        .or_else(|| excerpts(&book.title, title_ranges).into_iter().next())
+        .unwrap_or_default();
+    (snippet, snippets)
+}
+
+/// 欄ごとの出現回数に重みを掛けて足す。
+fn weighted(title: usize, tags: usize, content: usize) -> f32 {
+    TITLE_WEIGHT * title as f32 + TAGS_WEIGHT * tags as f32 + CONTENT_WEIGHT * content as f32
+}
+
+/// 索引を使わない全件走査。すべての語を title・タグ・content のどれかに含む書籍を、
+/// 重みを掛けた出現回数の多い順に返す。
+fn scan(books: &[Book], q: &str, synonyms: &Synonyms) -> Vec<SearchHit> {
+    let terms: Vec<Vec<char>> = parse_terms(q).iter()
+        .map(|t| t.chars().collect())
+        .collect();
+
+    if terms.is_empty() {
+        return Vec::new();
+    }
+
+    let mut hits: Vec<SearchHit> = books.iter()
+        .filter_map(|book| {
+            let title = Folded::new(&book.title);
+            let content = Folded::new(&book.content);
+            let tags = Folded::new(&synonyms.index_text(&book.tags));
+            let title_chars: Vec<char> = title.text.chars().collect();
+            let content_chars: Vec<char> = content.text.chars().collect();
+            let tags_chars: Vec<char> = tags.text.chars().collect();
+
+            let mut score = 0.0;
+            let mut title_ranges = Vec::new();
+            let mut content_ranges = Vec::new();
+            for term in &terms {
+                let in_title = find_folded(&title, &title_chars, term);
+                let in_content = find_folded(&content, &content_chars, term);
+                let in_tags = find_all(&tags_chars, term).len();
+                if in_title.is_empty() && in_content.is_empty() && in_tags == 0 {
+                    return None;
+                }
+                score += weighted(in_title.len(), in_tags, in_content.len());
+                title_ranges.extend(in_title);
+                content_ranges.extend(in_content);
+            }
+
+            let (snippet, snippets) = snippets(book, &title_ranges, &content_ranges);
+
+            Some(SearchHit { book: book.clone(), score, snippet, snippets })
+        })
+        .collect();
+
+    // 同点なら元の順序を保つ
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits
+}
+
+/// `tokens` の中で `term` の語が続けて現れる箇所 (バイト単位の範囲)。
+fn find_tokens(tokens: &[Token], term: &[String]) -> Vec<(usize, usize)> {
+    if term.is_empty() || term.len() > tokens.len() {
+        return Vec::new();
+    }
+
+    tokens.windows(term.len())
+        .filter(|window| window.iter().zip(term).all(|(token, text)| token.text == *text))
+        .map(|window| (window[0].start, window[window.len() - 1].end))
+        .collect()
+}
+
+/// 語やフレーズごとに、アナライザーで切り出した語の並び。
+fn analyzed_terms(q: &str, analyzer: Analyzer) -> Vec<Vec<String>> {
+    parse_terms(q).iter()
+        .map(|term| analyzer.tokens(term).into_iter().map(|t| t.text).collect::<Vec<_>>())
+        .filter(|term| !term.is_empty())
+        .collect()
+}
+
+/// アナライザーで切り出した語での走査。語やフレーズごとに、切り出した語が title・タグ・content の
+/// どれかに続けて現れる書籍を、`scan` と同じく重みを掛けた出現回数の多い順に返す。
+fn scan_analyzed(books: &[Book], q: &str, analyzer: Analyzer, synonyms: &Synonyms) -> Vec<SearchHit> {
+    let terms = analyzed_terms(q, analyzer);
+
+    if terms.is_empty() {
+        return Vec::new();
+    }
+
+    let mut hits: Vec<SearchHit> = books.iter()
+        .filter_map(|book| {
+            let title = analyzer.tokens(&book.title);
+            let content = analyzer.tokens(&book.content);
+            let tags = analyzer.tokens(&synonyms.index_text(&book.tags));
+
+            let mut score = 0.0;
+            let mut title_ranges = Vec::new();
+            let mut content_ranges = Vec::new();
+            for term in &terms {
+                let in_title = find_tokens(&title, term);
+                let in_content = find_tokens(&content, term);
+                let in_tags = find_tokens(&tags, term).len();
+                if in_title.is_empty() && in_content.is_empty() && in_tags == 0 {
+                    return None;
+                }
+                score += weighted(in_title.len(), in_tags, in_content.len());
+                title_ranges.extend(in_title);
+                content_ranges.extend(in_content);
+            }
+
+            let (snippet, snippets) = snippets(book, &title_ranges, &content_ranges);
+
+            Some(SearchHit { book: book.clone(), score, snippet, snippets })
+        })
+        .collect();
+
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits
+}
+
+#[cfg(feature = "fulltext")]
+mod fulltext {
+    use std::sync::Mutex;
+    use tantivy::collector::TopDocs;
+    use tantivy::query::QueryParser;
+    use tantivy::schema::{Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, Value, FAST, INDEXED, STORED};
+    use tantivy::tokenizer::TextAnalyzer;
+    use tantivy::snippet::SnippetGenerator;
+    use tantivy::{doc, Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+
+    use crate::analysis::tantivy_tokenizer::AnalyzerTokenizer;
+    use crate::analysis::Analyzer;
+    use crate::synonyms::Synonyms;
+    use crate::Book;
+
+    /// 登録するトークナイザーの名前。
+    const TOKENIZER: &str = "books";
+
+    /// IndexWriter に渡すメモリ量の上限。
+    const WRITER_HEAP: usize = 15_000_000;
+
+    pub struct TantivyIndex {
+        index: Index,
+        reader: IndexReader,
+        writer: Mutex<IndexWriter>,
+        id: Field,
+        title: Field,
+        content: Field,
+        tags: Field,
+        pub analyzer: Analyzer,
+    }
+
+    impl TantivyIndex {
+        pub fn new(analyzer: Analyzer) -> tantivy::Result<Self> {
+            // 言語を選ばなくても正規化はするので、いつも自前のトークナイザーを使う
+            let text = TextOptions::default().set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer(TOKENIZER)
+                    .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+            );
+
+            let mut builder = Schema::builder();
+            let id = builder.add_u64_field("id", INDEXED | STORED | FAST);
+            let title = builder.add_text_field("title", text.clone() | STORED);
+            let content = builder.add_text_field("content", text.clone() | STORED);
+            let tags = builder.add_text_field("tags", text);
+
+            let index = Index::create_in_ram(builder.build());
+            index.tokenizers().register(TOKENIZER, TextAnalyzer::from(AnalyzerTokenizer(analyzer)));
+            let reader = index.reader_builder()
+                .reload_policy(ReloadPolicy::Manual)
+                .try_into()?;
+            let writer = Mutex::new(index.writer(WRITER_HEAP)?);
+
+            Ok(TantivyIndex { index, reader, writer, id, title, content, tags, analyzer })
+        }
+
+        fn document(&self, book: &Book, synonyms: &Synonyms) -> TantivyDocument {
+            doc!(
+                self.id => book.id as u64,
+                self.title => book.title.clone(),
+                self.content => book.content.clone(),
+                self.tags => synonyms.index_text(&book.tags),
+            )
+        }
+
+        fn commit(&self, writer: &mut IndexWriter) -> tantivy::Result<()> {
+            writer.commit()?;
+            self.reader.reload()
+        }
+
+        pub fn rebuild(&self, books: &[Book], synonyms: &Synonyms) -> tantivy::Result<()> {
+            let mut writer = self.writer.lock().unwrap();
+
+            writer.delete_all_documents()?;
+            for book in books {
+                writer.add_document(self.document(book, synonyms))?;
+            }
+
+            self.commit(&mut writer)
+        }
+
+        pub fn upsert(&self, book: &Book, synonyms: &Synonyms) -> tantivy::Result<()> {
+            let mut writer = self.writer.lock().unwrap();
+
+            writer.delete_term(Term::from_field_u64(self.id, book.id as u64));
+            writer.add_document(self.document(book, synonyms))?;
+
+            self.commit(&mut writer)
+        }
+
+        pub fn delete(&self, id: u32) -> tantivy::Result<()> {
+            let mut writer = self.writer.lock().unwrap();
+
+            writer.delete_term(Term::from_field_u64(self.id, id as u64));
+
+            self.commit(&mut writer)
+        }
+
+        /// (id, スコア, スニペット) をスコア順に返す。`"..."` でフレーズ検索になる。
+        pub fn search(&self, q: &str, limit: usize) -> tantivy::Result<Vec<(u32, f32, String)>> {
+            let searcher = self.reader.searcher();
+
+            let mut parser = QueryParser::for_index(&self.index, vec![self.title, self.content, self.tags]);
+            parser.set_conjunction_by_default();
+            parser.set_field_boost(self.title, super::TITLE_WEIGHT);
+            parser.set_field_boost(self.tags, super::TAGS_WEIGHT);
+            parser.set_field_boost(self.content, super::CONTENT_WEIGHT);
+            let query = parser.parse_query(q)?;
+
+            let content_snippets = SnippetGenerator::create(&searcher, &*query, self.content)?;
+            let title_snippets = SnippetGenerator::create(&searcher, &*query, self.title)?;
+
+            searcher.search(&query, &TopDocs::with_limit(limit))?
+                .into_iter()
+                .map(|(score, address)| {
+                    let doc: TantivyDocument = searcher.doc(address)?;
+                    let id = doc.get_first(self.id).and_then(|v| v.as_u64()).unwrap_or_default() as u32;
+
+                    let mut snippet = content_snippets.snippet_from_doc(&doc);
+                    if snippet.highlighted().is_empty() {
+                        snippet = title_snippets.snippet_from_doc(&doc);
+                    }
+
+                    Ok((id, score, snippet.to_html()))
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(id: u32, title: &str, content: &str) -> Book {
+        Book { id, title: title.to_string(), content: content.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_scan_ranks_phrases_and_snippets() {
+        let books = vec![
+            book(1, "Rust Basics", "Intro to Rust"),
+            book(2, "Async in Rust", "Handling async <code> in Rust"),
+            book(3, "Traits", "Shared behaviour"),
+        ];
+
+        let hits = scan(&books, "rust", &Synonyms::default());
+        let ids: Vec<u32> = hits.iter().map(|h| h.book.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+
+        let hits = scan(&books, "\"handling async\"", &Synonyms::default());
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "<b>Handling async</b> &lt;code&gt; in Rust");
+
+        assert!(scan(&books, "rust traits", &Synonyms::default()).is_empty());
+
+        // 離れた一致箇所はそれぞれ抜粋にし、近い一致箇所は同じ抜粋にまとめる
+        let content = format!("Rust and rust {} then Rust", "x".repeat(80));
+        let hits = scan(&[book(4, "Notes", &content)], "rust", &Synonyms::default());
+        assert_eq!(hits[0].snippets.len(), 2);
+        assert!(hits[0].snippets[0].starts_with("<b>Rust</b> and <b>rust</b> "));
+        assert!(hits[0].snippets[1].ends_with(" then <b>Rust</b>"));
+        assert_eq!(hits[0].snippet, hits[0].snippets[0]);
+
+        // 全角や大文字の検索語でも、元の表記のまま強調する
+        let hits = scan(&books, "ＩＮＴＲＯ", &Synonyms::default());
+        assert_eq!(hits.iter().map(|h| h.book.id).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(hits[0].snippet, "<b>Intro</b> to Rust");
+        assert!(scan(&books, "  ", &Synonyms::default()).is_empty());
+    }
+
+    #[test]
+    fn test_weights_and_recency() {
+        let mut old = book(1, "Notes", "about rust");
+        old.published_year = Some(2000);
+        let mut tagged = book(2, "Notes", "");
+        tagged.tags = vec!["rust".to_string()];
+        let titled = book(3, "Rust", "");
+        let mut recent = book(4, "Notes", "about rust");
+        recent.published_year = Some(2024);
+        let books = vec![old, tagged, titled, recent];
+
+        let mut hits = scan(&books, "rust", &Synonyms::default());
+        assert_eq!(hits.iter().map(|h| h.book.id).collect::<Vec<_>>(), vec![3, 2, 1, 4]);
+
+        boost_recent(&mut hits, 2024);
+        assert_eq!(hits.iter().map(|h| h.book.id).collect::<Vec<_>>(), vec![3, 2, 4, 1]);
+        assert_eq!(hits[2].score, 1.5);
+        assert!((recency(Some(2019), 2024) - 1.25).abs() < 1e-6);
+        assert_eq!(recency(None, 2024), 1.0);
+    }
+
+    #[test]
+    fn test_scan_with_synonyms() {
+        let mut go = book(1, "Concurrency", "");
+        go.tags = vec!["go".to_string()];
+        let mut golang = book(2, "Web servers", "");
+        golang.tags = vec!["golang".to_string()];
+        let books = vec![go, golang];
+        let synonyms = Synonyms::new(vec![vec!["golang".to_string(), "go".to_string()]]).unwrap();
+
+        let ids = |q: &str, synonyms: &Synonyms| scan(&books, q, synonyms).iter().map(|h| h.book.id).collect::<Vec<_>>();
+        assert_eq!(ids("golang", &Synonyms::default()), vec![2]);
+        assert_eq!(ids("golang", &synonyms), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_scan_with_analyzer() {
+        use crate::analysis::Language;
+
+        let books = vec![
+            book(1, "Rust Basics", "Learning to write programs"),
+            book(2, "非同期プログラミング", "東京都の Rust 勉強会で使った資料"),
+        ];
+        let analyzer = Analyzer::new(&[Language::English, Language::Japanese]);
+
+        let hits = scan_analyzed(&books, "programming", analyzer, &Synonyms::default());
+        assert_eq!(hits.iter().map(|h| h.book.id).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(hits[0].snippet, "Learning to write <b>programs</b>");
+
+        // 文の途中の日本語の語も見つかる
+        let hits = scan_analyzed(&books, "勉強会 rust", analyzer, &Synonyms::default());
+        assert_eq!(hits.iter().map(|h| h.book.id).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(hits[0].snippet, "東京都の <b>Rust</b> <b>勉強会</b>で使った資料");
+
+        assert!(scan_analyzed(&books, "京都 大阪", analyzer, &Synonyms::default()).is_empty());
+    }
+
+    #[cfg(feature = "fulltext")]
+    #[test]
+    fn test_tantivy_with_analyzer() {
+        use crate::analysis::Language;
+
+        let books = vec![
+            book(1, "Rust Basics", "Learning to write programs"),
+            book(2, "非同期プログラミング", "東京都の Rust 勉強会で使った資料"),
+        ];
+        let index = SearchIndex::new(Analyzer::new(&[Language::English, Language::Japanese]));
+        index.rebuild(&books, &Synonyms::default());
+
+        let ids = |q: &str| {
+            index.search(&books, |id| books.iter().find(|b| b.id == id), q, &Synonyms::default())
+                .into_iter()
+                .map(|hit| hit.book.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids("programming"), vec![1]);
+        assert_eq!(ids("勉強会"), vec![2]);
+        assert!(ids("京都大阪").is_empty());
+
+        let hits = index.search(&books, |id| books.iter().find(|b| b.id == id), "勉強会", &Synonyms::default());
+        assert_eq!(hits[0].snippets, vec!["東京都の Rust <b>勉強会</b>で使った資料"]);
+    }
+}
@@ -0,0 +1,249 @@
+//! 開発・負荷試験用のダミーデータ。
+//!
+//! `books seed --count N` と `POST /admin/seed` で、それらしい書籍とユーザーを生成する。
+//! `--seed` を指定すれば同じデータを再現できる。生成したユーザーのパスワードは毎回ランダムで、表示しない。
+//! `POST /admin/seed` は `[storage] seed_endpoint = true` (`SEED_ENDPOINT`) のときだけ使える。
+
+use std::path::Path;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::rngs::{OsRng, StdRng};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::save_user_to;
+use crate::storage::BookRepository;
+use crate::{Book, BookError, Role};
+
+/// 1 回に生成できる書籍の上限。
+pub const MAX_COUNT: usize = 1_000_000;
+
+const ADJECTIVES: [&str; 16] = [
+    "Practical", "Modern", "Effective", "Advanced", "Essential", "Hands-On", "Pragmatic", "Concurrent",
+    "Functional", "Reliable", "Scalable", "Idiomatic", "Secure", "Distributed", "Embedded", "Professional",
+];
+
+const SUBJECTS: [&str; 16] = [
+    "Rust", "Async Programming", "Systems Design", "Databases", "Networking", "Compilers", "Web Services",
+    "Type Systems", "Algorithms", "Operating Systems", "Testing", "Cryptography", "Observability",
+    "Data Engineering", "Game Development", "Machine Learning",
+];
+
+const SUFFIXES: [&str; 8] = [
+    "", "in Action", "Cookbook", "from Scratch", "by Example", "Patterns", "for Beginners", "Deep Dive",
+];
+
+const FIRST_NAMES: [&str; 12] = [
+    "Aiko", "Ben", "Chloe", "Daichi", "Elena", "Felix", "Hana", "Ivan", "Julia", "Kenji", "Lena", "Mateo",
+];
+
+const LAST_NAMES: [&str; 12] = [
+    "Sato", "Miller", "Tanaka", "Garcia", "Suzuki", "Novak", "Kim", "Rossi", "Yamamoto", "Dubois", "Silva", "Ito",
+];
+
+const PUBLISHERS: [&str; 6] = [
+    "O'Reilly", "No Starch Press", "Manning", "Pragmatic Bookshelf", "Addison-Wesley", "技術評論社",
+];
+
+const TAGS: [&str; 14] = [
+    "rust", "async", "web", "database", "network", "security", "testing", "performance", "beginner",
+    "advanced", "design", "tooling", "cloud", "embedded",
+];
+
+const SENTENCES: [&str; 6] = [
+    "This book walks through real-world examples step by step.",
+    "Each chapter ends with exercises that build on the previous one.",
+    "It covers both the fundamentals and the trade-offs seen in production.",
+    "Readers are expected to know the basics of at least one programming language.",
+    "The final part applies everything to a complete sample project.",
+    "Code samples are available online and tested against the latest toolchain.",
+];
+
+#[derive(Clone, Debug, Deserialize, clap::Args)]
+pub struct SeedOptions {
+    /// Number of books to generate
+    #[arg(long, default_value_t = 100)]
+    #[serde(default = "default_count")]
+    pub count: usize,
+    /// Number of users to create (user0001, user0002, ...) with random passwords that are not shown
+    #[arg(long, default_value_t = 0)]
+    #[serde(default)]
+    pub users: usize,
+    /// Seed for the generator, so the same data can be reproduced
+    #[arg(long)]
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Replace the whole dataset instead of appending after the highest id
+    #[arg(long)]
+    #[serde(default)]
+    pub replace: bool,
+}
+
+fn default_count() -> usize {
+    100
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeedReport {
+    pub books: usize,
+    pub first_id: u32,
+    pub users: usize,
+}
+
+/// ISBN-13 (978 始まり) をチェックディジット付きで作る。
+fn isbn(rng: &mut impl Rng) -> String {
+    let mut digits = vec![9, 7, 8];
+    digits.extend((0..9).map(|_| rng.gen_range(0..10)));
+
+    let sum: u32 = digits.iter()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { *d } else { d * 3 })
+        .sum();
+    digits.push((10 - sum % 10) % 10);
+
+    digits.iter().map(|d| char::from(b'0' + *d as u8)).collect()
+}
+
+fn pick<'a>(rng: &mut impl Rng, items: &[&'a str]) -> &'a str {
+    items.choose(rng).copied().unwrap_or_default()
+}
+
+fn book(rng: &mut impl Rng, id: u32) -> Book {
+    let title = format!("{} {} {}", pick(rng, &ADJECTIVES), pick(rng, &SUBJECTS), pick(rng, &SUFFIXES));
+
+    let authors = (0..rng.gen_range(1..=3))
+        .map(|_| format!("{} {}", pick(rng, &FIRST_NAMES), pick(rng, &LAST_NAMES)))
+        .collect();
+
+    let tag_count = rng.gen_range(1..=4);
+    let mut tags: Vec<String> = TAGS.choose_multiple(rng, tag_count).map(|t| t.to_string()).collect();
+    tags.sort();
+
+    let content = (0..rng.gen_range(2..=5))
+        .map(|_| pick(rng, &SENTENCES))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    Book {
+        id,
+        title: title.trim_end().to_string(),
+        content,
+        tags,
+        genres: Vec::new(),
+        authors,
+        published_year: Some(rng.gen_range(1995..=2025)),
+        publisher: Some(pick(rng, &PUBLISHERS).to_string()),
+        isbn: Some(isbn(rng)),
+        language: None,
+        content_stats: None,
+        formats: Vec::new(),
+        audio: None,
+        work: None,
+        loan: None,
+        visibility: None,
+    }
+}
+
+/// `first_id` から連番で `count` 冊を生成する。
+pub fn books(rng: &mut impl Rng, first_id: u32, count: usize) -> Vec<Book> {
+    (0..count).map(|i| book(rng, first_id + i as u32)).collect()
+}
+
+/// ユーザーに付けるパスワード。`--seed` からは決めず、推測できないようにする。
+fn random_password() -> String {
+    let mut bytes = [0u8; 24];
+    OsRng.fill(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// `POST /admin/seed` を受け付けるか。本番のデータにダミーを混ぜないよう、既定では 404 にする。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SeedEndpoint {
+    pub enabled: bool,
+}
+
+/// 書籍とユーザーを生成して保存する。ユーザーは `users_file` に追加し、既存のユーザー名は飛ばす。
+pub fn run(repository: &BookRepository, options: &SeedOptions, users_file: &Path) -> Result<SeedReport, BookError> {
+    if options.count > MAX_COUNT {
+        return Err(BookError::BadRequest(format!("count must be at most {}", MAX_COUNT)));
+    }
+
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    let first_id = if options.replace {
+        1
+    } else {
+        repository.list()?.iter().map(|b| b.id).max().unwrap_or(0) + 1
+    };
+
+    let generated = books(&mut rng, first_id, options.count);
+    if options.replace {
+        repository.replace(generated)?;
+    } else {
+        repository.upsert_many(generated)?;
+    }
+
+    let mut users = 0;
+    for username in (1..=options.users).map(|i| format!("user{:04}", i)) {
+        if save_user_to(users_file, &username, &random_password(), Role::User)? {
+            users += 1;
+        }
+    }
+
+    Ok(SeedReport { books: options.count, first_id, users })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_books() {
+        let a = books(&mut StdRng::seed_from_u64(7), 10, 50);
+        let b = books(&mut StdRng::seed_from_u64(7), 10, 50);
+
+        assert_eq!(a.len(), 50);
+        assert_eq!(a[0].id, 10);
+        assert_eq!(a[49].id, 59);
+        assert_eq!(a.iter().map(|b| &b.title).collect::<Vec<_>>(), b.iter().map(|b| &b.title).collect::<Vec<_>>());
+
+        for book in &a {
+            let isbn = book.isbn.as_deref().unwrap();
+            assert_eq!(isbn.len(), 13);
+            assert!(!book.tags.is_empty() && !book.authors.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_isbn_check_digit() {
+        // 奇数桁に 1、偶数桁に 3 を掛けた和が 10 の倍数になる
+        let isbn = isbn(&mut StdRng::seed_from_u64(1));
+        let digits: Vec<u32> = isbn.chars().map(|c| c.to_digit(10).unwrap()).collect();
+        let sum: u32 = digits.iter().enumerate().map(|(i, d)| if i % 2 == 0 { *d } else { d * 3 }).sum();
+
+        assert_eq!(sum % 10, 0);
+    }
+
+    #[test]
+    fn test_seeded_users_have_random_passwords() {
+        let dir = std::env::temp_dir().join(format!("books_backend_seed_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("book.json"), "[]").unwrap();
+        let users_file = dir.join("users.json");
+
+        let options = SeedOptions { count: 1, users: 2, seed: Some(1), replace: false };
+        let report = run(&BookRepository::new(dir.join("book.json")), &options, &users_file).unwrap();
+        assert_eq!(report.users, 2);
+
+        // `--seed` が同じでも、ユーザー名からもパスワードを推測できない
+        let users = crate::auth::load_users_from(&users_file);
+        assert!(users.iter().all(|u| !crate::auth::verify_password(&u.password, &u.username)));
+        assert_ne!(users[0].password, users[1].password);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,343 @@
+//! 期限付きの共有リンク。
+//!
+//! `POST /books/{id}/share` で、アカウントのない人にも 1 冊だけ見せられる署名付きの URL を発行する。
+//! URL には期限と HMAC の署名が入っていて、期限を過ぎるか `DELETE /books/{id}/share/{share}` で
+//! 取り消すと使えなくなる。
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
+use actix_web::{delete, get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use time::OffsetDateTime;
+
+use crate::auth::authenticate;
+use crate::handlers::block;
+use crate::tenant::Tenant;
+use crate::{AppState, BookError, Role};
+
+pub const DEFAULT_SHARES_FILE: &str = "src/users/shares.json";
+pub const DEFAULT_SHARE_TTL: Duration = Duration::from_secs(24 * 3600);
+/// 指定できる期限の上限。
+pub const MAX_SHARE_TTL: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// 共有リンクのパスの接頭辞。読み取りに認証を求める設定でも、ここは認証なしで通す。
+pub const SHARED_PATH: &str = "/shared/";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Share {
+    pub id: String,
+    pub book_id: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub created_by: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SharesFile {
+    /// base64 で書いた 32 バイトの署名鍵
+    secret: String,
+    shares: Vec<Share>,
+}
+
+/// 発行した共有リンク。取り消したものと期限の過ぎたものは残さない。
+pub struct Shares {
+    file: PathBuf,
+    secret: Vec<u8>,
+    shares: RwLock<Vec<Share>>,
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+fn write_shares(path: &Path, contents: &SharesFile) -> io::Result<()> {
+    crate::private_file::write_private(path, serde_json::to_string_pretty(contents)?.as_bytes())
+}
+
+impl Shares {
+    /// 共有リンクのファイルを読む。なければ署名鍵を作って保存する。
+    pub fn open(file: impl Into<PathBuf>) -> io::Result<Self> {
+        let file = file.into();
+
+        let contents = match fs::read_to_string(&file) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut secret = [0u8; 32];
+                OsRng.fill_bytes(&mut secret);
+                let contents = SharesFile { secret: STANDARD.encode(secret), shares: Vec::new() };
+                write_shares(&file, &contents)?;
+                contents
+            }
+            Err(e) => return Err(e),
+        };
+
+        let secret = STANDARD.decode(&contents.secret)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(Shares { file, secret, shares: RwLock::new(contents.shares) })
+    }
+
+    fn save(&self, shares: &[Share]) -> io::Result<()> {
+        write_shares(&self.file, &SharesFile { secret: STANDARD.encode(&self.secret), shares: shares.to_vec() })
+    }
+
+    fn mac(&self, id: &str, book_id: u32, expires_at: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts any key length");
+        mac.update(format!("{}.{}.{}", id, book_id, expires_at).as_bytes());
+        mac
+    }
+
+    /// 共有リンクの署名。
+    pub fn sign(&self, share: &Share) -> String {
+        URL_SAFE_NO_PAD.encode(self.mac(&share.id, share.book_id, share.expires_at).finalize().into_bytes())
+    }
+
+    pub fn create(&self, book_id: u32, tenant: Option<&str>, created_by: &str, ttl: Duration) -> Result<Share, BookError> {
+        let now = now();
+        let share = Share {
+            id: format!("{:016x}", OsRng.next_u64()),
+            book_id,
+            tenant: tenant.map(str::to_string),
+            created_by: created_by.to_string(),
+            created_at: now,
+            expires_at: now + ttl.as_secs() as i64,
+        };
+
+        let mut shares = self.shares.write().unwrap();
+        shares.retain(|s| s.expires_at > now);
+        shares.push(share.clone());
+        self.save(&shares)?;
+
+        Ok(share)
+    }
+
+    /// `book_id` の期限内の共有リンク。
+    pub fn list(&self, book_id: u32, tenant: Option<&str>) -> Vec<Share> {
+        let now = now();
+
+        self.shares.read().unwrap()
+            .iter()
+            .filter(|s| s.book_id == book_id && s.tenant.as_deref() == tenant && s.expires_at > now)
+            .cloned()
+            .collect()
+    }
+
+    /// `username` が発行した期限内の共有リンク。
+    pub fn created_by(&self, username: &str, tenant: Option<&str>) -> Vec<Share> {
+        let now = now();
+
+        self.shares.read().unwrap()
+            .iter()
+            .filter(|s| s.created_by == username && s.tenant.as_deref() == tenant && s.expires_at > now)
+            .cloned()
+            .collect()
+    }
+
+    /// `username` が発行した共有リンクをすべて取り消し、その数を返す。
+    pub fn revoke_created_by(&self, username: &str, tenant: Option<&str>) -> Result<usize, BookError> {
+        let mut shares = self.shares.write().unwrap();
+        let before = shares.len();
+
+        shares.retain(|s| !(s.created_by == username && s.tenant.as_deref() == tenant));
+        let removed = before - shares.len();
+        if removed > 0 {
+            self.save(&shares)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// 署名と期限を確かめ、取り消されていなければ共有リンクを返す。
+    pub fn verify(&self, id: &str, expires_at: i64, signature: &str, tenant: Option<&str>) -> Option<Share> {
+        if expires_at <= now() {
+            return None;
+        }
+
+        let shares = self.shares.read().unwrap();
+        let share = shares.iter().find(|s| s.id == id && s.expires_at == expires_at && s.tenant.as_deref() == tenant)?;
+
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        self.mac(&share.id, share.book_id, share.expires_at).verify_slice(&signature).ok()?;
+
+        Some(share.clone())
+    }
+
+    /// 共有リンクを取り消す。`allowed` が false を返すものは消さず、`Err(Forbidden)` にする。
+    pub fn revoke(
+        &self,
+        book_id: u32,
+        id: &str,
+        tenant: Option<&str>,
+        allowed: impl Fn(&Share) -> bool,
+    ) -> Result<bool, BookError> {
+        let mut shares = self.shares.write().unwrap();
+
+        let Some(index) = shares.iter().position(|s| s.id == id && s.book_id == book_id && s.tenant.as_deref() == tenant) else {
+            return Ok(false);
+        };
+        if !allowed(&shares[index]) {
+            return Err(BookError::Forbidden);
+        }
+
+        shares.remove(index);
+        self.save(&shares)?;
+        Ok(true)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ShareRequest {
+    expires_in_secs: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct ShareLink {
+    url: String,
+    #[serde(flatten)]
+    share: Share,
+}
+
+fn link(req: &HttpRequest, shares: &Shares, share: Share) -> ShareLink {
+    let path = format!(
+        "{}{}{}?expires={}&sig={}",
+        crate::tenant::path_prefix(req),
+        SHARED_PATH,
+        share.id,
+        share.expires_at,
+        shares.sign(&share),
+    );
+
+    ShareLink { url: crate::proxy::absolute_url(req, &path), share }
+}
+
+/// 本の共有リンクを発行する。期限は `expires_in_secs` (既定 1 日、最長 30 日)。
+#[post("/books/{id}/share")]
+pub async fn share_book(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    shares: Option<web::Data<Shares>>,
+    id: web::Path<u32>,
+    body: Option<web::Json<ShareRequest>>,
+) -> Result<impl Responder, BookError> {
+    let shares = shares.ok_or(BookError::NotFound)?;
+    let user = authenticate(&req)?;
+    let id = id.into_inner();
+
+    block(&data.repository, move |r| r.get(id)).await?.ok_or(BookError::NotFound)?;
+
+    let ttl = body.and_then(|body| body.expires_in_secs)
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_SHARE_TTL);
+    if ttl.is_zero() || ttl > MAX_SHARE_TTL {
+        return Err(BookError::BadRequest(format!("expires_in_secs must be between 1 and {}", MAX_SHARE_TTL.as_secs())));
+    }
+
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+    let share = shares.create(id, tenant.as_deref(), &user.username, ttl)?;
+    log::info!("Book {} shared by {} until {}", id, user.username, share.expires_at);
+
+    Ok(HttpResponse::Created().json(link(&req, &shares, share)))
+}
+
+#[get("/books/{id}/shares")]
+pub async fn list_shares(
+    req: HttpRequest,
+    shares: Option<web::Data<Shares>>,
+    id: web::Path<u32>,
+) -> Result<impl Responder, BookError> {
+    let shares = shares.ok_or(BookError::NotFound)?;
+    authenticate(&req)?;
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+
+    Ok(HttpResponse::Ok().json(shares.list(id.into_inner(), tenant.as_deref())))
+}
+
+/// 共有リンクを取り消す。発行した本人か管理者だけができる。
+#[delete("/books/{id}/share/{share}")]
+pub async fn revoke_share(
+    req: HttpRequest,
+    shares: Option<web::Data<Shares>>,
+    path: web::Path<(u32, String)>,
+) -> Result<impl Responder, BookError> {
+    let shares = shares.ok_or(BookError::NotFound)?;
+    let user = authenticate(&req)?;
+    let (book_id, share_id) = path.into_inner();
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+
+    let allowed = |share: &Share| user.role == Role::Admin || share.created_by == user.username;
+    if !shares.revoke(book_id, &share_id, tenant.as_deref(), allowed)? {
+        return Err(BookError::NotFound);
+    }
+    log::info!("Share {} of book {} revoked by {}", share_id, book_id, user.username);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize)]
+pub struct SharedQuery {
+    expires: i64,
+    sig: String,
+}
+
+/// 共有リンクで本を見る。認証はいらない。期限切れ・取り消し・署名の誤りはすべて 404 にする。
+#[get("/shared/{share}")]
+pub async fn get_shared_book(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    shares: Option<web::Data<Shares>>,
+    share: web::Path<String>,
+    query: web::Query<SharedQuery>,
+) -> Result<impl Responder, BookError> {
+    let shares = shares.ok_or(BookError::NotFound)?;
+    let tenant = req.extensions().get::<Tenant>().map(|t| t.name.clone());
+
+    let share = shares.verify(&share, query.expires, &query.sig, tenant.as_deref()).ok_or(BookError::NotFound)?;
+    let mut book = block(&data.repository, move |r| r.get(share.book_id)).await?.ok_or(BookError::NotFound)?;
+    // リンクを知っていれば誰でも見られるので、誰が借りているかは見せない
+    book.loan = None;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "private, no-store"))
+        .json(book))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify_revoke() {
+        let path = std::env::temp_dir().join(format!("books_backend_shares_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let shares = Shares::open(&path).unwrap();
+
+        let share = shares.create(3, None, "alice", DEFAULT_SHARE_TTL).unwrap();
+        let signature = shares.sign(&share);
+
+        // 読み直しても同じ鍵で確かめられる
+        let shares = Shares::open(&path).unwrap();
+        assert_eq!(shares.verify(&share.id, share.expires_at, &signature, None).unwrap().book_id, 3);
+
+        // 期限を書き換えたもの、別のテナントからのものは通さない
+        assert!(shares.verify(&share.id, share.expires_at + 3600, &signature, None).is_none());
+        assert!(shares.verify(&share.id, share.expires_at, &signature, Some("acme")).is_none());
+        assert!(shares.verify(&share.id, share.expires_at, "AAAA", None).is_none());
+
+        assert!(matches!(shares.revoke(3, &share.id, None, |s| s.created_by == "bob"), Err(BookError::Forbidden)));
+        assert!(shares.revoke(3, &share.id, None, |_| true).unwrap());
+        assert!(shares.verify(&share.id, share.expires_at, &signature, None).is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
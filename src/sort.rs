@@ -0,0 +1,168 @@
+//! `?sort=` による並べ替え。
+//!
+//! `sort=author,-published_year,title` のように、カンマで区切ったフィールド名を優先度の高い順に並べる。
+//! 先頭の `-` は降順。使えるフィールドは `id` / `title` / `author` (先頭の著者) / `publisher` /
+//! `published_year` / `isbn`。文字列は `crate::normalize` で正規化してから比べ、値のない書籍は
+//! 昇順でも降順でも最後に置く。すべてのキーが同じなら元の順序 (ファイルの順や検索のスコア順) を保つ。
+//! 知らないフィールド名や空の項目は 400。
+
+use std::cmp::Ordering;
+use serde::Deserialize;
+
+use crate::normalize::fold;
+use crate::{Book, BookError};
+
+#[derive(Deserialize, Default)]
+pub struct SortOptions {
+    sort: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Id,
+    Title,
+    Author,
+    Publisher,
+    PublishedYear,
+    Isbn,
+}
+
+const FIELDS: [(&str, Field); 6] = [
+    ("id", Field::Id),
+    ("title", Field::Title),
+    ("author", Field::Author),
+    ("publisher", Field::Publisher),
+    ("published_year", Field::PublishedYear),
+    ("isbn", Field::Isbn),
+];
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Value {
+    Number(i64),
+    Text(String),
+}
+
+impl Field {
+    fn value(self, book: &Book) -> Option<Value> {
+        match self {
+            Field::Id => Some(Value::Number(book.id.into())),
+            Field::Title => Some(Value::Text(fold(&book.title))),
+            Field::Author => book.authors.first().map(|a| Value::Text(fold(a))),
+            Field::Publisher => book.publisher.as_deref().map(|p| Value::Text(fold(p))),
+            Field::PublishedYear => book.published_year.map(|y| Value::Number(y.into())),
+            Field::Isbn => book.isbn.as_deref().map(|i| Value::Text(fold(i))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Key {
+    field: Field,
+    descending: bool,
+}
+
+/// 読んだ `sort=`。指定がなければ何もしない。
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Sort(Vec<Key>);
+
+impl SortOptions {
+    pub fn parse(&self) -> Result<Sort, BookError> {
+        let Some(sort) = self.sort.as_deref() else {
+            return Ok(Sort::default());
+        };
+
+        sort.split(',')
+            .map(|item| {
+                let item = item.trim();
+                let (descending, name) = match item.strip_prefix('-') {
+                    Some(name) => (true, name),
+                    None => (false, item),
+                };
+                FIELDS.iter()
+                    .find(|(known, _)| *known == name)
+                    .map(|&(_, field)| Key { field, descending })
+                    .ok_or_else(|| {
+                        let known: Vec<&str> = FIELDS.iter().map(|(known, _)| *known).collect();
+                        BookError::BadRequest(format!("sort: unknown field {:?} (use {})", name, known.join(", ")))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Sort)
+    }
+}
+
+impl Sort {
+    /// キーの順に安定ソートする。
+    pub fn apply(&self, books: &mut Vec<Book>) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        let mut keyed: Vec<(Vec<Option<Value>>, Book)> = std::mem::take(books)
+            .into_iter()
+            .map(|book| (self.0.iter().map(|key| key.field.value(&book)).collect(), book))
+            .collect();
+
+        keyed.sort_by(|(a, _), (b, _)| {
+            self.0.iter().zip(a.iter().zip(b))
+                .map(|(key, (a, b))| match (a, b) {
+                    (Some(a), Some(b)) if key.descending => b.cmp(a),
+                    (Some(a), Some(b)) => a.cmp(b),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+
+        books.extend(keyed.into_iter().map(|(_, book)| book));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(id: u32, title: &str, author: Option<&str>, year: Option<i32>) -> Book {
+        Book {
+            id,
+            title: title.to_string(),
+            authors: author.map(|a| vec![a.to_string()]).unwrap_or_default(),
+            published_year: year,
+            ..Default::default()
+        }
+    }
+
+    fn sorted(sort: &str) -> Vec<u32> {
+        let mut books = vec![
+            book(1, "rust", Some("Klabnik"), Some(2018)),
+            book(2, "Async", Some("klabnik"), Some(2023)),
+            book(3, "Zero", None, Some(2023)),
+            book(4, "Basics", Some("Blandy"), None),
+            book(5, "Again", Some("Klabnik"), Some(2018)),
+        ];
+        SortOptions { sort: Some(sort.to_string()) }.parse().unwrap().apply(&mut books);
+        books.iter().map(|b| b.id).collect()
+    }
+
+    #[test]
+    fn test_sort() {
+        assert_eq!(sorted("author,-published_year,title"), vec![4, 2, 5, 1, 3]);
+        assert_eq!(sorted("-published_year"), vec![2, 3, 1, 5, 4]);
+        assert_eq!(sorted("title"), vec![5, 2, 4, 1, 3]);
+        // 同じ値なら元の順序を保つ
+        assert_eq!(sorted("author"), vec![4, 1, 2, 5, 3]);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(SortOptions::default().parse().unwrap(), Sort::default());
+        assert_eq!(
+            SortOptions { sort: Some("-id, title".to_string()) }.parse().unwrap(),
+            Sort(vec![Key { field: Field::Id, descending: true }, Key { field: Field::Title, descending: false }])
+        );
+        assert!(SortOptions { sort: Some("price".to_string()) }.parse().is_err());
+        assert!(SortOptions { sort: Some("title,".to_string()) }.parse().is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! 検索で 1 件も見つからなかったときの「もしかして」。
+//!
+//! 検索語のうちタイトルとタグに出てこない語を、編集距離 (隣り合う文字の入れ替えも 1 と数える) の
+//! 近い語に置き換えた検索式を作る。4 文字以下の語は距離 1、それより長い語は距離 2 まで。
+//! `tag:` と `title:` の値も直すが、ほかのフィールドや `-` で否定した語はそのまま残す。
+//!
+//! 候補は `X-Did-You-Mean` ヘッダーで返す。それぞれそのまま `q=` に使えるようにパーセントエンコードし、
+//! カンマで区切る。
+
+use std::collections::HashMap;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::HttpResponse;
+
+use crate::normalize::fold;
+use crate::query::split_words;
+use crate::Book;
+
+/// 返す候補の数の上限。
+pub const MAX_SUGGESTIONS: usize = 3;
+
+pub const DID_YOU_MEAN: HeaderName = HeaderName::from_static("x-did-you-mean");
+
+/// 候補に使う語と、その語が出てくる書籍の数。
+#[derive(Default)]
+pub struct Vocabulary {
+    words: HashMap<String, usize>,
+}
+
+fn words(text: &str) -> Vec<String> {
+    fold(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// 隣り合う文字の入れ替えも 1 回と数える編集距離。`max` を超えたら `None`。
+/// 長さの差だけで `max` を超えるものは表を作らずに除く。
+fn distance(a: &[char], b: &[char], max: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    let mut rows = vec![(0..=b.len()).collect::<Vec<usize>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1).min(row[j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+
+    Some(rows[a.len()][b.len()]).filter(|&d| d <= max)
+}
+
+impl Vocabulary {
+    pub fn new<'a>(books: impl IntoIterator<Item = &'a Book>) -> Self {
+        let mut vocabulary = Vocabulary::default();
+
+        for book in books {
+            let mut seen: Vec<String> = words(&book.title)
+                .into_iter()
+                .chain(book.tags.iter().flat_map(|tag| words(tag)))
+                .collect();
+            seen.sort();
+            seen.dedup();
+            for word in seen {
+                *vocabulary.words.entry(word).or_insert(0) += 1;
+            }
+        }
+
+        vocabulary
+    }
+
+    /// `word` に近い語を、距離の近い順 (同じなら書籍の多い順) に返す。知っている語なら空。
+    fn corrections(&self, word: &str) -> Vec<&str> {
+        let word = fold(word);
+        if self.words.contains_key(&word) || word.chars().any(|c| !c.is_alphanumeric()) {
+            return Vec::new();
+        }
+
+        let chars: Vec<char> = word.chars().collect();
+        let max = if chars.len() <= 4 { 1 } else { 2 };
+
+        let mut candidates: Vec<(usize, usize, &str)> = self.words.iter()
+            .filter_map(|(candidate, &count)| {
+                let candidate_chars: Vec<char> = candidate.chars().collect();
+                distance(&chars, &candidate_chars, max).map(|d| (d, count, candidate.as_str()))
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.cmp(&a.1)).then_with(|| a.2.cmp(b.2)));
+
+        candidates.into_iter().take(MAX_SUGGESTIONS).map(|(_, _, word)| word).collect()
+    }
+
+    /// 直した検索式を最大 `MAX_SUGGESTIONS` 件返す。直せる語がなければ空。
+    /// n 件目の候補は、直せる語をそれぞれ n 番目に近い語 (なければ最も近い語) に置き換えたもの。
+    pub fn did_you_mean(&self, q: &str) -> Vec<String> {
+        let parts: Vec<(String, Vec<&str>)> = split_words(q)
+            .into_iter()
+            .map(|word| {
+                let (prefix, value) = match word.split_once(':') {
+                    Some((field, value)) if matches!(field.to_ascii_lowercase().as_str(), "tag" | "title") => {
+                        (format!("{}:", field), value.to_string())
+                    }
+                    Some(_) => return (word, Vec::new()),
+                    None if word.starts_with('-') => return (word, Vec::new()),
+                    None => (String::new(), word.clone()),
+                };
+                let corrections = self.corrections(&value);
+                (if corrections.is_empty() { word } else { prefix }, corrections)
+            })
+            .collect();
+
+        let most = parts.iter().map(|(_, corrections)| corrections.len()).max().unwrap_or(0);
+        let mut suggestions: Vec<String> = Vec::new();
+        for n in 0..most {
+            let suggestion = parts.iter()
+                .map(|(word, corrections)| match corrections.get(n).or(corrections.first()) {
+                    Some(correction) => format!("{}{}", word, correction),
+                    None => word.clone(),
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            if !suggestions.contains(&suggestion) {
+                suggestions.push(suggestion);
+            }
+        }
+
+        suggestions
+    }
+}
+
+/// 候補があれば `X-Did-You-Mean` を付ける。
+pub fn insert_header(resp: &mut HttpResponse, suggestions: &[String]) {
+    if suggestions.is_empty() {
+        return;
+    }
+
+    let encoded: Vec<String> = suggestions.iter()
+        .filter_map(|q| serde_urlencoded::to_string([("q", q)]).ok())
+        .map(|pair| pair.trim_start_matches("q=").to_string())
+        .collect();
+    if let Ok(value) = HeaderValue::from_str(&encoded.join(", ")) {
+        resp.headers_mut().insert(DID_YOU_MEAN, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(title: &str, tags: &[&str]) -> Book {
+        Book {
+            title: title.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_distance() {
+        let d = |a: &str, b: &str| distance(&a.chars().collect::<Vec<_>>(), &b.chars().collect::<Vec<_>>(), 2);
+        assert_eq!(d("rust", "rust"), Some(0));
+        assert_eq!(d("rsut", "rust"), Some(1));
+        assert_eq!(d("asnyc", "async"), Some(1));
+        assert_eq!(d("memroy", "memory"), Some(1));
+        assert_eq!(d("kitten", "sitting"), None);
+    }
+
+    #[test]
+    fn test_did_you_mean() {
+        let books = [
+            book("Async in Rust", &["async", "tokio"]),
+            book("Rust Basics", &["beginner"]),
+            book("Ruby on Rails", &["ruby"]),
+        ];
+        let vocabulary = Vocabulary::new(&books);
+
+        assert_eq!(vocabulary.did_you_mean("rsut"), vec!["rust"]);
+        assert_eq!(vocabulary.did_you_mean("asnyc rust"), vec!["async rust"]);
+        assert_eq!(vocabulary.did_you_mean("tag:tokoi -rsut"), vec!["tag:tokio -rsut"]);
+        assert_eq!(vocabulary.did_you_mean("rubt"), vec!["rust", "ruby"]);
+        assert!(vocabulary.did_you_mean("rust").is_empty());
+        assert!(vocabulary.did_you_mean("zzzzzz").is_empty());
+    }
+}
@@ -0,0 +1,60 @@
+//! 蔵書全体の集計 (`/stats`)。
+//!
+//! 形式ごとの書籍数と、本文の語数・文字数・読む時間の目安 (`crate::reading`) の合計を返す。
+//! 複数の形式を持つ書籍はそれぞれの形式で数えるので、形式ごとの数を足すと書籍数より多くなることがある。
+
+use serde::Serialize;
+
+use crate::reading::{self, ContentStats};
+use crate::{Book, Format};
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct FormatCount {
+    pub format: Format,
+    pub count: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Stats {
+    pub books: usize,
+    /// `Format::ALL` の順に、その形式を持つ書籍数 (0 も含める)
+    pub formats: Vec<FormatCount>,
+    /// 形式が 1 つも記録されていない書籍数
+    pub unknown_format: usize,
+    /// 全書籍の本文の合計
+    pub content: ContentStats,
+}
+
+pub fn stats(books: &[Book]) -> Stats {
+    Stats {
+        books: books.len(),
+        formats: Format::ALL.into_iter()
+            .map(|format| FormatCount { format, count: books.iter().filter(|b| b.formats.contains(&format)).count() })
+            .collect(),
+        unknown_format: books.iter().filter(|b| b.formats.is_empty()).count(),
+        content: books.iter().filter_map(reading::of).fold(ContentStats::default(), |total, stats| ContentStats {
+            words: total.words + stats.words,
+            characters: total.characters + stats.characters,
+            reading_minutes: total.reading_minutes + stats.reading_minutes,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let book = |id, formats: &[Format]| Book { id, formats: formats.to_vec(), ..Default::default() };
+        let mut books = [book(1, &[Format::Hardcover, Format::Ebook]), book(2, &[Format::Ebook]), book(3, &[])];
+        books[0].content = "Intro to Rust".to_string();
+        books[1].content_stats = Some(ContentStats { words: 460, characters: 2000, reading_minutes: 2 });
+
+        let stats = stats(&books);
+        assert_eq!(stats.books, 3);
+        assert_eq!(stats.formats.iter().map(|c| c.count).collect::<Vec<_>>(), vec![1, 0, 2, 0]);
+        assert_eq!(stats.unknown_format, 1);
+        assert_eq!(stats.content, ContentStats { words: 463, characters: 2011, reading_minutes: 3 });
+    }
+}
@@ -0,0 +1,1392 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, RwLock};
+use std::time::{Instant, SystemTime};
+use time::macros::format_description;
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+use crate::analysis::Analyzer;
+use crate::audio::{self, Audio};
+use crate::authors::{self, Author, Authors, Edit};
+use crate::bulk_tags::{BulkTagReport, TagChange};
+use crate::events::{BookEventKind, EventBus};
+use crate::genres::{self, Genres};
+use crate::language;
+use crate::normalize::fold;
+use crate::pattern::{self, Pattern};
+use crate::publishers::{self, Publisher, Publishers};
+use crate::query::SearchQuery;
+use crate::reading;
+use crate::registry;
+use crate::quotes::{self, Quote, Quotes};
+use crate::search::{SearchHit, SearchIndex};
+use crate::spelling::Vocabulary;
+use crate::suggest::{SuggestIndex, Suggestion};
+use crate::synonyms::{self, Preview, Synonyms};
+use crate::tag_tree;
+use crate::works;
+use crate::sync::{self, LocalChange, SyncRequest, SyncResponse};
+use crate::{Book, BookError, BookQuery};
+use writer::{Change, Job, Outcome};
+
+mod crypto;
+mod journal;
+mod writer;
+
+pub use crypto::EncryptionKey;
+pub use journal::{BookChange, Changes, Since};
+
+/// パース済みのデータファイルと、id → 位置 / タグ → id の索引。
+struct Snapshot {
+    books: Vec<Book>,
+    index: HashMap<u32, usize>,
+    tags: HashMap<String, Vec<u32>>,
+    /// 正規化したタグ → id。`tag=` の絞り込みに使う。子孫のタグは続けて並ぶ
+    folded_tags: BTreeMap<String, Vec<u32>>,
+    suggestions: SuggestIndex,
+    modified: Option<SystemTime>,
+}
+
+impl Snapshot {
+    fn new(books: Vec<Book>, modified: Option<SystemTime>) -> Self {
+        let mut index = HashMap::with_capacity(books.len());
+        let mut tags: HashMap<String, Vec<u32>> = HashMap::new();
+        let mut folded_tags: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+
+        for (pos, book) in books.iter().enumerate() {
+            // id が重複していたら先頭のものを優先する (従来の線形探索と同じ挙動)
+            if index.contains_key(&book.id) {
+                continue;
+            }
+            index.insert(book.id, pos);
+
+            for tag in &book.tags {
+                for ids in [tags.entry(tag.clone()).or_default(), folded_tags.entry(fold(tag)).or_default()] {
+                    if ids.last() != Some(&book.id) {
+                        ids.push(book.id);
+                    }
+                }
+            }
+        }
+
+        let suggestions = SuggestIndex::new(&books);
+
+        Snapshot { books, index, tags, folded_tags, suggestions, modified }
+    }
+
+    fn get(&self, id: u32) -> Option<&Book> {
+        self.index.get(&id).map(|&pos| &self.books[pos])
+    }
+}
+
+/// [`BookRepository::iter`] が返すイテレーター。
+pub struct Books {
+    snapshot: Arc<Snapshot>,
+    pos: usize,
+}
+
+impl Iterator for Books {
+    type Item = Book;
+
+    fn next(&mut self) -> Option<Book> {
+        let book = self.snapshot.books.get(self.pos)?.clone();
+        self.pos += 1;
+        Some(book)
+    }
+}
+
+/// バイナリに埋め込んだデモ用のデータ。
+const DEMO_DATA: &str = include_str!("data/book.json");
+
+/// データファイルがないときに用意する内容。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InitialData {
+    /// 埋め込みのデモデータ
+    #[default]
+    Demo,
+    /// 空の書庫
+    Empty,
+    /// 作らない。データファイルがなければ読み込みに失敗する。
+    None,
+}
+
+impl FromStr for InitialData {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "demo" => Ok(InitialData::Demo),
+            "empty" => Ok(InitialData::Empty),
+            "none" => Ok(InitialData::None),
+            _ => Err(()),
+        }
+    }
+}
+
+/// データファイルの親ディレクトリを作り、ファイルがなければ `initial` の内容で作る。作ったら true。
+///
+/// `src/data` をマウントしないコンテナでも、そのまま起動できるようにするため。
+pub fn bootstrap(data_file: &Path, initial: InitialData) -> io::Result<bool> {
+    if let Some(dir) = data_file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let contents = match initial {
+        InitialData::Demo => DEMO_DATA,
+        InitialData::Empty => "[]\n",
+        InitialData::None => return Ok(false),
+    };
+
+    // 同時に起動したプロセスと競合しても、先に作った方を残す
+    match fs::OpenOptions::new().write(true).create_new(true).open(data_file) {
+        Ok(mut file) => {
+            file.write_all(contents.as_bytes())?;
+            Ok(true)
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// `path` を一時ファイル経由で差し替える。
+fn replace_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+/// 平文のデータファイルを `key` で暗号化し直す。暗号化したら true。
+///
+/// 鍵を設定しただけでは次の書き込みまで平文のまま残るので、起動時に呼ぶ。
+pub fn encrypt_file(data_file: &Path, key: &EncryptionKey) -> Result<bool, BookError> {
+    let contents = fs::read(data_file)?;
+    if crypto::is_encrypted(&contents) {
+        return Ok(false);
+    }
+
+    replace_file(data_file, &key.encrypt(contents)?)?;
+    Ok(true)
+}
+
+/// ストレージの動作に関する設定。
+#[derive(Clone, Debug, Default)]
+pub struct StorageOptions {
+    /// データファイルをメモリマップして読む。数百 MB のファイルで `read_to_string` の複製を避けられる。
+    pub mmap: bool,
+    /// 設定するとデータファイルを暗号化して書く。暗号化されたファイルを読むのにも要る。
+    pub encryption_key: Option<EncryptionKey>,
+    /// 保存する前に `content` の HTML を無害化する (`crate::sanitize`)。
+    pub sanitize_content: bool,
+    /// 全文検索の語の切り出し方 (`crate::analysis`)。
+    pub analyzer: Analyzer,
+}
+
+/// データファイルとキャッシュ。リポジトリと書き込みスレッドで共有する。
+#[derive(Clone)]
+struct Store {
+    data_file: PathBuf,
+    options: StorageOptions,
+    events: EventBus,
+    journal: Arc<journal::Journal>,
+    /// タグの同義語。書き込みスレッドだけが差し替える
+    synonyms: Arc<RwLock<Arc<Synonyms>>>,
+    /// ジャンルの一覧。書き込みスレッドだけが差し替える
+    genres: Arc<RwLock<Arc<Genres>>>,
+    /// 著者の記録。書き込みスレッドだけが差し替える
+    authors: Arc<RwLock<Arc<Authors>>>,
+    /// 出版社の記録。書き込みスレッドだけが差し替える
+    publishers: Arc<RwLock<Arc<Publishers>>>,
+    /// 引用。書き込みスレッドだけが差し替える
+    quotes: Arc<RwLock<Arc<Quotes>>>,
+    cache: Arc<RwLock<Option<Arc<Snapshot>>>>,
+    search_index: SearchIndex,
+}
+
+impl Store {
+    fn synonyms(&self) -> Arc<Synonyms> {
+        Arc::clone(&self.synonyms.read().unwrap())
+    }
+
+    fn genres(&self) -> Arc<Genres> {
+        Arc::clone(&self.genres.read().unwrap())
+    }
+
+    fn authors(&self) -> Arc<Authors> {
+        Arc::clone(&self.authors.read().unwrap())
+    }
+
+    fn publishers(&self) -> Arc<Publishers> {
+        Arc::clone(&self.publishers.read().unwrap())
+    }
+
+    fn quotes(&self) -> Arc<Quotes> {
+        Arc::clone(&self.quotes.read().unwrap())
+    }
+
+    fn modified(&self) -> Result<Option<SystemTime>, BookError> {
+        Ok(fs::metadata(&self.data_file)?.modified().ok())
+    }
+
+    /// キャッシュ済みのデータを返す。ファイルが外部で更新されていれば読み直す。
+    fn snapshot(&self) -> Result<Arc<Snapshot>, BookError> {
+        let modified = self.modified()?;
+
+        if let Some(snapshot) = self.cache.read().unwrap().as_ref() {
+            if modified.is_some() && snapshot.modified == modified {
+                return Ok(Arc::clone(snapshot));
+            }
+        }
+
+        let books = self.read()?;
+
+        let snapshot = Arc::new(Snapshot::new(books, modified));
+        *self.cache.write().unwrap() = Some(Arc::clone(&snapshot));
+
+        // 起動直後や外部からの編集で読み直したときは全文検索の索引も作り直す
+        self.search_index.rebuild(&snapshot.books, &self.synonyms());
+
+        Ok(snapshot)
+    }
+
+    #[tracing::instrument(skip(self), fields(mmap = self.options.mmap))]
+    fn read(&self) -> Result<Vec<Book>, BookError> {
+        if self.options.mmap {
+            let file = fs::File::open(&self.data_file)?;
+            // SAFETY: write() は一時ファイルを rename で差し替えるので、マップ中の内容が
+            // 切り詰められることはない。外部のエディタがその場で書き換えた場合は保証できない。
+            let map = unsafe { memmap2::Mmap::map(&file)? };
+            if !crypto::is_encrypted(&map) {
+                return Ok(serde_json::from_slice(&map)?);
+            }
+        }
+
+        Ok(serde_json::from_slice(&self.read_contents()?)?)
+    }
+
+    /// データファイルの中身。暗号化されていれば復号する。
+    fn read_contents(&self) -> Result<Vec<u8>, BookError> {
+        crypto::open(self.options.encryption_key.as_ref(), fs::read(&self.data_file)?)
+    }
+
+    /// 鍵があれば暗号化して書く。
+    fn write_contents(&self, contents: Vec<u8>) -> Result<(), BookError> {
+        let contents = crypto::seal(self.options.encryption_key.as_ref(), contents)?;
+        Ok(replace_file(&self.data_file, &contents)?)
+    }
+
+    /// ファイルに書き出し、同じ内容でキャッシュも差し替える。
+    ///
+    /// 一時ファイルに書いてから rename するので、読み込み中のファイルが途中で切れることはない。
+    #[tracing::instrument(skip_all, fields(count = books.len()))]
+    fn write(&self, books: Vec<Book>) -> Result<Arc<Snapshot>, BookError> {
+        self.write_contents(serde_json::to_vec_pretty(&books)?)?;
+
+        let snapshot = Arc::new(Snapshot::new(books, self.modified()?));
+        *self.cache.write().unwrap() = Some(Arc::clone(&snapshot));
+
+        Ok(snapshot)
+    }
+}
+
+/// 読み取りはキャッシュから直接行い、変更はすべて書き込みスレッドを経由させる。
+#[derive(Clone)]
+pub struct BookRepository {
+    store: Store,
+    writer: mpsc::Sender<Job>,
+}
+
+impl BookRepository {
+    pub const BACKEND: &'static str = "json-file";
+
+    pub fn new(data_file: impl Into<PathBuf>) -> Self {
+        BookRepository::with_options(data_file, StorageOptions::default())
+    }
+
+    pub fn with_options(data_file: impl Into<PathBuf>, options: StorageOptions) -> Self {
+        let search_index = SearchIndex::new(options.analyzer);
+        let data_file = data_file.into();
+        let synonyms = synonyms::load(&synonyms::path_for(&data_file)).unwrap_or_else(|e| {
+            log::error!("Failed to read tag synonyms for {}: {}", data_file.display(), e);
+            Synonyms::default()
+        });
+        let genres = genres::load(&genres::path_for(&data_file)).unwrap_or_else(|e| {
+            log::error!("Failed to read genres for {}: {}", data_file.display(), e);
+            Genres::default()
+        });
+        let authors = registry::load(&authors::path_for(&data_file)).unwrap_or_else(|e| {
+            log::error!("Failed to read authors for {}: {}", data_file.display(), e);
+            Authors::default()
+        });
+        let publishers = registry::load(&publishers::path_for(&data_file)).unwrap_or_else(|e| {
+            log::error!("Failed to read publishers for {}: {}", data_file.display(), e);
+            Publishers::default()
+        });
+        let quotes = quotes::load(&quotes::path_for(&data_file)).unwrap_or_else(|e| {
+            log::error!("Failed to read quotes for {}: {}", data_file.display(), e);
+            Quotes::default()
+        });
+        let store = Store {
+            authors: Arc::new(RwLock::new(Arc::new(authors))),
+            publishers: Arc::new(RwLock::new(Arc::new(publishers))),
+            quotes: Arc::new(RwLock::new(Arc::new(quotes))),
+            genres: Arc::new(RwLock::new(Arc::new(genres))),
+            journal: Arc::new(journal::Journal::new(journal::path_for(&data_file))),
+            synonyms: Arc::new(RwLock::new(Arc::new(synonyms))),
+            data_file,
+            options,
+            events: EventBus::new(),
+            cache: Arc::new(RwLock::new(None)),
+            search_index,
+        };
+        let writer = writer::spawn(store.clone());
+
+        BookRepository { store, writer }
+    }
+
+    /// データファイルのパス。
+    pub fn data_file(&self) -> &Path {
+        &self.store.data_file
+    }
+
+    /// データファイルの中身を復号して返す。壊れたファイルの調査・修復に使う。
+    pub(crate) fn read_contents(&self) -> Result<Vec<u8>, BookError> {
+        self.store.read_contents()
+    }
+
+    /// 書き込みスレッドを通さずにデータファイルを書き換える。読めなくなったファイルの修復にだけ使う。
+    pub(crate) fn write_contents(&self, contents: Vec<u8>) -> Result<(), BookError> {
+        self.store.write_contents(contents)
+    }
+
+    /// データファイルと同じ鍵で暗号化する。鍵がなければそのまま返す。
+    pub(crate) fn seal(&self, contents: Vec<u8>) -> Result<Vec<u8>, BookError> {
+        crypto::seal(self.store.options.encryption_key.as_ref(), contents)
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.store.events
+    }
+
+    fn snapshot(&self) -> Result<Arc<Snapshot>, BookError> {
+        self.store.snapshot()
+    }
+
+    /// 変更を書き込みスレッドに渡し、適用されるまで待つ。
+    fn submit(&self, change: Change) -> Result<Outcome, BookError> {
+        let (reply, outcome) = mpsc::channel();
+
+        self.writer
+            .send(Job { change, reply })
+            .map_err(|_| writer::stopped())?;
+
+        outcome.recv().map_err(|_| writer::stopped())?
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn list(&self) -> Result<Vec<Book>, BookError> {
+        Ok(self.snapshot()?.books.clone())
+    }
+
+    /// 現在のスナップショットを 1 件ずつ複製しながら返す。全件の `Vec` を作らずに済む。
+    pub fn iter(&self) -> Result<Books, BookError> {
+        Ok(Books { snapshot: self.snapshot()?, pos: 0 })
+    }
+
+    /// データファイルと、検索や集計に使うジャンルの一覧・同義語の辞書のうち、最後に更新された日時。
+    pub fn last_modified(&self) -> Result<Option<SystemTime>, BookError> {
+        let data_file = &self.store.data_file;
+        let sidecars = [genres::path_for(data_file), synonyms::path_for(data_file)]
+            .into_iter()
+            .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok());
+
+        Ok(self.snapshot()?.modified.into_iter().chain(sidecars).max())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn get(&self, id: u32) -> Result<Option<Book>, BookError> {
+        Ok(self.snapshot()?.get(id).cloned())
+    }
+
+    /// 全文検索に使っている索引の種類 ("tantivy" または "scan")。
+    pub fn search_backend(&self) -> &'static str {
+        self.store.search_index.name()
+    }
+
+    #[tracing::instrument(skip_all, fields(id = ?query.id, tag = ?query.tag, genre = ?query.genre, format = ?query.format, lang = ?query.lang, q = ?query.q))]
+    pub fn search(&self, query: &BookQuery) -> Result<Vec<Book>, BookError> {
+        let snapshot = self.snapshot()?;
+        let synonyms = self.store.synonyms();
+        // ジャンル・形式・言語での絞り込み
+        let narrow = |b: &Book| {
+            query.genre.as_deref().is_none_or(|genre| genres::has_genre(b, genre))
+                && query.format.is_none_or(|format| b.formats.contains(&format))
+                && query.lang.as_deref().is_none_or(|lang| language::matches(b, lang))
+        };
+
+        if query.regex {
+            // 正規表現では検索式を読まずに、q= 全体を title / content に当てる
+            let pattern = Pattern::new(query.q.as_deref().unwrap_or_default())?;
+            let tag = query.tag.as_deref().map(fold);
+            let candidates = snapshot.books.iter()
+                .filter(|b| query.id.is_none_or(|id| b.id == id))
+                .filter(|b| tag.as_deref().is_none_or(|tag| synonyms.matches(&b.tags, tag)))
+                .filter(|b| narrow(b));
+            return pattern.filter(candidates, Instant::now() + pattern::TIME_LIMIT);
+        }
+
+        if let Some(q) = query.q.as_deref().filter(|q| !q.trim().is_empty()) {
+            // q= に全文検索の語があればスコア順に並べ、検索式の条件と id / タグで絞り込む
+            let parsed = SearchQuery::parse(q)?;
+            let books = if parsed.text.is_empty() {
+                snapshot.books.clone()
+            } else {
+                self.full_text(&parsed.text)?.into_iter().map(|hit| hit.book).collect()
+            };
+            let tag = query.tag.as_deref().map(fold);
+            return Ok(books
+                .into_iter()
+                .filter(|b| query.id.is_none_or(|id| b.id == id))
+                .filter(|b| tag.as_deref().is_none_or(|tag| synonyms.matches(&b.tags, tag)))
+                .filter(|b| narrow(b))
+                .filter(|b| parsed.matches(b, &synonyms))
+                .collect());
+        }
+
+        // 同義語や子孫のタグが付いた書籍もファイルの順で合わせる
+        let tagged: Option<Vec<u32>> = query.tag.as_deref().map(|tag| {
+            let mut ids: Vec<u32> = synonyms.equivalents(tag_tree::trim(&fold(tag))).iter()
+                .flat_map(|tag| {
+                    snapshot.folded_tags.range(tag.clone()..)
+                        .take_while(|(t, _)| t.starts_with(tag.as_str()))
+                        .filter(|(t, _)| tag_tree::within(t, tag))
+                        .flat_map(|(_, ids)| ids.iter().copied())
+                })
+                .collect();
+            ids.sort_by_key(|id| snapshot.index[id]);
+            ids.dedup();
+            ids
+        });
+
+        // id やタグが指定されていれば索引で候補を絞れる
+        Ok(match (query.id, tagged) {
+            (Some(id), tagged) => snapshot.get(id)
+                .filter(|_| tagged.is_none_or(|ids| ids.contains(&id)))
+                .filter(|b| narrow(b))
+                .cloned()
+                .into_iter()
+                .collect(),
+            (None, Some(ids)) => ids.iter()
+                .filter_map(|&id| snapshot.get(id).filter(|b| narrow(b)).cloned())
+                .collect(),
+            (None, None) => snapshot.books.iter().filter(|b| narrow(b)).cloned().collect(),
+        })
+    }
+
+    /// 全文検索。スコアの高い順に、一致箇所のスニペット付きで返す。
+    #[tracing::instrument(skip(self))]
+    pub fn full_text(&self, q: &str) -> Result<Vec<SearchHit>, BookError> {
+        let snapshot = self.snapshot()?;
+
+        Ok(self.store.search_index.search(&snapshot.books, |id| snapshot.get(id), q, &self.store.synonyms()))
+    }
+
+    /// 見つからなかった検索式 `q` を、`visible` な書籍のタイトルとタグの語で直した候補。
+    #[tracing::instrument(skip(self, visible))]
+    pub fn did_you_mean(&self, q: &str, visible: impl Fn(&Book) -> bool) -> Result<Vec<String>, BookError> {
+        let snapshot = self.snapshot()?;
+
+        Ok(Vocabulary::new(snapshot.books.iter().filter(|b| visible(b))).did_you_mean(q))
+    }
+
+    /// タイトル・タグ・著者名から `prefix` で始まる語を含むものを返す。`visible` で見せる書籍を選ぶ。
+    #[tracing::instrument(skip(self, visible))]
+    pub fn suggest(&self, prefix: &str, limit: usize, visible: impl Fn(&Book) -> bool) -> Result<Vec<Suggestion>, BookError> {
+        let snapshot = self.snapshot()?;
+
+        Ok(snapshot.suggestions.suggest(prefix, limit, |id| snapshot.get(id).is_some_and(&visible)))
+    }
+
+    /// `since` より後の変更を書籍ごとにまとめ、最後に変わった順に返す。
+    pub fn changes(&self, since: Since) -> Result<Changes, BookError> {
+        let snapshot = self.snapshot()?;
+        let (entries, cursor) = self.store.journal.since(since)?;
+
+        // id → (最初の記録が作成か, 最後の時刻, 最後の seq)
+        let mut collapsed: HashMap<u32, (bool, i64, u64)> = HashMap::new();
+        for entry in entries {
+            let Some(id) = entry.id else { continue };
+            let created = entry.kind == journal::EntryKind::Created;
+            collapsed.entry(id)
+                .and_modify(|(_, at, seq)| (*at, *seq) = (entry.at, entry.seq))
+                .or_insert((created, entry.at, entry.seq));
+        }
+
+        let mut changes: Vec<(u64, BookChange)> = collapsed.into_iter()
+            .map(|(id, (created, at, seq))| {
+                let book = snapshot.get(id).cloned();
+                let kind = match &book {
+                    None => BookEventKind::Deleted,
+                    Some(_) if created => BookEventKind::Created,
+                    Some(_) => BookEventKind::Updated,
+                };
+                (seq, BookChange { id, kind, at, book })
+            })
+            .collect();
+        changes.sort_by_key(|(seq, _)| *seq);
+
+        Ok(Changes {
+            cursor: cursor.to_string(),
+            changes: changes.into_iter().map(|(_, change)| change).collect(),
+        })
+    }
+
+    /// タグごとの書籍数をタグ名順で返す。
+    #[tracing::instrument(skip(self))]
+    pub fn tags(&self) -> Result<Vec<(String, usize)>, BookError> {
+        let snapshot = self.snapshot()?;
+
+        let mut tags: Vec<(String, usize)> = snapshot.tags.iter()
+            .map(|(tag, ids)| (tag.clone(), ids.len()))
+            .collect();
+        tags.sort();
+
+        Ok(tags)
+    }
+
+    /// 登録しているタグの同義語。
+    pub fn synonyms(&self) -> Arc<Synonyms> {
+        self.store.synonyms()
+    }
+
+    /// タグの同義語を差し替え、全文検索の索引も作り直す。
+    #[tracing::instrument(skip_all, fields(groups = synonyms.groups().len()))]
+    pub fn set_synonyms(&self, synonyms: Synonyms) -> Result<(), BookError> {
+        match self.submit(Change::Synonyms(synonyms))? {
+            Outcome::SynonymsSet => Ok(()),
+            _ => unreachable!("synonyms always yields Outcome::SynonymsSet"),
+        }
+    }
+
+    /// `synonyms` を登録したら、グループごとにどの書籍がまとまるか。
+    pub fn preview_synonyms(&self, synonyms: &Synonyms) -> Result<Vec<Preview>, BookError> {
+        Ok(synonyms::preview(synonyms, &self.snapshot()?.books))
+    }
+
+    /// ジャンルの一覧。
+    pub fn genres(&self) -> Arc<Genres> {
+        self.store.genres()
+    }
+
+    /// ジャンルの一覧を差し替える。書籍に付いているジャンルが一覧から外れるなら 409。
+    #[tracing::instrument(skip_all, fields(genres = genres.names().len()))]
+    pub fn set_genres(&self, genres: Genres) -> Result<(), BookError> {
+        match self.submit(Change::Genres(genres))? {
+            Outcome::GenresSet => Ok(()),
+            _ => unreachable!("genres always yields Outcome::GenresSet"),
+        }
+    }
+
+    /// 著者の記録。
+    pub fn authors(&self) -> Arc<Authors> {
+        self.store.authors()
+    }
+
+    /// 著者の記録を変える。名前が変わった著者の書籍も書き換え、1 回で書き込む。
+    /// 戻り値は作成・更新・まとめた先・削除した記録 (取り込みなら作った記録すべて)。
+    #[tracing::instrument(skip(self))]
+    pub fn edit_authors(&self, edit: Edit) -> Result<Vec<Author>, BookError> {
+        match self.submit(Change::Authors(edit))? {
+            Outcome::Authors(result) => Ok(result),
+            _ => unreachable!("authors always yields Outcome::Authors"),
+        }
+    }
+
+    /// 出版社の記録。
+    pub fn publishers(&self) -> Arc<Publishers> {
+        self.store.publishers()
+    }
+
+    /// 出版社の記録を変える。名前が変わった出版社の書籍も書き換え、1 回で書き込む。
+    /// 戻り値は作成・更新・削除した記録。
+    #[tracing::instrument(skip(self))]
+    pub fn edit_publishers(&self, edit: publishers::Edit) -> Result<Publisher, BookError> {
+        match self.submit(Change::Publishers(edit))? {
+            Outcome::Publisher(result) => Ok(result),
+            _ => unreachable!("publishers always yields Outcome::Publisher"),
+        }
+    }
+
+    /// 引用。
+    pub fn quotes(&self) -> Arc<Quotes> {
+        self.store.quotes()
+    }
+
+    /// 引用を登録・削除する。登録する書籍がなければ `NotFound`。戻り値は登録・削除した引用。
+    #[tracing::instrument(skip(self))]
+    pub fn edit_quotes(&self, edit: quotes::Edit) -> Result<Quote, BookError> {
+        match self.submit(Change::Quotes(edit))? {
+            Outcome::Quote(quote) => Ok(quote),
+            _ => unreachable!("quotes always yields Outcome::Quote"),
+        }
+    }
+
+    /// `ids` の書籍のタグをまとめて付け外しし、1 回で書き込む。ない id は数えない。
+    #[tracing::instrument(skip_all, fields(count = ids.len()))]
+    pub fn bulk_tags(&self, mut ids: Vec<u32>, change: TagChange) -> Result<BulkTagReport, BookError> {
+        ids.sort_unstable();
+        ids.dedup();
+        match self.submit(Change::Tags { ids, change })? {
+            Outcome::Tagged { matched, updated } => Ok(BulkTagReport { matched, updated }),
+            _ => unreachable!("tags always yields Outcome::Tagged"),
+        }
+    }
+
+    /// 保存する前の書籍を整える。ジャンルは一覧の表記にそろえ、一覧になければ 400。
+    /// 別名で書いた著者や出版社は記録の名前にそろえる。`work` は作品の id にそろえ、指した書籍がなければ 400。
+    /// 形式は重なりを除いて決まった順に並べ、オーディオブックでない書籍に `audio` があれば 400。
+    /// 言語と本文の語数・文字数は、送られた値によらず title と content から求め直す。
+    fn prepare(&self, mut book: Book) -> Result<Book, BookError> {
+        if self.store.options.sanitize_content && !book.content.is_empty() {
+            book.content = crate::sanitize::sanitize_html(&book.content);
+        }
+        if !book.genres.is_empty() {
+            book.genres = self.store.genres().canonical(&book.genres)?;
+        }
+        if !book.authors.is_empty() {
+            book.authors = self.store.authors().canonical(&book.authors);
+        }
+        book.language = language::detect(&book.title, &book.content).map(str::to_string);
+        book.content_stats = reading::measure(&book.content, book.language.as_deref());
+        book.formats.sort();
+        book.formats.dedup();
+        audio::check(&book)?;
+        if let Some(publisher) = &book.publisher {
+            book.publisher = Some(self.store.publishers().canonical(publisher)).filter(|p| !p.is_empty());
+        }
+        if book.work.is_some() {
+            let snapshot = self.snapshot()?;
+            book.work = works::resolve(&book, |id| snapshot.get(id))?;
+        }
+        Ok(book)
+    }
+
+    /// オーディオブックの再生位置を秒で記録する。書籍がなければ `NotFound`、長さが記録されていなければ 409。
+    #[tracing::instrument(skip(self))]
+    pub fn set_progress(&self, id: u32, seconds: u32) -> Result<Audio, BookError> {
+        match self.submit(Change::Progress { id, seconds })? {
+            Outcome::Progress(audio) => Ok(audio),
+            _ => unreachable!("progress always yields Outcome::Progress"),
+        }
+    }
+
+    /// `borrower` の貸出をすべて返却扱いにする。読み込みと書き換えを書き込みスレッドで続けて行うので、
+    /// その間に届いた変更を上書きしない。戻り値は返却扱いにした書籍の id。
+    #[tracing::instrument(skip_all)]
+    pub fn return_loans(&self, borrower: &str) -> Result<Vec<u32>, BookError> {
+        match self.submit(Change::ReturnLoans(borrower.to_string()))? {
+            Outcome::Returned(ids) => Ok(ids),
+            _ => unreachable!("return_loans always yields Outcome::Returned"),
+        }
+    }
+
+    /// 既存の id なら置き換え、なければ追加する。戻り値は保存後の全件と新規作成かどうか。
+    #[tracing::instrument(skip_all, fields(id = book.id))]
+    pub fn upsert(&self, book: Book) -> Result<(Vec<Book>, bool), BookError> {
+        match self.submit(Change::Upsert(self.prepare(book)?))? {
+            Outcome::Upserted { books, created } => Ok((books, created)),
+            _ => unreachable!("upsert always yields Outcome::Upserted"),
+        }
+    }
+
+    /// 複数件をまとめて保存する。ファイルへの書き込みは 1 回だけ。戻り値は新規作成した件数。
+    #[tracing::instrument(skip_all, fields(count = incoming.len()))]
+    pub fn upsert_many(&self, incoming: Vec<Book>) -> Result<usize, BookError> {
+        let incoming = incoming.into_iter().map(|book| self.prepare(book)).collect::<Result<_, _>>()?;
+        match self.submit(Change::UpsertMany(incoming))? {
+            Outcome::UpsertedMany(created) => Ok(created),
+            _ => unreachable!("upsert_many always yields Outcome::UpsertedMany"),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn delete(&self, id: u32) -> Result<Option<Book>, BookError> {
+        match self.submit(Change::Delete(id))? {
+            Outcome::Deleted(removed) => Ok(removed.map(|book| *book)),
+            _ => unreachable!("delete always yields Outcome::Deleted"),
+        }
+    }
+
+    /// クライアントの変更を、`base` より後にサーバーで変わった書籍は `strategy` で解決しながら適用し、
+    /// `since` より後の変更と合わせて返す。`base` や `since` の記録が残っていなければ 410。
+    #[tracing::instrument(skip_all, fields(changes = request.changes.len()))]
+    pub fn sync(&self, request: SyncRequest) -> Result<SyncResponse, BookError> {
+        if request.changes.len() > sync::MAX_CHANGES {
+            return Err(BookError::BadRequest(format!("sync accepts at most {} changes", sync::MAX_CHANGES)));
+        }
+        let since: Since = request.since.parse()
+            .map_err(|_| BookError::BadRequest("since: expected a cursor or an RFC 3339 timestamp".to_string()))?;
+
+        let changes = request.changes.into_iter()
+            .map(|change| {
+                let base = change.base()?;
+                let change = match change {
+                    LocalChange::Upsert { book, base } => LocalChange::Upsert { book: Box::new(self.prepare(*book)?), base },
+                    delete => delete,
+                };
+                Ok((change, base))
+            })
+            .collect::<Result<Vec<_>, BookError>>()?;
+
+        // 記録の残っていない base では衝突を確かめられない
+        let bases = changes.iter().map(|(_, base)| *base);
+        for base in bases.clone().min().into_iter().chain(bases.max()) {
+            self.store.journal.since(Since::Cursor(base))?;
+        }
+        self.store.journal.since(since)?;
+
+        let applied = match self.submit(Change::Sync { changes, strategy: request.strategy })? {
+            Outcome::Synced(applied) => applied,
+            _ => unreachable!("sync always yields Outcome::Synced"),
+        };
+        let Changes { cursor, changes } = self.changes(since)?;
+
+        Ok(SyncResponse { accepted: applied.accepted, conflicts: applied.conflicts, cursor, changes })
+    }
+
+    /// データセット全体を差し替える。ほかの変更と同じく書き込みスレッドで直列に適用し、
+    /// キャッシュと全文検索の索引も作り直す。個々の書籍のイベントは流さない。
+    /// 戻り値は差し替え前の件数。
+    #[tracing::instrument(skip_all, fields(count = books.len()))]
+    pub fn replace(&self, books: Vec<Book>) -> Result<usize, BookError> {
+        let mut seen = HashSet::new();
+        let duplicates: Vec<u32> = books.iter()
+            .filter(|b| !seen.insert(b.id))
+            .map(|b| b.id)
+            .collect();
+
+        if !duplicates.is_empty() {
+            return Err(BookError::BadRequest(format!("Duplicate book ids: {:?}", duplicates)));
+        }
+
+        let books = books.into_iter().map(|book| self.prepare(book)).collect::<Result<_, _>>()?;
+        match self.submit(Change::Replace(books))? {
+            Outcome::Replaced(previous) => Ok(previous),
+            _ => unreachable!("replace always yields Outcome::Replaced"),
+        }
+    }
+
+    /// 書き込みスレッドに溜まっている変更がすべてファイルに書き込まれるまで待つ。
+    #[tracing::instrument(skip(self))]
+    pub fn flush(&self) -> Result<(), BookError> {
+        match self.submit(Change::Flush)? {
+            Outcome::Flushed => Ok(()),
+            _ => unreachable!("flush always yields Outcome::Flushed"),
+        }
+    }
+
+    /// バックアップを置くディレクトリ。データファイルと同じ場所の `backups/`。
+    pub fn backup_dir(&self) -> PathBuf {
+        self.store.data_file
+            .parent()
+            .map(|p| p.join("backups"))
+            .unwrap_or_else(|| PathBuf::from("backups"))
+    }
+
+    /// データファイルを `backups/` 以下に日時付きで複製する。
+    #[tracing::instrument(skip(self))]
+    pub fn backup(&self) -> Result<PathBuf, BookError> {
+        let dir = self.backup_dir();
+        fs::create_dir_all(&dir)?;
+
+        let stamp = OffsetDateTime::now_utc()
+            .format(format_description!("[year][month][day]T[hour][minute][second]Z"))
+            .map_err(|e| BookError::Serialize(e.to_string()))?;
+        let stem = self.store.data_file
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("books");
+
+        let path = dir.join(format!("{}-{}.json", stem, stamp));
+        fs::copy(&self.store.data_file, &path)?;
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authors::AuthorInput;
+    use crate::events::BookEventKind;
+    use crate::publishers::PublisherInput;
+    use crate::quotes::QuoteInput;
+
+    fn temp_repository(name: &str) -> BookRepository {
+        let path = std::env::temp_dir().join(format!("books_backend_{}_{}.json", name, std::process::id()));
+        fs::copy("src/data/book.json", &path).expect("Failed to copy fixture");
+
+        BookRepository::new(path)
+    }
+
+    #[test]
+    fn test_bootstrap_creates_missing_data_file() {
+        let dir = std::env::temp_dir().join(format!("books_backend_bootstrap_{}", std::process::id()));
+        let path = dir.join("nested").join("book.json");
+
+        assert!(!bootstrap(&path, InitialData::None).unwrap());
+        assert!(dir.join("nested").is_dir() && !path.exists());
+
+        assert!(bootstrap(&path, InitialData::Empty).unwrap());
+        assert!(BookRepository::new(&path).list().unwrap().is_empty());
+
+        // 既にあれば上書きしない
+        assert!(!bootstrap(&path, InitialData::Demo).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[]\n");
+
+        fs::remove_file(&path).unwrap();
+        assert!(bootstrap(&path, InitialData::Demo).unwrap());
+        assert!(!BookRepository::new(&path).list().unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_data_file() {
+        let repository = temp_repository("encrypted");
+        let path = repository.store.data_file.clone();
+        let key = EncryptionKey::parse("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=").unwrap();
+        let count = repository.list().unwrap().len();
+
+        assert!(encrypt_file(&path, &key).unwrap());
+        assert!(!encrypt_file(&path, &key).unwrap());
+        assert!(!fs::read_to_string(&path).is_ok_and(|s| s.contains("Rust Basics")));
+        assert!(BookRepository::new(&path).list().is_err());
+
+        for mmap in [false, true] {
+            let options = StorageOptions { mmap, encryption_key: Some(key.clone()), ..StorageOptions::default() };
+            assert_eq!(BookRepository::with_options(&path, options).list().unwrap().len(), count);
+        }
+
+        // 書き込んだ後も暗号化されたまま
+        let encrypted = BookRepository::with_options(&path, StorageOptions { encryption_key: Some(key), ..StorageOptions::default() });
+        encrypted.delete(1).unwrap();
+        assert!(crypto::is_encrypted(&fs::read(&path).unwrap()));
+        assert_eq!(encrypted.list().unwrap().len(), count - 1);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_upsert_and_delete_publish_events() {
+        let repository = temp_repository("storage_events");
+        let mut events = repository.events().subscribe();
+
+        let book = Book {
+            id: 1000,
+            title: "Macros".to_string(),
+            content: "macro_rules!".to_string(),
+            tags: vec!["macros".to_string()],
+            ..Default::default()
+        };
+
+        let (_, created) = repository.upsert(book.clone()).unwrap();
+        assert!(created);
+
+        let (_, created) = repository.upsert(book).unwrap();
+        assert!(!created);
+
+        assert!(repository.delete(1000).unwrap().is_some());
+        assert!(repository.delete(1000).unwrap().is_none());
+
+        let kinds: Vec<BookEventKind> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|e| e.kind)
+            .collect();
+
+        assert_eq!(kinds, vec![BookEventKind::Created, BookEventKind::Updated, BookEventKind::Deleted]);
+
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_upserts_are_not_lost() {
+        let repository = temp_repository("storage_concurrent");
+        let before = repository.list().unwrap().len();
+
+        let handles: Vec<_> = (0..16)
+            .map(|i| {
+                let repository = repository.clone();
+                std::thread::spawn(move || {
+                    repository.upsert(Book { id: 2000 + i, title: format!("Book {}", i), ..Default::default() }).unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(repository.list().unwrap().len(), before + 16);
+
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_flush_without_changes_does_not_write() {
+        let repository = temp_repository("storage_flush");
+        let before = fs::metadata(&repository.store.data_file).unwrap().modified().unwrap();
+
+        repository.flush().unwrap();
+
+        let after = fs::metadata(&repository.store.data_file).unwrap().modified().unwrap();
+        assert_eq!(before, after);
+
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_changes() {
+        let repository = temp_repository("storage_changes");
+        let _ = fs::remove_file(journal::path_for(repository.data_file()));
+
+        let cursor = repository.changes(Since::Cursor(0)).unwrap().cursor;
+        assert_eq!(cursor, "0");
+
+        let book = Book { id: 9000, title: "Draft".to_string(), ..Default::default() };
+        repository.upsert(book.clone()).unwrap();
+        repository.upsert(Book { title: "Final".to_string(), ..book }).unwrap();
+        repository.upsert(Book { id: 1, title: "Rust Basics 2nd".to_string(), ..Default::default() }).unwrap();
+        repository.delete(2).unwrap();
+
+        let changes = repository.changes(Since::Cursor(0)).unwrap();
+        assert_eq!(changes.cursor, "4");
+        let summary: Vec<(u32, BookEventKind)> = changes.changes.iter().map(|c| (c.id, c.kind)).collect();
+        assert_eq!(summary, vec![(9000, BookEventKind::Created), (1, BookEventKind::Updated), (2, BookEventKind::Deleted)]);
+        assert_eq!(changes.changes[0].book.as_ref().unwrap().title, "Final");
+        assert!(changes.changes[2].book.is_none());
+
+        assert!(repository.changes(Since::Cursor(4)).unwrap().changes.is_empty());
+
+        // 差し替えたあとは差分では追いつけない
+        repository.replace(Vec::new()).unwrap();
+        assert!(matches!(repository.changes(Since::Cursor(4)), Err(BookError::Gone(_))));
+
+        fs::remove_file(journal::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_sync() {
+        use crate::sync::{Resolution, Strategy};
+
+        let repository = temp_repository("storage_sync");
+        let _ = fs::remove_file(journal::path_for(repository.data_file()));
+
+        // クライアントが cursor 0 で揃えたあと、サーバーで 1 を編集した
+        let mut server = repository.get(1).unwrap().unwrap();
+        server.tags = vec!["edited".to_string()];
+        repository.upsert(server).unwrap();
+
+        let upsert = |book: Book, base: &str| LocalChange::Upsert { book: Box::new(book), base: base.to_string() };
+        let client = Book { id: 1, title: "Offline title".to_string(), tags: vec!["offline".to_string()], ..Default::default() };
+        let request = |strategy, changes| SyncRequest { since: "0".to_string(), strategy, changes };
+
+        let response = repository.sync(request(Strategy::ServerWins, vec![
+            upsert(client.clone(), "0"),
+            upsert(Book { id: 9001, title: "New".to_string(), ..Default::default() }, "0"),
+            LocalChange::Delete { id: 3, base: "0".to_string() },
+        ])).unwrap();
+        assert_eq!(response.accepted, vec![9001, 3]);
+        assert_eq!(response.conflicts.len(), 1);
+        assert_eq!(response.conflicts[0].resolution, Resolution::Server);
+        assert_eq!(repository.get(1).unwrap().unwrap().tags, vec!["edited"]);
+        assert!(repository.get(3).unwrap().is_none());
+        assert_eq!(response.cursor, "3");
+        assert_eq!(response.changes.iter().map(|c| c.id).collect::<Vec<_>>(), vec![1, 9001, 3]);
+
+        let response = repository.sync(request(Strategy::Merge, vec![upsert(client.clone(), "0")])).unwrap();
+        assert_eq!(response.conflicts[0].resolution, Resolution::Merged);
+        let merged = repository.get(1).unwrap().unwrap();
+        assert_eq!((merged.title.as_str(), merged.tags), ("Rust Basics", vec!["edited".to_string(), "offline".to_string()]));
+
+        // 最新の cursor からなら衝突しない
+        let response = repository.sync(request(Strategy::ServerWins, vec![upsert(client, "4")])).unwrap();
+        assert_eq!(response.accepted, vec![1]);
+        assert_eq!(repository.get(1).unwrap().unwrap().title, "Offline title");
+
+        assert!(matches!(
+            repository.sync(request(Strategy::ServerWins, vec![LocalChange::Delete { id: 1, base: "99".to_string() }])),
+            Err(BookError::Gone(_))
+        ));
+
+        fs::remove_file(journal::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_tag_index() {
+        let repository = temp_repository("storage_tags");
+
+        let tagged = repository.search(&BookQuery { tag: Some("ownership".to_string()), ..Default::default() }).unwrap();
+        let ids: Vec<u32> = tagged.iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![3, 4, 6]);
+
+        let tagged = repository.search(&BookQuery { id: Some(4), tag: Some("ownership".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(tagged.len(), 1);
+
+        let tagged = repository.search(&BookQuery { id: Some(1), tag: Some("ownership".to_string()), ..Default::default() }).unwrap();
+        assert!(tagged.is_empty());
+
+        // 全角や大文字でも同じタグとして引く
+        let tagged = repository.search(&BookQuery { tag: Some("ＯＷＮＥＲＳＨＩＰ".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(tagged.len(), 3);
+
+        let tags = repository.tags().unwrap();
+        assert!(tags.contains(&("ownership".to_string(), 3)));
+
+        // 親のタグで子孫のタグが付いた書籍も引く
+        let mut book = repository.get(1).unwrap().unwrap();
+        book.tags = vec!["Ownership/Borrowing".to_string()];
+        repository.upsert(book).unwrap();
+        let tagged = repository.search(&BookQuery { tag: Some("ownership/".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(tagged.iter().map(|b| b.id).collect::<Vec<_>>(), vec![1, 3, 4, 6]);
+
+        fs::remove_file(journal::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_synonyms() {
+        let repository = temp_repository("storage_synonyms");
+        let tagged = |repository: &BookRepository, tag: &str| {
+            repository.search(&BookQuery { tag: Some(tag.to_string()), ..Default::default() }).unwrap()
+                .iter().map(|b| b.id).collect::<Vec<_>>()
+        };
+        assert_eq!(tagged(&repository, "concurrency"), vec![16]);
+
+        let synonyms = Synonyms::new(vec![vec!["Concurrency".to_string(), "async".to_string()]]).unwrap();
+        repository.set_synonyms(synonyms.clone()).unwrap();
+        assert_eq!(tagged(&repository, "concurrency"), vec![2, 16, 17]);
+        assert_eq!(tagged(&repository, "ASYNC"), vec![2, 16, 17]);
+
+        let found: Vec<u32> = repository.full_text("concurrency").unwrap().iter().map(|hit| hit.book.id).collect();
+        assert!(found.contains(&2) && found.contains(&17));
+
+        // 作り直したリポジトリもファイルから読む
+        let reopened = BookRepository::new(&repository.store.data_file);
+        assert_eq!(*reopened.synonyms(), synonyms);
+
+        repository.set_synonyms(Synonyms::default()).unwrap();
+        assert_eq!(tagged(&repository, "concurrency"), vec![16]);
+
+        fs::remove_file(synonyms::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_genres() {
+        let repository = temp_repository("storage_genres");
+        let mut book = repository.get(1).unwrap().unwrap();
+        book.genres = vec!["programming".to_string()];
+
+        // 一覧にないジャンルは付けられない
+        assert!(matches!(repository.upsert(book.clone()), Err(BookError::BadRequest(_))));
+
+        let taxonomy = Genres::new(vec!["Programming".to_string(), "Poetry".to_string()]).unwrap();
+        repository.set_genres(taxonomy.clone()).unwrap();
+        // 書籍が変わらなくても、一覧を変えれば更新日時が進む
+        let genres_modified = fs::metadata(genres::path_for(repository.data_file())).unwrap().modified().unwrap();
+        assert!(repository.last_modified().unwrap() >= Some(genres_modified));
+        repository.upsert(book).unwrap();
+        assert_eq!(repository.get(1).unwrap().unwrap().genres, vec!["Programming"]);
+
+        let found = repository.search(&BookQuery { genre: Some("PROGRAMMING".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(found.iter().map(|b| b.id).collect::<Vec<_>>(), vec![1]);
+        let found = repository.search(&BookQuery { tag: Some("syntax".to_string()), genre: Some("poetry".to_string()), ..Default::default() }).unwrap();
+        assert!(found.is_empty());
+
+        // 付いているジャンルは外せない
+        let narrowed = Genres::new(vec!["Poetry".to_string()]).unwrap();
+        assert!(matches!(repository.set_genres(narrowed), Err(BookError::Conflict(_))));
+        assert_eq!(*BookRepository::new(&repository.store.data_file).genres(), taxonomy);
+
+        fs::remove_file(genres::path_for(repository.data_file())).unwrap();
+        fs::remove_file(journal::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_authors() {
+        let repository = temp_repository("storage_authors");
+        let mut first = repository.get(1).unwrap().unwrap();
+        first.authors = vec!["Steve Klabnik".to_string()];
+        let mut second = repository.get(2).unwrap().unwrap();
+        second.authors = vec!["S. Klabnik".to_string(), "Carol Nichols".to_string()];
+        repository.upsert_many(vec![first, second]).unwrap();
+
+        let created = repository.edit_authors(Edit::Import).unwrap();
+        assert_eq!(created.iter().map(|a| a.name.as_str()).collect::<Vec<_>>(), vec!["Steve Klabnik", "S. Klabnik", "Carol Nichols"]);
+
+        let mut events = repository.events().subscribe();
+        let merged = repository.edit_authors(Edit::Merge { into: 1, from: vec![2] }).unwrap();
+        assert_eq!(merged[0].aliases, vec!["S. Klabnik"]);
+        assert_eq!(repository.get(2).unwrap().unwrap().authors, vec!["Steve Klabnik", "Carol Nichols"]);
+        assert_eq!(events.try_recv().unwrap().id, 2);
+
+        // 別名で書いても記録の名前で保存する
+        let mut third = repository.get(3).unwrap().unwrap();
+        third.authors = vec!["s. klabnik".to_string()];
+        repository.upsert(third).unwrap();
+        assert_eq!(repository.get(3).unwrap().unwrap().authors, vec!["Steve Klabnik"]);
+
+        assert_eq!(BookRepository::new(&repository.store.data_file).authors().list().len(), 2);
+
+        fs::remove_file(authors::path_for(repository.data_file())).unwrap();
+        fs::remove_file(journal::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_keeps_side_files() {
+        let repository = temp_repository("storage_failed_write");
+        let mut book = repository.get(1).unwrap().unwrap();
+        book.authors = vec!["Steve Klabnik".to_string()];
+        repository.upsert(book).unwrap();
+        repository.edit_authors(Edit::Import).unwrap();
+        let saved = fs::read(authors::path_for(repository.data_file())).unwrap();
+
+        // 一時ファイルの場所をふさいで書籍の書き込みを失敗させる
+        let mut tmp = repository.data_file().to_path_buf().into_os_string();
+        tmp.push(".tmp");
+        fs::create_dir(&tmp).unwrap();
+
+        let renamed = AuthorInput { name: "Steve K.".to_string(), ..Default::default() };
+        assert!(repository.edit_authors(Edit::Update(1, renamed)).is_err());
+        assert_eq!(fs::read(authors::path_for(repository.data_file())).unwrap(), saved);
+        assert_eq!(repository.authors().list()[0].name, "Steve Klabnik");
+        assert_eq!(repository.get(1).unwrap().unwrap().authors, vec!["Steve Klabnik"]);
+
+        fs::remove_dir(&tmp).unwrap();
+        fs::remove_file(authors::path_for(repository.data_file())).unwrap();
+        fs::remove_file(journal::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_publishers() {
+        let repository = temp_repository("storage_publishers");
+        let mut book = repository.get(1).unwrap().unwrap();
+        book.publisher = Some("O'Reilly".to_string());
+        repository.upsert(book).unwrap();
+
+        // 書き込んだ書籍の出版社は記録になる
+        let publishers = repository.publishers();
+        assert_eq!(publishers.list().iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), vec!["O'Reilly"]);
+
+        let renamed = PublisherInput { name: "O'Reilly Media".to_string(), aliases: vec!["O'Reilly".to_string()], ..Default::default() };
+        repository.edit_publishers(publishers::Edit::Update(1, renamed)).unwrap();
+        assert_eq!(repository.get(1).unwrap().unwrap().publisher.as_deref(), Some("O'Reilly Media"));
+
+        let mut other = repository.get(2).unwrap().unwrap();
+        other.publisher = Some("o'reilly".to_string());
+        repository.upsert(other).unwrap();
+        assert_eq!(repository.get(2).unwrap().unwrap().publisher.as_deref(), Some("O'Reilly Media"));
+        assert_eq!(repository.publishers().list().len(), 1);
+
+        assert!(matches!(repository.edit_publishers(publishers::Edit::Delete(1)), Err(BookError::Conflict(_))));
+        assert_eq!(BookRepository::new(&repository.store.data_file).publishers().list()[0].name, "O'Reilly Media");
+
+        fs::remove_file(publishers::path_for(repository.data_file())).unwrap();
+        fs::remove_file(journal::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_editions() {
+        let repository = temp_repository("storage_editions");
+        let mut ebook = repository.get(1).unwrap().unwrap();
+        ebook.id = 100;
+        ebook.work = Some(1);
+        repository.upsert(ebook.clone()).unwrap();
+
+        // 版を指しても作品の id で保存する
+        let mut audiobook = Book { id: 101, work: Some(100), ..ebook.clone() };
+        repository.upsert(audiobook.clone()).unwrap();
+        assert_eq!(repository.get(101).unwrap().unwrap().work, Some(1));
+        assert_eq!(works::editions(&repository.list().unwrap(), 1).map(|b| b.id).collect::<Vec<_>>(), vec![1, 100, 101]);
+
+        audiobook.work = Some(999);
+        assert!(matches!(repository.upsert(audiobook), Err(BookError::BadRequest(_))));
+
+        fs::remove_file(journal::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_progress() {
+        let repository = temp_repository("storage_progress");
+        let mut book = repository.get(1).unwrap().unwrap();
+        book.audio = Some(Audio { duration_minutes: 600, listened_minutes: 0.0 });
+        assert!(matches!(repository.upsert(book.clone()), Err(BookError::BadRequest(_))));
+
+        book.formats = vec![crate::Format::Audiobook];
+        repository.upsert(book).unwrap();
+        assert_eq!(repository.set_progress(1, 4500).unwrap().percent_complete(), 12.5);
+        assert_eq!(repository.get(1).unwrap().unwrap().audio.unwrap().listened_minutes, 75.0);
+
+        assert!(matches!(repository.set_progress(1, 600 * 60 + 1), Err(BookError::BadRequest(_))));
+        assert!(matches!(repository.set_progress(2, 60), Err(BookError::Conflict(_))));
+        assert!(matches!(repository.set_progress(999, 60), Err(BookError::NotFound)));
+
+        fs::remove_file(journal::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_quotes() {
+        let repository = temp_repository("storage_quotes");
+        let input = QuoteInput { text: "Fearless concurrency".to_string(), page: Some(353), ..Default::default() };
+
+        let quote = repository.edit_quotes(quotes::Edit::Add { book_id: 1, input: input.clone(), now: 0 }).unwrap();
+        assert_eq!((quote.id, quote.page), (1, Some(353)));
+        assert!(matches!(repository.edit_quotes(quotes::Edit::Add { book_id: 999, input, now: 0 }), Err(BookError::NotFound)));
+        assert_eq!(BookRepository::new(&repository.store.data_file).quotes().list(), [quote]);
+
+        repository.edit_quotes(quotes::Edit::Delete(1)).unwrap();
+        assert!(repository.quotes().list().is_empty());
+        assert!(matches!(repository.edit_quotes(quotes::Edit::Delete(1)), Err(BookError::NotFound)));
+
+        fs::remove_file(quotes::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_bulk_tags() {
+        let repository = temp_repository("storage_bulk_tags");
+        let change = TagChange { add: vec!["imported".to_string()], remove: vec!["SYNTAX".to_string()] };
+        let before = repository.get(1).unwrap().unwrap().tags;
+
+        let mut events = repository.events().subscribe();
+        let report = repository.bulk_tags(vec![1, 1, 999], change.clone()).unwrap();
+        assert_eq!(report, BulkTagReport { matched: 1, updated: 1 });
+        assert_eq!(repository.get(1).unwrap().unwrap().tags, change.apply(&before).unwrap());
+        assert_eq!(events.try_recv().unwrap().id, 1);
+
+        // もう変わらなければ書き込まない
+        assert_eq!(repository.bulk_tags(vec![1], change).unwrap(), BulkTagReport { matched: 1, updated: 0 });
+        assert!(events.try_recv().is_err());
+
+        fs::remove_file(journal::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_return_loans() {
+        let repository = temp_repository("storage_return_loans");
+        let loan = |borrower: &str| Some(crate::Loan { borrower: borrower.to_string(), due: time::macros::date!(2030 - 01 - 01) });
+        let mut first = repository.get(1).unwrap().unwrap();
+        first.loan = loan("erin");
+        let mut second = repository.get(2).unwrap().unwrap();
+        second.loan = loan("frank");
+        repository.upsert_many(vec![first, second]).unwrap();
+
+        assert_eq!(repository.return_loans("erin").unwrap(), vec![1]);
+        assert!(repository.get(1).unwrap().unwrap().loan.is_none());
+        assert_eq!(repository.get(2).unwrap().unwrap().loan, loan("frank"));
+        assert!(repository.return_loans("erin").unwrap().is_empty());
+
+        fs::remove_file(journal::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_language() {
+        let repository = temp_repository("storage_language");
+        let mut book = repository.get(1).unwrap().unwrap();
+        book.title = "はじめてのRust".to_string();
+        book.content = "<p>所有権と借用を学ぶ</p>".to_string();
+        book.language = Some("en".to_string());
+        repository.upsert(book).unwrap();
+        let saved = repository.get(1).unwrap().unwrap();
+        assert_eq!(saved.language.as_deref(), Some("ja"));
+        assert_eq!(saved.content_stats.map(|s| (s.characters, s.reading_minutes)), Some((9, 1)));
+
+        let found = repository.search(&BookQuery { lang: Some("ja".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(found.iter().map(|b| b.id).collect::<Vec<_>>(), vec![1]);
+
+        fs::remove_file(journal::path_for(repository.data_file())).unwrap();
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_mmap_reads_same_books() {
+        let repository = temp_repository("storage_mmap");
+        let mapped = BookRepository::with_options(&repository.store.data_file, StorageOptions { mmap: true, ..StorageOptions::default() });
+
+        let expected: Vec<u32> = repository.list().unwrap().iter().map(|b| b.id).collect();
+        let actual: Vec<u32> = mapped.list().unwrap().iter().map(|b| b.id).collect();
+        assert_eq!(actual, expected);
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        mapped.upsert(Book { id: 4000, title: "Mapped".to_string(), ..Default::default() }).unwrap();
+        assert!(repository.get(4000).unwrap().is_some());
+
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_sanitize_content_on_write() {
+        let repository = temp_repository("storage_sanitize");
+        let options = StorageOptions { sanitize_content: true, ..StorageOptions::default() };
+        let sanitizing = BookRepository::with_options(&repository.store.data_file, options);
+
+        let book = Book { id: 5000, title: "XSS".to_string(), content: "<p onclick=x>hi</p><script>alert(1)</script>".to_string(), ..Default::default() };
+        sanitizing.upsert(book.clone()).unwrap();
+        assert_eq!(sanitizing.get(5000).unwrap().unwrap().content, "<p>hi</p>");
+
+        // 設定しなければそのまま保存する
+        repository.upsert(book.clone()).unwrap();
+        assert_eq!(repository.get(5000).unwrap().unwrap().content, book.content);
+
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_cache_reloads_after_external_edit() {
+        let repository = temp_repository("storage_cache");
+
+        assert_eq!(repository.get(1).unwrap().unwrap().title, "Rust Basics");
+
+        // 他のプロセスがファイルを書き換えた場合を想定する
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(&repository.store.data_file, r#"[{"id": 1, "title": "Edited", "content": "", "tags": []}]"#).unwrap();
+
+        assert_eq!(repository.get(1).unwrap().unwrap().title, "Edited");
+        assert!(repository.get(2).unwrap().is_none());
+
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+
+    #[test]
+    fn test_replace_swaps_dataset() {
+        let repository = temp_repository("storage_replace");
+        let before = repository.list().unwrap().len();
+
+        let duplicated = vec![Book { id: 1, ..Default::default() }, Book { id: 1, ..Default::default() }];
+        assert!(matches!(repository.replace(duplicated), Err(BookError::BadRequest(_))));
+        assert_eq!(repository.list().unwrap().len(), before);
+
+        let books = vec![Book { id: 7000, title: "Swapped".to_string(), content: "borrow checker".to_string(), ..Default::default() }];
+        assert_eq!(repository.replace(books).unwrap(), before);
+
+        assert_eq!(repository.list().unwrap().len(), 1);
+        assert!(repository.get(1).unwrap().is_none());
+        assert_eq!(repository.full_text("borrow").unwrap()[0].book.id, 7000);
+
+        fs::remove_file(&repository.store.data_file).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! データファイルの暗号化 (AES-256-GCM)。
+//!
+//! 暗号化したファイルは `MAGIC`・ノンス・暗号文 (末尾に認証タグ) の順に並ぶ。
+//! 先頭が `MAGIC` でなければ平文として読むので、鍵を設定した後も既存のファイルはそのまま読め、
+//! 次の書き込みで暗号化される。バックアップはファイルの複製なので、同じ鍵で暗号化されたままになる。
+
+use std::fmt;
+use std::sync::Arc;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::BookError;
+
+const MAGIC: &[u8] = b"BOOKSENC1\n";
+
+/// データファイルの鍵。`Debug` では中身を出さない。
+#[derive(Clone)]
+pub struct EncryptionKey(Arc<LessSafeKey>);
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+impl EncryptionKey {
+    /// base64 で書いた 32 バイトの鍵 (`openssl rand -base64 32` の出力) を読む。
+    pub fn parse(encoded: &str) -> Result<Self, String> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| e.to_string())?;
+
+        let key = UnboundKey::new(&AES_256_GCM, &bytes)
+            .map_err(|_| format!("expected {} bytes, got {}", AES_256_GCM.key_len(), bytes.len()))?;
+
+        Ok(EncryptionKey(Arc::new(LessSafeKey::new(key))))
+    }
+
+    pub fn encrypt(&self, mut contents: Vec<u8>) -> Result<Vec<u8>, BookError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| BookError::Encryption("failed to generate a nonce".to_string()))?;
+
+        self.0
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(MAGIC), &mut contents)
+            .map_err(|_| BookError::Encryption("failed to encrypt".to_string()))?;
+
+        let mut sealed = Vec::with_capacity(MAGIC.len() + NONCE_LEN + contents.len());
+        sealed.extend_from_slice(MAGIC);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&contents);
+        Ok(sealed)
+    }
+
+    pub fn decrypt(&self, sealed: &[u8]) -> Result<Vec<u8>, BookError> {
+        let body = sealed.strip_prefix(MAGIC).unwrap_or(sealed);
+        if body.len() < NONCE_LEN {
+            return Err(BookError::Encryption("encrypted file is truncated".to_string()));
+        }
+
+        let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| BookError::Encryption("invalid nonce".to_string()))?;
+
+        let mut contents = ciphertext.to_vec();
+        let len = self.0
+            .open_in_place(nonce, Aad::from(MAGIC), &mut contents)
+            .map_err(|_| BookError::Encryption("wrong key or corrupted file".to_string()))?
+            .len();
+        contents.truncate(len);
+
+        Ok(contents)
+    }
+}
+
+pub fn is_encrypted(contents: &[u8]) -> bool {
+    contents.starts_with(MAGIC)
+}
+
+/// 鍵があれば暗号化する。
+pub fn seal(key: Option<&EncryptionKey>, contents: Vec<u8>) -> Result<Vec<u8>, BookError> {
+    match key {
+        Some(key) => key.encrypt(contents),
+        None => Ok(contents),
+    }
+}
+
+/// 暗号化されていれば復号する。平文ならそのまま返す。
+pub fn open(key: Option<&EncryptionKey>, contents: Vec<u8>) -> Result<Vec<u8>, BookError> {
+    if !is_encrypted(&contents) {
+        return Ok(contents);
+    }
+
+    match key {
+        Some(key) => key.decrypt(&contents),
+        None => Err(BookError::Encryption("the data file is encrypted but no key is configured".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    #[test]
+    fn test_round_trip() {
+        let key = EncryptionKey::parse(KEY).unwrap();
+        let sealed = seal(Some(&key), b"[]".to_vec()).unwrap();
+
+        assert!(is_encrypted(&sealed));
+        assert_eq!(open(Some(&key), sealed.clone()).unwrap(), b"[]");
+        assert!(open(None, sealed.clone()).is_err());
+
+        let other = EncryptionKey::parse("ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=").unwrap();
+        assert!(open(Some(&other), sealed).is_err());
+
+        // 平文のファイルは鍵があってもそのまま読む
+        assert_eq!(open(Some(&key), b"[]".to_vec()).unwrap(), b"[]");
+
+        assert!(EncryptionKey::parse("c2hvcnQ=").is_err());
+        assert_eq!(format!("{:?}", key), "EncryptionKey(..)");
+    }
+}
@@ -0,0 +1,296 @@
+//! 差分同期 (`GET /books/changes`) のための変更の記録。
+//!
+//! 書き込みスレッドがデータファイルを書いたあとに、作成・更新・削除を 1 行 1 件の JSON で
+//! データファイルの隣 (`book.json` なら `book.changes.jsonl`) に追記する。削除も書籍がなくなった
+//! 印 (tombstone) として残すので、クライアントは前回の続きから消えた書籍も知ることができる。
+//!
+//! 残すのは直近 `MAX_ENTRIES` 件まで。それより古い位置や、データセットを差し替えた (`Reset`)
+//! あとの位置から求められたら、差分では追いつけないので全件を取り直してもらう (410)。
+//! データファイルを外部で直接編集した変更は記録されない。
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::events::BookEventKind;
+use crate::BookError;
+
+/// 残す記録の数。ファイルがこの 2 倍を超えたら詰め直す。
+pub const MAX_ENTRIES: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum EntryKind {
+    Created,
+    Updated,
+    Deleted,
+    /// データセット全体の差し替え
+    Reset,
+}
+
+impl From<BookEventKind> for EntryKind {
+    fn from(kind: BookEventKind) -> Self {
+        match kind {
+            BookEventKind::Created => EntryKind::Created,
+            BookEventKind::Updated => EntryKind::Updated,
+            BookEventKind::Deleted => EntryKind::Deleted,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(super) struct Entry {
+    pub seq: u64,
+    pub kind: EntryKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+    /// 記録した時刻 (UNIX 秒)
+    pub at: i64,
+}
+
+/// `since=` の値。数字だけならカーソル (前回の `cursor`)、そうでなければ RFC 3339 の時刻。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Since {
+    Cursor(u64),
+    /// UNIX 秒
+    Time(i64),
+}
+
+impl FromStr for Since {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            return s.parse().map(Since::Cursor).map_err(|_| ());
+        }
+        OffsetDateTime::parse(s, &Rfc3339)
+            .map(|t| Since::Time(t.unix_timestamp()))
+            .map_err(|_| ())
+    }
+}
+
+/// 1 冊の書籍の変更。同じ書籍の変更は最後の状態にまとめる。
+#[derive(Serialize, Clone, Debug)]
+pub struct BookChange {
+    pub id: u32,
+    /// 前回から見て新しく増えたか、変わったか、なくなったか
+    pub kind: BookEventKind,
+    /// 最後に変わった時刻 (UNIX 秒)
+    pub at: i64,
+    /// 今の書籍。削除されていれば省く
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub book: Option<crate::Book>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Changes {
+    /// 次に `since=` に渡す値
+    pub cursor: String,
+    pub changes: Vec<BookChange>,
+}
+
+struct State {
+    entries: VecDeque<Entry>,
+    next_seq: u64,
+    /// 捨てた記録のうち最後の seq。捨てていなければ 0
+    dropped: u64,
+    /// ファイルの行数
+    lines: usize,
+}
+
+pub(super) struct Journal {
+    path: PathBuf,
+    /// 最初に使うときに読む
+    state: Mutex<Option<State>>,
+}
+
+/// データファイルに対応する記録のファイル。
+pub(super) fn path_for(data_file: &Path) -> PathBuf {
+    data_file.with_extension("changes.jsonl")
+}
+
+fn load(path: &Path) -> io::Result<State> {
+    let mut entries = VecDeque::new();
+    let mut lines = 0;
+
+    match fs::File::open(path) {
+        Ok(file) => {
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                lines += 1;
+                match serde_json::from_str::<Entry>(&line) {
+                    Ok(entry) => {
+                        if entries.len() == MAX_ENTRIES {
+                            entries.pop_front();
+                        }
+                        entries.push_back(entry);
+                    }
+                    Err(e) => log::warn!("Skipping unreadable change record in {}: {}", path.display(), e),
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let next_seq = entries.back().map_or(1, |e: &Entry| e.seq + 1);
+    let dropped = entries.front().map_or(0, |e| e.seq - 1);
+    Ok(State { entries, next_seq, dropped, lines })
+}
+
+impl Journal {
+    pub fn new(path: PathBuf) -> Self {
+        Journal { path, state: Mutex::new(None) }
+    }
+
+    fn state(&self) -> Result<MutexGuard<'_, Option<State>>, BookError> {
+        let mut state = self.state.lock().unwrap();
+        if state.is_none() {
+            *state = Some(load(&self.path)?);
+        }
+        Ok(state)
+    }
+
+    /// 書き込んだ変更を記録する。`reset` ならデータセットを差し替えた印も残す。
+    /// 記録に失敗しても書き込み自体は成功しているので、ログに残すだけにする。
+    pub fn record(&self, events: &[(BookEventKind, u32, String)], reset: bool) {
+        if let Err(e) = self.append(events, reset) {
+            log::error!("Failed to record changes in {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn append(&self, events: &[(BookEventKind, u32, String)], reset: bool) -> Result<(), BookError> {
+        let mut guard = self.state()?;
+        let state = guard.as_mut().expect("journal state is loaded");
+        let at = OffsetDateTime::now_utc().unix_timestamp();
+
+        let changes = events.iter().map(|(kind, id, _)| (EntryKind::from(*kind), Some(*id)));
+        let reset = reset.then_some((EntryKind::Reset, None));
+
+        let mut out = Vec::new();
+        for (kind, id) in changes.chain(reset) {
+            let entry = Entry { seq: state.next_seq, kind, id, at };
+            state.next_seq += 1;
+            serde_json::to_writer(&mut out, &entry)?;
+            out.push(b'\n');
+            state.lines += 1;
+
+            if state.entries.len() == MAX_ENTRIES {
+                if let Some(old) = state.entries.pop_front() {
+                    state.dropped = old.seq;
+                }
+            }
+            state.entries.push_back(entry);
+        }
+
+        if state.lines > 2 * MAX_ENTRIES {
+            // 残している分だけで書き直す
+            let mut contents = Vec::new();
+            for entry in &state.entries {
+                serde_json::to_writer(&mut contents, entry)?;
+                contents.push(b'\n');
+            }
+            super::replace_file(&self.path, &contents)?;
+            state.lines = state.entries.len();
+        } else {
+            OpenOptions::new().create(true).append(true).open(&self.path)?.write_all(&out)?;
+        }
+
+        Ok(())
+    }
+
+    /// 残っている記録から、書籍ごとに最後に変わった seq。
+    pub fn last_seqs(&self) -> Result<HashMap<u32, u64>, BookError> {
+        let guard = self.state()?;
+        let state = guard.as_ref().expect("journal state is loaded");
+
+        Ok(state.entries.iter()
+            .filter_map(|e| e.id.map(|id| (id, e.seq)))
+            .collect())
+    }
+
+    /// `since` より後の記録と、今の位置 (次に `since` に渡すカーソル) を返す。
+    /// 記録が残っていない位置や差し替えをまたぐ位置なら `BookError::Gone`。
+    pub fn since(&self, since: Since) -> Result<(Vec<Entry>, u64), BookError> {
+        let guard = self.state()?;
+        let state = guard.as_ref().expect("journal state is loaded");
+        let latest = state.next_seq - 1;
+
+        let entries: Vec<Entry> = match since {
+            Since::Cursor(cursor) => {
+                if cursor > latest || cursor < state.dropped {
+                    return Err(BookError::Gone("the cursor is no longer valid, fetch all books again".to_string()));
+                }
+                state.entries.iter().filter(|e| e.seq > cursor).cloned().collect()
+            }
+            Since::Time(time) => {
+                if state.dropped > 0 && state.entries.front().is_none_or(|e| e.at > time) {
+                    return Err(BookError::Gone("changes since that time are no longer kept, fetch all books again".to_string()));
+                }
+                // 同じ秒の変更は取りこぼさないように含める
+                state.entries.iter().filter(|e| e.at >= time).cloned().collect()
+            }
+        };
+
+        if entries.iter().any(|e| e.kind == EntryKind::Reset) {
+            return Err(BookError::Gone("the data set was replaced, fetch all books again".to_string()));
+        }
+
+        Ok((entries, latest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_journal(name: &str) -> Journal {
+        let path = std::env::temp_dir().join(format!("books_backend_journal_{}_{}.jsonl", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        Journal::new(path)
+    }
+
+    fn event(kind: BookEventKind, id: u32) -> (BookEventKind, u32, String) {
+        (kind, id, String::new())
+    }
+
+    #[test]
+    fn test_since() {
+        let journal = temp_journal("since");
+        assert_eq!(journal.since(Since::Cursor(0)).unwrap(), (Vec::new(), 0));
+
+        journal.record(&[event(BookEventKind::Created, 1), event(BookEventKind::Updated, 2)], false);
+        journal.record(&[event(BookEventKind::Deleted, 1)], false);
+
+        let (entries, cursor) = journal.since(Since::Cursor(1)).unwrap();
+        assert_eq!(cursor, 3);
+        assert_eq!(entries.iter().map(|e| (e.seq, e.kind, e.id)).collect::<Vec<_>>(), vec![
+            (2, EntryKind::Updated, Some(2)),
+            (3, EntryKind::Deleted, Some(1)),
+        ]);
+        assert_eq!(journal.since(Since::Time(0)).unwrap().0.len(), 3);
+        assert!(matches!(journal.since(Since::Cursor(4)), Err(BookError::Gone(_))));
+
+        // 読み直しても続きから採番する
+        let reloaded = Journal::new(journal.path.clone());
+        reloaded.record(&[], true);
+        assert!(matches!(reloaded.since(Since::Cursor(3)), Err(BookError::Gone(_))));
+        assert_eq!(reloaded.since(Since::Cursor(4)).unwrap(), (Vec::new(), 4));
+
+        fs::remove_file(&journal.path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!("42".parse::<Since>(), Ok(Since::Cursor(42)));
+        assert_eq!("2024-01-02T03:04:05Z".parse::<Since>(), Ok(Since::Time(1704164645)));
+        assert!("yesterday".parse::<Since>().is_err());
+        assert!("".parse::<Since>().is_err());
+    }
+}
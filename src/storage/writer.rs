@@ -0,0 +1,583 @@
+//! 変更を 1 本のスレッドに集める書き込み役。
+//!
+//! 読み込み → 変更 → 書き込みを直列に行うので、同時に届いた POST が互いの変更を
+//! 上書きすることがない。書き込み中に溜まった変更はまとめて適用し、ファイルへの
+//! 書き込みを 1 回で済ませる。
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use serde::Serialize;
+
+use super::Store;
+use crate::audio::Audio;
+use crate::authors::{self, Author, Authors, Edit};
+use crate::bulk_tags::TagChange;
+use crate::events::BookEventKind;
+use crate::genres::{self, Genres};
+use crate::publishers::{self, Publisher, Publishers};
+use crate::quotes::{self, Quote, Quotes};
+use crate::registry::Named;
+use crate::synonyms::{self as tag_synonyms, Synonyms};
+use crate::sync::{self, Applied, Conflict, LocalChange, Resolution, Strategy};
+use crate::{Book, BookError};
+
+/// 1 回の書き込みにまとめる変更の上限。
+const MAX_BATCH: usize = 256;
+
+pub(super) enum Change {
+    Upsert(Book),
+    UpsertMany(Vec<Book>),
+    Delete(u32),
+    /// データセット全体の差し替え。
+    Replace(Vec<Book>),
+    /// それより前に送られた変更がすべて書き込まれたことを確認するだけの空の変更。
+    Flush,
+    /// クライアントの変更を、`base` より後にサーバーで変わっていないか確かめながら適用する。
+    Sync { changes: Vec<(LocalChange, u64)>, strategy: Strategy },
+    /// タグの同義語の差し替え。データファイルには触れず、全文検索の索引だけ作り直す。
+    Synonyms(Synonyms),
+    /// ジャンルの一覧の差し替え。書籍に付いているジャンルを外すものは断る。
+    Genres(Genres),
+    /// 著者の記録の変更。名前が変わった著者の書籍も書き換える。
+    Authors(Edit),
+    /// 出版社の記録の変更。名前が変わった出版社の書籍も書き換える。
+    Publishers(publishers::Edit),
+    /// オーディオブックの再生位置 (秒) の記録。
+    Progress { id: u32, seconds: u32 },
+    /// 引用の登録・削除。データファイルには触れない。
+    Quotes(quotes::Edit),
+    /// 複数の書籍のタグの付け外し。ない id は飛ばす。
+    Tags { ids: Vec<u32>, change: TagChange },
+    /// `borrower` が借りている書籍をすべて返却扱いにする。
+    ReturnLoans(String),
+}
+
+pub(super) enum Outcome {
+    Upserted { books: Vec<Book>, created: bool },
+    UpsertedMany(usize),
+    Deleted(Option<Box<Book>>),
+    /// 差し替え前の件数
+    Replaced(usize),
+    Flushed,
+    Synced(Applied),
+    SynonymsSet,
+    GenresSet,
+    Authors(Vec<Author>),
+    Publisher(Publisher),
+    Progress(Audio),
+    Quote(Quote),
+    /// (対象になった書籍数, タグが変わった書籍数)
+    Tagged { matched: usize, updated: usize },
+    /// 返却扱いにした書籍の id
+    Returned(Vec<u32>),
+}
+
+pub(super) struct Job {
+    pub change: Change,
+    pub reply: mpsc::Sender<Result<Outcome, BookError>>,
+}
+
+/// 書き込む前の付属ファイルの中身。元々なかったファイルは `None`。
+type Backup = Vec<(PathBuf, Option<Vec<u8>>)>;
+
+/// 書籍の書き込みに合わせて保存する付属ファイル (同義語・ジャンル・著者・出版社・引用) の変更。
+///
+/// 付属ファイルとメモリ上の記録だけが先に変わって `book.json` と食い違わないよう、
+/// 書籍の書き込みに失敗したらファイルを元に戻し、記録は成功してから差し替える。
+#[derive(Default)]
+struct Pending {
+    synonyms: Option<Arc<Synonyms>>,
+    genres: Option<Arc<Genres>>,
+    authors: Option<Arc<Authors>>,
+    publishers: Option<Arc<Publishers>>,
+    quotes: Option<Arc<Quotes>>,
+    /// パス → 書き込む内容。同じ回に同じファイルを何度か変えたら最後の内容だけ書く
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl Pending {
+    /// 同じ回に先に変えた記録があればそれを、なければ今の記録を返す。
+    fn authors(&self, store: &Store) -> Arc<Authors> {
+        self.authors.clone().unwrap_or_else(|| store.authors())
+    }
+
+    fn publishers(&self, store: &Store) -> Arc<Publishers> {
+        self.publishers.clone().unwrap_or_else(|| store.publishers())
+    }
+
+    fn quotes(&self, store: &Store) -> Arc<Quotes> {
+        self.quotes.clone().unwrap_or_else(|| store.quotes())
+    }
+
+    fn stage(&mut self, path: PathBuf, value: &impl Serialize) -> Result<(), BookError> {
+        self.files.insert(path, serde_json::to_vec_pretty(value)?);
+        Ok(())
+    }
+
+    /// 付属ファイルを書き込み、元の内容を返す。途中で失敗したら書いた分を戻す。
+    fn save(&self) -> Result<Backup, BookError> {
+        let mut previous = Vec::with_capacity(self.files.len());
+
+        for (path, contents) in &self.files {
+            let saved = match fs::read(path) {
+                Ok(old) => Ok(Some(old)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+            .and_then(|old| {
+                super::replace_file(path, contents)?;
+                Ok(old)
+            });
+
+            match saved {
+                Ok(old) => previous.push((path.clone(), old)),
+                Err(e) => {
+                    restore(previous);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(previous)
+    }
+
+    /// メモリ上の記録を差し替える。ファイルはもう書き込んである。
+    fn swap(self, store: &Store) {
+        if let Some(synonyms) = self.synonyms {
+            *store.synonyms.write().unwrap() = synonyms;
+        }
+        if let Some(genres) = self.genres {
+            *store.genres.write().unwrap() = genres;
+        }
+        if let Some(authors) = self.authors {
+            *store.authors.write().unwrap() = authors;
+        }
+        if let Some(publishers) = self.publishers {
+            *store.publishers.write().unwrap() = publishers;
+        }
+        if let Some(quotes) = self.quotes {
+            *store.quotes.write().unwrap() = quotes;
+        }
+    }
+}
+
+/// `Pending::save` で書き込んだ付属ファイルを元に戻す。元々なかったファイルは消す。
+fn restore(previous: Backup) {
+    for (path, contents) in previous {
+        let restored = match contents {
+            Some(contents) => super::replace_file(&path, &contents),
+            None => fs::remove_file(&path),
+        };
+        if let Err(e) = restored {
+            log::error!("Failed to restore {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// 書き込みスレッドが止まっているときのエラー。
+pub(super) fn stopped() -> BookError {
+    BookError::FileReadError(io::Error::other("storage writer has stopped"))
+}
+
+/// 書き込みスレッドを起動する。送信側がすべて破棄されるとスレッドも終了する。
+pub(super) fn spawn(store: Store) -> mpsc::Sender<Job> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::Builder::new()
+        .name("books-writer".to_string())
+        .spawn(move || run(store, receiver))
+        .expect("Failed to spawn storage writer thread");
+
+    sender
+}
+
+fn run(store: Store, receiver: mpsc::Receiver<Job>) {
+    while let Ok(first) = receiver.recv() {
+        let mut jobs = vec![first];
+        jobs.extend(receiver.try_iter().take(MAX_BATCH - 1));
+
+        apply(&store, jobs);
+    }
+}
+
+fn upsert(books: &mut Vec<Book>, index: &mut HashMap<u32, usize>, book: Book) -> bool {
+    match index.get(&book.id) {
+        Some(&pos) => {
+            books[pos] = book;
+            false
+        }
+        None => {
+            index.insert(book.id, books.len());
+            books.push(book);
+            true
+        }
+    }
+}
+
+fn delete(books: &mut Vec<Book>, index: &mut HashMap<u32, usize>, id: u32) -> Option<Book> {
+    let pos = index.remove(&id)?;
+    let removed = books.remove(pos);
+
+    for p in index.values_mut() {
+        if *p > pos {
+            *p -= 1;
+        }
+    }
+
+    Some(removed)
+}
+
+/// 溜まった変更を順に適用し、1 回だけ書き込んでから各送信元に結果を返す。
+#[tracing::instrument(skip_all, fields(jobs = jobs.len()))]
+fn apply(store: &Store, jobs: Vec<Job>) {
+    let snapshot = match store.snapshot() {
+        Ok(snapshot) => snapshot,
+        Err(e) => return fail(jobs.into_iter().map(|job| job.reply), &e),
+    };
+
+    let mut books = snapshot.books.clone();
+    let mut index = snapshot.index.clone();
+    let mut events = Vec::new();
+    let mut replaced = false;
+    let mut replies = Vec::with_capacity(jobs.len());
+    // 同じ回にまとめた変更はまだ記録されていないので、同期の衝突の判定のために覚えておく
+    let mut touched: HashSet<u32> = HashSet::new();
+    let mut reindex = false;
+    let mut pending = Pending::default();
+
+    let single = match jobs.as_slice() {
+        [Job { change: Change::Upsert(book), .. }] => Some(Change::Upsert(book.clone())),
+        [Job { change: Change::Delete(id), .. }] => Some(Change::Delete(*id)),
+        [Job { change: Change::Progress { id, seconds }, .. }] => Some(Change::Progress { id: *id, seconds: *seconds }),
+        _ => None,
+    };
+
+    for Job { change, reply } in jobs {
+        let outcome = match change {
+            Change::Upsert(book) => {
+                let (id, title) = (book.id, book.title.clone());
+                let created = upsert(&mut books, &mut index, book);
+                touched.insert(id);
+
+                let kind = if created { BookEventKind::Created } else { BookEventKind::Updated };
+                events.push((kind, id, title));
+
+                Outcome::Upserted { books: books.clone(), created }
+            }
+            Change::UpsertMany(incoming) => {
+                let mut created = 0;
+
+                for book in incoming {
+                    let (id, title) = (book.id, book.title.clone());
+                    touched.insert(id);
+
+                    let kind = if upsert(&mut books, &mut index, book) {
+                        created += 1;
+                        BookEventKind::Created
+                    } else {
+                        BookEventKind::Updated
+                    };
+                    events.push((kind, id, title));
+                }
+
+                Outcome::UpsertedMany(created)
+            }
+            Change::Delete(id) => {
+                let removed = delete(&mut books, &mut index, id);
+                touched.insert(id);
+
+                if let Some(book) = &removed {
+                    events.push((BookEventKind::Deleted, book.id, book.title.clone()));
+                }
+
+                Outcome::Deleted(removed.map(Box::new))
+            }
+            Change::Replace(incoming) => {
+                let previous = books.len();
+
+                books = incoming;
+                index = HashMap::with_capacity(books.len());
+                for (pos, book) in books.iter().enumerate() {
+                    index.entry(book.id).or_insert(pos);
+                }
+                replaced = true;
+
+                Outcome::Replaced(previous)
+            }
+            Change::Flush => Outcome::Flushed,
+            Change::Sync { changes, strategy } => {
+                // 記録が読めないのに変わっていないと見なすと、サーバーの新しい変更を黙って上書きしてしまう
+                let last = match store.journal.last_seqs() {
+                    Ok(last) => last,
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                        continue;
+                    }
+                };
+                let mut applied = Applied::default();
+
+                for (change, base) in changes {
+                    let id = change.id();
+                    let changed = replaced || touched.contains(&id) || last.get(&id).is_some_and(|&seq| seq > base);
+
+                    let target = if changed {
+                        let server = index.get(&id).map(|&pos| books[pos].clone());
+                        let client = change.into_book();
+                        let (resolution, result) = sync::resolve(strategy, server.as_ref(), client.as_ref());
+                        applied.conflicts.push(Conflict { id, resolution, server, client, result: result.clone() });
+                        if resolution == Resolution::Server {
+                            continue;
+                        }
+                        result
+                    } else {
+                        applied.accepted.push(id);
+                        change.into_book()
+                    };
+
+                    touched.insert(id);
+                    match target {
+                        Some(book) => {
+                            let title = book.title.clone();
+                            let kind = if upsert(&mut books, &mut index, book) { BookEventKind::Created } else { BookEventKind::Updated };
+                            events.push((kind, id, title));
+                        }
+                        None => {
+                            if let Some(removed) = delete(&mut books, &mut index, id) {
+                                events.push((BookEventKind::Deleted, id, removed.title));
+                            }
+                        }
+                    }
+                }
+
+                Outcome::Synced(applied)
+            }
+            Change::Synonyms(synonyms) => {
+                if let Err(e) = pending.stage(tag_synonyms::path_for(&store.data_file), &synonyms) {
+                    let _ = reply.send(Err(e));
+                    continue;
+                }
+
+                pending.synonyms = Some(Arc::new(synonyms));
+                reindex = true;
+
+                Outcome::SynonymsSet
+            }
+            Change::Genres(taxonomy) => {
+                // 同じ回に先に適用した変更も含めて確かめる
+                let missing = taxonomy.missing(&books);
+                let staged = if missing.is_empty() {
+                    pending.stage(genres::path_for(&store.data_file), &taxonomy)
+                } else {
+                    Err(BookError::Conflict(format!("genres still assigned to books: {}", missing.join(", "))))
+                };
+                if let Err(e) = staged {
+                    let _ = reply.send(Err(e));
+                    continue;
+                }
+
+                pending.genres = Some(Arc::new(taxonomy));
+
+                Outcome::GenresSet
+            }
+            Change::Authors(edit) => {
+                let staged = pending.authors(store).plan(edit, &books).and_then(|plan| {
+                    pending.stage(authors::path_for(&store.data_file), &plan.records)?;
+                    Ok(plan)
+                });
+                let plan = match staged {
+                    Ok(plan) => plan,
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                        continue;
+                    }
+                };
+
+                rename_books::<Author>(&mut books, &plan.renames, &mut touched, &mut events);
+                pending.authors = Some(Arc::new(plan.records));
+
+                Outcome::Authors(plan.result)
+            }
+            Change::Publishers(edit) => {
+                let staged = pending.publishers(store).plan(edit, &books).and_then(|plan| {
+                    pending.stage(publishers::path_for(&store.data_file), &plan.records)?;
+                    Ok(plan)
+                });
+                let plan = match staged {
+                    Ok(plan) => plan,
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                        continue;
+                    }
+                };
+
+                rename_books::<Publisher>(&mut books, &plan.renames, &mut touched, &mut events);
+                pending.publishers = Some(Arc::new(plan.records));
+
+                Outcome::Publisher(plan.result)
+            }
+            Change::Progress { id, seconds } => {
+                let Some(book) = index.get(&id).map(|&pos| &mut books[pos]) else {
+                    let _ = reply.send(Err(BookError::NotFound));
+                    continue;
+                };
+                let updated = match book.audio.as_mut() {
+                    Some(audio) => audio.set_position(seconds).map(|()| audio.clone()),
+                    None => Err(BookError::Conflict(format!("book {} has no audiobook duration", id))),
+                };
+                let audio = match updated {
+                    Ok(audio) => audio,
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                        continue;
+                    }
+                };
+
+                touched.insert(id);
+                events.push((BookEventKind::Updated, id, book.title.clone()));
+
+                Outcome::Progress(audio)
+            }
+            Change::Quotes(edit) => {
+                let staged = match &edit {
+                    quotes::Edit::Add { book_id, .. } if !index.contains_key(book_id) => Err(BookError::NotFound),
+                    _ => pending.quotes(store).apply(edit),
+                }
+                .and_then(|(quotes, quote)| {
+                    pending.stage(quotes::path_for(&store.data_file), &quotes)?;
+                    Ok((quotes, quote))
+                });
+                let (quotes, quote) = match staged {
+                    Ok(saved) => saved,
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                        continue;
+                    }
+                };
+
+                pending.quotes = Some(Arc::new(quotes));
+
+                Outcome::Quote(quote)
+            }
+            Change::Tags { ids, change } => {
+                let (mut matched, mut updated) = (0, 0);
+
+                for id in ids {
+                    let Some(&pos) = index.get(&id) else {
+                        continue;
+                    };
+                    matched += 1;
+                    let book = &mut books[pos];
+                    if let Some(tags) = change.apply(&book.tags) {
+                        book.tags = tags;
+                        updated += 1;
+                        touched.insert(id);
+                        events.push((BookEventKind::Updated, id, book.title.clone()));
+                    }
+                }
+
+                Outcome::Tagged { matched, updated }
+            }
+            Change::ReturnLoans(borrower) => {
+                let mut returned = Vec::new();
+
+                for book in books.iter_mut() {
+                    if book.loan.as_ref().is_some_and(|loan| loan.borrower == borrower) {
+                        book.loan = None;
+                        returned.push(book.id);
+                        touched.insert(book.id);
+                        events.push((BookEventKind::Updated, book.id, book.title.clone()));
+                    }
+                }
+
+                Outcome::Returned(returned)
+            }
+        };
+
+        replies.push((reply, outcome));
+    }
+
+    // 書籍にあって記録のない出版社は、書籍と同じ回に記録にする
+    if !events.is_empty() || replaced {
+        if let Some(registered) = pending.publishers(store).register(&books) {
+            match pending.stage(publishers::path_for(&store.data_file), &registered) {
+                Ok(()) => pending.publishers = Some(Arc::new(registered)),
+                Err(e) => log::error!("Failed to register publishers for {}: {}", store.data_file.display(), e),
+            }
+        }
+    }
+
+    let previous = match pending.save() {
+        Ok(previous) => previous,
+        Err(e) => return fail(replies.into_iter().map(|(reply, _)| reply), &e),
+    };
+
+    if events.is_empty() && !replaced {
+        // 存在しない id の削除や Flush だけならデータファイルに触れない
+        pending.swap(store);
+        if reindex {
+            store.search_index.rebuild(&snapshot.books, &store.synonyms());
+        }
+        for (reply, outcome) in replies {
+            let _ = reply.send(Ok(outcome));
+        }
+        return;
+    }
+
+    let written = match store.write(books) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            restore(previous);
+            return fail(replies.into_iter().map(|(reply, _)| reply), &e);
+        }
+    };
+    pending.swap(store);
+
+    // 1 件だけなら索引も差分で更新し、まとめて書いたときは作り直す
+    let synonyms = store.synonyms();
+    match single {
+        Some(Change::Upsert(book)) => store.search_index.upsert(&book, &synonyms),
+        Some(Change::Delete(id)) => store.search_index.delete(id),
+        // 再生位置は索引に入らない
+        Some(Change::Progress { .. }) => {}
+        _ => store.search_index.rebuild(&written.books, &synonyms),
+    }
+
+    for (kind, id, title) in &events {
+        // 削除した本はもうないので見せ方もない
+        let visibility = written.get(*id).and_then(|book| book.visibility);
+        store.events.publish(*kind, *id, title, visibility);
+    }
+    store.journal.record(&events, replaced);
+
+    for (reply, outcome) in replies {
+        let _ = reply.send(Ok(outcome));
+    }
+}
+
+/// 記録の名前の変更を書籍に反映する。
+fn rename_books<T: Named>(
+    books: &mut [Book],
+    renames: &[(String, String)],
+    touched: &mut HashSet<u32>,
+    events: &mut Vec<(BookEventKind, u32, String)>,
+) {
+    for book in books.iter_mut() {
+        if T::rename_in(book, renames) {
+            touched.insert(book.id);
+            events.push((BookEventKind::Updated, book.id, book.title.clone()));
+        }
+    }
+}
+
+fn fail(replies: impl IntoIterator<Item = mpsc::Sender<Result<Outcome, BookError>>>, error: &BookError) {
+    log::error!("Failed to apply storage changes: {}", error);
+
+    for reply in replies {
+        let _ = reply.send(Err(BookError::FileReadError(io::Error::other(error.to_string()))));
+    }
+}
@@ -0,0 +1,176 @@
+//! 入力途中の語の補完 (`/suggest`)。
+//!
+//! タイトル・タグ・著者名を、それぞれの語の先頭から前方一致で引けるように、正規化した
+//! 「語の先頭から末尾まで」の文字列を `BTreeMap` の範囲検索で引く。`Async in Rust` なら
+//! `async in rust` / `in rust` / `rust` の 3 つのキーで登録するので、`ru` でも見つかる。
+//! 索引は書籍のスナップショットと一緒に作り直す。
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use serde::Serialize;
+
+use crate::normalize::fold;
+use crate::Book;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestionKind {
+    Title,
+    Tag,
+    Author,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Suggestion {
+    #[serde(rename = "type")]
+    pub kind: SuggestionKind,
+    pub value: String,
+    /// その値を持つ (見える) 書籍の数
+    pub count: usize,
+}
+
+struct Entry {
+    kind: SuggestionKind,
+    value: String,
+    ids: Vec<u32>,
+}
+
+#[derive(Default)]
+pub struct SuggestIndex {
+    /// 正規化した語の先頭からの文字列 → `entries` の位置
+    keys: BTreeMap<String, Vec<usize>>,
+    entries: Vec<Entry>,
+}
+
+/// 各語の先頭から末尾までの文字列。
+fn word_suffixes(value: &str) -> Vec<String> {
+    let lower = fold(value);
+    let mut suffixes = Vec::new();
+    let mut previous: Option<char> = None;
+
+    for (i, c) in lower.char_indices() {
+        if c.is_alphanumeric() && previous.is_none_or(|p| !p.is_alphanumeric()) {
+            suffixes.push(lower[i..].to_string());
+        }
+        previous = Some(c);
+    }
+
+    suffixes
+}
+
+impl SuggestIndex {
+    pub fn new(books: &[Book]) -> Self {
+        let mut positions: HashMap<(SuggestionKind, &str), usize> = HashMap::new();
+        let mut entries: Vec<Entry> = Vec::new();
+
+        for book in books {
+            let values = std::iter::once((SuggestionKind::Title, book.title.as_str()))
+                .chain(book.tags.iter().map(|t| (SuggestionKind::Tag, t.as_str())))
+                .chain(book.authors.iter().map(|a| (SuggestionKind::Author, a.as_str())));
+
+            for (kind, value) in values {
+                if value.trim().is_empty() {
+                    continue;
+                }
+                let pos = *positions.entry((kind, value)).or_insert_with(|| {
+                    entries.push(Entry { kind, value: value.to_string(), ids: Vec::new() });
+                    entries.len() - 1
+                });
+                if entries[pos].ids.last() != Some(&book.id) {
+                    entries[pos].ids.push(book.id);
+                }
+            }
+        }
+
+        let mut keys: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (pos, entry) in entries.iter().enumerate() {
+            for suffix in word_suffixes(&entry.value) {
+                keys.entry(suffix).or_default().push(pos);
+            }
+        }
+
+        SuggestIndex { keys, entries }
+    }
+
+    /// `prefix` で始まる語を含む値を、値全体が前方一致するもの・書籍の多いもの・値の順に
+    /// `limit` 件まで返す。`count` は `visible` な書籍だけを数え、0 になる値は返さない。
+    pub fn suggest(&self, prefix: &str, limit: usize, visible: impl Fn(u32) -> bool) -> Vec<Suggestion> {
+        let prefix = fold(prefix.trim());
+        if prefix.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+
+        let matched: BTreeSet<usize> = self.keys.range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .flat_map(|(_, positions)| positions.iter().copied())
+            .collect();
+
+        let mut suggestions: Vec<(bool, Suggestion)> = matched.into_iter()
+            .filter_map(|pos| {
+                let entry = &self.entries[pos];
+                let count = entry.ids.iter().filter(|&&id| visible(id)).count();
+                (count > 0).then(|| {
+                    let whole = fold(&entry.value).starts_with(&prefix);
+                    (whole, Suggestion { kind: entry.kind, value: entry.value.clone(), count })
+                })
+            })
+            .collect();
+
+        suggestions.sort_by(|(a_whole, a), (b_whole, b)| {
+            b_whole.cmp(a_whole)
+                .then_with(|| b.count.cmp(&a.count))
+                .then_with(|| a.value.to_lowercase().cmp(&b.value.to_lowercase()))
+                .then_with(|| a.kind.cmp(&b.kind))
+        });
+        suggestions.truncate(limit);
+
+        suggestions.into_iter().map(|(_, suggestion)| suggestion).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(id: u32, title: &str, tags: &[&str], authors: &[&str]) -> Book {
+        Book {
+            id,
+            title: title.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            authors: authors.iter().map(|a| a.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_suggest() {
+        let index = SuggestIndex::new(&[
+            book(1, "Rust Basics", &["rust", "beginner"], &["Steve Klabnik"]),
+            book(2, "Async in Rust", &["async", "rust"], &[]),
+            book(3, "Ruby on Rails", &["ruby"], &["Russ Olsen"]),
+        ]);
+
+        let values = |prefix: &str, visible: &dyn Fn(u32) -> bool| {
+            index.suggest(prefix, 10, visible)
+                .into_iter()
+                .map(|s| (s.kind, s.value, s.count))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(values("RU", &|_| true), vec![
+            (SuggestionKind::Tag, "rust".to_string(), 2),
+            (SuggestionKind::Tag, "ruby".to_string(), 1),
+            (SuggestionKind::Title, "Ruby on Rails".to_string(), 1),
+            (SuggestionKind::Author, "Russ Olsen".to_string(), 1),
+            (SuggestionKind::Title, "Rust Basics".to_string(), 1),
+            (SuggestionKind::Title, "Async in Rust".to_string(), 1),
+        ]);
+        assert_eq!(values("klab", &|_| true), vec![(SuggestionKind::Author, "Steve Klabnik".to_string(), 1)]);
+
+        // 見えない書籍だけが持つ値は出さない
+        assert_eq!(values("rust", &|id| id == 2), vec![
+            (SuggestionKind::Tag, "rust".to_string(), 1),
+            (SuggestionKind::Title, "Async in Rust".to_string(), 1),
+        ]);
+        assert!(values(" ", &|_| true).is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+//! `POST /sync` (オフラインで編集したクライアントとの同期)。
+//!
+//! クライアントは手元の変更を、それぞれ最後にサーバーと揃えたときの `cursor` (`base`) と一緒に送る。
+//! `base` より後にサーバー側でもその書籍が変わっていれば衝突として、`strategy` で決める。
+//!
+//! - `server-wins` (既定): サーバーの書籍を残し、クライアントの変更は捨てる。
+//! - `client-wins`: クライアントの変更で上書きする (削除も含む)。
+//! - `merge`: 両方にある書籍は、サーバーの値を優先しつつ空の欄をクライアントの値で埋め、
+//!   タグ・ジャンル・著者は両方を合わせる。片方が削除していれば、編集した方を残す。
+//!
+//! 衝突の判定と適用は書き込みスレッドでほかの変更と直列に行う。応答には受け入れた変更と衝突のほか、
+//! `since` より後のサーバー側の変更 (`GET /books/changes` と同じもの) を入れる。
+
+use serde::{Deserialize, Serialize};
+
+use crate::normalize::fold;
+use crate::storage::BookChange;
+use crate::{Book, BookError};
+
+/// 1 回の同期で送れる変更の数の上限。
+pub const MAX_CHANGES: usize = 1000;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    #[default]
+    ServerWins,
+    ClientWins,
+    Merge,
+}
+
+/// クライアントの手元の変更。`base` はその書籍を最後にサーバーと揃えたときの `cursor`。
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum LocalChange {
+    Upsert { book: Box<Book>, base: String },
+    Delete { id: u32, base: String },
+}
+
+impl LocalChange {
+    pub fn id(&self) -> u32 {
+        match self {
+            LocalChange::Upsert { book, .. } => book.id,
+            LocalChange::Delete { id, .. } => *id,
+        }
+    }
+
+    pub fn base(&self) -> Result<u64, BookError> {
+        let base = match self {
+            LocalChange::Upsert { base, .. } | LocalChange::Delete { base, .. } => base,
+        };
+        base.parse().map_err(|_| BookError::BadRequest(format!("base: {:?} is not a cursor", base)))
+    }
+
+    /// 適用したあとの書籍。削除なら `None`。
+    pub fn into_book(self) -> Option<Book> {
+        match self {
+            LocalChange::Upsert { book, .. } => Some(*book),
+            LocalChange::Delete { .. } => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SyncRequest {
+    /// 前回の同期で受け取った `cursor`。サーバー側の変更をここから返す
+    pub since: String,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub changes: Vec<LocalChange>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    /// サーバーの書籍を残した
+    Server,
+    /// クライアントの変更を適用した
+    Client,
+    /// 両方を合わせた
+    Merged,
+}
+
+/// 衝突した変更。`None` は削除されていることを表す。
+#[derive(Serialize, Clone, Debug)]
+pub struct Conflict {
+    pub id: u32,
+    pub resolution: Resolution,
+    pub server: Option<Book>,
+    pub client: Option<Book>,
+    /// 解決したあとの書籍
+    pub result: Option<Book>,
+}
+
+/// 書き込みスレッドが返す適用結果。
+#[derive(Debug, Default)]
+pub struct Applied {
+    /// 衝突せずにそのまま適用した書籍の id
+    pub accepted: Vec<u32>,
+    pub conflicts: Vec<Conflict>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SyncResponse {
+    pub accepted: Vec<u32>,
+    pub conflicts: Vec<Conflict>,
+    /// 次の同期で `since` と `base` に使う値
+    pub cursor: String,
+    /// `since` より後のサーバー側の変更 (この同期で適用したものを含む)
+    pub changes: Vec<BookChange>,
+}
+
+/// 重なっていない値を `extra` から足す。
+fn union(mut values: Vec<String>, extra: &[String]) -> Vec<String> {
+    for value in extra {
+        if !values.iter().any(|v| fold(v) == fold(value)) {
+            values.push(value.clone());
+        }
+    }
+    values
+}
+
+/// サーバーの値を優先し、サーバーで空の欄だけクライアントの値で埋める。タグ・ジャンル・著者・形式は合わせる。
+pub fn merge(server: &Book, client: &Book) -> Book {
+    let or = |a: &String, b: &String| if a.trim().is_empty() { b.clone() } else { a.clone() };
+
+    Book {
+        id: server.id,
+        title: or(&server.title, &client.title),
+        content: or(&server.content, &client.content),
+        tags: union(server.tags.clone(), &client.tags),
+        genres: union(server.genres.clone(), &client.genres),
+        authors: union(server.authors.clone(), &client.authors),
+        published_year: server.published_year.or(client.published_year),
+        publisher: server.publisher.clone().or_else(|| client.publisher.clone()),
+        isbn: server.isbn.clone().or_else(|| client.isbn.clone()),
+        language: server.language.clone().or_else(|| client.language.clone()),
+        content_stats: if server.content.trim().is_empty() { client.content_stats.clone() } else { server.content_stats.clone() },
+        formats: {
+            let mut formats = server.formats.clone();
+            formats.extend(client.formats.iter().filter(|f| !server.formats.contains(f)));
+            formats
+        },
+        audio: server.audio.clone().or_else(|| client.audio.clone()),
+        work: server.work.or(client.work),
+        loan: server.loan.clone().or_else(|| client.loan.clone()),
+        visibility: server.visibility.or(client.visibility),
+    }
+}
+
+/// 衝突したときにどちらを残すか。戻り値の書籍を適用する (`None` なら削除)。
+pub fn resolve(strategy: Strategy, server: Option<&Book>, client: Option<&Book>) -> (Resolution, Option<Book>) {
+    match (strategy, server, client) {
+        (Strategy::ServerWins, server, _) => (Resolution::Server, server.cloned()),
+        (Strategy::ClientWins, _, client) => (Resolution::Client, client.cloned()),
+        (Strategy::Merge, Some(server), Some(client)) => (Resolution::Merged, Some(merge(server, client))),
+        // 片方が削除していれば、編集した方を残す
+        (Strategy::Merge, None, Some(client)) => (Resolution::Client, Some(client.clone())),
+        (Strategy::Merge, server, None) => (Resolution::Server, server.cloned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(title: &str, tags: &[&str], year: Option<i32>) -> Book {
+        Book {
+            id: 1,
+            title: title.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            published_year: year,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let server = book("Rust Basics", &["rust", "beginner"], None);
+        let client = book("Rust basics (draft)", &["Rust", "ownership"], Some(2021));
+
+        let merged = merge(&server, &client);
+        assert_eq!(merged.title, "Rust Basics");
+        assert_eq!(merged.tags, vec!["rust", "beginner", "ownership"]);
+        assert_eq!(merged.published_year, Some(2021));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let server = book("Server", &[], None);
+        let client = book("Client", &[], None);
+
+        assert_eq!(resolve(Strategy::ServerWins, Some(&server), Some(&client)).1.unwrap().title, "Server");
+        let (resolution, result) = resolve(Strategy::ClientWins, Some(&server), None);
+        assert_eq!(resolution, Resolution::Client);
+        assert!(result.is_none());
+        assert_eq!(resolve(Strategy::Merge, Some(&server), None).0, Resolution::Server);
+        assert_eq!(resolve(Strategy::Merge, None, Some(&client)).1.unwrap().title, "Client");
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let request: SyncRequest = serde_json::from_str(r#"{
+            "since": "3",
+            "strategy": "client-wins",
+            "changes": [
+                {"op": "upsert", "base": "3", "book": {"id": 1, "title": "New", "content": "", "tags": []}},
+                {"op": "delete", "base": "2", "id": 4}
+            ]
+        }"#).unwrap();
+
+        assert_eq!(request.strategy, Strategy::ClientWins);
+        assert_eq!(request.changes.iter().map(LocalChange::id).collect::<Vec<_>>(), vec![1, 4]);
+        assert_eq!(request.changes[1].base().unwrap(), 2);
+    }
+}
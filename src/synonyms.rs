@@ -0,0 +1,225 @@
+//! タグの同義語 (`golang` と `go` など)。
+//!
+//! 管理者が同じ意味のタグのグループを登録すると、`tag=` や検索式の `tag:` はグループ内のどのタグが
+//! 付いた書籍にも一致し、全文検索ではタグの欄にグループの別のタグも含めて索引する。
+//! タグは `crate::normalize` で正規化して比べる。1 つのタグは 1 つのグループにしか入れられない。
+//! 辞書はデータファイルの隣 (`book.json` なら `book.synonyms.json`) に置き、`/admin/synonyms` で
+//! 確認・変更する。`/admin/synonyms/preview` で、変更する前にどの書籍がまとまるかを確かめられる。
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::normalize::fold;
+use crate::tag_tree;
+use crate::{Book, BookError};
+
+/// 同じ意味のタグのグループ。
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "Vec<Vec<String>>", into = "Vec<Vec<String>>")]
+pub struct Synonyms {
+    groups: Vec<Vec<String>>,
+    /// 正規化したタグ → グループの位置
+    by_tag: HashMap<String, usize>,
+}
+
+impl Synonyms {
+    /// グループを読む。空のタグや 2 つ未満のグループ、複数のグループに入ったタグがあれば 400。
+    pub fn new(groups: Vec<Vec<String>>) -> Result<Self, BookError> {
+        let mut synonyms = Synonyms::default();
+
+        for group in groups {
+            let mut tags: Vec<String> = Vec::with_capacity(group.len());
+            for tag in group {
+                let tag = tag.trim().to_string();
+                if tag.is_empty() {
+                    return Err(BookError::BadRequest("synonyms: tags must not be empty".to_string()));
+                }
+                if !tags.iter().any(|t| fold(t) == fold(&tag)) {
+                    tags.push(tag);
+                }
+            }
+            if tags.len() < 2 {
+                return Err(BookError::BadRequest(format!("synonyms: {:?} needs at least two different tags", tags)));
+            }
+
+            let pos = synonyms.groups.len();
+            for tag in &tags {
+                if synonyms.by_tag.insert(fold(tag), pos).is_some() {
+                    return Err(BookError::BadRequest(format!("synonyms: {:?} appears in more than one group", tag)));
+                }
+            }
+            synonyms.groups.push(tags);
+        }
+
+        Ok(synonyms)
+    }
+
+    pub fn groups(&self) -> &[Vec<String>] {
+        &self.groups
+    }
+
+    /// 正規化したタグ `a` と `b` が同じか、同じグループに入っているか。
+    pub fn same(&self, a: &str, b: &str) -> bool {
+        a == b || self.by_tag.get(a).is_some_and(|group| self.by_tag.get(b) == Some(group))
+    }
+
+    /// `tags` のどれかが正規化したタグ `tag` かその同義語か、それらの子孫 (`crate::tag_tree`) か。
+    pub fn matches(&self, tags: &[String], tag: &str) -> bool {
+        let equivalents = self.equivalents(tag_tree::trim(tag));
+        tags.iter().any(|t| {
+            let t = fold(t);
+            equivalents.iter().any(|tag| tag_tree::within(&t, tag))
+        })
+    }
+
+    /// 正規化したタグ `tag` と、その同義語 (正規化済み)。
+    pub fn equivalents(&self, tag: &str) -> Vec<String> {
+        match self.by_tag.get(tag) {
+            Some(&group) => self.groups[group].iter().map(|t| fold(t)).collect(),
+            None => vec![tag.to_string()],
+        }
+    }
+
+    /// 全文検索でタグの欄に索引する文字列。付いているタグのあとに、その同義語を並べる。
+    pub fn index_text(&self, tags: &[String]) -> String {
+        let mut text = tags.to_vec();
+        for tag in tags {
+            if let Some(&group) = self.by_tag.get(&fold(tag)) {
+                text.extend(self.groups[group].iter().filter(|t| !tags.contains(t)).cloned());
+            }
+        }
+        text.join(" ")
+    }
+}
+
+impl TryFrom<Vec<Vec<String>>> for Synonyms {
+    type Error = BookError;
+
+    fn try_from(groups: Vec<Vec<String>>) -> Result<Self, Self::Error> {
+        Synonyms::new(groups)
+    }
+}
+
+impl From<Synonyms> for Vec<Vec<String>> {
+    fn from(synonyms: Synonyms) -> Self {
+        synonyms.groups
+    }
+}
+
+/// データファイルに対応する辞書のファイル。
+pub fn path_for(data_file: &Path) -> PathBuf {
+    data_file.with_extension("synonyms.json")
+}
+
+/// 辞書を読む。ファイルがなければ空。
+pub fn load(path: &Path) -> Result<Synonyms, BookError> {
+    match fs::read(path) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Synonyms::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// プレビューに載せる書籍。
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct TaggedBook {
+    pub id: u32,
+    pub title: String,
+    pub tags: Vec<String>,
+}
+
+/// 1 つのグループを登録したときに、まとめて見つかるようになる書籍。
+#[derive(Serialize, Debug)]
+pub struct Preview {
+    pub tags: Vec<String>,
+    /// グループのどれかのタグが付いた書籍
+    pub books: Vec<TaggedBook>,
+    /// タグごとに、`tag=` で新しく見つかるようになる書籍の id
+    pub gained: HashMap<String, Vec<u32>>,
+}
+
+/// `synonyms` を登録したら、グループごとにどの書籍がまとまるか。
+pub fn preview(synonyms: &Synonyms, books: &[Book]) -> Vec<Preview> {
+    synonyms.groups.iter()
+        .map(|group| {
+            let folded: Vec<String> = group.iter().map(|t| fold(t)).collect();
+            let tagged: Vec<&Book> = books.iter()
+                .filter(|b| b.tags.iter().any(|t| folded.contains(&fold(t))))
+                .collect();
+
+            let gained = group.iter().zip(&folded)
+                .map(|(tag, folded)| {
+                    let ids = tagged.iter()
+                        .filter(|b| !b.tags.iter().any(|t| fold(t) == *folded))
+                        .map(|b| b.id)
+                        .collect();
+                    (tag.clone(), ids)
+                })
+                .collect();
+
+            Preview {
+                tags: group.clone(),
+                books: tagged.iter().map(|b| TaggedBook { id: b.id, title: b.title.clone(), tags: b.tags.clone() }).collect(),
+                gained,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups(groups: &[&[&str]]) -> Vec<Vec<String>> {
+        groups.iter().map(|g| g.iter().map(|t| t.to_string()).collect()).collect()
+    }
+
+    fn book(id: u32, tags: &[&str]) -> Book {
+        Book { id, title: format!("Book {}", id), tags: tags.iter().map(|t| t.to_string()).collect(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_new() {
+        let synonyms = Synonyms::new(groups(&[&["golang", "Go"], &["JS", "javascript", "js"]])).unwrap();
+        assert_eq!(synonyms.groups(), groups(&[&["golang", "Go"], &["JS", "javascript"]]));
+        assert!(synonyms.same("go", "golang"));
+        assert!(!synonyms.same("go", "javascript"));
+        assert!(synonyms.same("rust", "rust"));
+        assert_eq!(synonyms.equivalents("js"), vec!["js", "javascript"]);
+        assert_eq!(synonyms.equivalents("rust"), vec!["rust"]);
+
+        assert!(Synonyms::new(groups(&[&["go"]])).is_err());
+        assert!(Synonyms::new(groups(&[&["go", "GO"]])).is_err());
+        assert!(Synonyms::new(groups(&[&["go", " "]])).is_err());
+        assert!(Synonyms::new(groups(&[&["go", "golang"], &["Golang", "go-lang"]])).is_err());
+    }
+
+    #[test]
+    fn test_index_text() {
+        let synonyms = Synonyms::new(groups(&[&["golang", "go"]])).unwrap();
+        assert_eq!(synonyms.index_text(&["go".to_string(), "web".to_string()]), "go web golang");
+        assert_eq!(synonyms.index_text(&["web".to_string()]), "web");
+    }
+
+    #[test]
+    fn test_preview() {
+        let synonyms = Synonyms::new(groups(&[&["golang", "go"]])).unwrap();
+        let books = [book(1, &["Go"]), book(2, &["golang", "web"]), book(3, &["rust"]), book(4, &["go", "golang"])];
+
+        let preview = preview(&synonyms, &books);
+        assert_eq!(preview.len(), 1);
+        assert_eq!(preview[0].books.iter().map(|b| b.id).collect::<Vec<_>>(), vec![1, 2, 4]);
+        assert_eq!(preview[0].gained["golang"], vec![1]);
+        assert_eq!(preview[0].gained["go"], vec![2]);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let synonyms: Synonyms = serde_json::from_str(r#"[["golang", "go"]]"#).unwrap();
+        assert!(synonyms.same("golang", "go"));
+        assert!(serde_json::from_str::<Synonyms>(r#"[["go"]]"#).is_err());
+    }
+}
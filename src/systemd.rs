@@ -0,0 +1,83 @@
+//! systemd のソケットアクティベーション。
+//!
+//! `books.socket` で起動されたときは、systemd が開いたソケット (`LISTEN_FDS`) をそのまま使い、
+//! 設定の `bind` と `unix_socket` は無視する。ソケットは systemd が持ち続けるので、
+//! サービスを再起動しても接続は待たされるだけで拒否されない。
+
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+
+/// systemd が渡す最初のファイルディスクリプター (`SD_LISTEN_FDS_START`)。
+const LISTEN_FDS_START: RawFd = 3;
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// `LISTEN_PID` と `LISTEN_FDS` から、このプロセスに渡されたソケットの数を求める。
+fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> usize {
+    // LISTEN_PID が別のプロセスを指していれば、親から環境変数を引き継いだだけ
+    if listen_pid.and_then(|p| p.parse::<u32>().ok()) != Some(pid) {
+        return 0;
+    }
+
+    listen_fds.and_then(|n| n.parse().ok()).unwrap_or(0)
+}
+
+fn is_unix_socket(fd: RawFd) -> io::Result<bool> {
+    let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+    // SAFETY: addr と len は getsockname が書き込める大きさを持つ
+    if unsafe { libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(i32::from(addr.ss_family) == libc::AF_UNIX)
+}
+
+/// 引き継いだソケットを取り出す。ソケットアクティベーションでなければ空。
+///
+/// 子プロセスに渡らないよう、読んだ環境変数は消しておく。
+pub fn listeners() -> io::Result<Vec<Listener>> {
+    let count = listen_fds(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count as RawFd)
+        .map(|fd| {
+            // SAFETY: systemd から渡された fd で、ほかに所有者はいない
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+
+            if is_unix_socket(fd)? {
+                Ok(Listener::Unix(unsafe { UnixListener::from_raw_fd(fd) }))
+            } else {
+                Ok(Listener::Tcp(unsafe { TcpListener::from_raw_fd(fd) }))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_fds() {
+        assert_eq!(listen_fds(Some("42"), Some("2"), 42), 2);
+        assert_eq!(listen_fds(Some("41"), Some("2"), 42), 0);
+        assert_eq!(listen_fds(None, Some("2"), 42), 0);
+        assert_eq!(listen_fds(Some("42"), None, 42), 0);
+        assert_eq!(listen_fds(Some("42"), Some("x"), 42), 0);
+    }
+}
@@ -0,0 +1,128 @@
+//! `/` で区切った階層のあるタグ (`programming/rust/async` など)。
+//!
+//! `tag=` や検索式の `tag:` に親のタグを渡すと、そのタグか子孫のタグが付いた書籍に一致する
+//! (`programming/rust` は `programming/rust/async` に一致するが、`programming/rusty` には一致しない)。
+//! `/tags/tree` はタグを階層に組み直し、各ノードにそのタグ自体が付いた書籍数 (`count`) と、
+//! 子孫を含めた書籍数 (`total`、同じ書籍は 1 回だけ数える) を付けて返す。
+
+use std::collections::{BTreeMap, HashSet};
+use serde::Serialize;
+
+use crate::Book;
+
+/// 階層の区切り。
+pub const SEPARATOR: char = '/';
+
+/// 検索に渡されたタグの前後の区切りを落とす。`programming/` は `programming` として扱う。
+pub fn trim(tag: &str) -> &str {
+    tag.trim_matches(SEPARATOR)
+}
+
+/// `tag` が `ancestor` そのものか、その子孫か。どちらも正規化済みで比べる。
+pub fn within(tag: &str, ancestor: &str) -> bool {
+    tag.strip_prefix(ancestor).is_some_and(|rest| rest.is_empty() || rest.starts_with(SEPARATOR))
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct TagNode {
+    /// 階層の 1 段分の名前
+    pub name: String,
+    /// 根からのタグ全体
+    pub path: String,
+    /// このタグ自体が付いた書籍数
+    pub count: usize,
+    /// 子孫のタグを含めて、どれかが付いた書籍数
+    pub total: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TagNode>,
+}
+
+#[derive(Default)]
+struct Builder {
+    count: usize,
+    books: HashSet<u32>,
+    children: BTreeMap<String, Builder>,
+}
+
+impl Builder {
+    fn build(self, name: String, path: String) -> TagNode {
+        let children = self.children.into_iter()
+            .map(|(child, builder)| {
+                let child_path = format!("{}{}{}", path, SEPARATOR, child);
+                builder.build(child, child_path)
+            })
+            .collect();
+
+        TagNode { name, path, count: self.count, total: self.books.len(), children }
+    }
+}
+
+/// `books` のタグを階層に組み直す。兄弟は名前順。
+pub fn tree(books: &[Book]) -> Vec<TagNode> {
+    let mut root = Builder::default();
+
+    for book in books {
+        let mut seen = HashSet::new();
+        for tag in &book.tags {
+            let segments: Vec<&str> = trim(tag).split(SEPARATOR).filter(|s| !s.is_empty()).collect();
+            if segments.is_empty() || !seen.insert(segments.clone()) {
+                continue;
+            }
+
+            let mut node = &mut root;
+            for segment in &segments {
+                node = node.children.entry(segment.to_string()).or_default();
+                node.books.insert(book.id);
+            }
+            node.count += 1;
+        }
+    }
+
+    root.children.into_iter()
+        .map(|(name, builder)| builder.build(name.clone(), name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(id: u32, tags: &[&str]) -> Book {
+        Book { id, tags: tags.iter().map(|t| t.to_string()).collect(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_within() {
+        assert!(within("programming/rust/async", "programming/rust"));
+        assert!(within("programming/rust", "programming/rust"));
+        assert!(!within("programming/rusty", "programming/rust"));
+        assert!(!within("programming", "programming/rust"));
+        assert_eq!(trim("/programming/"), "programming");
+    }
+
+    #[test]
+    fn test_tree() {
+        let books = [
+            book(1, &["programming/rust", "programming/rust/async"]),
+            book(2, &["programming/rust/async"]),
+            book(3, &["programming/go", "web"]),
+        ];
+
+        let tree = tree(&books);
+        assert_eq!(tree.iter().map(|n| n.name.as_str()).collect::<Vec<_>>(), vec!["programming", "web"]);
+
+        let programming = &tree[0];
+        assert_eq!((programming.count, programming.total), (0, 3));
+        assert_eq!(programming.children.iter().map(|n| n.path.as_str()).collect::<Vec<_>>(), vec!["programming/go", "programming/rust"]);
+
+        let rust = &programming.children[1];
+        assert_eq!((rust.count, rust.total), (1, 2));
+        assert_eq!(rust.children[0], TagNode {
+            name: "async".to_string(),
+            path: "programming/rust/async".to_string(),
+            count: 2,
+            total: 2,
+            children: Vec::new(),
+        });
+    }
+}
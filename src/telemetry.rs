@@ -0,0 +1,229 @@
+use std::env;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use log::{error, info};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+use crate::logging::{self, LogFormat, RotatingFile};
+use crate::Config;
+
+const SERVICE_NAME: &str = "books_backend";
+
+/// `logging.level` も `RUST_LOG` もないときのフィルター。
+const DEFAULT_FILTER: &str = "debug";
+
+/// 実行中にログのフィルターを変える手段。テキストと JSON で仕組みが違う。
+enum LevelHandle {
+    Text(&'static ReloadableLogger),
+    Json(reload::Handle<EnvFilter, Registry>),
+}
+
+static LEVEL: OnceLock<LevelHandle> = OnceLock::new();
+
+/// 複数の env_logger から書けるように共有するログファイル。
+#[derive(Clone)]
+struct SharedFile(Arc<Mutex<RotatingFile>>);
+
+impl Write for SharedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+/// フィルターを差し替えられる env_logger。
+struct ReloadableLogger {
+    inner: RwLock<env_logger::Logger>,
+    file: Option<SharedFile>,
+}
+
+impl ReloadableLogger {
+    fn build(filter: &str, file: Option<SharedFile>) -> env_logger::Logger {
+        let mut builder = env_logger::Builder::new();
+        builder.parse_filters(filter);
+        if let Some(file) = file {
+            builder.target(env_logger::Target::Pipe(Box::new(file)));
+        }
+        builder.build()
+    }
+
+    fn set_filter(&self, filter: &str) {
+        let logger = Self::build(filter, self.file.clone());
+        log::set_max_level(logger.filter());
+        *self.inner.write().unwrap() = logger;
+    }
+}
+
+impl log::Log for ReloadableLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.inner.read().unwrap().log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.read().unwrap().flush();
+    }
+}
+
+/// `logging.level`、`RUST_LOG`、既定値の順に使うフィルター。
+fn filter(config: &Config) -> String {
+    config.log_level.clone()
+        .or_else(|| env::var("RUST_LOG").ok())
+        .unwrap_or_else(|| DEFAULT_FILTER.to_string())
+}
+
+/// `RUST_LOG` の書式として読めるか。
+pub fn is_valid_filter(filter: &str) -> bool {
+    EnvFilter::try_new(filter).is_ok()
+}
+
+/// 設定に合わせてログのフィルターを変える。`init` の前に呼んでも何もしない。
+pub fn reload_level(config: &Config) {
+    let filter = filter(config);
+
+    match LEVEL.get() {
+        Some(LevelHandle::Text(logger)) => logger.set_filter(&filter),
+        Some(LevelHandle::Json(handle)) => match EnvFilter::try_new(&filter) {
+            Ok(new) => {
+                if let Err(e) = handle.reload(new) {
+                    error!("Failed to change log filter: {}", e);
+                }
+            }
+            Err(e) => error!("Invalid log filter {:?}: {}", filter, e),
+        },
+        None => {}
+    }
+}
+
+fn otlp_provider(endpoint: &str) -> Option<TracerProvider> {
+    let exporter = match SpanExporter::builder().with_tonic().with_endpoint(endpoint).build() {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            error!("Failed to create OTLP exporter: {}", e);
+            return None;
+        }
+    };
+
+    Some(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new(vec![KeyValue::new("service.name", SERVICE_NAME)]))
+        .build())
+}
+
+/// `dir` の下にログファイルを開く。開けなければ標準出力 / 標準エラーに戻す。
+fn open_log_file(config: &Config, name: &str) -> Option<RotatingFile> {
+    let dir = config.log_dir.as_ref()?;
+
+    match RotatingFile::open(dir.join(name), config.log_rotation) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            eprintln!("Failed to open log file {}: {}", dir.join(name).display(), e);
+            None
+        }
+    }
+}
+
+/// ログの出力を設定する。プロセスの最初に 1 回だけ呼ぶ。
+///
+/// `LogFormat::Json` なら `log` クレートの出力も `tracing` に流し、1 行 1 つの JSON にする。
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` が設定されていれば OTLP へのトレース送信も有効にする。
+/// 受信リクエストの `traceparent` は常に引き継ぐ。
+pub fn init(config: &Config) -> Option<TracerProvider> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let format = config.log_format;
+    let mut app_file = open_log_file(config, logging::APP_LOG_FILE);
+
+    if let Some(file) = open_log_file(config, logging::ACCESS_LOG_FILE) {
+        logging::set_access_file(file);
+    }
+
+    match format {
+        LogFormat::Text => {
+            let file = app_file.take().map(|file| SharedFile(Arc::new(Mutex::new(file))));
+            let inner = ReloadableLogger::build(&filter(config), file.clone());
+            let max_level = inner.filter();
+            let logger: &'static ReloadableLogger = Box::leak(Box::new(ReloadableLogger {
+                inner: RwLock::new(inner),
+                file,
+            }));
+
+            match log::set_logger(logger) {
+                Ok(()) => {
+                    log::set_max_level(max_level);
+                    let _ = LEVEL.set(LevelHandle::Text(logger));
+                }
+                Err(e) => eprintln!("Failed to install logger: {}", e),
+            }
+        }
+        LogFormat::Json => {
+            if let Err(e) = tracing_log::LogTracer::init() {
+                eprintln!("Failed to forward log records to tracing: {}", e);
+            }
+        }
+    }
+    logging::set_json(format == LogFormat::Json);
+
+    let endpoint = env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok();
+    let provider = endpoint.as_deref().and_then(otlp_provider);
+
+    let json = (format == LogFormat::Json).then(|| {
+        let filter = EnvFilter::try_new(filter(config)).unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+        let (filter, handle) = reload::Layer::new(filter);
+        let _ = LEVEL.set(LevelHandle::Json(handle));
+
+        let writer = match app_file {
+            Some(file) => BoxMakeWriter::new(Mutex::new(file)),
+            None => BoxMakeWriter::new(std::io::stdout),
+        };
+
+        tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(false)
+            .with_filter(filter)
+    });
+    let otel = provider.as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+
+    if json.is_none() && otel.is_none() {
+        return None;
+    }
+
+    let subscriber = tracing_subscriber::registry().with(json).with(otel);
+
+    if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
+        error!("Failed to install tracing subscriber: {}", e);
+        return None;
+    }
+
+    let provider = provider?;
+    global::set_tracer_provider(provider.clone());
+    info!("Exporting traces to {}", endpoint.unwrap_or_default());
+
+    Some(provider)
+}
+
+pub fn shutdown(provider: Option<TracerProvider>) {
+    if let Some(provider) = provider {
+        if let Err(e) = provider.shutdown() {
+            error!("Failed to flush traces: {}", e);
+        }
+    }
+}
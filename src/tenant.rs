@@ -0,0 +1,144 @@
+//! 1 つのプロセスで複数の独立した書庫 (テナント) を扱う。
+//!
+//! テナントは `X-Tenant` ヘッダーか `/t/{tenant}/...` のパスで選ぶ。パスで選んだときは
+//! 接頭辞を取り除いてからルーティングするので、ハンドラーはテナントを意識しない。
+//! テナントごとにデータファイル・Webhook・ユーザーを持ち、機能フラグとメンテナンスモードは共有する。
+//! どちらも指定しなければ、これまでどおり既定の書庫を使う。
+//! 共有する状態を変えられるのは既定の書庫の管理者 (`auth::OperatorUser`) だけ。
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Extensions, ServiceRequest, ServiceResponse};
+use actix_web::http::Uri;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, HttpRequest, ResponseError};
+
+use crate::{AppState, BookError};
+
+pub const TENANT_HEADER: &str = "x-tenant";
+
+/// パスでテナントを選ぶときの接頭辞。
+const PATH_PREFIX: &str = "/t/";
+
+/// テナント名に使える文字 (`[A-Za-z0-9_-]`、64 文字まで) か。
+pub fn is_valid_name(name: &str) -> bool {
+    (1..=64).contains(&name.len()) && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// 設定ファイルの `[tenants.<name>]`。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TenantConfig {
+    pub data_file: PathBuf,
+    pub users_file: PathBuf,
+    pub webhooks_file: PathBuf,
+}
+
+/// リクエストが属するテナント。認証で使うので extensions に入れる。
+#[derive(Clone, Debug)]
+pub struct Tenant {
+    pub name: String,
+    pub users_file: PathBuf,
+    /// `/t/{tenant}` で選んだときの接頭辞。生成するリンクに付け直す。
+    pub path_prefix: Option<String>,
+}
+
+/// 起動時に開いたテナントの一覧。
+#[derive(Default)]
+pub struct Tenants(HashMap<String, (Tenant, web::Data<AppState>)>);
+
+impl Tenants {
+    pub fn insert(&mut self, name: &str, users_file: PathBuf, state: web::Data<AppState>) {
+        let tenant = Tenant { name: name.to_string(), users_file, path_prefix: None };
+        self.0.insert(name.to_string(), (tenant, state));
+    }
+}
+
+/// `/t/acme/books` を `("acme", "/books")` に分ける。
+fn split_prefix(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix(PATH_PREFIX)?;
+
+    match rest.find('/') {
+        Some(pos) => Some((&rest[..pos], &rest[pos..])),
+        None => Some((rest, "/")),
+    }
+}
+
+fn rewrite_path(req: &mut ServiceRequest, path: &str) -> Result<(), BookError> {
+    let path_and_query = match req.query_string() {
+        "" => path.to_string(),
+        query => format!("{}?{}", path, query),
+    };
+
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().map_err(|_| BookError::NotFound)?);
+    let uri = Uri::from_parts(parts).map_err(|_| BookError::NotFound)?;
+
+    req.match_info_mut().get_mut().update(&uri);
+    req.head_mut().uri = uri;
+
+    Ok(())
+}
+
+/// 生成するリンクに付ける接頭辞。`/t/{tenant}` で来たときだけ付く。
+pub fn path_prefix(req: &HttpRequest) -> String {
+    req.extensions()
+        .get::<Tenant>()
+        .and_then(|tenant| tenant.path_prefix.clone())
+        .unwrap_or_default()
+}
+
+/// `middleware::from_fn` に渡すミドルウェア。テナントを選び、その `AppState` をハンドラーに渡す。
+pub async fn resolve(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let (name, rest) = match split_prefix(req.path()) {
+        Some((name, rest)) => (name.to_string(), Some(rest.to_string())),
+        None => match req.headers().get(TENANT_HEADER).and_then(|v| v.to_str().ok()) {
+            Some(name) => (name.trim().to_string(), None),
+            None => return Ok(next.call(req).await?.map_into_boxed_body()),
+        },
+    };
+
+    let selected = req.app_data::<web::Data<Tenants>>()
+        .and_then(|tenants| tenants.0.get(&name).cloned());
+
+    let Some((mut tenant, state)) = selected else {
+        let resp = BookError::NotFound.error_response();
+        return Ok(req.into_response(resp));
+    };
+
+    if let Some(rest) = rest {
+        if let Err(e) = rewrite_path(&mut req, &rest) {
+            let resp = e.error_response();
+            return Ok(req.into_response(resp));
+        }
+        tenant.path_prefix = Some(format!("{}{}", PATH_PREFIX, name));
+    }
+
+    // 後から積んだ app_data が優先されるので、既定の `AppState` の代わりにこちらが使われる
+    let mut data = Extensions::new();
+    data.insert(state);
+    req.add_data_container(Rc::new(data));
+    req.extensions_mut().insert(tenant);
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_prefix() {
+        assert_eq!(split_prefix("/t/acme/books/id/1"), Some(("acme", "/books/id/1")));
+        assert_eq!(split_prefix("/t/acme"), Some(("acme", "/")));
+        assert_eq!(split_prefix("/books"), None);
+
+        assert!(is_valid_name("team-a_2"));
+        assert!(!is_valid_name("../etc"));
+        assert!(!is_valid_name(""));
+    }
+}
@@ -0,0 +1,76 @@
+//! リクエスト全体のタイムアウト。
+//!
+//! 時間内にレスポンスヘッダーまで返せなかったハンドラーを打ち切り、504 を返す。
+//! ストリーミングのボディ (SSE や NDJSON) はヘッダーを返した時点で対象外になる。
+//! ブロッキング用スレッドで実行中のファイル IO は打ち切られず、最後まで実行される。
+
+use std::time::Duration;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+
+use crate::BookError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestTimeout(pub Duration);
+
+impl Default for RequestTimeout {
+    fn default() -> Self {
+        RequestTimeout(Duration::from_secs(30))
+    }
+}
+
+/// `middleware::from_fn` に渡すタイムアウトミドルウェア。時間は `web::Data<RequestTimeout>` から読む。
+pub async fn timeout(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let RequestTimeout(limit) = req.app_data::<web::Data<RequestTimeout>>()
+        .map(|t| *t.get_ref())
+        .unwrap_or_default();
+
+    if limit.is_zero() {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    // ルーティング中のリクエストは複製できないので、ログ用に必要な分だけ控えておく
+    let (method, path) = (req.method().clone(), req.path().to_string());
+
+    match tokio::time::timeout(limit, next.call(req)).await {
+        Ok(res) => Ok(res?.map_into_boxed_body()),
+        Err(_) => {
+            log::warn!("{} {} timed out after {:?}", method, path, limit);
+            Err(BookError::Timeout.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{middleware, test, App, HttpResponse};
+
+    #[actix_rt::test]
+    async fn test_slow_handler_times_out() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(RequestTimeout(Duration::from_millis(10))))
+                .wrap(middleware::from_fn(timeout))
+                .route("/slow", web::get().to(|| async {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    HttpResponse::Ok().finish()
+                }))
+                .route("/fast", web::get().to(HttpResponse::Ok)),
+        ).await;
+
+        let err = test::try_call_service(&app, test::TestRequest::get().uri("/slow").to_request())
+            .await
+            .unwrap_err();
+        assert_eq!(err.error_response().status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/fast").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,149 @@
+//! rustls による HTTPS。
+//!
+//! 証明書と鍵のファイルを定期的に確認し、更新されていれば読み直す。証明書を更新しても
+//! サーバーを再起動しなくてよい。読み直しに失敗したときは古い証明書を使い続ける。
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use rustls::crypto::ring;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+
+/// 証明書ファイルの更新を確認する間隔。
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+fn invalid(path: &Path, message: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message))
+}
+
+fn load(cert_file: &Path, key_file: &Path) -> io::Result<CertifiedKey> {
+    let certs = CertificateDer::pem_file_iter(cert_file)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(cert_file, e))?;
+    if certs.is_empty() {
+        return Err(invalid(cert_file, "no certificates found"));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_file).map_err(|e| invalid(key_file, e))?;
+    let signing_key = ring::sign::any_supported_type(&key).map_err(|e| invalid(key_file, e))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+/// 証明書と鍵のうち、新しい方の更新日時。
+fn modified(cert_file: &Path, key_file: &Path) -> Option<SystemTime> {
+    [cert_file, key_file].iter()
+        .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+#[derive(Debug)]
+struct Loaded {
+    key: Arc<CertifiedKey>,
+    modified: Option<SystemTime>,
+}
+
+/// 読み込んだ証明書を返す `ResolvesServerCert`。
+#[derive(Debug)]
+pub struct CertResolver {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    current: RwLock<Loaded>,
+}
+
+impl CertResolver {
+    pub fn new(cert_file: impl Into<PathBuf>, key_file: impl Into<PathBuf>) -> io::Result<Self> {
+        let (cert_file, key_file) = (cert_file.into(), key_file.into());
+
+        let loaded = Loaded {
+            modified: modified(&cert_file, &key_file),
+            key: Arc::new(load(&cert_file, &key_file)?),
+        };
+
+        Ok(CertResolver { cert_file, key_file, current: RwLock::new(loaded) })
+    }
+
+    /// ファイルが更新されていれば読み直す。読み直したら `true`。
+    pub fn reload_if_changed(&self) -> io::Result<bool> {
+        let modified = modified(&self.cert_file, &self.key_file);
+        if modified == self.current.read().unwrap().modified {
+            return Ok(false);
+        }
+
+        let key = Arc::new(load(&self.cert_file, &self.key_file)?);
+        *self.current.write().unwrap() = Loaded { key, modified };
+
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.current.read().unwrap().key))
+    }
+}
+
+fn spawn_reloader(resolver: Arc<CertResolver>) {
+    thread::Builder::new()
+        .name("tls-reloader".to_string())
+        .spawn(move || loop {
+            thread::sleep(RELOAD_INTERVAL);
+
+            match resolver.reload_if_changed() {
+                Ok(true) => log::info!("Reloaded TLS certificate from {}", resolver.cert_file.display()),
+                Ok(false) => {}
+                Err(e) => log::error!("Failed to reload TLS certificate, keeping the current one: {}", e),
+            }
+        })
+        .expect("Failed to spawn TLS reloader thread");
+}
+
+/// 証明書を読み込み、更新を監視するスレッドを起動して rustls の設定を作る。
+pub fn server_config(cert_file: &Path, key_file: &Path) -> io::Result<ServerConfig> {
+    let resolver = Arc::new(CertResolver::new(cert_file, key_file)?);
+    spawn_reloader(Arc::clone(&resolver));
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_if_changed() {
+        let dir = std::env::temp_dir().join(format!("books_backend_tls_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::copy("tests/fixtures/tls-cert.pem", &cert).unwrap();
+        fs::copy("tests/fixtures/tls-key.pem", &key).unwrap();
+
+        let resolver = CertResolver::new(&cert, &key).unwrap();
+        assert!(!resolver.reload_if_changed().unwrap());
+
+        // 壊れた証明書に差し替えても、読み直しに失敗するだけで古い証明書は残る
+        std::thread::sleep(Duration::from_millis(20));
+        fs::write(&cert, "not a certificate").unwrap();
+        assert!(resolver.reload_if_changed().is_err());
+        assert_eq!(resolver.current.read().unwrap().key.cert.len(), 1);
+
+        std::thread::sleep(Duration::from_millis(20));
+        fs::copy("tests/fixtures/tls-cert.pem", &cert).unwrap();
+        assert!(resolver.reload_if_changed().unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+//! Unix ドメインソケットでの待ち受け。同じホストの nginx / Caddy から接続する構成向け。
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+
+/// 前回の起動で残ったソケットファイルを消す。ソケット以外のファイルがあれば消さずにエラーにする。
+pub fn remove_stale(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// ソケットファイルのパーミッションと所有者を設定する。`None` の項目は変えない。
+pub fn set_permissions(path: &Path, mode: Option<u32>, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+
+    if uid.is_some() || gid.is_some() {
+        std::os::unix::fs::chown(path, uid, gid)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_stale_socket_is_replaced() {
+        let path = std::env::temp_dir().join(format!("books_backend_{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        drop(UnixListener::bind(&path).unwrap());
+        remove_stale(&path).unwrap();
+
+        let _listener = UnixListener::bind(&path).unwrap();
+        set_permissions(&path, Some(0o660), None, None).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, "").unwrap();
+        assert!(remove_stale(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
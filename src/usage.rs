@@ -0,0 +1,160 @@
+//! ディスク使用量。
+//!
+//! `GET /admin/storage` で使う。書き込みが失敗し始める前に、データファイルやバックアップの
+//! 肥大化と空き容量の不足に気づけるようにする。
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::storage::BookRepository;
+use crate::BookError;
+
+#[derive(Debug, Serialize)]
+pub struct FileUsage {
+    pub path: PathBuf,
+    /// ファイルがなければ 0
+    pub bytes: u64,
+}
+
+impl FileUsage {
+    fn of(path: &Path) -> Self {
+        let bytes = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        FileUsage { path: path.to_path_buf(), bytes }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupFile {
+    pub name: String,
+    pub bytes: u64,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub modified: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupUsage {
+    pub dir: PathBuf,
+    pub bytes: u64,
+    /// 新しい順
+    pub files: Vec<BackupFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiskSpace {
+    pub total_bytes: u64,
+    /// 特権のないプロセスが使える空き容量
+    pub available_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StorageUsage {
+    pub data_file: FileUsage,
+    pub users_file: FileUsage,
+    pub backups: BackupUsage,
+    /// データファイルがあるファイルシステムの容量。調べられない環境では `None`。
+    pub disk: Option<DiskSpace>,
+}
+
+fn backups(dir: &Path) -> Result<BackupUsage, BookError> {
+    let mut files = Vec::new();
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(BackupUsage { dir: dir.to_path_buf(), bytes: 0, files });
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        files.push(BackupFile {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            bytes: metadata.len(),
+            modified: metadata.modified().ok().map(OffsetDateTime::from),
+        });
+    }
+
+    files.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| b.name.cmp(&a.name)));
+    let bytes = files.iter().map(|f| f.bytes).sum();
+
+    Ok(BackupUsage { dir: dir.to_path_buf(), bytes, files })
+}
+
+#[cfg(unix)]
+fn disk_space(path: &Path) -> Option<DiskSpace> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    // SAFETY: path は NUL 終端された文字列で、stat は statvfs が書き込める大きさを持つ
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    let block = stat.f_frsize as u64;
+    Some(DiskSpace {
+        total_bytes: stat.f_blocks as u64 * block,
+        available_bytes: stat.f_bavail as u64 * block,
+    })
+}
+
+#[cfg(not(unix))]
+fn disk_space(_path: &Path) -> Option<DiskSpace> {
+    None
+}
+
+/// データファイル・ユーザー・バックアップの大きさと、空き容量を調べる。
+pub fn usage(repository: &BookRepository, users_file: &Path) -> Result<StorageUsage, BookError> {
+    let data_file = repository.data_file();
+    let dir = match data_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    Ok(StorageUsage {
+        data_file: FileUsage::of(data_file),
+        users_file: FileUsage::of(users_file),
+        backups: backups(&repository.backup_dir())?,
+        disk: disk_space(dir),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage() {
+        let dir = std::env::temp_dir().join(format!("books_backend_usage_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("book.json");
+        fs::copy("src/data/book.json", &path).unwrap();
+
+        let repository = BookRepository::new(&path);
+        let report = usage(&repository, &dir.join("users.json")).unwrap();
+        assert_eq!(report.data_file.bytes, fs::metadata(&path).unwrap().len());
+        assert_eq!(report.users_file.bytes, 0);
+        assert!(report.backups.files.is_empty());
+
+        let backup = repository.backup().unwrap();
+        let report = usage(&repository, &dir.join("users.json")).unwrap();
+        assert_eq!(report.backups.files.len(), 1);
+        assert_eq!(report.backups.bytes, fs::metadata(&backup).unwrap().len());
+
+        #[cfg(unix)]
+        assert!(report.disk.is_some_and(|d| d.available_bytes <= d.total_bytes));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
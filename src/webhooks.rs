@@ -0,0 +1,483 @@
+//! 書籍の変更を外部の URL へ知らせる Webhook。
+//!
+//! 配信には 2 種類の署名を付ける。`X-Books-Signature` は本文だけの HMAC-SHA256 (以前からの形式)。
+//! `X-Books-Signature-V2` は `{X-Books-Timestamp}.{X-Books-Nonce}.{本文}` の HMAC-SHA256 で、
+//! 受け取った側は `verify_signature` と `ReplayGuard` で古い配信や再送の使い回しを拒否できる。
+//! ノンスは配信ごとに決まり、再試行でも同じ値を使う (時刻と署名は試行ごとに作り直す)。
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::auth::AdminUser;
+use crate::events::{BookEvent, BookEventKind, EventBus};
+use crate::flags::{Flag, Flags};
+use crate::{AppState, BookError};
+
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const DELIVERY_LOG_CAPACITY: usize = 500;
+const SIGNATURE_HEADER: &str = "X-Books-Signature";
+pub const SIGNATURE_V2_HEADER: &str = "X-Books-Signature-V2";
+pub const TIMESTAMP_HEADER: &str = "X-Books-Timestamp";
+pub const NONCE_HEADER: &str = "X-Books-Nonce";
+
+/// 署名の時刻と受け取った時刻の差の既定の許容範囲。
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Webhook {
+    pub id: u32,
+    pub url: String,
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub secret: String,
+    /// 空なら全種類のイベントを配信する
+    #[serde(default)]
+    pub events: Vec<BookEventKind>,
+}
+
+impl Webhook {
+    fn wants(&self, kind: BookEventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NewWebhook {
+    url: String,
+    secret: Option<String>,
+    #[serde(default)]
+    events: Vec<BookEventKind>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Delivery {
+    webhook_id: u32,
+    event_seq: u64,
+    attempt: u32,
+    status: Option<u16>,
+    error: Option<String>,
+    success: bool,
+    timestamp: u64,
+}
+
+#[derive(Deserialize)]
+pub struct DeliveryQuery {
+    webhook_id: Option<u32>,
+}
+
+#[derive(Clone)]
+pub struct Webhooks {
+    file: PathBuf,
+    /// 追加と削除の読み込み → 書き込みを直列にする。同時に届いても互いの変更を消さない
+    edit: Arc<Mutex<()>>,
+    deliveries: Arc<Mutex<VecDeque<Delivery>>>,
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(body);
+
+    let digest: String = mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    format!("sha256={}", digest)
+}
+
+fn signed_payload(timestamp: u64, nonce: &str, body: &[u8]) -> Vec<u8> {
+    [format!("{}.{}.", timestamp, nonce).as_bytes(), body].concat()
+}
+
+/// 時刻とノンス付きの署名 (`X-Books-Signature-V2`)。
+pub fn sign_v2(secret: &str, timestamp: u64, nonce: &str, body: &[u8]) -> String {
+    sign(secret, &signed_payload(timestamp, nonce, body))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SignatureError {
+    #[error("the signature is missing or malformed")]
+    Malformed,
+    #[error("the signature does not match")]
+    Mismatch,
+    #[error("the signature timestamp is outside the allowed window")]
+    Stale,
+    #[error("the nonce has already been used")]
+    Replayed,
+}
+
+/// `X-Books-Signature-V2` を確かめる。時刻が `now` から `tolerance` 以上ずれていれば拒否する。
+/// ノンスの使い回しは `ReplayGuard` で確かめる。
+pub fn verify_signature(
+    secret: &str,
+    timestamp: u64,
+    nonce: &str,
+    body: &[u8],
+    signature: &str,
+    tolerance: Duration,
+    now: u64,
+) -> Result<(), SignatureError> {
+    let hex = signature.strip_prefix("sha256=").ok_or(SignatureError::Malformed)?;
+    if hex.len() != 64 || nonce.is_empty() {
+        return Err(SignatureError::Malformed);
+    }
+    let expected = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()
+        .ok_or(SignatureError::Malformed)?;
+
+    if now.abs_diff(timestamp) > tolerance.as_secs() {
+        return Err(SignatureError::Stale);
+    }
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(&signed_payload(timestamp, nonce, body));
+    // 比較にかかる時間から署名を推測されないように、定数時間で比べる
+    mac.verify_slice(&expected).map_err(|_| SignatureError::Mismatch)
+}
+
+/// 受け取ったノンスを許容範囲の間だけ覚え、同じ配信の使い回しを拒否する。
+pub struct ReplayGuard {
+    tolerance: Duration,
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl Default for ReplayGuard {
+    fn default() -> Self {
+        ReplayGuard::new(DEFAULT_TOLERANCE)
+    }
+}
+
+impl ReplayGuard {
+    pub fn new(tolerance: Duration) -> Self {
+        ReplayGuard { tolerance, seen: Mutex::new(HashMap::new()) }
+    }
+
+    /// 署名と時刻を確かめ、初めてのノンスなら覚える。
+    pub fn verify(&self, secret: &str, timestamp: u64, nonce: &str, body: &[u8], signature: &str) -> Result<(), SignatureError> {
+        let now = now_secs();
+        verify_signature(secret, timestamp, nonce, body, signature, self.tolerance, now)?;
+
+        let mut seen = self.seen.lock().unwrap();
+        // 許容範囲を過ぎたノンスは時刻の確認で拒否されるので、忘れてよい
+        let tolerance = self.tolerance.as_secs();
+        seen.retain(|_, at| now.abs_diff(*at) <= tolerance);
+
+        if seen.insert(nonce.to_string(), timestamp).is_some() {
+            return Err(SignatureError::Replayed);
+        }
+        Ok(())
+    }
+}
+
+impl Webhooks {
+    pub fn new(file: impl Into<PathBuf>) -> Self {
+        Webhooks {
+            file: file.into(),
+            edit: Arc::new(Mutex::new(())),
+            deliveries: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    pub fn list(&self) -> Result<Vec<Webhook>, BookError> {
+        let contents = match fs::read_to_string(&self.file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// 署名の鍵を含むので、他のユーザーに読めないファイルに書く。
+    fn save(&self, webhooks: &Vec<Webhook>) -> Result<(), BookError> {
+        let contents = serde_json::to_string_pretty(webhooks)?;
+
+        crate::private_file::write_private(&self.file, contents.as_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn add(&self, new_webhook: NewWebhook) -> Result<Webhook, BookError> {
+        if !(new_webhook.url.starts_with("http://") || new_webhook.url.starts_with("https://")) {
+            return Err(BookError::BadRequest("url must be an http(s) URL".to_string()));
+        }
+
+        let _edit = self.edit.lock().unwrap();
+        let mut webhooks = self.list()?;
+
+        let secret = new_webhook.secret.unwrap_or_else(|| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(32)
+                .map(char::from)
+                .collect()
+        });
+
+        let webhook = Webhook {
+            id: webhooks.iter().map(|w| w.id).max().unwrap_or(0) + 1,
+            url: new_webhook.url,
+            secret,
+            events: new_webhook.events,
+        };
+
+        webhooks.push(webhook.clone());
+        self.save(&webhooks)?;
+
+        Ok(webhook)
+    }
+
+    pub fn remove(&self, id: u32) -> Result<bool, BookError> {
+        let _edit = self.edit.lock().unwrap();
+        let mut webhooks = self.list()?;
+        let before = webhooks.len();
+
+        webhooks.retain(|w| w.id != id);
+
+        if webhooks.len() == before {
+            return Ok(false);
+        }
+
+        self.save(&webhooks)?;
+        Ok(true)
+    }
+
+    pub fn deliveries(&self, webhook_id: Option<u32>) -> Vec<Delivery> {
+        let deliveries = self.deliveries.lock().unwrap();
+
+        deliveries.iter()
+            .filter(|d| webhook_id.is_none_or(|id| d.webhook_id == id))
+            .cloned()
+            .collect()
+    }
+
+    fn record(&self, delivery: Delivery) {
+        let mut deliveries = self.deliveries.lock().unwrap();
+
+        if deliveries.len() == DELIVERY_LOG_CAPACITY {
+            deliveries.pop_front();
+        }
+        deliveries.push_back(delivery);
+    }
+
+    /// 書籍の変更イベントを購読し、登録済みの URL へ配信するタスクを起動する。
+    /// `webhooks` フラグが無効な間に起きたイベントは配信しない。
+    pub fn spawn_dispatcher(&self, events: &EventBus, flags: Flags) {
+        let mut receiver = events.subscribe();
+        let webhooks = self.clone();
+        let client = reqwest::Client::new();
+
+        tokio::spawn(async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Webhook dispatcher skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                if !flags.is_enabled(Flag::Webhooks) {
+                    continue;
+                }
+
+                let targets = match webhooks.list() {
+                    Ok(targets) => targets,
+                    Err(e) => {
+                        error!("Failed to load webhooks: {}", e);
+                        continue;
+                    }
+                };
+
+                for webhook in targets.into_iter().filter(|w| w.wants(event.kind)) {
+                    tokio::spawn(webhooks.clone().deliver(client.clone(), webhook, event.clone()));
+                }
+            }
+        });
+    }
+
+    async fn deliver(self, client: reqwest::Client, webhook: Webhook, event: BookEvent) {
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to serialize book event: {}", e);
+                return;
+            }
+        };
+        let signature = sign(&webhook.secret, &body);
+        let nonce: String = rand::thread_rng().sample_iter(&Alphanumeric).take(24).map(char::from).collect();
+        let mut backoff = INITIAL_BACKOFF;
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let timestamp = now_secs();
+            let result = client.post(&webhook.url)
+                .header("Content-Type", "application/json")
+                .header("X-Books-Event", event.kind.as_str())
+                .header(SIGNATURE_HEADER, &signature)
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(NONCE_HEADER, &nonce)
+                .header(SIGNATURE_V2_HEADER, sign_v2(&webhook.secret, timestamp, &nonce, &body))
+                .body(body.clone())
+                .send()
+                .await;
+
+            let (status, error) = match result {
+                Ok(resp) => (Some(resp.status().as_u16()), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let success = status.is_some_and(|s| (200..300).contains(&s));
+
+            self.record(Delivery {
+                webhook_id: webhook.id,
+                event_seq: event.seq,
+                attempt,
+                status,
+                error,
+                success,
+                timestamp,
+            });
+
+            if success {
+                return;
+            }
+
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+
+        warn!("Giving up delivering event {} to webhook {}", event.seq, webhook.id);
+    }
+}
+
+#[get("/admin/webhooks")]
+pub async fn list_webhooks(_admin: AdminUser, data: web::Data<AppState>) -> Result<impl Responder, BookError> {
+    // 一覧では署名用の secret を返さない
+    let webhooks: Vec<Webhook> = data.webhooks
+        .list()?
+        .into_iter()
+        .map(|w| Webhook { secret: String::new(), ..w })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+#[post("/admin/webhooks")]
+pub async fn create_webhook(
+    admin: AdminUser,
+    data: web::Data<AppState>,
+    new_webhook: web::Json<NewWebhook>,
+) -> Result<impl Responder, BookError> {
+    let webhook = data.webhooks.add(new_webhook.into_inner())?;
+    info!("{} registered webhook {} for {}", admin.0.username, webhook.id, webhook.url);
+
+    Ok(HttpResponse::Created().json(webhook))
+}
+
+#[delete("/admin/webhooks/{id}")]
+pub async fn delete_webhook(
+    _admin: AdminUser,
+    data: web::Data<AppState>,
+    id: web::Path<u32>,
+) -> Result<impl Responder, BookError> {
+    if !data.webhooks.remove(id.into_inner())? {
+        return Err(BookError::NotFound);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/admin/webhooks/deliveries")]
+pub async fn list_deliveries(
+    _admin: AdminUser,
+    data: web::Data<AppState>,
+    query: web::Query<DeliveryQuery>,
+) -> Result<impl Responder, BookError> {
+    let deliveries = data.webhooks.deliveries(query.webhook_id);
+
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_matches_known_hmac() {
+        // RFC 4231 test case 2
+        let signature = sign("Jefe", b"what do ya want for nothing?");
+
+        assert_eq!(signature, "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[test]
+    fn test_verify_signature_v2() {
+        let tolerance = DEFAULT_TOLERANCE;
+        let signature = sign_v2("secret", 1_000_000, "n1", b"{}");
+
+        assert_eq!(verify_signature("secret", 1_000_000, "n1", b"{}", &signature, tolerance, 1_000_100), Ok(()));
+        assert_eq!(verify_signature("secret", 1_000_000, "n1", b"{}", &signature, tolerance, 1_000_301), Err(SignatureError::Stale));
+        assert_eq!(verify_signature("secret", 1_000_000, "n2", b"{}", &signature, tolerance, 1_000_000), Err(SignatureError::Mismatch));
+        assert_eq!(verify_signature("secret", 1_000_001, "n1", b"{}", &signature, tolerance, 1_000_000), Err(SignatureError::Mismatch));
+        assert_eq!(verify_signature("other", 1_000_000, "n1", b"{}", &signature, tolerance, 1_000_000), Err(SignatureError::Mismatch));
+        assert_eq!(verify_signature("secret", 1_000_000, "n1", b"{}", "sha256=zz", tolerance, 1_000_000), Err(SignatureError::Malformed));
+
+        // 同じ配信を 2 度受け取ったら拒否する
+        let guard = ReplayGuard::default();
+        let now = now_secs();
+        let signature = sign_v2("secret", now, "n3", b"{}");
+        assert_eq!(guard.verify("secret", now, "n3", b"{}", &signature), Ok(()));
+        assert_eq!(guard.verify("secret", now, "n3", b"{}", &signature), Err(SignatureError::Replayed));
+    }
+
+    #[test]
+    fn test_concurrent_adds_are_kept_private() {
+        let file = std::env::temp_dir().join(format!("books_backend_webhooks_concurrent_{}.json", std::process::id()));
+        let _ = fs::remove_file(&file);
+        let webhooks = Webhooks::new(&file);
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let webhooks = webhooks.clone();
+                std::thread::spawn(move || {
+                    let new_webhook = NewWebhook { url: format!("https://example.com/{}", i), secret: None, events: Vec::new() };
+                    webhooks.add(new_webhook).unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(webhooks.list().unwrap().len(), 8);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+//! 作品と版 (`/works/{id}/editions`)。
+//!
+//! 1 冊の書籍は 1 つの版 (ISBN や形式、言語の違うもの) で、同じ作品の版は `work` に同じ作品の id を持つ。
+//! 作品の id は最初の版の書籍の id で、`work` のない書籍はそれだけで 1 つの作品になる。
+//! 別の形式で買い直した本は、`work` に既存の版の id を書いて保存すれば同じ作品の版になる
+//! (既存の版がさらに別の作品の版なら、その作品にそろえる)。
+
+use crate::{Book, BookError};
+
+/// `book` の作品の id。
+pub fn work_of(book: &Book) -> u32 {
+    book.work.unwrap_or(book.id)
+}
+
+/// 保存する書籍の `work` を作品の id にそろえる。`get` で既存の書籍を引く。
+/// 指した書籍がなければ 400。自分自身を指すなら作品の id はいらないので `None`。
+pub fn resolve<'a>(book: &Book, get: impl Fn(u32) -> Option<&'a Book>) -> Result<Option<u32>, BookError> {
+    let Some(target) = book.work else {
+        return Ok(None);
+    };
+    if target == book.id {
+        return Ok(None);
+    }
+
+    let work = get(target)
+        .map(work_of)
+        .ok_or_else(|| BookError::BadRequest(format!("work: no book with id {}", target)))?;
+    Ok(Some(work).filter(|&work| work != book.id))
+}
+
+/// 作品 `work` の版。データファイルの順。
+pub fn editions(books: &[Book], work: u32) -> impl Iterator<Item = &Book> {
+    books.iter().filter(move |b| work_of(b) == work)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(id: u32, work: Option<u32>) -> Book {
+        Book { id, work, ..Default::default() }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let books = [book(1, None), book(2, Some(1)), book(3, None)];
+        let get = |id: u32| books.iter().find(|b| b.id == id);
+
+        // 版を指しても作品の id にそろえる
+        assert_eq!(resolve(&book(4, Some(2)), get).unwrap(), Some(1));
+        assert_eq!(resolve(&book(4, Some(3)), get).unwrap(), Some(3));
+        assert_eq!(resolve(&book(4, None), get).unwrap(), None);
+        assert_eq!(resolve(&book(1, Some(2)), get).unwrap(), None);
+        assert!(matches!(resolve(&book(4, Some(9)), get), Err(BookError::BadRequest(_))));
+
+        assert_eq!(editions(&books, 1).map(|b| b.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(editions(&books, 3).count(), 1);
+        assert_eq!(editions(&books, 2).count(), 0);
+    }
+}